@@ -1,56 +1,1374 @@
 #[cfg(feature = "x11")]
 pub mod x11;
 
-use crate::rules::CompiledRule;
+use crate::matchers::{ALL_WORKSPACES, MonitorId, Needs, WindowProps};
+use crate::memory::{self, Memory, Remembered};
+use crate::process::{ProcFs, ProcessInfoProvider};
+use crate::rules::{
+    Action, Apply, Axis, CompiledRule, GeometryTarget, PositionTarget, RestoreSuppression, Scope, SizeTarget,
+    StartupMode, Trigger, WorkspaceTarget,
+};
 
 #[cfg(feature = "x11")]
 use self::x11::X11Backend;
 
-enum Backend {
-    #[cfg(feature = "x11")]
-    X11(X11Backend),
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant, SystemTime};
+
+pub type WindowId = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowEvent {
+    // A window appeared
+    Created(WindowId),
+    // A window that already existed when the backend connected; goes
+    // through the [startup] policy instead of applying unconditionally
+    Existing(WindowId),
+    // The active window changed
+    FocusChanged {
+        old: Option<WindowId>,
+        new: Option<WindowId>,
+    },
+    // The current workspace changed to the given one
+    WorkspaceChanged(u32),
+    // A property rules match against changed after the window appeared
+    PropertyChanged(WindowId, ChangedProperty),
+    // A window passed to watch_geometry was moved or resized
+    GeometryChanged(WindowId),
+    // A window left the client list
+    Closed(WindowId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangedProperty {
+    Title,
+    // Class, role, or window type
+    Other,
+}
+
+impl std::fmt::Display for WindowEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let id = |w: &Option<WindowId>| w.map_or("none".to_string(), |w| format!("0x{:x}", w));
+        match self {
+            WindowEvent::Created(w) => write!(f, "created 0x{:x}", w),
+            WindowEvent::Existing(w) => write!(f, "existing 0x{:x}", w),
+            WindowEvent::FocusChanged { old, new } => write!(f, "focus {} -> {}", id(old), id(new)),
+            WindowEvent::WorkspaceChanged(ws) => write!(f, "workspace {}", ws),
+            WindowEvent::PropertyChanged(w, ChangedProperty::Title) => write!(f, "title changed on 0x{:x}", w),
+            WindowEvent::PropertyChanged(w, ChangedProperty::Other) => write!(f, "properties changed on 0x{:x}", w),
+            WindowEvent::GeometryChanged(w) => write!(f, "geometry changed on 0x{:x}", w),
+            WindowEvent::Closed(w) => write!(f, "closed 0x{:x}", w),
+        }
+    }
+}
+
+// A window system backend. The daemon owns exactly one and drives it from
+// its poll loop: wait on connection_fd(), then process_events() asks the
+// backend for newly appeared windows, reads their properties, matches the
+// rules and applies actions. Out-of-tree backends implement this trait and
+// are plugged in through a Registry or WindowManager::from_backend.
+pub trait WindowBackend {
+    fn name(&self) -> &str;
+
+    // Fd the daemon polls for readability.
+    fn connection_fd(&self) -> i32;
+
+    // False once the connection to the window system has failed.
+    fn connected(&self) -> bool {
+        true
+    }
+
+    // Drain pending events, in order. The first call also reports windows
+    // that existed at startup (and the window focused at startup).
+    fn poll_events(&self) -> Vec<WindowEvent>;
+
+    // Every client window the backend currently knows about.
+    fn list_windows(&self) -> Vec<WindowId> {
+        Vec::new()
+    }
+
+    // Window properties. Missing values are returned as empty strings.
+    fn get_class(&self, window: WindowId) -> String;
+    // WM_CLASS instance name
+    fn get_instance(&self, _window: WindowId) -> String {
+        String::new()
+    }
+    fn get_title(&self, window: WindowId) -> String;
+    fn get_role(&self, window: WindowId) -> String;
+    fn get_window_type(&self, window: WindowId) -> String;
+
+    // Pid of the window's process (_NET_WM_PID). When it is known, the
+    // process getters below are skipped in favour of the WindowManager's
+    // ProcessInfoProvider.
+    fn get_pid(&self, _window: WindowId) -> Option<u32> {
+        None
+    }
+
+    fn get_process_name(&self, _window: WindowId) -> String {
+        String::new()
+    }
+
+    // Environment of the window's process, if its pid is known and
+    // readable (same user).
+    fn get_process_env(&self, _window: WindowId) -> Option<HashMap<String, String>> {
+        None
+    }
+
+    // Uid owning the window's process
+    fn get_process_uid(&self, _window: WindowId) -> Option<u32> {
+        None
+    }
+
+    // Startup-notification ID the window was launched with
+    fn get_startup_id(&self, _window: WindowId) -> Option<String> {
+        None
+    }
+
+    // Leader of the window's group (WM_HINTS window_group, or
+    // WM_CLIENT_LEADER)
+    fn get_group_leader(&self, _window: WindowId) -> Option<WindowId> {
+        None
+    }
+
+    // Initial _NET_WM_STATE as names from matchers::WINDOW_STATES.
+    fn get_states(&self, _window: WindowId) -> Vec<String> {
+        Vec::new()
+    }
+
+    // Workspace the window is on (_NET_WM_DESKTOP), falling back to the
+    // current workspace if the window has not been assigned one yet.
+    fn current_workspace(&self) -> Option<u32> {
+        None
+    }
+
+    fn get_workspace(&self, _window: WindowId) -> Option<u32> {
+        None
+    }
+
+    // Output the window's center is on
+    fn get_monitor(&self, _window: WindowId) -> Option<MonitorId> {
+        None
+    }
+
+    // Root-relative (x, y, width, height), if the backend can report it.
+    fn get_geometry(&self, _window: WindowId) -> Option<(i32, i32, u32, u32)> {
+        None
+    }
+
+    // Number of connected outputs, if the backend can tell
+    fn monitor_count(&self) -> Option<u32> {
+        None
+    }
+
+    // Connected outputs, in the order monitor indices refer to
+    fn monitors(&self) -> Vec<MonitorInfo> {
+        Vec::new()
+    }
+
+    // One name per workspace, empty where unnamed, if the backend can tell
+    fn workspaces(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    // Whether a compositing manager is running, if the backend can tell
+    fn compositor_running(&self) -> Option<bool> {
+        None
+    }
+
+    // Hints the window manager advertises (_NET_SUPPORTED), if it does
+    fn supported_hints(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    // Actions. Failures are collected per action rather than aborting the
+    // rule, so one rejected request doesn't skip the rest.
+    fn apply_rule(&self, window: WindowId, rule: &CompiledRule, access: &WriteAccess) -> ApplyReport;
+
+    // Undo the reversible actions of a rule (state toggles, opacity,
+    // decorations). Used when a focus-triggered rule's window loses focus.
+    fn revert_rule(&self, _window: WindowId, _rule: &CompiledRule, _access: &WriteAccess) -> ApplyReport {
+        ApplyReport::default()
+    }
+
+    // Push any buffered requests to the server.
+    fn flush(&self) {}
+
+    // Report the window's moves and resizes as GeometryChanged from now
+    // on, for enforce rules
+    fn watch_geometry(&self, _window: WindowId) {}
+}
+
+// Proof that the daemon may change server state. Only WindowManager mints
+// one, and never in dry-run mode; every backend path that sends a mutating
+// request takes it by reference, so dry-run cannot reach those paths.
+pub struct WriteAccess {
+    _private: (),
+}
+
+// Outcome of applying one rule to one window
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApplyReport {
+    // (action name, error) for every action the server rejected
+    pub failures: Vec<(&'static str, String)>,
+}
+
+impl ApplyReport {
+    pub fn fail(&mut self, action: &Action, error: String) {
+        self.failures.push((action.name(), error));
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+// Whether the window manager will act on an action
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Support {
+    Supported,
+    Unsupported,
+    // Depends on something the window manager doesn't advertise
+    Unknown,
+}
+
+impl std::fmt::Display for Support {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Support::Supported => "supported",
+            Support::Unsupported => "unsupported",
+            Support::Unknown => "unknown",
+        })
+    }
+}
+
+// What an action relies on the window manager for
+enum Requires {
+    // Core protocol requests and properties, which always go through
+    Nothing,
+    // An EWMH hint listed in _NET_SUPPORTED
+    Hint(&'static str),
+    // A running compositor
+    Compositor,
+    // Something not advertised anywhere (Motif decorations, compositor
+    // extensions)
+    Unadvertised,
+}
+
+fn requires(action: &Action) -> Requires {
+    match action {
+        Action::Workspace(_) | Action::Pin(_) => Requires::Hint("_NET_WM_DESKTOP"),
+        Action::Follow(_) => Requires::Hint("_NET_CURRENT_DESKTOP"),
+        Action::Maximize(_) | Action::MaximizeAxis(Axis::Vertical) => {
+            Requires::Hint("_NET_WM_STATE_MAXIMIZED_VERT")
+        }
+        Action::MaximizeAxis(Axis::Horizontal) => Requires::Hint("_NET_WM_STATE_MAXIMIZED_HORZ"),
+        Action::Fullscreen(_) => Requires::Hint("_NET_WM_STATE_FULLSCREEN"),
+        Action::Sticky(_) => Requires::Hint("_NET_WM_STATE_STICKY"),
+        Action::Shade(_) => Requires::Hint("_NET_WM_STATE_SHADED"),
+        Action::Above(_) => Requires::Hint("_NET_WM_STATE_ABOVE"),
+        Action::Below(_) => Requires::Hint("_NET_WM_STATE_BELOW"),
+        Action::Focus(_) => Requires::Hint("_NET_ACTIVE_WINDOW"),
+        Action::Close(_) => Requires::Hint("_NET_CLOSE_WINDOW"),
+        Action::IconGeometry(_) => Requires::Hint("_NET_WM_ICON_GEOMETRY"),
+        Action::HandledIcons(_) => Requires::Hint("_NET_WM_HANDLED_ICONS"),
+        Action::Strut(..) => Requires::Hint("_NET_WM_STRUT_PARTIAL"),
+        Action::Opacity(_) => Requires::Compositor,
+        // Taskbars and switchers read _NET_WM_ICON, not the WM
+        Action::Icon(_) => Requires::Unadvertised,
+        Action::Decorate(_) | Action::Border(..) | Action::Blur(_) | Action::Shadow(_) => {
+            Requires::Unadvertised
+        }
+        _ => Requires::Nothing,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorInfo {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub primary: bool,
+}
+
+// What the daemon can report about one window without its title or
+// anything else that may be private
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowState {
+    pub id: WindowId,
+    pub class: String,
+    pub process: String,
+    pub window_type: String,
+    pub workspace: Option<u32>,
+    pub monitor: Option<MonitorId>,
+    pub geometry: Option<(i32, i32, u32, u32)>,
+    pub states: Vec<String>,
+}
+
+// Entries kept in the event history for debug dumps
+const HISTORY_LEN: usize = 200;
+
+// A rule with alert_on_failure whose actions failed on a window
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub rule: usize,
+    pub window: WindowId,
+    pub class: String,
+    pub title: String,
+    pub failures: Vec<(&'static str, String)>,
+}
+
+impl Alert {
+    pub fn summary(&self) -> String {
+        format!("cherrypie: rule[{}] failed on {}", self.rule, self.class)
+    }
+
+    // One line per failed action
+    pub fn body(&self) -> String {
+        let mut body = format!("window 0x{:x} '{}'", self.window, self.title);
+        for (action, err) in &self.failures {
+            body.push_str(&format!("\n{}: {}", action, err));
+        }
+        body
+    }
+}
+
+pub type BackendInit = fn(signal_fd: i32) -> Result<Box<dyn WindowBackend>, String>;
+
+// Ordered list of backend constructors. WindowManager::init tries each in
+// turn and keeps the first that comes up.
+pub struct Registry {
+    entries: Vec<(&'static str, BackendInit)>,
+}
+
+impl Registry {
+    pub fn empty() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn builtin() -> Self {
+        #[allow(unused_mut)]
+        let mut registry = Self::empty();
+
+        #[cfg(feature = "x11")]
+        registry.register("x11", |signal_fd| {
+            X11Backend::init(signal_fd).map(|b| Box::new(b) as Box<dyn WindowBackend>)
+        });
+
+        registry
+    }
+
+    pub fn register(&mut self, name: &'static str, init: BackendInit) -> &mut Self {
+        self.entries.push((name, init));
+        self
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.entries.iter().map(|(name, _)| *name).collect()
+    }
+}
+
+// A rule held back for a window, by delay_ms or until the title settles
+struct Pending {
+    due: Instant,
+    window: WindowId,
+    rule: usize,
+    // wait_for_title: how long the title has to stay put, and the latest
+    // the rule is evaluated regardless
+    title: Option<(Duration, Instant)>,
+    // The rules after it wait as well (the rule has stop = true), and run
+    // if it doesn't match once the title has settled
+    rest: bool,
+}
+
+// Why run_rules was called
+#[derive(Clone, Copy, PartialEq)]
+enum Pass {
+    Apply,
+    // A window that just appeared, with when its event was read
+    Created(Instant),
+    // A delay_ms rule whose delay is over
+    Delayed,
+    // A wait_for_title rule whose window's title has settled
+    Settled,
+    Revert,
+    // Windows present at startup, under the [startup] policy
+    Startup,
+    // An enforce rule putting a window back after it moved itself
+    Enforce,
+}
+
+// Geometry changes this soon after an enforce rule applied answer its own
+// request; more than ENFORCE_LIMIT re-applications within ENFORCE_SPAN
+// mean the window fights back, and it is left alone
+const ENFORCE_GRACE: Duration = Duration::from_millis(100);
+const ENFORCE_LIMIT: u32 = 5;
+const ENFORCE_SPAN: Duration = Duration::from_secs(10);
+
+// A window kept in place by enforce rules
+struct Enforced {
+    rules: Vec<usize>,
+    quiet_until: Instant,
+    // Geometry the last application settled on, if the backend reports it
+    expected: Option<(i32, i32, u32, u32)>,
+    reapplied: u32,
+    since: Instant,
 }
 
 pub struct WindowManager {
-    backend: Backend,
+    backend: Box<dyn WindowBackend>,
+    last_failure: Cell<Option<SystemTime>>,
+    alerts: RefCell<Vec<Alert>>,
+    // Rule index and window class of each application, for usage stats
+    fired: RefCell<Vec<(usize, String)>>,
+    // Rule index and time from the window appearing to the rule applied
+    latencies: RefCell<Vec<(usize, Duration)>>,
+    // Rules waiting on delay_ms or wait_for_title
+    pending: RefCell<Vec<Pending>>,
+    enforced: RefCell<HashMap<WindowId, Enforced>>,
+    // Geometry of remember rules' windows: what is kept across restarts,
+    // whether that changed since take_memory, and the windows being
+    // followed with their key and last seen placement
+    memory: RefCell<Memory>,
+    memory_changed: Cell<bool>,
+    remembering: RefCell<HashMap<WindowId, (String, Option<Remembered>)>>,
+    // Scratchpad name -> the window last hidden into it, and the rule
+    scratchpads: RefCell<HashMap<String, (WindowId, usize)>>,
+    // Window of the last focus change
+    active: Cell<Option<WindowId>>,
+    // Process details for windows whose backend reports a pid
+    process: Box<dyn ProcessInfoProvider>,
+    // Recent events and rule outcomes, oldest first
+    history: RefCell<VecDeque<String>>,
+    // Start of the [session_restore] window
+    started: Instant,
 }
 
 impl WindowManager {
     pub fn init(signal_fd: i32) -> Result<Self, String> {
-        // X11
-        #[cfg(feature = "x11")]
-        {
-            match X11Backend::init(signal_fd) {
-                Ok(b) => {
-                    return Ok(Self {
-                        backend: Backend::X11(b),
-                    });
-                }
-                Err(e) => eprintln!("[backend] x11: {}", e),
+        Self::init_from(&Registry::builtin(), signal_fd)
+    }
+
+    pub fn init_from(registry: &Registry, signal_fd: i32) -> Result<Self, String> {
+        for (name, init) in &registry.entries {
+            match init(signal_fd) {
+                Ok(backend) => return Ok(Self::from_backend(backend)),
+                Err(e) => eprintln!("[backend] {}: {}", name, e),
             }
         }
 
         Err("no usable backend found".into())
     }
 
-    pub fn backend_name(&self) -> &str {
-        match &self.backend {
-            #[cfg(feature = "x11")]
-            Backend::X11(_) => "x11",
+    pub fn from_backend(backend: Box<dyn WindowBackend>) -> Self {
+        Self {
+            backend,
+            last_failure: Cell::new(None),
+            alerts: RefCell::new(Vec::new()),
+            fired: RefCell::new(Vec::new()),
+            latencies: RefCell::new(Vec::new()),
+            pending: RefCell::new(Vec::new()),
+            enforced: RefCell::new(HashMap::new()),
+            memory: RefCell::new(Memory::default()),
+            memory_changed: Cell::new(false),
+            remembering: RefCell::new(HashMap::new()),
+            scratchpads: RefCell::new(HashMap::new()),
+            active: Cell::new(None),
+            process: Box::new(ProcFs::new()),
+            history: RefCell::new(VecDeque::with_capacity(HISTORY_LEN)),
+            started: Instant::now(),
         }
     }
 
+    // Look processes up through `provider` instead of the local /proc
+    pub fn with_process_info(mut self, provider: Box<dyn ProcessInfoProvider>) -> Self {
+        self.process = provider;
+        self
+    }
+
+    pub fn backend_name(&self) -> &str {
+        self.backend.name()
+    }
+
     pub fn connection_fd(&self) -> i32 {
-        match &self.backend {
-            #[cfg(feature = "x11")]
-            Backend::X11(b) => b.connection_fd(),
+        self.backend.connection_fd()
+    }
+
+    pub fn connected(&self) -> bool {
+        self.backend.connected()
+    }
+
+    // When an action last failed to apply, for health reporting
+    pub fn last_failure(&self) -> Option<SystemTime> {
+        self.last_failure.get()
+    }
+
+    pub fn active_window(&self) -> Option<WindowId> {
+        self.active.get()
+    }
+
+    // The scratchpad's window and the rule that put it there, while the
+    // window exists
+    pub fn scratchpad(&self, name: &str) -> Option<(WindowId, usize)> {
+        let entry = self.scratchpads.borrow().get(name).copied()?;
+        if self.backend.list_windows().contains(&entry.0) {
+            return Some(entry);
         }
+        self.scratchpads.borrow_mut().remove(name);
+        None
+    }
+
+    // Minimized, or on another workspace than the current one
+    pub fn out_of_sight(&self, window: WindowId) -> bool {
+        if self.backend.get_states(window).iter().any(|s| s == "hidden") {
+            return true;
+        }
+        match (self.backend.get_workspace(window), self.backend.current_workspace()) {
+            (Some(on), Some(current)) => on != current && on != ALL_WORKSPACES,
+            _ => false,
+        }
+    }
+
+    pub fn current_workspace(&self) -> Option<u32> {
+        self.backend.current_workspace()
+    }
+
+    pub fn monitors(&self) -> Vec<MonitorInfo> {
+        self.backend.monitors()
+    }
+
+    pub fn workspaces(&self) -> Option<Vec<String>> {
+        self.backend.workspaces()
+    }
+
+    pub fn title(&self, window: WindowId) -> String {
+        self.backend.get_title(window)
+    }
+
+    // The last events and rule outcomes, oldest first
+    pub fn history(&self) -> Vec<String> {
+        self.history.borrow().iter().cloned().collect()
+    }
+
+    pub fn window_state(&self, window: WindowId) -> WindowState {
+        WindowState {
+            id: window,
+            class: self.backend.get_class(window),
+            process: self.process_name(window),
+            window_type: self.backend.get_window_type(window),
+            workspace: self.backend.get_workspace(window),
+            monitor: self.backend.get_monitor(window),
+            geometry: self.backend.get_geometry(window),
+            states: self.backend.get_states(window),
+        }
+    }
+
+    fn record(&self, entry: String) {
+        let mut history = self.history.borrow_mut();
+        if history.len() == HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(format!("{} {}", local_time(), entry));
+    }
+
+    // Client windows, or those on one workspace (sticky ones included)
+    pub fn windows_on(&self, workspace: Option<u32>) -> Vec<WindowId> {
+        let mut windows = self.backend.list_windows();
+        if let Some(ws) = workspace {
+            windows.retain(|&w| matches!(self.backend.get_workspace(w), Some(on) if on == ws || on == ALL_WORKSPACES));
+        }
+        windows
+    }
+
+    // Run the map rules, or only rule[`only`], over existing windows as if
+    // they had just appeared
+    pub fn reapply(&self, windows: &[WindowId], rules: &[CompiledRule], only: Option<usize>, dry_run: bool) {
+        let access = if dry_run { None } else { Some(WriteAccess { _private: () }) };
+        let select = |r: &CompiledRule| r.trigger == Trigger::Map && only.is_none_or(|i| r.index == i);
+        for &window in windows {
+            self.run_rules(window, rules, &select, Pass::Apply, access.as_ref());
+        }
+        self.backend.flush();
+    }
+
+    // When the next held-back rule is due, for the daemon's timer
+    pub fn next_delayed(&self) -> Option<Instant> {
+        self.pending.borrow().iter().map(|p| p.due).min()
+    }
+
+    // Run the held-back rules that are due on windows that still exist;
+    // they are matched again first
+    pub fn run_delayed(&self, rules: &[CompiledRule], dry_run: bool) {
+        let now = Instant::now();
+        let due: Vec<Pending> = {
+            let mut pending = self.pending.borrow_mut();
+            let (due, waiting) = pending.drain(..).partition(|p| p.due <= now);
+            *pending = waiting;
+            due
+        };
+        if due.is_empty() {
+            return;
+        }
+        let access = if dry_run { None } else { Some(WriteAccess { _private: () }) };
+        let windows = self.backend.list_windows();
+        for p in due.into_iter().filter(|p| windows.contains(&p.window)) {
+            let select = |r: &CompiledRule| r.index == p.rule;
+            let pass = if p.title.is_some() { Pass::Settled } else { Pass::Delayed };
+            if self.run_rules(p.window, rules, &select, pass, access.as_ref()) || !p.rest {
+                continue;
+            }
+            let Some(at) = rules.iter().position(|r| r.index == p.rule) else {
+                continue;
+            };
+            let later: Vec<usize> = rules[at + 1..].iter().map(|r| r.index).collect();
+            let rest = |r: &CompiledRule| r.trigger == Trigger::Map && later.contains(&r.index);
+            self.run_rules(p.window, rules, &rest, Pass::Settled, access.as_ref());
+        }
+        self.backend.flush();
+    }
+
+    // Start (or keep) watching a window an enforce rule was applied to
+    fn enforce(&self, window: WindowId, rule: usize) {
+        let quiet_until = Instant::now() + ENFORCE_GRACE;
+        let mut enforced = self.enforced.borrow_mut();
+        if let Some(e) = enforced.get_mut(&window) {
+            if !e.rules.contains(&rule) {
+                e.rules.push(rule);
+            }
+            e.quiet_until = quiet_until;
+            return;
+        }
+        // Forget windows that have gone away
+        let windows = self.backend.list_windows();
+        enforced.retain(|w, _| windows.contains(w));
+        let since = Instant::now();
+        let entry = Enforced { rules: vec![rule], quiet_until, expected: None, reapplied: 0, since };
+        enforced.insert(window, entry);
+        self.backend.watch_geometry(window);
+    }
+
+    // Put a window back when it moved or resized itself
+    fn geometry_changed(&self, window: WindowId, rules: &[CompiledRule], access: Option<&WriteAccess>) {
+        let geometry = self.backend.get_geometry(window);
+        let select = {
+            let mut enforced = self.enforced.borrow_mut();
+            let Some(e) = enforced.get_mut(&window) else {
+                return;
+            };
+            if Instant::now() < e.quiet_until {
+                e.expected = geometry;
+                return;
+            }
+            // Stacking changes are reported too
+            if geometry.is_some() && geometry == e.expected {
+                return;
+            }
+            if e.since.elapsed() > ENFORCE_SPAN {
+                e.reapplied = 0;
+                e.since = Instant::now();
+            }
+            e.reapplied += 1;
+            if e.reapplied > ENFORCE_LIMIT {
+                let rules = std::mem::take(&mut e.rules);
+                enforced.remove(&window);
+                eprintln!(
+                    "[{}] [WARN]   window 0x{:x} keeps moving itself; no longer enforcing rule{} {:?}",
+                    local_time(),
+                    window,
+                    if rules.len() == 1 { "" } else { "s" },
+                    rules
+                );
+                self.record(format!("gave up enforcing on 0x{:x}", window));
+                return;
+            }
+            e.rules.clone()
+        };
+        let enforcing = |r: &CompiledRule| select.contains(&r.index);
+        self.run_rules(window, rules, &enforcing, Pass::Enforce, access);
+    }
+
+    // Geometry remembered by an earlier run, from the daemon's state file
+    pub fn set_memory(&self, memory: Memory) {
+        *self.memory.borrow_mut() = memory;
+    }
+
+    // Everything remembered, if a window closed since the last call
+    pub fn take_memory(&self) -> Option<Memory> {
+        self.memory_changed.replace(false).then(|| self.memory.borrow().clone())
+    }
+
+    fn memory_key(&self, window: WindowId, rule: &CompiledRule, props: &WindowProps) -> Option<String> {
+        let titles = rule.remember.as_ref()?;
+        Some(memory::key(&props.class, &self.backend.get_instance(window), titles))
+    }
+
+    // Follow a window's geometry until it closes
+    fn remember(&self, window: WindowId, key: String) {
+        self.remembering.borrow_mut().entry(window).or_insert((key, None));
+        self.backend.watch_geometry(window);
+    }
+
+    fn note_geometry(&self, window: WindowId) {
+        if let Some((_, last)) = self.remembering.borrow_mut().get_mut(&window)
+            && let Some((x, y, width, height)) = self.backend.get_geometry(window)
+        {
+            let workspace = self.backend.get_workspace(window);
+            *last = Some(Remembered { x, y, width, height, workspace });
+        }
+    }
+
+    fn closed(&self, window: WindowId) {
+        self.enforced.borrow_mut().remove(&window);
+        if let Some((key, Some(last))) = self.remembering.borrow_mut().remove(&window) {
+            self.memory.borrow_mut().windows.insert(key, last);
+            self.memory_changed.set(true);
+        }
+    }
+
+    // Hold a rule back, once per window
+    fn hold(&self, held: Pending) {
+        let mut pending = self.pending.borrow_mut();
+        if !pending.iter().any(|p| p.window == held.window && p.rule == held.rule) {
+            pending.push(held);
+        }
+    }
+
+    // Restart the settle time of rules waiting on the window's title
+    fn title_changed(&self, window: WindowId) {
+        let now = Instant::now();
+        for p in self.pending.borrow_mut().iter_mut().filter(|p| p.window == window) {
+            if let Some((settle, deadline)) = p.title {
+                p.due = (now + settle).min(deadline);
+            }
+        }
+    }
+
+    // Apply actions to a window on request rather than through a rule
+    pub fn place(&self, window: WindowId, actions: Vec<Action>, dry_run: bool) -> Result<(), String> {
+        if !self.backend.list_windows().contains(&window) {
+            return Err(format!("no client window 0x{:x}", window));
+        }
+        let rule = CompiledRule::from_actions(actions);
+        if dry_run {
+            log_actions(&rule);
+            return Ok(());
+        }
+
+        let report = self.backend.apply_rule(window, &rule, &WriteAccess { _private: () });
+        self.backend.flush();
+        if report.is_ok() {
+            return Ok(());
+        }
+        self.last_failure.set(Some(SystemTime::now()));
+        let failures: Vec<String> = report.failures.iter().map(|(a, e)| format!("{}: {}", a, e)).collect();
+        Err(failures.join(", "))
+    }
+
+    // Failures of alert_on_failure rules since the last call, for the
+    // daemon to notify about
+    pub fn take_alerts(&self) -> Vec<Alert> {
+        self.alerts.take()
+    }
+
+    // Rules applied since the last call, with the class of the window
+    // each was applied to
+    pub fn take_fired(&self) -> Vec<(usize, String)> {
+        self.fired.take()
+    }
+
+    // Apply latencies of rules on new windows since the last call, for the
+    // metrics file
+    pub fn take_latencies(&self) -> Vec<(usize, Duration)> {
+        self.latencies.take()
+    }
+
+    // Each action the rules use, once, with whether the window manager
+    // will act on it, for the daemon to report at startup
+    pub fn support_report(&self, rules: &[CompiledRule]) -> Vec<(&'static str, Support)> {
+        let hints = self.backend.supported_hints();
+        let mut report: Vec<(&'static str, Support)> = Vec::new();
+        for action in rules.iter().flat_map(|r| &r.actions) {
+            if report.iter().any(|(name, _)| *name == action.name()) {
+                continue;
+            }
+            let support = match (requires(action), &hints) {
+                (Requires::Nothing, _) => Support::Supported,
+                (Requires::Hint(hint), Some(hints)) if hints.iter().any(|h| h == hint) => Support::Supported,
+                (Requires::Hint(_), Some(_)) => Support::Unsupported,
+                (Requires::Compositor, _) => match self.backend.compositor_running() {
+                    Some(true) => Support::Supported,
+                    Some(false) => Support::Unsupported,
+                    None => Support::Unknown,
+                },
+                (Requires::Hint(_), None) | (Requires::Unadvertised, _) => Support::Unknown,
+            };
+            report.push((action.name(), support));
+        }
+        report
+    }
+
+    // Problems with the rule set that depend on the running session, for
+    // the daemon to report after each (re)load
+    pub fn rule_warnings(&self, rules: &[CompiledRule]) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        let compositor_action = |r: &CompiledRule| {
+            r.actions
+                .iter()
+                .find(|a| matches!(a, Action::Opacity(_) | Action::Blur(_) | Action::Shadow(_)))
+                .map(Action::name)
+        };
+        if rules.iter().any(|r| compositor_action(r).is_some())
+            && self.backend.compositor_running() == Some(false)
+        {
+            for rule in rules {
+                // Rules conditioned on the compositor already know
+                if let Some(action) = compositor_action(rule)
+                    && !rule.needs().compositor
+                {
+                    warnings.push(format!(
+                        "{}: {} has no effect without a running compositor",
+                        rule.label(),
+                        action
+                    ));
+                }
+            }
+        }
+
+        warnings
     }
 
     pub fn process_events(&self, rules: &[CompiledRule], dry_run: bool) {
-        match &self.backend {
-            #[cfg(feature = "x11")]
-            Backend::X11(b) => b.process_events(rules, dry_run),
+        let access = if dry_run { None } else { Some(WriteAccess { _private: () }) };
+        let mut need_flush = false;
+
+        // Loop: handling events involves property round-trips. During
+        // those reads the backend may buffer additional events from its
+        // socket. If we don't re-drain, those events sit in the internal
+        // queue while poll() sees no socket data and never wakes us.
+        loop {
+            let events = self.backend.poll_events();
+            if events.is_empty() {
+                break;
+            }
+            let received = Instant::now();
+
+            for event in events {
+                self.record(event.to_string());
+                match event {
+                    WindowEvent::Created(window) => {
+                        let on_map = |r: &CompiledRule| r.trigger == Trigger::Map;
+                        self.run_rules(window, rules, &on_map, Pass::Created(received), access.as_ref());
+                    }
+                    WindowEvent::Existing(window) => {
+                        let swept = |r: &CompiledRule| {
+                            r.trigger == Trigger::Map && r.startup != StartupMode::Skip
+                        };
+                        self.run_rules(window, rules, &swept, Pass::Startup, access.as_ref());
+                    }
+                    WindowEvent::FocusChanged { old, new } => {
+                        self.active.set(new);
+                        let on_focus = |r: &CompiledRule| r.trigger == Trigger::Focus;
+                        if let Some(window) = old {
+                            // Catches moves to another workspace
+                            self.note_geometry(window);
+                            self.run_rules(window, rules, &on_focus, Pass::Revert, access.as_ref());
+                        }
+                        if let Some(window) = new {
+                            self.run_rules(window, rules, &on_focus, Pass::Apply, access.as_ref());
+                        }
+                    }
+                    WindowEvent::PropertyChanged(window, prop) => {
+                        if prop == ChangedProperty::Title {
+                            self.title_changed(window);
+                        }
+                        // Map rules that asked to re-fire as the window evolves
+                        let refires = |r: &CompiledRule| {
+                            r.trigger == Trigger::Map
+                                && match r.apply {
+                                    Apply::Once => false,
+                                    Apply::Always => true,
+                                    Apply::OnTitleChange => prop == ChangedProperty::Title,
+                                }
+                        };
+                        self.run_rules(window, rules, &refires, Pass::Apply, access.as_ref());
+                    }
+                    WindowEvent::WorkspaceChanged(workspace) => {
+                        self.workspace_switched(workspace, rules, access.as_ref());
+                    }
+                    WindowEvent::GeometryChanged(window) => {
+                        self.note_geometry(window);
+                        self.geometry_changed(window, rules, access.as_ref());
+                    }
+                    WindowEvent::Closed(window) => self.closed(window),
+                }
+            }
+            need_flush = true;
+        }
+
+        if need_flush {
+            self.backend.flush();
+        }
+    }
+
+    // Run workspace-switch rules on the windows visible on the new workspace
+    fn workspace_switched(&self, workspace: u32, rules: &[CompiledRule], access: Option<&WriteAccess>) {
+        if !rules.iter().any(|r| r.trigger == Trigger::WorkspaceSwitch) {
+            return;
+        }
+        for window in self.backend.list_windows() {
+            let on = self.backend.get_workspace(window);
+            if on == Some(workspace) || on == Some(ALL_WORKSPACES) {
+                let on_switch = |r: &CompiledRule| r.trigger == Trigger::WorkspaceSwitch;
+                self.run_rules(window, rules, &on_switch, Pass::Apply, access);
+            }
+        }
+    }
+
+    // Apply (or revert) every selected rule that matches; whether any did
+    fn run_rules(
+        &self,
+        window: WindowId,
+        rules: &[CompiledRule],
+        select: &dyn Fn(&CompiledRule) -> bool,
+        pass: Pass,
+        access: Option<&WriteAccess>,
+    ) -> bool {
+        if !rules.iter().any(select) {
+            return false;
+        }
+        let props = self.window_props(window, rules);
+        let revert = pass == Pass::Revert;
+        let mut matched = false;
+
+        for rule in rules.iter().filter(|r| select(r)) {
+            // Reverts still go through so focus rules undo what they did,
+            // and enforcing keeps a window where it was already placed
+            if !revert && pass != Pass::Enforce && rule.exhausted() {
+                continue;
+            }
+            // Monitor defaults are for windows no rule took care of
+            if rule.fallback && matched {
+                continue;
+            }
+            // Matched once the title has stopped changing, if the rest of
+            // the rule matches now
+            if let Some((settle, timeout)) = rule.wait_for_title
+                && let Pass::Created(appeared) = pass
+            {
+                if !rule.matches_besides_title(&props) {
+                    continue;
+                }
+                let title = Some((settle, appeared + timeout));
+                let due = Instant::now() + settle;
+                self.hold(Pending { due, window, rule: rule.index, title, rest: rule.stop });
+                // Monitor defaults still apply meanwhile unless stop holds
+                // them back with the other later rules
+                if rule.stop {
+                    break;
+                }
+                continue;
+            }
+            if rule.matches(&props) {
+                matched = true;
+                // A startup dry run leaves the rule its matches for live windows
+                let preview = pass == Pass::Startup && rule.startup == StartupMode::DryRun;
+                let access = if preview { None } else { access };
+                let now = local_time();
+                // Comes back through run_delayed once the delay is over
+                if let Some(delay) = rule.delay
+                    && !revert
+                    && pass != Pass::Delayed
+                    && pass != Pass::Enforce
+                    && access.is_some()
+                {
+                    eprintln!(
+                        "[{}] [INFO]   delaying {} on 0x{:x} by {} ms",
+                        now,
+                        rule.label(),
+                        window,
+                        delay.as_millis()
+                    );
+                    let due = Instant::now() + delay;
+                    self.hold(Pending { due, window, rule: rule.index, title: None, rest: false });
+                    if rule.stop {
+                        break;
+                    }
+                    continue;
+                }
+                if !revert && !preview && pass != Pass::Enforce {
+                    rule.record_match();
+                }
+                let verb = match pass {
+                    Pass::Revert => "reverting",
+                    Pass::Enforce => "enforcing",
+                    _ => "matched",
+                };
+                eprintln!(
+                    "[{}] [INFO]   {} '{}' with {} (class='{}', title='{}', process='{}')",
+                    now,
+                    verb,
+                    props.class,
+                    rule.label(),
+                    props.class,
+                    props.title,
+                    props.process
+                );
+
+                // Opens where the last window like it closed
+                let key = self.memory_key(window, rule, &props);
+                let restored = match (&key, pass) {
+                    (Some(key), Pass::Created(_)) => {
+                        self.memory.borrow().windows.get(key).map(|last| restore(rule, last))
+                    }
+                    _ => None,
+                };
+
+                match access {
+                    Some(access) => {
+                        let placement;
+                        let applying = if pass == Pass::Enforce {
+                            placement = rule.with_actions(|a| {
+                                matches!(a, Action::Monitor(_) | Action::Size(_) | Action::Position(_))
+                            });
+                            &placement
+                        } else {
+                            restored.as_ref().unwrap_or(rule)
+                        };
+                        let mut report = if revert {
+                            self.backend.revert_rule(window, rule, access)
+                        } else if let Some(held) = self.held_back(rule) {
+                            let rest = applying.with_actions(|a| !held.actions.iter().any(|n| n == a.name()));
+                            if rest.actions.len() < applying.actions.len() {
+                                eprintln!(
+                                    "[{}] [INFO]   session restore: holding back {}",
+                                    now,
+                                    held.actions.join(", ")
+                                );
+                            }
+                            self.backend.apply_rule(window, &rest, access)
+                        } else {
+                            self.backend.apply_rule(window, applying, access)
+                        };
+                        if rule.enforce && !revert {
+                            self.enforce(window, rule.index);
+                        }
+                        if let Some(key) = key
+                            && !revert
+                        {
+                            self.remember(window, key);
+                        }
+                        if let Some(ref name) = rule.scratchpad
+                            && !revert
+                        {
+                            self.scratchpads.borrow_mut().insert(name.clone(), (window, rule.index));
+                        }
+                        if rule.move_group && !revert && pass != Pass::Enforce {
+                            report.failures.extend(self.move_group(window, rule, access).failures);
+                        }
+                        if rule.move_with_main && !revert && pass != Pass::Enforce {
+                            report.failures.extend(self.move_with_main(window, access).failures);
+                        }
+                        if rule.scope == Scope::Application && !revert && pass != Pass::Enforce {
+                            report.failures.extend(self.apply_to_app(window, rule, access).failures);
+                        }
+                        if !report.is_ok() {
+                            self.last_failure.set(Some(SystemTime::now()));
+                        }
+                        let verb = match pass {
+                            Pass::Revert => "reverted on",
+                            Pass::Enforce => "enforced on",
+                            _ => "applied to",
+                        };
+                        self.record(format!("{} {} 0x{:x}", rule.label(), verb, window));
+                        if !revert && pass != Pass::Enforce {
+                            self.fired.borrow_mut().push((rule.index, props.class.clone()));
+                        }
+                        if let Pass::Created(appeared) = pass {
+                            let latency = appeared.elapsed();
+                            self.latencies.borrow_mut().push((rule.index, latency));
+                            if rule.slow_apply.is_some_and(|slow| latency > slow) {
+                                eprintln!(
+                                    "[{}] [WARN]   {} took {} ms to apply to 0x{:x}",
+                                    now,
+                                    rule.label(),
+                                    latency.as_millis(),
+                                    window
+                                );
+                                self.record(format!(
+                                    "{} slow: {} ms on 0x{:x}",
+                                    rule.label(),
+                                    latency.as_millis(),
+                                    window
+                                ));
+                            }
+                        }
+                        for (action, err) in &report.failures {
+                            eprintln!("[{}] [WARN]   {} failed on 0x{:x}: {}", now, action, window, err);
+                            let label = rule.label();
+                            self.record(format!("{} {} failed on 0x{:x}: {}", label, action, window, err));
+                        }
+                        if rule.alert_on_failure && !report.is_ok() && self.held_back(rule).is_none() {
+                            self.alerts.borrow_mut().push(Alert {
+                                rule: rule.index,
+                                window,
+                                class: props.class.clone(),
+                                title: props.title.clone(),
+                                failures: report.failures,
+                            });
+                        }
+                    }
+                    None if revert => eprintln!("[{}] [DRY]    revert", now),
+                    None => log_actions(rule),
+                }
+
+                if rule.stop {
+                    break;
+                }
+            }
+        }
+        matched
+    }
+
+    // Carry the rule's workspace and monitor moves over to the other
+    // windows of the group. A monitor move takes the position along, since
+    // that is what places the window on the output.
+    fn move_group(&self, window: WindowId, rule: &CompiledRule, access: &WriteAccess) -> ApplyReport {
+        let Some(leader) = self.backend.get_group_leader(window) else {
+            return ApplyReport::default();
+        };
+        let to_monitor = rule.monitor().is_some();
+        let moves = rule.with_actions(|a| match a {
+            Action::Workspace(_) => true,
+            Action::Monitor(_) | Action::Position(_) => to_monitor,
+            _ => false,
+        });
+
+        let mut report = ApplyReport::default();
+        for member in self.backend.list_windows() {
+            if member != window && self.backend.get_group_leader(member) == Some(leader) {
+                report.failures.extend(self.backend.apply_rule(member, &moves, access).failures);
+            }
         }
+        report
+    }
+
+    // Put a secondary window (toolbox, palette) on the workspace and
+    // monitor of its group's main window: the leader if it is a client
+    // window, else the first normal window of the group. A monitor change
+    // keeps the window's offset within the monitor.
+    fn move_with_main(&self, window: WindowId, access: &WriteAccess) -> ApplyReport {
+        let b = &self.backend;
+        let Some(leader) = b.get_group_leader(window) else {
+            return ApplyReport::default();
+        };
+        let windows = b.list_windows();
+        let main = if windows.contains(&leader) && leader != window {
+            Some(leader)
+        } else {
+            windows.iter().copied().find(|&w| {
+                w != window && b.get_group_leader(w) == Some(leader) && b.get_window_type(w) == "normal"
+            })
+        };
+        let Some(main) = main else {
+            return ApplyReport::default();
+        };
+
+        let mut actions = Vec::new();
+        if let Some(workspace) = b.get_workspace(main) {
+            actions.push(Action::Workspace(WorkspaceTarget::Index(workspace)));
+        }
+        let monitors = b.monitors();
+        let origin = |w: WindowId| {
+            let name = b.get_monitor(w)?.name;
+            monitors.iter().find(|m| m.name == name).map(|m| (m.x, m.y))
+        };
+        let geometry = b.get_geometry(window);
+        if let (Some(from), Some(to), Some((x, y, _, _))) = (origin(window), origin(main), geometry)
+            && from != to
+        {
+            actions.push(Action::Position(PositionTarget::Absolute(x - from.0 + to.0, y - from.1 + to.1)));
+        }
+        let mut moves = CompiledRule::from_actions(actions);
+        moves.target = Some(GeometryTarget::Client);
+        b.apply_rule(window, &moves, access)
+    }
+
+    // Apply the rule to the application's other open windows: those of the
+    // same class or process. Hooks and captures are for the matched window.
+    fn apply_to_app(&self, window: WindowId, rule: &CompiledRule, access: &WriteAccess) -> ApplyReport {
+        let class = self.backend.get_class(window);
+        let pid = self.backend.get_pid(window);
+        let rest = rule.with_actions(|a| {
+            !matches!(a, Action::ExecBefore(_) | Action::Exec(_) | Action::ExecAfter(_) | Action::Capture(_))
+        });
+
+        let mut report = ApplyReport::default();
+        for other in self.backend.list_windows() {
+            let same = (!class.is_empty() && self.backend.get_class(other) == class)
+                || (pid.is_some() && self.backend.get_pid(other) == pid);
+            if other != window && same {
+                report.failures.extend(self.backend.apply_rule(other, &rest, access).failures);
+            }
+        }
+        report
+    }
+
+    // The rule's [session_restore] suppression, while it is in effect
+    fn held_back<'a>(&self, rule: &'a CompiledRule) -> Option<&'a RestoreSuppression> {
+        rule.session_restore
+            .as_ref()
+            .filter(|r| self.started.elapsed() < r.window)
+    }
+
+    // Extras cost additional round-trips; only fetch what some rule needs
+    fn process_name(&self, window: WindowId) -> String {
+        match self.backend.get_pid(window) {
+            Some(pid) => self.process.name(pid).unwrap_or_default(),
+            None => self.backend.get_process_name(window),
+        }
+    }
+
+    fn process_env(&self, window: WindowId) -> Option<HashMap<String, String>> {
+        match self.backend.get_pid(window) {
+            Some(pid) => self.process.env(pid),
+            None => self.backend.get_process_env(window),
+        }
+    }
+
+    fn window_props(&self, window: WindowId, rules: &[CompiledRule]) -> WindowProps {
+        let b = &self.backend;
+        let needs = rules
+            .iter()
+            .fold(Needs::default(), |acc, r| acc.union(r.needs()));
+
+        WindowProps {
+            class: b.get_class(window),
+            title: b.get_title(window),
+            role: b.get_role(window),
+            process: self.process_name(window),
+            window_type: b.get_window_type(window),
+            size: if needs.geometry {
+                b.get_geometry(window).map(|(_, _, w, h)| (w, h))
+            } else {
+                None
+            },
+            states: if needs.states {
+                b.get_states(window)
+            } else {
+                Vec::new()
+            },
+            workspace: if needs.workspace {
+                b.get_workspace(window)
+            } else {
+                None
+            },
+            monitor: if needs.monitor {
+                b.get_monitor(window)
+            } else {
+                None
+            },
+            process_env: if needs.process_env {
+                self.process_env(window)
+            } else {
+                None
+            },
+            uid: if needs.uid {
+                match b.get_pid(window) {
+                    Some(pid) => self.process.uid(pid),
+                    None => b.get_process_uid(window),
+                }
+            } else {
+                None
+            },
+            startup_id: if needs.startup_id {
+                // Toolkits that don't copy DESKTOP_STARTUP_ID onto the window
+                // at least leave it in their environment
+                b.get_startup_id(window)
+                    .or_else(|| self.process_env(window)?.remove("DESKTOP_STARTUP_ID"))
+            } else {
+                None
+            },
+            group_class: if needs.group {
+                b.get_group_leader(window).map(|leader| b.get_class(leader))
+            } else {
+                None
+            },
+            compositor: if needs.compositor {
+                b.compositor_running()
+            } else {
+                None
+            },
+            monitor_count: if needs.monitor_count {
+                b.monitor_count()
+            } else {
+                None
+            },
+            minute_of_day: if needs.time {
+                Some(local_minute_of_day())
+            } else {
+                None
+            },
+        }
+    }
+}
+
+// The rule with its placement replaced by a remembered client geometry
+// and workspace
+fn restore(rule: &CompiledRule, last: &Remembered) -> CompiledRule {
+    let mut restored = rule.with_actions(|a| {
+        !matches!(
+            a,
+            Action::Monitor(_)
+                | Action::Size(_)
+                | Action::ResizeBy(..)
+                | Action::Aspect(..)
+                | Action::Position(_)
+                | Action::MoveBy(..)
+                | Action::Workspace(_)
+        )
+    });
+    let mut placement = vec![
+        Action::Size(SizeTarget::Absolute(last.width, last.height)),
+        Action::Position(PositionTarget::Absolute(last.x, last.y)),
+    ];
+    if let Some(workspace) = last.workspace {
+        placement.push(Action::Workspace(WorkspaceTarget::Index(workspace)));
+    }
+    // exec_before still goes first
+    let at = restored.actions.iter().take_while(|a| matches!(a, Action::ExecBefore(_))).count();
+    restored.actions.splice(at..at, placement);
+    restored.target = Some(GeometryTarget::Client);
+    restored
+}
+
+fn log_actions(rule: &CompiledRule) {
+    let now = local_time();
+    for action in &rule.actions {
+        eprintln!("[{}] [DRY]    {}", now, action);
+    }
+}
+
+fn local_minute_of_day() -> u32 {
+    unsafe {
+        let mut t: libc::time_t = 0;
+        libc::time(&mut t);
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&t, &mut tm);
+        (tm.tm_hour * 60 + tm.tm_min) as u32
+    }
+}
+
+pub(crate) fn local_time() -> String {
+    unsafe {
+        let mut t: libc::time_t = 0;
+        libc::time(&mut t);
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&t, &mut tm);
+        format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
     }
 }
@@ -1,15 +1,26 @@
+use std::collections::{HashMap, HashSet};
 use std::os::fd::AsRawFd;
+use std::process::Command;
 
 use x11rb::atom_manager;
 use x11rb::connection::Connection;
-use x11rb::properties::WmClass;
-use x11rb::protocol::randr::ConnectionExt as RandrExt;
+use x11rb::properties::{WmClass, WmHints, WmSizeHints, WmSizeHintsSpecification};
+use x11rb::protocol::Event;
+use x11rb::protocol::randr::{ConnectionExt as RandrExt, NotifyMask, Rotation};
 use x11rb::protocol::xproto::*;
 use x11rb::rust_connection::RustConnection;
 use x11rb::wrapper::ConnectionExt as _;
 
+use super::{ApplyReport, ChangedProperty, MonitorInfo, WindowBackend, WindowEvent, WriteAccess};
+use crate::capture;
+use crate::config::PropValue;
+use crate::exec;
+use crate::icon;
+use crate::matchers::{ALL_WORKSPACES, MonitorId};
 use crate::rules::{
-    CompiledRule, DimensionVal, MonitorTarget, NamedPosition, PositionTarget, SizeTarget,
+    Action, Axis, CompiledRule, Edge, GeometryTarget, Margin, MonitorTarget, NamedPosition, Placement,
+    OutputInfo, PositionTarget, SizeTarget, WorkspaceTarget, at_pointer, fit_aspect, smart_position,
+    snap_to_increments, strut_partial,
 };
 
 atom_manager! {
@@ -17,12 +28,18 @@ atom_manager! {
         WM_NAME,
         WM_CLASS,
         WM_WINDOW_ROLE,
+        WM_CLIENT_LEADER,
         WM_CHANGE_STATE,
         UTF8_STRING,
         _NET_CLIENT_LIST,
+        _NET_SUPPORTED,
         _NET_WM_NAME,
         _NET_WM_PID,
+        _NET_STARTUP_ID,
         _NET_WM_DESKTOP,
+        _NET_CURRENT_DESKTOP,
+        _NET_NUMBER_OF_DESKTOPS,
+        _NET_DESKTOP_NAMES,
         _NET_WM_STATE,
         _NET_WM_STATE_MAXIMIZED_VERT,
         _NET_WM_STATE_MAXIMIZED_HORZ,
@@ -32,6 +49,10 @@ atom_manager! {
         _NET_WM_STATE_FULLSCREEN,
         _NET_WM_STATE_SHADED,
         _NET_WM_STATE_HIDDEN,
+        _NET_WM_STATE_MODAL,
+        _NET_WM_STATE_SKIP_TASKBAR,
+        _NET_WM_STATE_SKIP_PAGER,
+        _NET_WM_STATE_DEMANDS_ATTENTION,
         _NET_WM_WINDOW_TYPE,
         _NET_WM_WINDOW_TYPE_NORMAL,
         _NET_WM_WINDOW_TYPE_DESKTOP,
@@ -43,6 +64,16 @@ atom_manager! {
         _NET_WM_WINDOW_TYPE_SPLASH,
         _NET_WM_WINDOW_OPACITY,
         _NET_ACTIVE_WINDOW,
+        _NET_CLOSE_WINDOW,
+        _KDE_NET_WM_BLUR_BEHIND_REGION,
+        _COMPTON_SHADOW,
+        _NET_FRAME_EXTENTS,
+        _NET_WM_ICON_GEOMETRY,
+        _NET_WM_HANDLED_ICONS,
+        _NET_WM_STRUT,
+        _NET_WM_STRUT_PARTIAL,
+        _NET_WM_ICON,
+        _NET_WM_FULLSCREEN_MONITORS,
         _MOTIF_WM_HINTS,
     }
 }
@@ -54,16 +85,53 @@ pub struct MonitorGeometry {
     pub y: i32,
     pub width: u32,
     pub height: u32,
+    // Physical size as reported by RandR, 0 if unknown
+    pub mm_width: u32,
+    pub mm_height: u32,
+    // RandR primary output
+    pub primary: bool,
+}
+
+impl MonitorGeometry {
+    // The area inside `margin`, at the same pixel density
+    fn inset(self, margin: &Margin) -> Self {
+        let (x, y, width, height) = margin.inset(self.x, self.y, self.width, self.height);
+        let scale = |mm: u32, px: u32, of: u32| (mm as u64 * px as u64 / of.max(1) as u64) as u32;
+        Self {
+            mm_width: scale(self.mm_width, width, self.width),
+            mm_height: scale(self.mm_height, height, self.height),
+            x,
+            y,
+            width,
+            height,
+            ..self
+        }
+    }
 }
 
 pub struct X11Backend {
     conn: RustConnection,
     root: Window,
     atoms: Atoms,
-    monitors: Vec<MonitorGeometry>,
+    // Layout as last queried from RandR; re-queried only when RandR reports
+    // a change. Targets, tiles and cells are resolved against it as needed
+    monitors: std::cell::RefCell<Vec<MonitorGeometry>>,
     known_clients: std::cell::RefCell<Vec<Window>>,
     handled: std::cell::RefCell<Vec<Window>>,
     pending_startup: std::cell::RefCell<Vec<Window>>,
+    active: std::cell::Cell<Option<Window>>,
+    current_desktop: std::cell::Cell<Option<u32>>,
+    // _NET_DESKTOP_NAMES, fetched on first use after each change
+    desktop_names: std::cell::RefCell<Option<Vec<String>>>,
+    // _NET_WM_CM_Sn for our screen; owned while a compositor runs
+    cm_selection: Atom,
+    // WM_NORMAL_HINTS of windows resized with honor_size_hints = false,
+    // put back once the window manager has handled the resize
+    unhinted: std::cell::RefCell<HashMap<Window, WmSizeHints>>,
+    // Windows whose ConfigureNotify is reported, for enforce rules
+    geometry_watched: std::cell::RefCell<HashSet<Window>>,
+    // Set once reading from the server has failed
+    lost: std::cell::Cell<bool>,
 }
 
 impl X11Backend {
@@ -116,178 +184,218 @@ impl X11Backend {
             .reply()
             .map_err(|e| format!("intern atoms reply: {}", e))?;
 
+        conn.randr_select_input(
+            root,
+            NotifyMask::SCREEN_CHANGE | NotifyMask::OUTPUT_CHANGE | NotifyMask::CRTC_CHANGE,
+        )
+        .map_err(|e| format!("randr select input: {}", e))?;
+
         let monitors = query_monitors(&conn, root)?;
 
+        let cm_name = format!("_NET_WM_CM_S{}", screen_num);
+        let cm_selection = conn
+            .intern_atom(false, cm_name.as_bytes())
+            .map_err(|e| format!("intern {}: {}", cm_name, e))?
+            .reply()
+            .map_err(|e| format!("intern {} reply: {}", cm_name, e))?
+            .atom;
+
         let initial_clients = get_client_list(&conn, root, &atoms);
+        let active = get_active_window(&conn, root, &atoms);
+        let current_desktop = get_current_desktop(&conn, root, &atoms);
 
         conn.flush().map_err(|e| format!("flush: {}", e))?;
 
-        for (i, mon) in monitors.iter().enumerate() {
-            eprintln!(
-                "[x11] monitor {}: '{}' {}x{}+{}+{}",
-                i, mon.name, mon.width, mon.height, mon.x, mon.y
-            );
-        }
+        log_monitors(&monitors);
         eprintln!("[x11] found {} existing windows", initial_clients.len());
 
         Ok(Self {
             conn,
             root,
             atoms,
-            monitors,
+            monitors: std::cell::RefCell::new(monitors),
             known_clients: std::cell::RefCell::new(initial_clients.clone()),
             handled: std::cell::RefCell::new(Vec::new()),
             pending_startup: std::cell::RefCell::new(initial_clients),
+            active: std::cell::Cell::new(active),
+            current_desktop: std::cell::Cell::new(current_desktop),
+            desktop_names: std::cell::RefCell::new(None),
+            cm_selection,
+            unhinted: std::cell::RefCell::new(HashMap::new()),
+            geometry_watched: std::cell::RefCell::new(HashSet::new()),
+            lost: std::cell::Cell::new(false),
         })
     }
 
-    pub fn connection_fd(&self) -> i32 {
-        self.conn.stream().as_raw_fd()
+    // Next queued event. A read error means the server is gone, which no
+    // later read recovers from
+    fn next_event(&self) -> Option<Event> {
+        match self.conn.poll_for_event() {
+            Ok(event) => event,
+            Err(e) => {
+                if !self.lost.replace(true) {
+                    eprintln!("[cherrypie] X connection: {}", e);
+                }
+                None
+            }
+        }
     }
 
-    pub fn process_events(&self, rules: &[CompiledRule], dry_run: bool) {
-        let mut need_flush = false;
+    fn drain_events(&self) -> Vec<WindowEvent> {
+        let mut events = Vec::new();
 
-        // Apply rules to windows that existed at startup
+        // Windows that existed at startup, then whichever one had focus
         let startup = self.pending_startup.take();
         if !startup.is_empty() {
             let mut handled = self.handled.borrow_mut();
             for window in startup {
-                self.handle_new_window(window, rules, dry_run);
+                self.watch_properties(window);
                 handled.push(window);
-                need_flush = true;
+                events.push(WindowEvent::Existing(window));
+            }
+            if let Some(active) = self.active.get() {
+                events.push(WindowEvent::FocusChanged { old: None, new: Some(active) });
             }
         }
 
-        // Loop: handling new windows involves get_property round-trips.
-        // During those reads, x11rb may buffer additional events from the
-        // socket. If we don't re-drain, those events sit in the internal
-        // queue while poll() sees no socket data and never wakes us.
+        // Loop: fetching the client list is a round-trip, during which x11rb
+        // may buffer further PropertyNotify events from the socket.
         loop {
             let mut client_list_changed = false;
-
-            while let Some(event) = self.conn.poll_for_event().ok().flatten() {
-                if let x11rb::protocol::Event::PropertyNotify(ev) = event
-                    && ev.window == self.root
-                    && ev.atom == self.atoms._NET_CLIENT_LIST
-                {
-                    client_list_changed = true;
+            let mut active_changed = false;
+            let mut desktop_changed = false;
+            let mut monitors_changed = false;
+            let mut property_changes = Vec::new();
+
+            while let Some(event) = self.next_event() {
+                match event {
+                    Event::PropertyNotify(ev) if ev.window == self.root => {
+                        if ev.atom == self.atoms._NET_CLIENT_LIST {
+                            client_list_changed = true;
+                        } else if ev.atom == self.atoms._NET_ACTIVE_WINDOW {
+                            active_changed = true;
+                        } else if ev.atom == self.atoms._NET_CURRENT_DESKTOP {
+                            desktop_changed = true;
+                        } else if ev.atom == self.atoms._NET_DESKTOP_NAMES {
+                            self.desktop_names.take();
+                        }
+                    }
+                    Event::PropertyNotify(ev) => {
+                        if let Some(prop) = self.changed_property(ev.atom) {
+                            let change = WindowEvent::PropertyChanged(ev.window, prop);
+                            // Titles can change many times per batch
+                            if !property_changes.contains(&change) {
+                                property_changes.push(change);
+                            }
+                        }
+                    }
+                    Event::RandrScreenChangeNotify(_) | Event::RandrNotify(_) => {
+                        monitors_changed = true;
+                    }
+                    // Selected while a window's size hints are cleared, and
+                    // on windows enforce rules keep in place
+                    Event::ConfigureNotify(ev) => {
+                        self.restore_hints(ev.window);
+                        let change = WindowEvent::GeometryChanged(ev.window);
+                        let watched = self.geometry_watched.borrow().contains(&ev.window);
+                        if watched && !property_changes.contains(&change) {
+                            property_changes.push(change);
+                        }
+                    }
+                    Event::DestroyNotify(ev) => {
+                        self.unhinted.borrow_mut().remove(&ev.window);
+                        self.geometry_watched.borrow_mut().remove(&ev.window);
+                    }
+                    _ => {}
                 }
             }
 
-            if !client_list_changed {
+            if !client_list_changed
+                && !active_changed
+                && !desktop_changed
+                && !monitors_changed
+                && property_changes.is_empty()
+            {
                 break;
             }
 
-            let current = get_client_list(&self.conn, self.root, &self.atoms);
-            let mut known = self.known_clients.borrow_mut();
-            let mut handled = self.handled.borrow_mut();
-
-            for &window in &current {
-                if !known.contains(&window) && !handled.contains(&window) {
-                    self.handle_new_window(window, rules, dry_run);
-                    handled.push(window);
-                    need_flush = true;
-                }
+            // Before anything else, so windows handled in this batch are
+            // placed against the new layout
+            if monitors_changed {
+                self.refresh_monitors();
             }
 
-            // Prune closed windows from handled list to prevent unbounded growth
-            handled.retain(|w| current.contains(w));
-            *known = current;
-        }
+            if client_list_changed {
+                let current = get_client_list(&self.conn, self.root, &self.atoms);
+                let mut known = self.known_clients.borrow_mut();
+                let mut handled = self.handled.borrow_mut();
 
-        if need_flush {
-            let _ = self.conn.flush();
-        }
-    }
+                for &window in &current {
+                    if !known.contains(&window) && !handled.contains(&window) {
+                        self.watch_properties(window);
+                        handled.push(window);
+                        events.push(WindowEvent::Created(window));
+                    }
+                }
 
-    fn handle_new_window(&self, window: Window, rules: &[CompiledRule], dry_run: bool) {
-        let class = self.get_class(window);
-        let title = self.get_title(window);
-        let role = self.get_role(window);
-        let process = self.get_process_name(window);
-        let window_type = self.get_window_type(window);
+                for &window in known.iter().filter(|w| !current.contains(w)) {
+                    self.geometry_watched.borrow_mut().remove(&window);
+                    events.push(WindowEvent::Closed(window));
+                }
 
-        for rule in rules {
-            if rule.matches(&class, &title, &role, &process, &window_type) {
-                let now = local_time();
-                eprintln!(
-                    "[{}] [INFO]   matched '{}' (class='{}', title='{}', process='{}')",
-                    now, class, class, title, process
-                );
+                // Prune closed windows from handled list to prevent unbounded growth
+                handled.retain(|w| current.contains(w));
+                *known = current;
+            }
 
-                if !dry_run {
-                    self.apply_rule(window, rule);
-                } else {
-                    self.log_actions(rule);
+            if active_changed {
+                let new = get_active_window(&self.conn, self.root, &self.atoms);
+                let old = self.active.replace(new);
+                if old != new {
+                    events.push(WindowEvent::FocusChanged { old, new });
                 }
             }
-        }
-    }
 
-    // PROPERTY GETTERS
-
-    fn get_class(&self, window: Window) -> String {
-        WmClass::get(&self.conn, window)
-            .ok()
-            .and_then(|cookie| cookie.reply().ok())
-            .and_then(|opt| opt)
-            .map(|wm| String::from_utf8_lossy(wm.class()).to_string())
-            .unwrap_or_default()
-    }
+            if desktop_changed {
+                let new = get_current_desktop(&self.conn, self.root, &self.atoms);
+                let old = self.current_desktop.replace(new);
+                if let Some(desktop) = new
+                    && old != new
+                {
+                    events.push(WindowEvent::WorkspaceChanged(desktop));
+                }
+            }
 
-    fn get_title(&self, window: Window) -> String {
-        if let Some(title) = self.get_string_property(window, self.atoms._NET_WM_NAME) {
-            return title;
+            events.append(&mut property_changes);
         }
-        self.get_string_property(window, self.atoms.WM_NAME)
-            .unwrap_or_default()
-    }
 
-    fn get_role(&self, window: Window) -> String {
-        self.get_string_property(window, self.atoms.WM_WINDOW_ROLE)
-            .unwrap_or_default()
+        events
     }
 
-    fn get_process_name(&self, window: Window) -> String {
-        let pid = self.get_cardinal_property(window, self.atoms._NET_WM_PID);
-        match pid {
-            Some(pid) => {
-                let comm_path = format!("/proc/{}/comm", pid);
-                std::fs::read_to_string(&comm_path)
-                    .map(|s| s.trim().to_string())
-                    .unwrap_or_default()
-            }
-            None => String::new(),
+    // PROPERTY HELPERS
+
+    // Ask for PropertyNotify on a client so later title/class changes can
+    // re-fire rules. Only changes our own event mask on the window.
+    fn watch_properties(&self, window: Window) {
+        let mut mask = EventMask::PROPERTY_CHANGE;
+        if self.geometry_watched.borrow().contains(&window) {
+            mask |= EventMask::STRUCTURE_NOTIFY;
         }
+        let aux = ChangeWindowAttributesAux::new().event_mask(mask);
+        let _ = self.conn.change_window_attributes(window, &aux);
     }
 
-    fn get_window_type(&self, window: Window) -> String {
-        let type_atom = match self.get_atom_property(window, self.atoms._NET_WM_WINDOW_TYPE) {
-            Some(a) => a,
-            None => return "normal".into(),
-        };
-
-        if type_atom == self.atoms._NET_WM_WINDOW_TYPE_NORMAL {
-            "normal"
-        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_DIALOG {
-            "dialog"
-        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_DOCK {
-            "dock"
-        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_TOOLBAR {
-            "toolbar"
-        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_MENU {
-            "menu"
-        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_UTILITY {
-            "utility"
-        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_SPLASH {
-            "splash"
-        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_DESKTOP {
-            "desktop"
+    // Which matched-against property a client PropertyNotify is about.
+    // State and desktop are left out: rules write those themselves.
+    fn changed_property(&self, atom: Atom) -> Option<ChangedProperty> {
+        let a = &self.atoms;
+        if atom == a._NET_WM_NAME || atom == a.WM_NAME {
+            Some(ChangedProperty::Title)
+        } else if atom == a.WM_CLASS || atom == a.WM_WINDOW_ROLE || atom == a._NET_WM_WINDOW_TYPE {
+            Some(ChangedProperty::Other)
         } else {
-            "unknown"
+            None
         }
-        .into()
     }
 
     fn get_string_property(&self, window: Window, atom: Atom) -> Option<String> {
@@ -344,6 +452,102 @@ impl X11Backend {
         }
     }
 
+    fn get_atom_list_property(&self, window: Window, atom: Atom) -> Vec<Atom> {
+        let reply = self
+            .conn
+            .get_property(false, window, atom, AtomEnum::ATOM, 0, 64)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok());
+
+        match reply {
+            Some(prop) => prop
+                .value
+                .chunks_exact(4)
+                .map(|chunk| u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn desktop_count(&self) -> u32 {
+        self.get_cardinal_property(self.root, self.atoms._NET_NUMBER_OF_DESKTOPS)
+            .unwrap_or(1)
+    }
+
+    // Desktop names in desktop order, cached until _NET_DESKTOP_NAMES changes
+    fn desktop_names(&self) -> Vec<String> {
+        if let Some(names) = &*self.desktop_names.borrow() {
+            return names.clone();
+        }
+        let names: Vec<String> = self
+            .conn
+            .get_property(false, self.root, self.atoms._NET_DESKTOP_NAMES, self.atoms.UTF8_STRING, 0, 1024)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .map(|reply| {
+                // NUL-terminated, so drop what follows the last one
+                let mut names: Vec<String> =
+                    reply.value.split(|&b| b == 0).map(|n| String::from_utf8_lossy(n).into_owned()).collect();
+                names.pop();
+                names
+            })
+            .unwrap_or_default();
+        *self.desktop_names.borrow_mut() = Some(names.clone());
+        names
+    }
+
+    // Workspace of every other client, for population balancing. Sticky
+    // windows are on all workspaces and so count for none.
+    fn occupied_desktops(&self, window: Window) -> Vec<u32> {
+        self.known_clients
+            .borrow()
+            .iter()
+            .filter(|&&c| c != window)
+            .filter_map(|&c| self.get_cardinal_property(c, self.atoms._NET_WM_DESKTOP))
+            .filter(|&ws| ws != ALL_WORKSPACES)
+            .collect()
+    }
+
+    // Decoration widths (left, right, top, bottom); zero if the WM does not
+    // publish _NET_FRAME_EXTENTS
+    fn get_frame_extents(&self, window: Window) -> (u32, u32, u32, u32) {
+        let values = self
+            .conn
+            .get_property(false, window, self.atoms._NET_FRAME_EXTENTS, AtomEnum::CARDINAL, 0, 4)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .and_then(|reply| reply.value32().map(|v| v.collect::<Vec<_>>()))
+            .unwrap_or_default();
+        match values[..] {
+            [left, right, top, bottom] => (left, right, top, bottom),
+            _ => (0, 0, 0, 0),
+        }
+    }
+
+    // The frame of the window a dialog is transient for, as an area to
+    // place it in
+    fn get_parent_frame(&self, window: Window) -> Option<MonitorGeometry> {
+        let reply = self
+            .conn
+            .get_property(false, window, AtomEnum::WM_TRANSIENT_FOR, AtomEnum::WINDOW, 0, 1)
+            .ok()?
+            .reply()
+            .ok()?;
+        let parent = reply.value32()?.next().filter(|&p| p != x11rb::NONE && p != self.root)?;
+        let (x, y, width, height) = self.get_window_geometry(parent)?;
+        let (left, right, top, bottom) = self.get_frame_extents(parent);
+        Some(MonitorGeometry {
+            name: format!("0x{:x}", parent),
+            x: x - left as i32,
+            y: y - top as i32,
+            width: width + left + right,
+            height: height + top + bottom,
+            mm_width: 0,
+            mm_height: 0,
+            primary: false,
+        })
+    }
+
     fn get_window_geometry(&self, window: Window) -> Option<(i32, i32, u32, u32)> {
         let geo = self.conn.get_geometry(window).ok()?.reply().ok()?;
         // Translate to root coordinates
@@ -361,120 +565,58 @@ impl X11Backend {
         ))
     }
 
-    // ACTION APPLICATION
-
-    fn apply_rule(&self, window: Window, rule: &CompiledRule) {
-        let target_monitor = self.resolve_monitor(window, rule);
-
-        // Size first (position may depend on resolved size for centering)
-        let resolved_size = rule.size.as_ref().map(|sz| self.resolve_size(sz, &target_monitor));
-
-        if let Some((w, h)) = resolved_size {
-            let _ = self.conn.configure_window(
-                window,
-                &ConfigureWindowAux::new().width(w).height(h),
-            );
-        }
-
-        if let Some(ref pos) = rule.position {
-            let win_size = resolved_size.or_else(|| {
-                self.get_window_geometry(window).map(|(_, _, w, h)| (w, h))
-            });
-            let (x, y) = self.resolve_position(pos, &target_monitor, win_size);
-            let _ = self.conn.configure_window(
-                window,
-                &ConfigureWindowAux::new().x(x).y(y),
-            );
-        }
-
-        if let Some(ws) = rule.workspace {
-            self.send_client_message(window, self.atoms._NET_WM_DESKTOP, [ws, 1, 0, 0, 0]);
-        }
-
-        if let Some(true) = rule.maximize {
-            self.set_wm_state(
-                window,
-                1,
-                self.atoms._NET_WM_STATE_MAXIMIZED_VERT,
-                self.atoms._NET_WM_STATE_MAXIMIZED_HORZ,
-            );
-        }
-
-        if let Some(true) = rule.fullscreen {
-            self.set_wm_state(window, 1, self.atoms._NET_WM_STATE_FULLSCREEN, 0);
-        }
-
-        if let Some(true) = rule.pin {
-            self.send_client_message(
-                window,
-                self.atoms._NET_WM_DESKTOP,
-                [0xFFFFFFFF, 1, 0, 0, 0],
-            );
-            self.set_wm_state(window, 1, self.atoms._NET_WM_STATE_STICKY, 0);
-        }
-
-        if let Some(true) = rule.minimize {
-            // WM_CHANGE_STATE with IconicState (3)
-            let event = ClientMessageEvent::new(32, window, self.atoms.WM_CHANGE_STATE, [3u32, 0, 0, 0, 0]);
-            let _ = self.conn.send_event(
-                false,
-                self.root,
-                EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
-                event,
-            );
-        }
-
-        if let Some(true) = rule.shade {
-            self.set_wm_state(window, 1, self.atoms._NET_WM_STATE_SHADED, 0);
-        }
-
-        if let Some(true) = rule.above {
-            self.set_wm_state(window, 1, self.atoms._NET_WM_STATE_ABOVE, 0);
-        }
-
-        if let Some(true) = rule.below {
-            self.set_wm_state(window, 1, self.atoms._NET_WM_STATE_BELOW, 0);
-        }
-
-        if let Some(false) = rule.decorate {
-            self.set_decoration(window, false);
-        }
-        if let Some(true) = rule.decorate {
-            self.set_decoration(window, true);
-        }
-
-        if let Some(true) = rule.focus {
-            self.send_client_message(
-                window,
-                self.atoms._NET_ACTIVE_WINDOW,
-                [1, 0, 0, 0, 0], // source = application
-            );
-        }
+    // MONITOR RESOLUTION
 
-        if let Some(opacity) = rule.opacity {
-            let value = (opacity.clamp(0.0, 1.0) * 0xFFFFFFFF_u64 as f64) as u32;
-            let _ = self.conn.change_property32(
-                PropMode::REPLACE,
-                window,
-                self.atoms._NET_WM_WINDOW_OPACITY,
-                AtomEnum::CARDINAL,
-                &[value],
-            );
+    fn refresh_monitors(&self) {
+        match query_monitors(&self.conn, self.root) {
+            Ok(monitors) => {
+                eprintln!("[x11] monitor layout changed");
+                log_monitors(&monitors);
+                *self.monitors.borrow_mut() = monitors;
+            }
+            Err(e) => eprintln!("[x11] monitor refresh failed, keeping old layout: {}", e),
         }
     }
 
-    // MONITOR RESOLUTION
-
-    fn resolve_monitor(&self, window: Window, rule: &CompiledRule) -> MonitorGeometry {
-        if let Some(ref target) = rule.monitor {
+    fn resolve_monitor(&self, window: Window, target: Option<&MonitorTarget>) -> MonitorGeometry {
+        let counts = match target {
+            Some(MonitorTarget::LeastPopulated | MonitorTarget::Emptiest) => self.monitor_population(window),
+            _ => Vec::new(),
+        };
+        let monitors = &*self.monitors.borrow();
+        if let Some(target) = target {
             match target {
+                MonitorTarget::LeastPopulated | MonitorTarget::Emptiest => {
+                    if let Some(idx) = target.pick_by_population(&counts) {
+                        return monitors[idx].clone();
+                    }
+                }
+                MonitorTarget::Primary | MonitorTarget::Largest | MonitorTarget::External => {
+                    let outputs: Vec<OutputInfo> = monitors
+                        .iter()
+                        .map(|m| OutputInfo { name: &m.name, width: m.width, height: m.height, primary: m.primary })
+                        .collect();
+                    if let Some(idx) = target.pick_by_layout(&outputs) {
+                        return monitors[idx].clone();
+                    }
+                }
+                MonitorTarget::Current => {
+                    // Where the pointer is, else where the focused window is
+                    let idx = self
+                        .pointer_position()
+                        .and_then(|(x, y)| monitor_at(monitors, x, y))
+                        .or_else(|| self.active.get().and_then(|active| self.monitor_of(active)));
+                    if let Some(idx) = idx {
+                        return monitors[idx].clone();
+                    }
+                }
                 MonitorTarget::Index(idx) => {
-                    if let Some(mon) = self.monitors.get(*idx as usize) {
+                    if let Some(mon) = monitors.get(*idx as usize) {
                         return mon.clone();
                     }
                 }
                 MonitorTarget::Name(name) => {
-                    if let Some(mon) = self.monitors.iter().find(|m| m.name == *name) {
+                    if let Some(mon) = monitors.iter().find(|m| m.name == *name) {
                         return mon.clone();
                     }
                     // Also try matching against EWMH desktop names / awesomewm tags
@@ -484,21 +626,11 @@ impl X11Backend {
         }
 
         // Default: monitor the window is on, or first monitor
-        if let Some(geo) = self.get_window_geometry(window) {
-            let cx = geo.0 + geo.2 as i32 / 2;
-            let cy = geo.1 + geo.3 as i32 / 2;
-            for mon in &self.monitors {
-                if cx >= mon.x
-                    && cx < mon.x + mon.width as i32
-                    && cy >= mon.y
-                    && cy < mon.y + mon.height as i32
-                {
-                    return mon.clone();
-                }
-            }
+        if let Some(idx) = self.monitor_of(window) {
+            return monitors[idx].clone();
         }
 
-        self.monitors
+        monitors
             .first()
             .cloned()
             .unwrap_or(MonitorGeometry {
@@ -507,9 +639,74 @@ impl X11Backend {
                 y: 0,
                 width: 1920,
                 height: 1080,
+                mm_width: 0,
+                mm_height: 0,
+                primary: false,
             })
     }
 
+    // Visible windows per monitor, other than `window`: clients on the
+    // current workspace or sticky, counted where their center is
+    fn monitor_population(&self, window: Window) -> Vec<usize> {
+        let current = self.current_desktop.get();
+        let mut counts = vec![0; self.monitors.borrow().len()];
+        for &client in self.known_clients.borrow().iter().filter(|&&c| c != window) {
+            let desktop = self.get_cardinal_property(client, self.atoms._NET_WM_DESKTOP);
+            let visible = desktop.is_none() || desktop == current || desktop == Some(ALL_WORKSPACES);
+            if visible && let Some(idx) = self.monitor_of(client) {
+                counts[idx] += 1;
+            }
+        }
+        counts
+    }
+
+    // Frames of the other windows on screen: on the current workspace or
+    // sticky, not minimized, and not docks or the desktop
+    fn visible_frames(&self, window: Window) -> Vec<(i32, i32, u32, u32)> {
+        let current = self.current_desktop.get();
+        let mut frames = Vec::new();
+        for &client in self.known_clients.borrow().iter().filter(|&&c| c != window) {
+            let desktop = self.get_cardinal_property(client, self.atoms._NET_WM_DESKTOP);
+            let visible = desktop.is_none() || desktop == current || desktop == Some(ALL_WORKSPACES);
+            if !visible
+                || matches!(self.get_window_type(client).as_str(), "dock" | "desktop")
+                || self.get_states(client).iter().any(|s| s == "hidden")
+            {
+                continue;
+            }
+            if let Some((x, y, w, h)) = self.get_window_geometry(client) {
+                let (left, right, top, bottom) = self.get_frame_extents(client);
+                frames.push((x - left as i32, y - top as i32, w + left + right, h + top + bottom));
+            }
+        }
+        frames
+    }
+
+    // Index of the monitor containing the window's center
+    fn monitor_of(&self, window: Window) -> Option<usize> {
+        let geo = self.get_window_geometry(window)?;
+        let cx = geo.0 + geo.2 as i32 / 2;
+        let cy = geo.1 + geo.3 as i32 / 2;
+        monitor_at(&self.monitors.borrow(), cx, cy)
+    }
+
+    // A rule's exec command for this window
+    fn hook(&self, window: Window, rule: &CompiledRule, line: &str) -> Command {
+        rule.command_env.hook(line, window, &self.get_class(window), &self.get_title(window))
+    }
+
+    // Root-relative pointer position
+    fn pointer_position(&self) -> Option<(i32, i32)> {
+        let reply = self.conn.query_pointer(self.root).ok()?.reply().ok()?;
+        Some((reply.root_x as i32, reply.root_y as i32))
+    }
+
+    fn pointer_monitor(&self) -> Option<MonitorGeometry> {
+        let (x, y) = self.pointer_position()?;
+        let monitors = &self.monitors.borrow();
+        monitor_at(monitors, x, y).map(|idx| monitors[idx].clone())
+    }
+
     // POSITION RESOLUTION
 
     fn resolve_position(
@@ -539,9 +736,29 @@ impl X11Backend {
                 NamedPosition::Top => (mx + (mw - ww) / 2, my),
                 NamedPosition::Bottom => (mx + (mw - ww) / 2, my + mh - wh),
             },
+            // `monitor` is the parent's frame here when the dialog has one
+            // Smart placement is resolved in apply_rule, which sees the other
+            // windows; alone it centers
+            PositionTarget::ParentCenter | PositionTarget::Smart => {
+                self.resolve_position(&PositionTarget::Named(NamedPosition::Center), monitor, win_size)
+            }
+            PositionTarget::Anchored(anchor, dx, dy) => {
+                let (x, y) = self.resolve_position(&PositionTarget::Named(*anchor), monitor, win_size);
+                (x + dx, y + dy)
+            }
+            // `monitor` is the one under the pointer; without a pointer, center
+            PositionTarget::Pointer(dx, dy) => match self.pointer_position() {
+                Some(pointer) => {
+                    let area = (mx, my, monitor.width, monitor.height);
+                    at_pointer(pointer, (*dx, *dy), (win_w, win_h), area)
+                }
+                None => {
+                    self.resolve_position(&PositionTarget::Named(NamedPosition::Center), monitor, win_size)
+                }
+            },
             PositionTarget::Flexible(xv, yv) => {
-                let x = resolve_dim(*xv, mw) + mx;
-                let y = resolve_dim(*yv, mh) + my;
+                let x = xv.resolve(mw, monitor.mm_width) + mx;
+                let y = yv.resolve(mh, monitor.mm_height) + my;
                 (x, y)
             }
         }
@@ -553,8 +770,8 @@ impl X11Backend {
         match sz {
             SizeTarget::Absolute(w, h) => (*w, *h),
             SizeTarget::Flexible(wv, hv) => {
-                let w = resolve_dim(*wv, monitor.width as i32).max(1) as u32;
-                let h = resolve_dim(*hv, monitor.height as i32).max(1) as u32;
+                let w = wv.resolve(monitor.width as i32, monitor.mm_width).max(1) as u32;
+                let h = hv.resolve(monitor.height as i32, monitor.mm_height).max(1) as u32;
                 (w, h)
             }
         }
@@ -562,90 +779,909 @@ impl X11Backend {
 
     // EWMH HELPERS
 
-    fn set_wm_state(&self, window: Window, action: u32, prop1: Atom, prop2: Atom) {
+    fn set_wm_state(
+        &self,
+        w: &WriteAccess,
+        window: Window,
+        action: u32,
+        prop1: Atom,
+        prop2: Atom,
+    ) -> Result<(), String> {
         self.send_client_message(
+            w,
             window,
             self.atoms._NET_WM_STATE,
             [action, prop1, prop2, 1, 0],
-        );
+        )
     }
 
-    fn send_client_message(&self, window: Window, msg_type: Atom, data: [u32; 5]) {
+    // Client messages go to the root window for the WM to act on; the WM's
+    // verdict isn't reported back, so only connection errors surface here.
+    fn send_client_message(
+        &self,
+        _: &WriteAccess,
+        window: Window,
+        msg_type: Atom,
+        data: [u32; 5],
+    ) -> Result<(), String> {
         let event = ClientMessageEvent::new(32, window, msg_type, data);
-        let _ = self.conn.send_event(
-            false,
-            self.root,
-            EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
-            event,
-        );
+        self.conn
+            .send_event(
+                false,
+                self.root,
+                EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+                event,
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
     }
 
-    fn set_decoration(&self, window: Window, decorated: bool) {
-        // _MOTIF_WM_HINTS: [flags, functions, decorations, input_mode, status]
-        // flags = 2 (MWM_HINTS_DECORATIONS), decorations = 0 or 1
-        let decorations: u32 = if decorated { 1 } else { 0 };
-        let hints: [u32; 5] = [2, 0, decorations, 0, 0];
-        let _ = self.conn.change_property32(
-            PropMode::REPLACE,
-            window,
-            self.atoms._MOTIF_WM_HINTS,
-            self.atoms._MOTIF_WM_HINTS,
-            &hints,
-        );
+    fn configure(&self, _: &WriteAccess, window: Window, aux: &ConfigureWindowAux) -> Result<(), String> {
+        checked(self.conn.configure_window(window, aux))
     }
 
-    fn log_actions(&self, rule: &CompiledRule) {
-        let now = local_time();
-        if let Some(ref mon) = rule.monitor {
-            match mon {
-                MonitorTarget::Index(i) => eprintln!("[{}] [DRY]    monitor -> {}", now, i),
-                MonitorTarget::Name(n) => eprintln!("[{}] [DRY]    monitor -> '{}'", now, n),
+    // Resize the client, first rounding to its size increments or
+    // clearing them as the rule's honor_size_hints asks
+    fn resize(
+        &self,
+        w: &WriteAccess,
+        window: Window,
+        rule: &CompiledRule,
+        size: (u32, u32),
+    ) -> Result<(), String> {
+        let (width, height) = match rule.honor_size_hints {
+            Some(true) => match self.get_normal_hints(window) {
+                Some(hints) => {
+                    let pair = |p: Option<(i32, i32)>| {
+                        p.map_or((0, 0), |(a, b)| (a.max(0) as u32, b.max(0) as u32))
+                    };
+                    // ICCCM: the minimum size stands in for a missing base
+                    let base = pair(hints.base_size.or(hints.min_size));
+                    snap_to_increments(size, base, pair(hints.size_increment))
+                }
+                None => size,
+            },
+            Some(false) => {
+                self.clear_increments(w, window)?;
+                size
             }
+            None => size,
+        };
+        match rule.placement {
+            Placement::Configure => self.configure(w, window, &ConfigureWindowAux::new().width(width).height(height)),
+            Placement::Hints => self.set_placement_hints(w, window, None, Some((width, height))),
+        }
+    }
+
+    fn get_normal_hints(&self, window: Window) -> Option<WmSizeHints> {
+        WmSizeHints::get_normal_hints(&self.conn, window).ok()?.reply().ok().flatten()
+    }
+
+    // Drop the resize increments and base size from WM_NORMAL_HINTS so the
+    // window manager takes a pixel size as is. The client's hints are kept
+    // and restored on the ConfigureNotify that answers the resize.
+    fn clear_increments(&self, _: &WriteAccess, window: Window) -> Result<(), String> {
+        let Some(hints) = self.get_normal_hints(window) else {
+            return Ok(());
+        };
+        if hints.size_increment.is_none() || self.unhinted.borrow().contains_key(&window) {
+            return Ok(());
+        }
+        let cleared = WmSizeHints { size_increment: None, base_size: None, ..hints };
+        checked(cleared.set_normal_hints(&self.conn, window))?;
+        let mask = EventMask::PROPERTY_CHANGE | EventMask::STRUCTURE_NOTIFY;
+        let aux = ChangeWindowAttributesAux::new().event_mask(mask);
+        checked(self.conn.change_window_attributes(window, &aux))?;
+        self.unhinted.borrow_mut().insert(window, hints);
+        Ok(())
+    }
+
+    fn restore_hints(&self, window: Window) {
+        if let Some(hints) = self.unhinted.borrow_mut().remove(&window) {
+            let _ = hints.set_normal_hints(&self.conn, window);
+            self.watch_properties(window);
+        }
+    }
+
+    // Set the minimum or maximum size in WM_NORMAL_HINTS, keeping the
+    // client's other hints
+    fn set_size_limit(
+        &self,
+        _: &WriteAccess,
+        window: Window,
+        min: bool,
+        (width, height): (u32, u32),
+    ) -> Result<(), String> {
+        let mut hints = self.get_normal_hints(window).unwrap_or_default();
+        let limit = Some((width as i32, height as i32));
+        if min {
+            hints.min_size = limit;
+        } else {
+            hints.max_size = limit;
+        }
+        checked(hints.set_normal_hints(&self.conn, window))
+    }
+
+    // Rewrite WM_NORMAL_HINTS with a program-specified position and/or
+    // size, keeping the client's other hints (min/max size, increments)
+    fn set_placement_hints(
+        &self,
+        _: &WriteAccess,
+        window: Window,
+        position: Option<(i32, i32)>,
+        size: Option<(u32, u32)>,
+    ) -> Result<(), String> {
+        let mut hints = self.get_normal_hints(window).unwrap_or_default();
+        if let Some((x, y)) = position {
+            hints.position = Some((WmSizeHintsSpecification::ProgramSpecified, x, y));
         }
-        if let Some(ref pos) = rule.position {
-            eprintln!("[{}] [DRY]    position -> {:?}", now, pos);
+        if let Some((width, height)) = size {
+            hints.size = Some((WmSizeHintsSpecification::ProgramSpecified, width as i32, height as i32));
         }
-        if let Some(ref sz) = rule.size {
-            eprintln!("[{}] [DRY]    size -> {:?}", now, sz);
+        checked(hints.set_normal_hints(&self.conn, window))
+    }
+
+    // Border width via ConfigureWindow, pixel via ChangeWindowAttributes
+    fn set_border(
+        &self,
+        w: &WriteAccess,
+        window: Window,
+        width: Option<u32>,
+        pixel: Option<u32>,
+    ) -> Result<(), String> {
+        if let Some(width) = width {
+            self.configure(w, window, &ConfigureWindowAux::new().border_width(width))?;
         }
-        if let Some(ws) = rule.workspace {
-            eprintln!("[{}] [DRY]    workspace -> {}", now, ws);
+        if let Some(pixel) = pixel {
+            let aux = ChangeWindowAttributesAux::new().border_pixel(pixel);
+            checked(self.conn.change_window_attributes(window, &aux))?;
         }
-        if let Some(true) = rule.maximize {
-            eprintln!("[{}] [DRY]    maximize", now);
+        Ok(())
+    }
+
+    // _NET_WM_STRUT_PARTIAL for the window's current geometry and monitor,
+    // plus the older _NET_WM_STRUT for window managers that only read that
+    fn set_strut(&self, w: &WriteAccess, window: Window, edge: Edge, size: u32) -> Result<(), String> {
+        let geometry = self.get_window_geometry(window).ok_or("window geometry unavailable")?;
+        let (monitor, screen) = {
+            let monitors = &self.monitors.borrow();
+            let m = &monitors[self.monitor_of(window).unwrap_or(0)];
+            let right = monitors.iter().map(|m| m.x + m.width as i32).max().unwrap_or(0);
+            let bottom = monitors.iter().map(|m| m.y + m.height as i32).max().unwrap_or(0);
+            ((m.x, m.y, m.width, m.height), (right.max(0) as u32, bottom.max(0) as u32))
+        };
+        let strut = strut_partial(edge, size, geometry, monitor, screen);
+        self.set_cardinal_property(w, window, self.atoms._NET_WM_STRUT_PARTIAL, AtomEnum::CARDINAL, &strut)?;
+        self.set_cardinal_property(w, window, self.atoms._NET_WM_STRUT, AtomEnum::CARDINAL, &strut[..4])
+    }
+
+    fn maximized_atom(&self, axis: Axis) -> Atom {
+        match axis {
+            Axis::Horizontal => self.atoms._NET_WM_STATE_MAXIMIZED_HORZ,
+            Axis::Vertical => self.atoms._NET_WM_STATE_MAXIMIZED_VERT,
         }
-        if let Some(true) = rule.fullscreen {
-            eprintln!("[{}] [DRY]    fullscreen", now);
+    }
+
+    // With a monitor target, fullscreen covers that monitor rather than
+    // whichever one the window manager picks. _NET_WM_FULLSCREEN_MONITORS
+    // takes Xinerama indices, which follow the RandR monitor order
+    fn set_fullscreen_monitor(
+        &self,
+        w: &WriteAccess,
+        window: Window,
+        monitor: Option<&MonitorGeometry>,
+    ) -> Result<(), String> {
+        let Some(monitor) = monitor else { return Ok(()) };
+        let index = self.monitors.borrow().iter().position(|m| m.name == monitor.name);
+        let Some(index) = index else { return Ok(()) };
+        let index = index as u32;
+        let atom = self.atoms._NET_WM_FULLSCREEN_MONITORS;
+        // top, bottom, left, right, source = application
+        self.send_client_message(w, window, atom, [index, index, index, index, 1])
+    }
+
+    fn set_cardinal_property(
+        &self,
+        _: &WriteAccess,
+        window: Window,
+        atom: Atom,
+        type_: impl Into<Atom>,
+        values: &[u32],
+    ) -> Result<(), String> {
+        checked(self.conn.change_property32(PropMode::REPLACE, window, atom, type_, values))
+    }
+
+    fn delete_property(&self, _: &WriteAccess, window: Window, atom: Atom) -> Result<(), String> {
+        checked(self.conn.delete_property(window, atom))
+    }
+
+    fn intern(&self, name: &str) -> Result<Atom, String> {
+        self.conn
+            .intern_atom(false, name.as_bytes())
+            .map_err(|e| e.to_string())?
+            .reply()
+            .map(|r| r.atom)
+            .map_err(|e| e.to_string())
+    }
+
+    fn set_named_property(
+        &self,
+        w: &WriteAccess,
+        window: Window,
+        name: &str,
+        value: &PropValue,
+    ) -> Result<(), String> {
+        let atom = self.intern(name)?;
+        match value {
+            PropValue::Cardinal(n) => self.set_cardinal_property(w, window, atom, AtomEnum::CARDINAL, &[*n]),
+            PropValue::Text(s) => checked(self.conn.change_property8(
+                PropMode::REPLACE,
+                window,
+                atom,
+                self.atoms.UTF8_STRING,
+                s.as_bytes(),
+            )),
         }
-        if let Some(true) = rule.pin {
-            eprintln!("[{}] [DRY]    pin (all workspaces)", now);
+    }
+
+    // SIGKILL the window's process when _NET_WM_PID is known to belong to
+    // this machine (WM_CLIENT_MACHINE), otherwise have the server drop the
+    // client's connection
+    fn kill_client(&self, _: &WriteAccess, window: Window) -> Result<(), String> {
+        let local = self
+            .get_string_property(window, AtomEnum::WM_CLIENT_MACHINE.into())
+            .is_some_and(|machine| machine == crate::rules::hostname());
+        // Never 0, 1, or anything kill() would read as a process group
+        let pid = self
+            .get_cardinal_property(window, self.atoms._NET_WM_PID)
+            .and_then(|p| libc::pid_t::try_from(p).ok())
+            .filter(|&p| p > 1);
+        if local
+            && let Some(pid) = pid
+            && unsafe { libc::kill(pid, libc::SIGKILL) } == 0
+        {
+            return Ok(());
         }
-        if let Some(true) = rule.minimize {
-            eprintln!("[{}] [DRY]    minimize", now);
+        checked(self.conn.kill_client(window))
+    }
+
+    // Screenshot of the window's contents as the server has them now. The
+    // WM may not have carried out the rule's placement yet.
+    fn capture(&self, window: Window, template: &str) -> Result<(), String> {
+        let (_, _, width, height) = self.get_window_geometry(window).ok_or("window is gone")?;
+        let image = self
+            .conn
+            .get_image(ImageFormat::Z_PIXMAP, window, 0, 0, width as u16, height as u16, !0)
+            .map_err(|e| e.to_string())?
+            .reply()
+            .map_err(|e| format!("{:?} (is the window viewable?)", e))?;
+        if image.data.len() != width as usize * height as usize * 4 {
+            return Err(format!("unsupported depth {}", image.depth));
         }
-        if let Some(true) = rule.shade {
-            eprintln!("[{}] [DRY]    shade", now);
+
+        let time = crate::metrics::unix_seconds(std::time::SystemTime::now());
+        let path = capture::path(template, &self.get_class(window), &self.get_title(window), window, time);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
         }
-        if let Some(true) = rule.above {
-            eprintln!("[{}] [DRY]    above", now);
+        let png = capture::encode_png(width, height, &capture::bgrx_to_rgb(&image.data));
+        std::fs::write(&path, png).map_err(|e| format!("{}: {}", path.display(), e))?;
+        eprintln!("[cherrypie] captured 0x{:x} to {}", window, path.display());
+        Ok(())
+    }
+
+    fn set_decoration(&self, w: &WriteAccess, window: Window, decorated: bool) -> Result<(), String> {
+        // _MOTIF_WM_HINTS: [flags, functions, decorations, input_mode, status]
+        // flags = 2 (MWM_HINTS_DECORATIONS), decorations = 0 or 1
+        let decorations: u32 = if decorated { 1 } else { 0 };
+        let hints: [u32; 5] = [2, 0, decorations, 0, 0];
+        self.set_cardinal_property(
+            w,
+            window,
+            self.atoms._MOTIF_WM_HINTS,
+            self.atoms._MOTIF_WM_HINTS,
+            &hints,
+        )
+    }
+}
+
+impl WindowBackend for X11Backend {
+    fn name(&self) -> &str {
+        "x11"
+    }
+
+    fn connection_fd(&self) -> i32 {
+        self.conn.stream().as_raw_fd()
+    }
+
+    fn connected(&self) -> bool {
+        !self.lost.get()
+    }
+
+    fn poll_events(&self) -> Vec<WindowEvent> {
+        self.drain_events()
+    }
+
+    fn list_windows(&self) -> Vec<Window> {
+        self.known_clients.borrow().clone()
+    }
+
+    fn flush(&self) {
+        let _ = self.conn.flush();
+    }
+
+    fn watch_geometry(&self, window: Window) {
+        self.geometry_watched.borrow_mut().insert(window);
+        self.watch_properties(window);
+    }
+
+    // PROPERTY GETTERS
+
+    fn get_class(&self, window: Window) -> String {
+        WmClass::get(&self.conn, window)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .and_then(|opt| opt)
+            .map(|wm| String::from_utf8_lossy(wm.class()).to_string())
+            .unwrap_or_default()
+    }
+
+    fn get_instance(&self, window: Window) -> String {
+        WmClass::get(&self.conn, window)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .and_then(|opt| opt)
+            .map(|wm| String::from_utf8_lossy(wm.instance()).to_string())
+            .unwrap_or_default()
+    }
+
+    fn get_title(&self, window: Window) -> String {
+        if let Some(title) = self.get_string_property(window, self.atoms._NET_WM_NAME) {
+            return title;
         }
-        if let Some(true) = rule.below {
-            eprintln!("[{}] [DRY]    below", now);
+        self.get_string_property(window, self.atoms.WM_NAME)
+            .unwrap_or_default()
+    }
+
+    fn get_role(&self, window: Window) -> String {
+        self.get_string_property(window, self.atoms.WM_WINDOW_ROLE)
+            .unwrap_or_default()
+    }
+
+    fn get_pid(&self, window: Window) -> Option<u32> {
+        self.get_cardinal_property(window, self.atoms._NET_WM_PID)
+    }
+
+    // Set by toolkits that honour startup notification
+    fn get_startup_id(&self, window: Window) -> Option<String> {
+        self.get_string_property(window, self.atoms._NET_STARTUP_ID)
+    }
+
+    // WM_HINTS window_group, else the session management WM_CLIENT_LEADER
+    // that toolkits set on every top-level of a client
+    fn get_group_leader(&self, window: Window) -> Option<Window> {
+        let hinted = WmHints::get(&self.conn, window).ok().and_then(|c| c.reply().ok()).flatten();
+        if let Some(leader) = hinted.and_then(|h| h.window_group) {
+            return Some(leader);
         }
-        if let Some(d) = rule.decorate {
-            eprintln!("[{}] [DRY]    decorate -> {}", now, d);
+        let reply = self
+            .conn
+            .get_property(false, window, self.atoms.WM_CLIENT_LEADER, AtomEnum::WINDOW, 0, 1)
+            .ok()?
+            .reply()
+            .ok()?;
+        reply.value32()?.next().filter(|&leader| leader != 0)
+    }
+
+    fn get_window_type(&self, window: Window) -> String {
+        let type_atom = match self.get_atom_property(window, self.atoms._NET_WM_WINDOW_TYPE) {
+            Some(a) => a,
+            None => return "normal".into(),
+        };
+
+        if type_atom == self.atoms._NET_WM_WINDOW_TYPE_NORMAL {
+            "normal"
+        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_DIALOG {
+            "dialog"
+        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_DOCK {
+            "dock"
+        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_TOOLBAR {
+            "toolbar"
+        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_MENU {
+            "menu"
+        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_UTILITY {
+            "utility"
+        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_SPLASH {
+            "splash"
+        } else if type_atom == self.atoms._NET_WM_WINDOW_TYPE_DESKTOP {
+            "desktop"
+        } else {
+            "unknown"
         }
-        if let Some(true) = rule.focus {
-            eprintln!("[{}] [DRY]    focus", now);
+        .into()
+    }
+
+    fn get_states(&self, window: Window) -> Vec<String> {
+        let atoms = self.get_atom_list_property(window, self.atoms._NET_WM_STATE);
+        let a = &self.atoms;
+        let table = [
+            (a._NET_WM_STATE_MAXIMIZED_VERT, "maximized_vert"),
+            (a._NET_WM_STATE_MAXIMIZED_HORZ, "maximized_horz"),
+            (a._NET_WM_STATE_FULLSCREEN, "fullscreen"),
+            (a._NET_WM_STATE_ABOVE, "above"),
+            (a._NET_WM_STATE_BELOW, "below"),
+            (a._NET_WM_STATE_STICKY, "sticky"),
+            (a._NET_WM_STATE_SHADED, "shaded"),
+            (a._NET_WM_STATE_HIDDEN, "hidden"),
+            (a._NET_WM_STATE_MODAL, "modal"),
+            (a._NET_WM_STATE_SKIP_TASKBAR, "skip_taskbar"),
+            (a._NET_WM_STATE_SKIP_PAGER, "skip_pager"),
+            (a._NET_WM_STATE_DEMANDS_ATTENTION, "demands_attention"),
+        ];
+
+        let mut states: Vec<String> = table
+            .iter()
+            .filter(|(atom, _)| atoms.contains(atom))
+            .map(|(_, name)| name.to_string())
+            .collect();
+
+        if atoms.contains(&a._NET_WM_STATE_MAXIMIZED_VERT)
+            && atoms.contains(&a._NET_WM_STATE_MAXIMIZED_HORZ)
+        {
+            states.push("maximized".into());
         }
-        if let Some(opacity) = rule.opacity {
-            eprintln!("[{}] [DRY]    opacity -> {}", now, opacity);
+        states
+    }
+
+    fn current_workspace(&self) -> Option<u32> {
+        self.current_desktop.get()
+    }
+
+    fn get_workspace(&self, window: Window) -> Option<u32> {
+        self.get_cardinal_property(window, self.atoms._NET_WM_DESKTOP)
+            .or_else(|| self.get_cardinal_property(self.root, self.atoms._NET_CURRENT_DESKTOP))
+    }
+
+    fn get_monitor(&self, window: Window) -> Option<MonitorId> {
+        self.monitor_of(window).map(|idx| MonitorId {
+            index: idx as u32,
+            name: self.monitors.borrow()[idx].name.clone(),
+        })
+    }
+
+    fn get_geometry(&self, window: Window) -> Option<(i32, i32, u32, u32)> {
+        self.get_window_geometry(window)
+    }
+
+    fn monitor_count(&self) -> Option<u32> {
+        Some(self.monitors.borrow().len() as u32)
+    }
+
+    fn monitors(&self) -> Vec<MonitorInfo> {
+        self.monitors
+            .borrow()
+            .iter()
+            .map(|m| MonitorInfo {
+                name: m.name.clone(),
+                x: m.x,
+                y: m.y,
+                width: m.width,
+                height: m.height,
+                primary: m.primary,
+            })
+            .collect()
+    }
+
+    fn workspaces(&self) -> Option<Vec<String>> {
+        let names = self.desktop_names();
+        Some((0..self.desktop_count() as usize).map(|i| names.get(i).cloned().unwrap_or_default()).collect())
+    }
+
+    fn compositor_running(&self) -> Option<bool> {
+        let reply = self.conn.get_selection_owner(self.cm_selection).ok()?.reply().ok()?;
+        Some(reply.owner != x11rb::NONE)
+    }
+
+    fn supported_hints(&self) -> Option<Vec<String>> {
+        let reply = self
+            .conn
+            .get_property(false, self.root, self.atoms._NET_SUPPORTED, AtomEnum::ATOM, 0, 4096)
+            .ok()?
+            .reply()
+            .ok()?;
+        // Not an EWMH window manager, or one that doesn't say
+        if reply.type_ == x11rb::NONE {
+            return None;
         }
+        // Send every lookup before waiting on the first
+        let cookies: Vec<_> = reply.value32()?.filter_map(|atom| self.conn.get_atom_name(atom).ok()).collect();
+        let names = cookies
+            .into_iter()
+            .filter_map(|cookie| cookie.reply().ok())
+            .map(|reply| String::from_utf8_lossy(&reply.name).into_owned())
+            .collect();
+        Some(names)
+    }
+
+    // ACTION APPLICATION
+
+    fn apply_rule(&self, window: Window, rule: &CompiledRule, w: &WriteAccess) -> ApplyReport {
+        let target_monitor = self.resolve_monitor(window, rule.monitor()).inset(&rule.margin);
+        let mut report = ApplyReport::default();
+
+        // Size resolved before position (position may depend on it for centering)
+        let mut resolved_size = None;
+        // Where Workspace sent the window, for Follow
+        let mut moved_to = None;
+
+        // Configure requests and size hints give the client size, and (with
+        // the usual NorthWest gravity) the frame's top-left corner
+        let (left, right, top, bottom) = match rule.target {
+            Some(_) => self.get_frame_extents(window),
+            None => (0, 0, 0, 0),
+        };
+
+        for action in &rule.actions {
+            let result = match action {
+                Action::Monitor(_) => Ok(()),
+                Action::Size(sz) => {
+                    let (mut width, mut height) = self.resolve_size(sz, &target_monitor);
+                    if rule.target == Some(GeometryTarget::Frame) {
+                        width = width.saturating_sub(left + right).max(1);
+                        height = height.saturating_sub(top + bottom).max(1);
+                    }
+                    resolved_size = Some((width, height));
+                    self.resize(w, window, rule, (width, height))
+                }
+                Action::Position(pos) => {
+                    let parent = match pos {
+                        PositionTarget::ParentCenter => self.get_parent_frame(window),
+                        PositionTarget::Pointer(..) => self.pointer_monitor().map(|m| m.inset(&rule.margin)),
+                        _ => None,
+                    };
+                    let area = parent.as_ref().unwrap_or(&target_monitor);
+                    let win_size = resolved_size.or_else(|| {
+                        self.get_window_geometry(window).map(|(_, _, ww, wh)| (ww, wh))
+                    });
+                    let (x, y) = match rule.target {
+                        // Frames are what overlap, whatever the rule's target
+                        _ if *pos == PositionTarget::Smart => {
+                            let (l, r, t, b) = self.get_frame_extents(window);
+                            let frame = win_size.map_or((0, 0), |(ww, wh)| (ww + l + r, wh + t + b));
+                            let area = (area.x, area.y, area.width, area.height);
+                            smart_position(area, frame, &self.visible_frames(window))
+                        }
+                        None => self.resolve_position(pos, area, win_size),
+                        Some(GeometryTarget::Frame) => {
+                            let frame = win_size.map(|(ww, wh)| (ww + left + right, wh + top + bottom));
+                            self.resolve_position(pos, area, frame)
+                        }
+                        Some(GeometryTarget::Client) => {
+                            let (cx, cy) = self.resolve_position(pos, area, win_size);
+                            (cx - left as i32, cy - top as i32)
+                        }
+                    };
+                    match rule.placement {
+                        Placement::Configure => self.configure(w, window, &ConfigureWindowAux::new().x(x).y(y)),
+                        Placement::Hints => self.set_placement_hints(w, window, Some((x, y)), None),
+                    }
+                }
+                Action::ResizeBy(dw, dh) => match self.get_window_geometry(window) {
+                    None => Err("window is gone".into()),
+                    Some((_, _, width, height)) => {
+                        let width = (width as i32 + dw).max(1) as u32;
+                        let height = (height as i32 + dh).max(1) as u32;
+                        resolved_size = Some((width, height));
+                        self.resize(w, window, rule, (width, height))
+                    }
+                },
+                Action::Aspect(rw, rh) => {
+                    let current = resolved_size.or_else(|| {
+                        self.get_window_geometry(window).map(|(_, _, ww, wh)| (ww, wh))
+                    });
+                    match current {
+                        None => Err("window is gone".into()),
+                        Some((width, height)) => {
+                            // The ratio is of whatever the rule's target is;
+                            // the whole frame has to fit the monitor
+                            let (ew, eh) = (left + right, top + bottom);
+                            let framed = rule.target == Some(GeometryTarget::Frame);
+                            let size = if framed { (width + ew, height + eh) } else { (width, height) };
+                            let bounds = if framed {
+                                (target_monitor.width, target_monitor.height)
+                            } else {
+                                (
+                                    target_monitor.width.saturating_sub(ew).max(1),
+                                    target_monitor.height.saturating_sub(eh).max(1),
+                                )
+                            };
+                            let (mut fw, mut fh) = fit_aspect(size, (*rw, *rh), bounds);
+                            if framed {
+                                fw = fw.saturating_sub(ew).max(1);
+                                fh = fh.saturating_sub(eh).max(1);
+                            }
+                            resolved_size = Some((fw, fh));
+                            self.resize(w, window, rule, (fw, fh))
+                        }
+                    }
+                }
+                Action::MinSize(bound, hinted) | Action::MaxSize(bound, hinted) => {
+                    let min = matches!(action, Action::MinSize(..));
+                    let (mut bw, mut bh) = self.resolve_size(bound, &target_monitor);
+                    if rule.target == Some(GeometryTarget::Frame) {
+                        bw = bw.saturating_sub(left + right).max(1);
+                        bh = bh.saturating_sub(top + bottom).max(1);
+                    }
+                    let current = resolved_size.or_else(|| {
+                        self.get_window_geometry(window).map(|(_, _, ww, wh)| (ww, wh))
+                    });
+                    match current {
+                        None => Err("window is gone".into()),
+                        Some((width, height)) => {
+                            let clamped = if min {
+                                (width.max(bw), height.max(bh))
+                            } else {
+                                (width.min(bw), height.min(bh))
+                            };
+                            let limited = match hinted {
+                                true => self.set_size_limit(w, window, min, (bw, bh)),
+                                false => Ok(()),
+                            };
+                            limited.and_then(|()| {
+                                if clamped == (width, height) {
+                                    return Ok(());
+                                }
+                                resolved_size = Some(clamped);
+                                self.resize(w, window, rule, clamped)
+                            })
+                        }
+                    }
+                }
+                Action::MoveBy(dx, dy) => match self.get_window_geometry(window) {
+                    None => Err("window is gone".into()),
+                    Some((cx, cy, _, _)) => {
+                        // The client's origin is inside the frame; requests
+                        // place the frame's corner
+                        let (left, _, top, _) = self.get_frame_extents(window);
+                        let (x, y) = (cx - left as i32 + dx, cy - top as i32 + dy);
+                        match rule.placement {
+                            Placement::Configure => self.configure(w, window, &ConfigureWindowAux::new().x(x).y(y)),
+                            Placement::Hints => self.set_placement_hints(w, window, Some((x, y)), None),
+                        }
+                    }
+                },
+                Action::Workspace(target) => {
+                    // Names and occupancy both cost round-trips; fetch only
+                    // what the target needs
+                    let (names, occupied) = match target {
+                        WorkspaceTarget::Index(_) => (Vec::new(), Vec::new()),
+                        WorkspaceTarget::Name(_) => (self.desktop_names(), Vec::new()),
+                        WorkspaceTarget::LeastPopulated(_) => (Vec::new(), self.occupied_desktops(window)),
+                    };
+                    match (target.resolve(self.desktop_count(), &names, &occupied), target) {
+                        (Some(ws), _) => {
+                            moved_to = Some(ws);
+                            self.send_client_message(w, window, self.atoms._NET_WM_DESKTOP, [ws, 1, 0, 0, 0])
+                        }
+                        (None, WorkspaceTarget::Name(name)) => Err(format!("no workspace named '{}'", name)),
+                        (None, _) => Err("no workspace to choose from".into()),
+                    }
+                }
+                // Sticky windows are on the current workspace already
+                Action::Follow(true) => match moved_to {
+                    Some(ws) if ws != ALL_WORKSPACES => {
+                        let data = [ws, 0, 0, 0, 0];
+                        self.send_client_message(w, self.root, self.atoms._NET_CURRENT_DESKTOP, data)
+                    }
+                    _ => Ok(()),
+                },
+                Action::Maximize(true) => self.set_wm_state(
+                    w,
+                    window,
+                    1,
+                    self.atoms._NET_WM_STATE_MAXIMIZED_VERT,
+                    self.atoms._NET_WM_STATE_MAXIMIZED_HORZ,
+                ),
+                Action::MaximizeAxis(axis) => self.set_wm_state(w, window, 1, self.maximized_atom(*axis), 0),
+                Action::Fullscreen(true) => {
+                    let monitor = rule.monitor().map(|_| &target_monitor);
+                    self.set_fullscreen_monitor(w, window, monitor)
+                        .and_then(|_| self.set_wm_state(w, window, 1, self.atoms._NET_WM_STATE_FULLSCREEN, 0))
+                }
+                Action::Pin(true) => self
+                    .send_client_message(
+                        w,
+                        window,
+                        self.atoms._NET_WM_DESKTOP,
+                        [0xFFFFFFFF, 1, 0, 0, 0],
+                    )
+                    .and_then(|_| self.set_wm_state(w, window, 1, self.atoms._NET_WM_STATE_STICKY, 0)),
+                // Unlike pin, false is an explicit request to unstick
+                Action::Sticky(sticky) => {
+                    self.set_wm_state(w, window, *sticky as u32, self.atoms._NET_WM_STATE_STICKY, 0)
+                }
+                Action::IconGeometry(geometry) => self.set_cardinal_property(
+                    w,
+                    window,
+                    self.atoms._NET_WM_ICON_GEOMETRY,
+                    AtomEnum::CARDINAL,
+                    geometry,
+                ),
+                // Only its presence matters; false takes the mark away
+                Action::HandledIcons(true) => self.set_cardinal_property(
+                    w,
+                    window,
+                    self.atoms._NET_WM_HANDLED_ICONS,
+                    AtomEnum::CARDINAL,
+                    &[],
+                ),
+                Action::HandledIcons(false) => {
+                    self.delete_property(w, window, self.atoms._NET_WM_HANDLED_ICONS)
+                }
+                Action::Minimize(true) => {
+                    // WM_CHANGE_STATE with IconicState (3)
+                    self.send_client_message(w, window, self.atoms.WM_CHANGE_STATE, [3u32, 0, 0, 0, 0])
+                }
+                Action::Shade(true) => {
+                    self.set_wm_state(w, window, 1, self.atoms._NET_WM_STATE_SHADED, 0)
+                }
+                Action::Above(true) => {
+                    self.set_wm_state(w, window, 1, self.atoms._NET_WM_STATE_ABOVE, 0)
+                }
+                Action::Below(true) => {
+                    self.set_wm_state(w, window, 1, self.atoms._NET_WM_STATE_BELOW, 0)
+                }
+                Action::Decorate(decorated) => self.set_decoration(w, window, *decorated),
+                Action::Border(width, color) => self.set_border(w, window, *width, *color),
+                Action::Strut(edge, size) => self.set_strut(w, window, *edge, *size),
+                Action::Icon(path) => icon::load(&exec::expand_home(path)).and_then(|icon| {
+                    self.set_cardinal_property(w, window, self.atoms._NET_WM_ICON, AtomEnum::CARDINAL, &icon)
+                }),
+                Action::Focus(true) => {
+                    self.send_client_message(
+                        w,
+                        window,
+                        self.atoms._NET_ACTIVE_WINDOW,
+                        [1, 0, 0, 0, 0], // source = application
+                    )
+                }
+                // An empty region means the whole window
+                Action::Blur(true) => self.set_cardinal_property(
+                    w,
+                    window,
+                    self.atoms._KDE_NET_WM_BLUR_BEHIND_REGION,
+                    AtomEnum::CARDINAL,
+                    &[],
+                ),
+                Action::Blur(false) => {
+                    self.delete_property(w, window, self.atoms._KDE_NET_WM_BLUR_BEHIND_REGION)
+                }
+                Action::Shadow(shadow) => self.set_cardinal_property(
+                    w,
+                    window,
+                    self.atoms._COMPTON_SHADOW,
+                    AtomEnum::CARDINAL,
+                    &[*shadow as u32],
+                ),
+                Action::Close(true) => {
+                    self.send_client_message(
+                        w,
+                        window,
+                        self.atoms._NET_CLOSE_WINDOW,
+                        [0, 2, 0, 0, 0], // CurrentTime, source = pager
+                    )
+                }
+                Action::SetProperty(name, value) => self.set_named_property(w, window, name, value),
+                Action::DeleteProperty(name) => {
+                    self.intern(name).and_then(|atom| self.delete_property(w, window, atom))
+                }
+                Action::Kill(true) => self.kill_client(w, window),
+                Action::Capture(template) => self.capture(window, template),
+                Action::Exec(line) | Action::ExecAfter(line) => {
+                    exec::spawn(self.hook(window, rule, line)).map_err(|e| e.to_string())
+                }
+                Action::ExecBefore(line) => exec::run(self.hook(window, rule, line), exec::BEFORE_TIMEOUT),
+                Action::Opacity(opacity) => {
+                    let value = (opacity.clamp(0.0, 1.0) * 0xFFFFFFFF_u64 as f64) as u32;
+                    self.set_cardinal_property(
+                        w,
+                        window,
+                        self.atoms._NET_WM_WINDOW_OPACITY,
+                        AtomEnum::CARDINAL,
+                        &[value],
+                    )
+                }
+                Action::Follow(false)
+                | Action::Maximize(false)
+                | Action::Fullscreen(false)
+                | Action::Pin(false)
+                | Action::Minimize(false)
+                | Action::Shade(false)
+                | Action::Above(false)
+                | Action::Below(false)
+                | Action::Focus(false)
+                | Action::Close(false)
+                | Action::Kill(false) => Ok(()),
+            };
+
+            if let Err(e) = result {
+                report.fail(action, e);
+            }
+        }
+
+        report
+    }
+
+    fn revert_rule(&self, window: Window, rule: &CompiledRule, w: &WriteAccess) -> ApplyReport {
+        let mut report = ApplyReport::default();
+
+        for action in &rule.actions {
+            let state = |atom| self.set_wm_state(w, window, 0, atom, 0);
+            let result = match action {
+                Action::Maximize(true) => self.set_wm_state(
+                    w,
+                    window,
+                    0,
+                    self.atoms._NET_WM_STATE_MAXIMIZED_VERT,
+                    self.atoms._NET_WM_STATE_MAXIMIZED_HORZ,
+                ),
+                Action::MaximizeAxis(axis) => state(self.maximized_atom(*axis)),
+                Action::Fullscreen(true) => state(self.atoms._NET_WM_STATE_FULLSCREEN),
+                Action::Sticky(true) => state(self.atoms._NET_WM_STATE_STICKY),
+                Action::Shade(true) => state(self.atoms._NET_WM_STATE_SHADED),
+                Action::Above(true) => state(self.atoms._NET_WM_STATE_ABOVE),
+                Action::Below(true) => state(self.atoms._NET_WM_STATE_BELOW),
+                Action::Decorate(decorated) => self.set_decoration(w, window, !*decorated),
+                Action::Border(Some(_), _) => self.set_border(w, window, Some(0), None),
+                Action::Strut(..) => self
+                    .delete_property(w, window, self.atoms._NET_WM_STRUT_PARTIAL)
+                    .and_then(|_| self.delete_property(w, window, self.atoms._NET_WM_STRUT)),
+                Action::Opacity(_) => {
+                    self.delete_property(w, window, self.atoms._NET_WM_WINDOW_OPACITY)
+                }
+                Action::Blur(true) => {
+                    self.delete_property(w, window, self.atoms._KDE_NET_WM_BLUR_BEHIND_REGION)
+                }
+                Action::Shadow(_) => self.delete_property(w, window, self.atoms._COMPTON_SHADOW),
+                Action::IconGeometry(_) => {
+                    self.delete_property(w, window, self.atoms._NET_WM_ICON_GEOMETRY)
+                }
+                Action::HandledIcons(true) => {
+                    self.delete_property(w, window, self.atoms._NET_WM_HANDLED_ICONS)
+                }
+                Action::SetProperty(name, _) => {
+                    self.intern(name).and_then(|atom| self.delete_property(w, window, atom))
+                }
+                // Geometry, workspace, focus and the rest have no meaningful undo
+                _ => Ok(()),
+            };
+
+            if let Err(e) = result {
+                report.fail(action, e);
+            }
+        }
+
+        report
     }
 }
 
 // MONITOR QUERY
 
+fn log_monitors(monitors: &[MonitorGeometry]) {
+    for (i, mon) in monitors.iter().enumerate() {
+        eprintln!(
+            "[x11] monitor {}: '{}' {}x{}+{}+{}{}",
+            i,
+            mon.name,
+            mon.width,
+            mon.height,
+            mon.x,
+            mon.y,
+            if mon.primary { " (primary)" } else { "" }
+        );
+    }
+}
+
 fn query_monitors(conn: &RustConnection, root: Window) -> Result<Vec<MonitorGeometry>, String> {
     let resources = conn
         .randr_get_screen_resources_current(root)
@@ -653,6 +1689,13 @@ fn query_monitors(conn: &RustConnection, root: Window) -> Result<Vec<MonitorGeom
         .reply()
         .map_err(|e| format!("randr get resources reply: {}", e))?;
 
+    // None (0) when no output is marked primary
+    let primary = conn
+        .randr_get_output_primary(root)
+        .ok()
+        .and_then(|cookie| cookie.reply().ok())
+        .map_or(0, |reply| reply.output);
+
     let mut monitors = Vec::new();
 
     for &output_id in &resources.outputs {
@@ -679,12 +1722,24 @@ fn query_monitors(conn: &RustConnection, root: Window) -> Result<Vec<MonitorGeom
 
         let name = String::from_utf8_lossy(&output_info.name).to_string();
 
+        // Output mm are for the unrotated panel; the crtc size is post-rotation
+        let rotated = crtc_info.rotation.contains(Rotation::ROTATE90)
+            || crtc_info.rotation.contains(Rotation::ROTATE270);
+        let (mm_width, mm_height) = if rotated {
+            (output_info.mm_height, output_info.mm_width)
+        } else {
+            (output_info.mm_width, output_info.mm_height)
+        };
+
         monitors.push(MonitorGeometry {
             name,
             x: crtc_info.x as i32,
             y: crtc_info.y as i32,
             width: crtc_info.width as u32,
             height: crtc_info.height as u32,
+            mm_width,
+            mm_height,
+            primary: output_id == primary,
         });
     }
 
@@ -697,12 +1752,21 @@ fn query_monitors(conn: &RustConnection, root: Window) -> Result<Vec<MonitorGeom
             y: 0,
             width: screen.width_in_pixels as u32,
             height: screen.height_in_pixels as u32,
+            mm_width: screen.width_in_millimeters as u32,
+            mm_height: screen.height_in_millimeters as u32,
+            primary: true,
         });
     }
 
     Ok(monitors)
 }
 
+fn monitor_at(monitors: &[MonitorGeometry], x: i32, y: i32) -> Option<usize> {
+    monitors.iter().position(|mon| {
+        x >= mon.x && x < mon.x + mon.width as i32 && y >= mon.y && y < mon.y + mon.height as i32
+    })
+}
+
 fn get_client_list(conn: &RustConnection, root: Window, atoms: &Atoms) -> Vec<Window> {
     let reply = conn
         .get_property(false, root, atoms._NET_CLIENT_LIST, AtomEnum::WINDOW, 0, 4096)
@@ -720,19 +1784,40 @@ fn get_client_list(conn: &RustConnection, root: Window, atoms: &Atoms) -> Vec<Wi
     }
 }
 
-fn resolve_dim(val: DimensionVal, total: i32) -> i32 {
-    match val {
-        DimensionVal::Pixels(px) => px,
-        DimensionVal::Percent(pct) => (total as f64 * pct) as i32,
-    }
+fn get_active_window(conn: &RustConnection, root: Window, atoms: &Atoms) -> Option<Window> {
+    let reply = conn
+        .get_property(false, root, atoms._NET_ACTIVE_WINDOW, AtomEnum::WINDOW, 0, 1)
+        .ok()?
+        .reply()
+        .ok()?;
+
+    reply
+        .value
+        .get(..4)
+        .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+        .filter(|&w| w != 0)
 }
 
-fn local_time() -> String {
-    unsafe {
-        let mut t: libc::time_t = 0;
-        libc::time(&mut t);
-        let mut tm: libc::tm = std::mem::zeroed();
-        libc::localtime_r(&t, &mut tm);
-        format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
-    }
+// Sends the request and waits for the server's verdict, turning X errors
+// (BadWindow for a window that vanished, BadValue, ...) into an Err.
+fn checked(
+    cookie: Result<x11rb::cookie::VoidCookie<'_, RustConnection>, x11rb::errors::ConnectionError>,
+) -> Result<(), String> {
+    cookie
+        .map_err(|e| e.to_string())?
+        .check()
+        .map_err(|e| match e {
+            x11rb::errors::ReplyError::X11Error(err) => format!("{:?}", err.error_kind),
+            other => other.to_string(),
+        })
+}
+
+fn get_current_desktop(conn: &RustConnection, root: Window, atoms: &Atoms) -> Option<u32> {
+    let reply = conn
+        .get_property(false, root, atoms._NET_CURRENT_DESKTOP, AtomEnum::CARDINAL, 0, 1)
+        .ok()?
+        .reply()
+        .ok()?;
+
+    reply.value32()?.next()
 }
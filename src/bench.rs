@@ -0,0 +1,165 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::matchers::{MonitorId, WINDOW_STATES, WindowProps};
+use crate::rules::CompiledRule;
+
+// SYNTHETIC WINDOWS
+
+const CLASSES: &[&str] = &[
+    "kitty", "Alacritty", "firefox", "Navigator", "chromium", "Code", "Thunar", "mpv",
+    "Steam", "discord", "Slack", "libreoffice-writer", "Gimp-2.10", "obs", "zoom",
+];
+
+const TITLE_WORDS: &[&str] = &[
+    "zsh", "vim", "YouTube", "Mozilla", "Firefox", "Inbox", "README.md", "~/src/cherrypie",
+    "Picture-in-Picture", "Settings", "Untitled", "Document", "Meeting", "-", "—", "(1)",
+];
+
+const TYPES: &[&str] = &["normal", "normal", "normal", "normal", "dialog", "utility", "splash", "menu"];
+
+const MONITORS: &[&str] = &["eDP-1", "HDMI-1", "DP-2"];
+
+// xorshift64; reproducible across runs so timings are comparable
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, list: &[&'a str]) -> &'a str {
+        list[self.below(list.len())]
+    }
+}
+
+// Property sets covering every field a matcher can read. Titles vary in
+// length, with the occasional very long one, since that is where slow
+// regexes show.
+pub fn synthetic_windows(count: usize) -> Vec<WindowProps> {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    (0..count)
+        .map(|_| {
+            let words = if rng.below(20) == 0 { 200 } else { 1 + rng.below(8) };
+            let title = (0..words)
+                .map(|_| rng.pick(TITLE_WORDS))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let class = rng.pick(CLASSES).to_string();
+            let monitor = rng.below(MONITORS.len());
+
+            WindowProps {
+                process: class.to_lowercase(),
+                role: if rng.below(4) == 0 { "browser".into() } else { String::new() },
+                window_type: rng.pick(TYPES).to_string(),
+                size: Some((200 + rng.below(3000) as u32, 150 + rng.below(2000) as u32)),
+                states: (0..rng.below(3)).map(|_| rng.pick(WINDOW_STATES).to_string()).collect(),
+                workspace: Some(rng.below(10) as u32),
+                monitor: Some(MonitorId { index: monitor as u32, name: MONITORS[monitor].into() }),
+                process_env: Some(
+                    [("PROJECT".to_string(), rng.pick(&["work", "home", "oss"]).to_string())].into(),
+                ),
+                uid: Some(if rng.below(50) == 0 { 0 } else { 1000 }),
+                startup_id: (rng.below(3) == 0).then(|| format!("rofi-{}-host_TIME{}", rng.below(99999), rng.below(99999))),
+                group_class: (rng.below(4) == 0).then(|| rng.pick(CLASSES).to_string()),
+                compositor: Some(true),
+                monitor_count: Some(MONITORS.len() as u32),
+                minute_of_day: Some(rng.below(24 * 60) as u32),
+                class,
+                title,
+            }
+        })
+        .collect()
+}
+
+// MEASUREMENT
+
+pub struct RuleTiming {
+    pub index: usize,
+    pub label: String,
+    pub elapsed: Duration,
+    // Windows matched in one round
+    pub matched: usize,
+}
+
+pub struct BenchReport {
+    pub windows: usize,
+    pub rounds: usize,
+    pub total: Duration,
+    pub rules: Vec<RuleTiming>,
+}
+
+impl BenchReport {
+    pub fn evaluations_per_sec(&self) -> f64 {
+        let evaluations = (self.windows * self.rounds * self.rules.len()) as f64;
+        evaluations / self.total.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+// Evaluate every rule against every window, `rounds` times
+pub fn run(rules: &[CompiledRule], windows: &[WindowProps], rounds: usize) -> BenchReport {
+    let mut timings: Vec<RuleTiming> = rules
+        .iter()
+        .map(|r| RuleTiming { index: r.index, label: r.label(), elapsed: Duration::ZERO, matched: 0 })
+        .collect();
+
+    let start = Instant::now();
+    for round in 0..rounds {
+        for props in windows {
+            for (rule, timing) in rules.iter().zip(&mut timings) {
+                let t = Instant::now();
+                let hit = rule.matches(props);
+                timing.elapsed += t.elapsed();
+                if hit && round == 0 {
+                    timing.matched += 1;
+                }
+            }
+        }
+    }
+    let total = start.elapsed();
+
+    BenchReport { windows: windows.len(), rounds, total, rules: timings }
+}
+
+// Rules shown in the report, slowest first
+const REPORT_TOP: usize = 10;
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} rules x {} windows x {} rounds: {:.1} ms, {:.0} rule evaluations/s",
+            self.rules.len(),
+            self.windows,
+            self.rounds,
+            self.total.as_secs_f64() * 1000.0,
+            self.evaluations_per_sec()
+        )?;
+
+        let mut slowest: Vec<&RuleTiming> = self.rules.iter().collect();
+        slowest.sort_by_key(|t| std::cmp::Reverse(t.elapsed));
+        let sum: f64 = self.rules.iter().map(|r| r.elapsed.as_secs_f64()).sum();
+
+        writeln!(f, "slowest rules:")?;
+        for timing in slowest.iter().take(REPORT_TOP) {
+            let secs = timing.elapsed.as_secs_f64();
+            writeln!(
+                f,
+                "  {}  {:.2} ms  {:.1}%  matched {}/{}",
+                timing.label,
+                secs * 1000.0,
+                secs / sum.max(f64::EPSILON) * 100.0,
+                timing.matched,
+                self.windows
+            )?;
+        }
+        Ok(())
+    }
+}
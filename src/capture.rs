@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+
+use crate::exec::expand_home;
+
+// Window screenshots for the `capture` action, which rules only accept
+// when cherrypie is built with the `capture` feature. Images are written
+// as uncompressed PNGs, so no image or compression crate is needed.
+
+// `template` with %c (class), %t (title), %w (window id), %T (unix time)
+// and %% expanded, and a leading ~/ for $HOME. '/' in the substituted
+// text becomes '_' so a title can't pick the directory.
+pub fn path(template: &str, class: &str, title: &str, window: u32, time: u64) -> PathBuf {
+    let clean = |s: &str| s.replace('/', "_");
+    let mut out = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('c') => out.push_str(&clean(class)),
+            Some('t') => out.push_str(&clean(title)),
+            Some('w') => out.push_str(&format!("0x{:x}", window)),
+            Some('T') => out.push_str(&time.to_string()),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    expand_home(&out)
+}
+
+// Pixels of a 32 bits-per-pixel ZPixmap (B, G, R, unused) as RGB
+pub fn bgrx_to_rgb(data: &[u8]) -> Vec<u8> {
+    data.chunks_exact(4).flat_map(|px| [px[2], px[1], px[0]]).collect()
+}
+
+// 8-bit RGB PNG, stored in uncompressed deflate blocks
+pub fn encode_png(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    // Each scanline starts with filter type 0 (none)
+    let stride = width as usize * 3;
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for row in rgb.chunks(stride.max(1)).take(height as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = raw.chunks(0xffff).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        let len = block.len() as u16;
+        zlib.push((i + 1 == blocks.len()) as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // Bit depth 8, color type 2 (RGB), default compression/filter, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib);
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(kind.iter().chain(data));
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
@@ -0,0 +1,231 @@
+use std::rc::Rc;
+
+use regex::Regex;
+
+use crate::matchers::{
+    AllOf, AnyOf, CompositorMatcher, Constant, Matcher, MonitorCountMatcher, Not, TimeMatcher,
+};
+use crate::rules::{CmpOp, Comparison};
+
+// Named boolean expressions from the [conditions] table, which rules
+// reference with `when = "<name>"`:
+//
+//   time within 09:00-18:00 && host == "desk"
+//   !compositor || monitors >= 2
+//
+// Terms: `time within HH:MM-HH:MM` (local time, may wrap past midnight),
+// `host == "x"` / `host != "x"` / `host ~ "regex"`, `monitors <op> N`, and
+// `compositor`; combined with `!`, `&&`, `||`, and parentheses. Host
+// terms are settled when the config is compiled; the rest are checked
+// each time a rule using the condition is evaluated.
+pub fn compile(expr: &str, host: &str) -> Result<Rc<dyn Matcher>, String> {
+    let tokens = tokenize(expr)?;
+    let mut parser = Parser { tokens, pos: 0, host };
+    let matcher = parser.or()?;
+    match parser.peek() {
+        None => Ok(Rc::from(matcher)),
+        Some(tok) => Err(format!("unexpected {}", tok)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Number(u32),
+    // Minutes since midnight
+    Time(u32),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Ident(s) => write!(f, "'{}'", s),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Number(n) => write!(f, "'{}'", n),
+            Token::Time(m) => write!(f, "'{:02}:{:02}'", m / 60, m % 60),
+            Token::Op(op) => write!(f, "'{}'", op),
+        }
+    }
+}
+
+// Longest first so "&&" isn't read as a stray '&'
+const OPERATORS: &[&str] = &["&&", "||", "==", "!=", ">=", "<=", "!", "(", ")", "~", "-", "<", ">"];
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = src.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if c == '"' || c == '\'' {
+            let end = rest[1..]
+                .find(c)
+                .ok_or_else(|| "unterminated string".to_string())?;
+            tokens.push(Token::Str(rest[1..1 + end].to_string()));
+            rest = &rest[end + 2..];
+        } else if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !c.is_ascii_digit() && c != ':').unwrap_or(rest.len());
+            tokens.push(number_or_time(&rest[..end])?);
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(format!("unexpected '{}'", c));
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+fn number_or_time(s: &str) -> Result<Token, String> {
+    let invalid = || format!("invalid number or time '{}'", s);
+    match s.split_once(':') {
+        None => s.parse().map(Token::Number).map_err(|_| invalid()),
+        Some((h, m)) => {
+            let h: u32 = h.parse().map_err(|_| invalid())?;
+            let m: u32 = m.parse().map_err(|_| invalid())?;
+            if h > 24 || m > 59 || (h == 24 && m > 0) {
+                return Err(invalid());
+            }
+            Ok(Token::Time(h * 60 + m))
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    host: &'a str,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let tok = self.tokens.get(self.pos).cloned().ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        Ok(tok)
+    }
+
+    fn eat(&mut self, op: &'static str) -> bool {
+        if self.peek() == Some(&Token::Op(op)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, op: &'static str) -> Result<(), String> {
+        if self.eat(op) {
+            Ok(())
+        } else {
+            match self.peek() {
+                Some(tok) => Err(format!("expected '{}', found {}", op, tok)),
+                None => Err(format!("expected '{}'", op)),
+            }
+        }
+    }
+
+    fn or(&mut self) -> Result<Box<dyn Matcher>, String> {
+        let mut terms = vec![self.and()?];
+        while self.eat("||") {
+            terms.push(self.and()?);
+        }
+        Ok(if terms.len() == 1 { terms.remove(0) } else { Box::new(AnyOf(terms)) })
+    }
+
+    fn and(&mut self) -> Result<Box<dyn Matcher>, String> {
+        let mut terms = vec![self.unary()?];
+        while self.eat("&&") {
+            terms.push(self.unary()?);
+        }
+        Ok(if terms.len() == 1 { terms.remove(0) } else { Box::new(AllOf(terms)) })
+    }
+
+    fn unary(&mut self) -> Result<Box<dyn Matcher>, String> {
+        if self.eat("!") {
+            return Ok(Box::new(Not(self.unary()?)));
+        }
+        if self.eat("(") {
+            let inner = self.or()?;
+            self.expect(")")?;
+            return Ok(inner);
+        }
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<Box<dyn Matcher>, String> {
+        let name = match self.next()? {
+            Token::Ident(name) => name,
+            tok => return Err(format!("expected a term, found {}", tok)),
+        };
+        match name.as_str() {
+            "time" => {
+                match self.next()? {
+                    Token::Ident(w) if w == "within" => {}
+                    tok => return Err(format!("expected 'within' after 'time', found {}", tok)),
+                }
+                let start = self.time()?;
+                self.expect("-")?;
+                let end = self.time()?;
+                Ok(Box::new(TimeMatcher { start, end }))
+            }
+            "host" => {
+                let op = self.next()?;
+                let value = match self.next()? {
+                    Token::Str(s) | Token::Ident(s) => s,
+                    tok => return Err(format!("expected a host name, found {}", tok)),
+                };
+                let holds = match op {
+                    Token::Op("==") => self.host == value,
+                    Token::Op("!=") => self.host != value,
+                    Token::Op("~") => Regex::new(&value)
+                        .map_err(|e| format!("bad host pattern '{}': {}", value, e))?
+                        .is_match(self.host),
+                    tok => return Err(format!("expected '==', '!=' or '~' after 'host', found {}", tok)),
+                };
+                Ok(Box::new(Constant(holds)))
+            }
+            "monitors" => {
+                let op = match self.next()? {
+                    Token::Op("==") => CmpOp::Eq,
+                    Token::Op("!=") => CmpOp::Ne,
+                    Token::Op("<") => CmpOp::Lt,
+                    Token::Op("<=") => CmpOp::Le,
+                    Token::Op(">") => CmpOp::Gt,
+                    Token::Op(">=") => CmpOp::Ge,
+                    tok => return Err(format!("expected a comparison after 'monitors', found {}", tok)),
+                };
+                let value = match self.next()? {
+                    Token::Number(n) => n,
+                    tok => return Err(format!("expected a number, found {}", tok)),
+                };
+                Ok(Box::new(MonitorCountMatcher(Comparison { op, value })))
+            }
+            "compositor" => Ok(Box::new(CompositorMatcher(true))),
+            other => Err(format!(
+                "unknown term '{}' (expected time, host, monitors, or compositor)",
+                other
+            )),
+        }
+    }
+
+    fn time(&mut self) -> Result<u32, String> {
+        match self.next()? {
+            Token::Time(m) => Ok(m),
+            tok => Err(format!("expected a time (HH:MM), found {}", tok)),
+        }
+    }
+}
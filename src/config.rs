@@ -1,10 +1,24 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use crate::matchers;
+use crate::exec;
+use crate::rules;
 
 pub struct Paths {
     pub config_file: PathBuf,
+    // [profile.<name>] picked by name, else profiles apply by their `when`
+    pub profile: Option<String>,
+}
+
+// Picks a profile the way --profile does
+pub const PROFILE_VAR: &str = "CHERRYPIE_PROFILE";
+
+fn profile_from_env() -> Option<String> {
+    std::env::var(PROFILE_VAR).ok().filter(|name| !name.is_empty())
 }
 
 impl Paths {
@@ -17,24 +31,35 @@ impl Paths {
 
         Ok(Self {
             config_file: config_dir.join("config.toml"),
+            profile: profile_from_env(),
         })
     }
 
     pub fn with_config(path: PathBuf) -> Self {
-        Self { config_file: path }
+        Self { config_file: path, profile: profile_from_env() }
     }
 }
 
 // Position can be:
 //   "center", "top-left", "top-right", "bottom-left", "bottom-right",
 //   "left", "right", "top", "bottom"           -> Named anchor
+//   "left-half", "top-right-quarter", ...      -> Named tile (sets size too)
+//   "center+100,-50"                            -> Named anchor plus offset
+//   { anchor = "top-right", offset = [-20, 20] } -> Same, as a table
 //   [100, 200]                                  -> Absolute pixels
 //   ["25%", "50%"]                              -> Percentage of monitor
+//   ["20mm", "15mm"]                            -> Physical length on monitor
 //   ["100", "200"]                              -> Absolute as strings
 #[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(untagged)]
 pub enum PositionValue {
     Named(String),
+    Anchored {
+        anchor: String,
+        #[serde(default)]
+        offset: [i32; 2],
+    },
     Absolute([i32; 2]),
     Flexible([String; 2]),
 }
@@ -42,87 +67,1239 @@ pub enum PositionValue {
 // Size can be:
 //   [800, 600]                                  -> Absolute pixels
 //   ["50%", "100%"]                             -> Percentage of monitor
+//   ["300mm", "200mm"]                          -> Physical size on monitor
 //   ["800", "600"]                              -> Absolute as strings
 #[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(untagged)]
 pub enum SizeValue {
     Absolute([u32; 2]),
     Flexible([String; 2]),
 }
 
+// Margin can be:
+//   12                                          -> Same on every side
+//   [8, 12, 8, 12]                              -> [top, right, bottom, left]
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum MarginValue {
+    Uniform(u32),
+    Sides([u32; 4]),
+}
+
+// String matchers can be:
+//   "kitty"                                     -> One pattern
+//   ["kitty", "alacritty", "foot"]              -> Any of these patterns
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum PatternValue {
+    One(String),
+    Any(Vec<String>),
+}
+
+impl PatternValue {
+    pub fn patterns(&self) -> &[String] {
+        match self {
+            PatternValue::One(s) => std::slice::from_ref(s),
+            PatternValue::Any(list) => list,
+        }
+    }
+}
+
+// Workspace can be:
+//   0, 1, 2                                     -> By index
+//   "least-populated"                           -> Fewest windows, picked
+//                                                  per window (limited to
+//                                                  workspace_among if set)
+//   "mail", "web"                               -> By desktop name
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum WorkspaceValue {
+    Index(u32),
+    Named(String),
+}
+
+// Maximize can be:
+//   true, false                                 -> Both axes
+//   "horizontal", "vertical", "both"            -> Only the named axis
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum MaximizeValue {
+    Toggle(bool),
+    Axis(String),
+}
+
 // Monitor can be:
 //   0, 1, 2                                     -> By index
 //   "Z", "HDMI-1", "DP-2"                      -> By output name
 #[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(untagged)]
 pub enum MonitorValue {
     Index(u32),
     Name(String),
 }
 
+// set_prop = { name = "_MY_MARKER", value = "1" }
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SetProp {
+    pub name: String,
+    pub value: PropValue,
+}
+
+// border = { width = 2, color = "#ff0000" }; either may be left out
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Border {
+    pub width: Option<u32>,
+    pub color: Option<String>,
+}
+
+// strut = { edge = "top", size = 40 }
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Strut {
+    pub edge: String,
+    pub size: u32,
+}
+
+// Property value can be:
+//   1, 42                                       -> CARDINAL
+//   "1", "work"                                 -> UTF8_STRING
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum PropValue {
+    Cardinal(u32),
+    Text(String),
+}
+
 #[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Rule {
     // Matchers
-    pub class: Option<String>,
-    pub title: Option<String>,
-    pub role: Option<String>,
-    pub process: Option<String>,
+    pub class: Option<PatternValue>,
+    pub title: Option<PatternValue>,
+    pub role: Option<PatternValue>,
+    pub process: Option<PatternValue>,
     #[serde(rename = "type")]
-    pub window_type: Option<String>,
+    pub window_type: Option<PatternValue>,
+    pub class_not: Option<PatternValue>,
+    pub title_not: Option<PatternValue>,
+    pub role_not: Option<PatternValue>,
+    pub process_not: Option<PatternValue>,
+    #[serde(rename = "type_not")]
+    pub window_type_not: Option<PatternValue>,
+    pub min_width: Option<u32>,
+    pub max_width: Option<u32>,
+    pub min_height: Option<u32>,
+    pub max_height: Option<u32>,
+    pub state: Option<Vec<String>>,
+    pub on_workspace: Option<u32>,
+    pub on_monitor: Option<MonitorValue>,
+    // Variable name -> pattern, against /proc/<pid>/environ
+    pub process_env: Option<BTreeMap<String, String>>,
+    // Owner of the window's process, by uid or user name
+    pub uid: Option<u32>,
+    pub user: Option<String>,
+    // Startup-notification ID (_NET_STARTUP_ID), or just the launcher at
+    // its front
+    pub startup_id: Option<PatternValue>,
+    pub launched_by: Option<PatternValue>,
+    // Class of the window's group leader (WM_HINTS window_group)
+    pub group_of: Option<PatternValue>,
+
+    // Conditions on the session rather than the window: a table, or the
+    // name of an expression in [conditions]
+    pub when: Option<WhenValue>,
+
+    // Only apply while a compositor is (true) or is not (false) running.
+    // A condition, not a matcher: the rule still needs one of MATCHER_KEYS.
+    pub if_compositor: Option<bool>,
+
+    // Shown next to the rule's index in logs, dry runs, and listings
+    pub name: Option<String>,
+    // false keeps the rule in the file without it matching anything
+    pub enabled: Option<bool>,
+
+    // Evaluation order: higher first, file order among equal priorities
+    pub priority: Option<i32>,
+    // Re-fire on property changes: "once" (default), "always", or
+    // "on_title_change"
+    pub apply: Option<String>,
+    // Stop evaluating later rules for a window this one matched;
+    // overrides settings.first_match
+    pub stop: Option<bool>,
+    // Whether position/size describe the "frame" or the "client" area
+    pub target: Option<String>,
+    // Overrides settings.placement for this rule's position and size
+    pub placement: Option<String>,
+    // Overrides settings.margin
+    pub margin: Option<MarginValue>,
+    // Apply to at most this many windows
+    pub max_matches: Option<u32>,
+    // Shorthand for max_matches = 1
+    pub once_per_session: Option<bool>,
+    // Raise a desktop notification when one of the rule's actions fails
+    pub alert_on_failure: Option<bool>,
+
+    // When the rule fires: "map" (default), "focus", or "workspace-switch"
+    pub on: Option<String>,
+    // Overrides settings.match_mode for this rule's string matchers
+    pub match_mode: Option<String>,
+    // Overrides settings.case_insensitive for this rule's string matchers
+    pub case_insensitive: Option<bool>,
+
+    // Context for commands the rule runs; override [settings]
+    pub env: Option<BTreeMap<String, String>>,
+    pub cwd: Option<String>,
+    pub shell: Option<String>,
 
     // Actions
-    pub workspace: Option<u32>,
+    pub workspace: Option<WorkspaceValue>,
+    // Candidates for workspace = "least-populated"
+    pub workspace_among: Option<Vec<u32>>,
+    // Switch to the workspace the window was moved to
+    pub follow: Option<bool>,
+    // Apply workspace and monitor moves to the rest of the window's group
+    pub move_group: Option<bool>,
+    // "move-with-main": put the window where its group's main window is,
+    // for toolboxes and palettes
+    pub group: Option<String>,
+    // "window" (default), or "application" to also apply the actions to
+    // the other open windows of the same class or process
+    pub scope: Option<String>,
     pub monitor: Option<MonitorValue>,
     pub position: Option<PositionValue>,
     pub size: Option<SizeValue>,
-    pub maximize: Option<bool>,
+    // [col, row, colspan, rowspan] of the [grid], setting position and size
+    pub cell: Option<[u32; 4]>,
+    // Pixels to add to the current position and size
+    pub move_by: Option<[i32; 2]>,
+    pub resize_by: Option<[i32; 2]>,
+    // Bounds the size is clamped into once the other size actions ran
+    pub min_size: Option<SizeValue>,
+    pub max_size: Option<SizeValue>,
+    // Also advertise min_size/max_size in WM_NORMAL_HINTS
+    pub size_hints: Option<bool>,
+    // Width:height ratio, e.g. "16:9"
+    pub aspect: Option<String>,
+    pub maximize: Option<MaximizeValue>,
     pub fullscreen: Option<bool>,
     pub pin: Option<bool>,
+    // Only _NET_WM_STATE_STICKY, leaving _NET_WM_DESKTOP alone
+    pub sticky: Option<bool>,
+    // Where the window minimizes to, [x, y, width, height], for taskbars
+    // and minimize animations (_NET_WM_ICON_GEOMETRY)
+    pub icon_geometry: Option<[u32; 4]>,
+    // Mark the window as a pager that draws icons for iconified windows
+    // (_NET_WM_HANDLED_ICONS)
+    pub handled_icons: Option<bool>,
     pub minimize: Option<bool>,
+    // Hide the window into a named scratchpad, shown and hidden again by
+    // `cherrypie scratchpad toggle <name>`
+    pub scratchpad: Option<String>,
     pub shade: Option<bool>,
     pub above: Option<bool>,
     pub below: Option<bool>,
     pub decorate: Option<bool>,
+    // X border of the window itself, for WMs that don't draw over it
+    pub border: Option<Border>,
+    // Reserve screen space along one edge of the window's monitor, like a
+    // panel (_NET_WM_STRUT_PARTIAL)
+    pub strut: Option<Strut>,
+    // PNG written as the window's _NET_WM_ICON, for the taskbar and
+    // alt-tab; `~` expands to $HOME
+    pub icon: Option<String>,
     pub focus: Option<bool>,
+    // Compositor hints: _KDE_NET_WM_BLUR_BEHIND_REGION (KWin, picom with
+    // blur) and _COMPTON_SHADOW (picom)
+    pub blur: Option<bool>,
+    pub shadow: Option<bool>,
+    // Write or remove an arbitrary property on the window, for other
+    // tools to key off
+    pub set_prop: Option<SetProp>,
+    pub del_prop: Option<String>,
+    // Screenshot the window to this path template (capture feature)
+    pub capture: Option<String>,
+    // Apply the actions this long after the match, for apps that fight
+    // placement while they start up
+    pub delay_ms: Option<u64>,
+    // Evaluate the rule on a new window only once its title has stopped
+    // changing for title_settle_ms (default 250), or title_timeout_ms
+    // (default 2000) after it appeared
+    pub wait_for_title: Option<bool>,
+    pub title_settle_ms: Option<u64>,
+    pub title_timeout_ms: Option<u64>,
+    // Re-apply monitor, size, and position whenever the window moves or
+    // resizes itself afterwards
+    pub enforce: Option<bool>,
+    // Open the window where the last one like it was when it closed
+    // (geometry and workspace, kept under $XDG_STATE_HOME)
+    pub remember: Option<bool>,
+    // true rounds sizes to the window's resize increments (e.g. terminal
+    // character cells); false clears them so sizes are exact pixels
+    pub honor_size_hints: Option<bool>,
+    // Shell command run when the rule matches, with CHERRYPIE_WINDOW_ID,
+    // CHERRYPIE_CLASS, and CHERRYPIE_TITLE in its environment
+    pub exec: Option<String>,
+    // Hooks like exec, run before any other action (waited on briefly)
+    // and after all of them
+    pub exec_before: Option<String>,
+    pub exec_after: Option<String>,
+    // Ask the window to close (_NET_CLOSE_WINDOW)
+    pub close: Option<bool>,
+    // Terminate the client: SIGKILL to its local process, else XKillClient
+    pub kill: Option<bool>,
     pub opacity: Option<f64>,
+
+    // The [profile.<name>] whose `when` decides whether the rule applies
+    #[serde(skip)]
+    pub profile: Option<String>,
+    // Keys the rule took from [defaults], for errors they cause
+    #[serde(default, rename = "[defaults]")]
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    pub defaulted: Vec<String>,
+}
+
+pub const MATCHER_KEYS: &[&str] = &[
+    "class",
+    "title",
+    "role",
+    "process",
+    "type",
+    "class_not",
+    "title_not",
+    "role_not",
+    "process_not",
+    "type_not",
+    "min_width",
+    "max_width",
+    "min_height",
+    "max_height",
+    "state",
+    "on_workspace",
+    "on_monitor",
+    "process_env",
+    "uid",
+    "user",
+    "startup_id",
+    "launched_by",
+    "group_of",
+];
+
+impl Rule {
+    pub fn has_matcher(&self) -> bool {
+        self.class.is_some()
+            || self.title.is_some()
+            || self.role.is_some()
+            || self.process.is_some()
+            || self.window_type.is_some()
+            || self.class_not.is_some()
+            || self.title_not.is_some()
+            || self.role_not.is_some()
+            || self.process_not.is_some()
+            || self.window_type_not.is_some()
+            || self.min_width.is_some()
+            || self.max_width.is_some()
+            || self.min_height.is_some()
+            || self.max_height.is_some()
+            || self.state.is_some()
+            || self.on_workspace.is_some()
+            || self.on_monitor.is_some()
+            || self.process_env.is_some()
+            || self.uid.is_some()
+            || self.user.is_some()
+            || self.startup_id.is_some()
+            || self.launched_by.is_some()
+            || self.group_of.is_some()
+    }
+}
+
+// A rule's `when` table. Conditions that cannot change while the daemon
+// runs are checked once, when the config is loaded.
+#[derive(Debug, Default, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct When {
+    // Regex against gethostname()
+    pub hostname: Option<String>,
+    // Connected outputs; checked each time the rule is evaluated
+    pub monitors: Option<CountValue>,
+}
+
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum WhenValue {
+    Named(String),
+    Table(When),
+}
+
+impl WhenValue {
+    pub fn table(&self) -> Option<&When> {
+        match self {
+            WhenValue::Named(_) => None,
+            WhenValue::Table(when) => Some(when),
+        }
+    }
+}
+
+// Count condition can be:
+//   2                                           -> Exactly
+//   ">= 2", "< 3", "!= 1"                       -> Comparison
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum CountValue {
+    Exact(u32),
+    Compare(String),
+}
+
+// Global options from the [settings] table
+#[derive(Debug, Default, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Settings {
+    // How string matchers are interpreted: "regex" (default), "glob",
+    // "exact", or "substring"
+    pub match_mode: Option<String>,
+    // Match string patterns regardless of case, as if each began with (?i)
+    pub case_insensitive: Option<bool>,
+    // Every rule stops evaluation when it matches (first match wins)
+    pub first_match: Option<bool>,
+    // How position/size are applied: "configure" (default) or "hints"
+    pub placement: Option<String>,
+    // Pixels kept free along the monitor edges when resolving relative
+    // positions and sizes
+    pub margin: Option<MarginValue>,
+    // Environment, working directory ("~/" expands to $HOME), and shell
+    // (a path, or "user" for $SHELL) for commands rules run
+    pub env: Option<BTreeMap<String, String>>,
+    pub cwd: Option<String>,
+    pub shell: Option<String>,
+    // Keep local usage statistics for `cherrypie stats` (default false)
+    pub stats: Option<bool>,
+    // Default for rules' honor_size_hints
+    pub honor_size_hints: Option<bool>,
+    // Warn when a rule takes longer than this to apply to a new window,
+    // in milliseconds (default 100; 0 turns the warning off)
+    pub slow_apply_ms: Option<u64>,
+}
+
+// The [startup] table: the pass over windows that already exist when the
+// daemon starts. Windows mapped afterwards are unaffected.
+#[derive(Debug, Default, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Startup {
+    // false skips the pass entirely
+    pub enabled: Option<bool>,
+    // Only these rules (by position in the file, from 0) take part
+    pub rules: Option<Vec<usize>>,
+    // Log what the pass would do instead of applying it
+    pub dry_run: Option<bool>,
+}
+
+// The [session_restore] table: actions (and failure alerts) held back for
+// windows that appear right after login, while a session manager restores
+// the last session
+#[derive(Debug, Default, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionRestore {
+    // Length of the burst from daemon start; unset or 0 disables
+    pub seconds: Option<u64>,
+    // Action keys to hold back (default: focus, above)
+    pub suppress: Option<Vec<String>>,
+}
+
+// The [grid] table: equal cells over each monitor, for rules' `cell`
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GridLayout {
+    pub cols: u32,
+    pub rows: u32,
+}
+
+// A [[monitor_defaults]] entry: baseline actions for every window on one
+// output that no [[rule]] matched. Takes the action keys of a rule, but no
+// matchers; the output is the only condition.
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MonitorDefault {
+    // Output name (e.g. "HDMI-1") or index
+    pub output: MonitorValue,
+    #[serde(flatten)]
+    pub rule: Rule,
+}
+
+// A [vars] entry: a monitor name, a workspace, a size, ...
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum VarValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    List(Vec<VarValue>),
 }
 
 #[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Config {
+    // More files of rules, relative to this one (`~` expands to $HOME),
+    // read in order; `*`, `?` and `[...]` work in the file name, e.g.
+    // "rules.d/*.toml"
+    #[serde(default)]
+    pub include: Vec<String>,
+    // Per-machine constants, referenced as "${name}" in any other value
+    #[serde(default)]
+    pub vars: BTreeMap<String, VarValue>,
+    // Action keys every [[rule]] gets unless it sets them itself
+    pub defaults: Option<Rule>,
+    #[serde(default)]
+    pub settings: Settings,
+    #[serde(default)]
+    pub startup: Startup,
+    #[serde(default)]
+    pub session_restore: SessionRestore,
+    pub grid: Option<GridLayout>,
+    // Raw WM_CLASS -> normalized name, applied before class matchers run
+    #[serde(default)]
+    pub class_aliases: BTreeMap<String, String>,
+    // Name -> boolean expression over session state, for `when = "<name>"`
+    #[serde(default)]
+    pub conditions: BTreeMap<String, String>,
+    #[serde(default)]
+    pub rule: Vec<Rule>,
+    // Evaluated after the rules, for windows none of them matched
+    #[serde(default)]
+    pub monitor_defaults: Vec<MonitorDefault>,
+    // Rules sharing keys, e.g. one class with an entry per title. Read as
+    // plain rules after the file's own; the field is here for the schema
+    #[serde(default)]
+    pub group: Vec<Group>,
+    // Alternative rule sets, e.g. [profile.docked] and [profile.mobile]
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
+    // Files `include` read, in order
+    #[serde(skip)]
+    pub included: Vec<PathBuf>,
+}
+
+// A [[group]]: keys, usually matchers, that each of its [[group.rule]]
+// entries gets unless it sets them itself
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Group {
+    #[serde(flatten)]
+    pub shared: Rule,
+    #[serde(default)]
+    pub rule: Vec<Rule>,
+}
+
+// One placement scheme. Its rules come after all others, profile by
+// profile in name order
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Profile {
+    // Expression as in [conditions]; while it holds, and no profile was
+    // picked by name, the profile's rules apply
+    pub when: Option<String>,
+    #[serde(default)]
     pub rule: Vec<Rule>,
 }
 
+impl Config {
+    // Directories `include` reads from, watched for hot reload along with
+    // the main config's own
+    pub fn include_dirs(&self, config_file: &Path) -> Vec<PathBuf> {
+        let base = config_file.parent().unwrap_or(Path::new("."));
+        let dir = |pattern: &String| base.join(exec::expand_home(pattern)).parent().map(Path::to_path_buf);
+        self.include.iter().filter_map(dir).collect()
+    }
+}
+
+// An included file: rules and the tables they refer to, but no settings
+// of its own
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Fragment {
+    #[serde(default)]
+    class_aliases: BTreeMap<String, String>,
+    #[serde(default)]
+    conditions: BTreeMap<String, String>,
+    #[serde(default)]
+    rule: Vec<Rule>,
+    #[serde(default)]
+    monitor_defaults: Vec<MonitorDefault>,
+}
+
+// JSON Schema of the config file, derived from the types above, for
+// editor validation and completion
+#[cfg(feature = "schema")]
+pub fn schema() -> String {
+    let schema = schemars::schema_for!(Config);
+    serde_json::to_string_pretty(&schema).expect("schema is plain JSON")
+}
+
 pub fn load(paths: &Paths) -> Result<Config, String> {
-    let content = fs::read_to_string(&paths.config_file).map_err(|e| {
-        format!("{}: {}", paths.config_file.display(), e)
-    })?;
+    let shared = {
+        let content = fs::read_to_string(&paths.config_file)
+            .map_err(|e| format!("{}: {}", paths.config_file.display(), e))?;
+        let value: toml::Value =
+            toml::from_str(&content).map_err(|e| format!("{}: {}", paths.config_file.display(), e))?;
+        let table = |key| value.get(key).and_then(toml::Value::as_table).cloned().unwrap_or_default();
+        let (mut vars, mut defaults) = (table("vars"), table("defaults"));
+        let fail = |e| format!("{}: {}", paths.config_file.display(), e);
+        if let Some(key) = defaults.keys().find(|key| PER_RULE_KEYS.contains(&key.as_str())) {
+            return Err(fail(format!("defaults: {} is set per rule, not in [defaults]", key)));
+        }
+        for (_, item) in vars.iter_mut() {
+            interpolate(item, &toml::Table::new(), Env::All).map_err(fail)?;
+        }
+        for (key, item) in defaults.iter_mut() {
+            interpolate(item, &vars, Env::All.under(key)).map_err(fail)?;
+        }
+        Shared { vars, defaults }
+    };
+    let mut config: Config = read(&paths.config_file, &shared)?;
+
+    // Included rules are numbered after the main config's, in include order
+    let base = paths.config_file.parent().unwrap_or(Path::new("."));
+    for pattern in config.include.clone() {
+        for path in expand_include(base, &pattern)? {
+            if path == paths.config_file {
+                continue;
+            }
+            let fragment: Fragment = read(&path, &shared)?;
+            config.class_aliases.extend(fragment.class_aliases);
+            config.conditions.extend(fragment.conditions);
+            config.rule.extend(fragment.rule);
+            config.monitor_defaults.extend(fragment.monitor_defaults);
+            config.included.push(path);
+        }
+    }
+
+    // Rules of profiles not in use stay in as disabled, so they are still
+    // checked and keep their indices
+    if let Some(ref picked) = paths.profile
+        && !config.profile.contains_key(picked)
+    {
+        let names: Vec<&str> = config.profile.keys().map(String::as_str).collect();
+        return Err(format!("unknown profile '{}' (defined: {})", picked, names.join(", ")));
+    }
+    for (name, profile) in config.profile.iter_mut() {
+        for mut rule in std::mem::take(&mut profile.rule) {
+            match paths.profile {
+                Some(ref picked) if picked == name => {}
+                None if profile.when.is_some() => rule.profile = Some(name.clone()),
+                _ => rule.enabled = Some(false),
+            }
+            config.rule.push(rule);
+        }
+    }
+    validate(config)
+}
+
+// What the main config lends every file it reads
+struct Shared {
+    vars: toml::Table,
+    // Keys every [[rule]] gets unless it sets them itself
+    defaults: toml::Table,
+}
+
+// Without vars, defaults, groups, or a `$` anywhere the text is parsed
+// straight into `T`, which keeps line numbers in errors
+fn read<T: serde::de::DeserializeOwned>(path: &Path, shared: &Shared) -> Result<T, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut value: toml::Value = toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let plain = shared.vars.is_empty() && shared.defaults.is_empty() && value.get("group").is_none();
+    if plain && !content.contains('$') {
+        return toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+    if let Some(table) = value.as_table_mut() {
+        for (_, item) in table.iter_mut().filter(|(key, _)| *key != "vars") {
+            interpolate(item, &shared.vars, Env::All).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        expand_groups(table).map_err(|e| format!("{}: {}", path.display(), e))?;
+        fill_defaults(table, &shared.defaults);
+        let profiles = table.get_mut("profile").and_then(toml::Value::as_table_mut).into_iter();
+        for (_, profile) in profiles.flat_map(|p| p.iter_mut()) {
+            if let Some(profile) = profile.as_table_mut() {
+                fill_defaults(profile, &shared.defaults);
+            }
+        }
+    }
+    value.try_into().map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Append the rules of each [[group]] to the [[rule]] list, with the keys
+// of the group they don't set themselves
+pub fn expand_groups(table: &mut toml::Table) -> Result<(), String> {
+    let Some(groups) = table.remove("group") else {
+        return Ok(());
+    };
+    let toml::Value::Array(groups) = groups else {
+        return Err("group: expected [[group]] entries".into());
+    };
+    let mut expanded = Vec::new();
+    for (i, group) in groups.into_iter().enumerate() {
+        let toml::Value::Table(mut keys) = group else {
+            return Err(format!("group[{}]: expected a table", i));
+        };
+        let rules = match keys.remove("rule") {
+            Some(toml::Value::Array(rules)) if !rules.is_empty() => rules,
+            _ => return Err(format!("group[{}]: no [[group.rule]] entries", i)),
+        };
+        for mut rule in rules {
+            if let Some(rule) = rule.as_table_mut() {
+                for (key, item) in &keys {
+                    rule.entry(key.clone()).or_insert_with(|| item.clone());
+                }
+            }
+            expanded.push(rule);
+        }
+    }
+    match table.entry("rule").or_insert_with(|| toml::Value::Array(Vec::new())) {
+        toml::Value::Array(rules) => rules.extend(expanded),
+        _ => return Err("rule: expected [[rule]] entries".into()),
+    }
+    Ok(())
+}
+
+// Keys that say which rule it is or how it is evaluated, not what it does
+const PER_RULE_KEYS: &[&str] = &["name", "priority", "enabled", "stop", "apply", "group"];
+
+// Give each [[rule]] of `table` the default keys it doesn't set itself.
+// A rule placed by a tile, cell, move_by or resize_by keeps that layout
+// rather than taking a default size or position on top
+fn fill_defaults(table: &mut toml::Table, defaults: &toml::Table) {
+    let rules = table.get_mut("rule").and_then(toml::Value::as_array_mut).into_iter().flatten();
+    for rule in rules.filter_map(toml::Value::as_table_mut) {
+        let position = rule.get("position").and_then(toml::Value::as_str);
+        let tiled = position.is_some_and(|name| rules::tile(name).is_some());
+        let laid_out = tiled || ["cell", "move_by", "resize_by"].iter().any(|key| rule.contains_key(*key));
+        let mut filled = Vec::new();
+        for (key, item) in defaults {
+            if rule.contains_key(key) || (laid_out && (key == "size" || key == "position")) {
+                continue;
+            }
+            rule.insert(key.clone(), item.clone());
+            filled.push(toml::Value::String(key.clone()));
+        }
+        if !filled.is_empty() {
+            rule.insert("[defaults]".into(), toml::Value::Array(filled));
+        }
+    }
+}
+
+// Shell command lines; the shell expands $NAME in them itself, when the
+// command runs and with the rule's environment
+const COMMAND_KEYS: &[&str] = &["exec", "exec_before", "exec_after"];
+
+// Which references the environment fills in
+#[derive(Clone, Copy, PartialEq)]
+enum Env {
+    Off,
+    // Only ${NAME}: in patterns a bare `$` is an anchor
+    Braced,
+    // ${NAME} and $NAME
+    All,
+}
+
+impl Env {
+    // For the value under `key`
+    fn under(self, key: &str) -> Self {
+        if COMMAND_KEYS.contains(&key) {
+            Env::Off
+        } else if MATCHER_KEYS.contains(&key) && self == Env::All {
+            Env::Braced
+        } else {
+            self
+        }
+    }
+}
+
+// Replace "${name}" with the [vars] entry. A string that is one reference
+// and nothing else takes the variable's type, so `workspace = "${mail}"`
+// can be a number; elsewhere the value is spliced into the text. Names
+// [vars] doesn't define come from the environment as text, as `env`
+// allows; there `$$` is a literal `$`. Anything else is left alone
+fn interpolate(value: &mut toml::Value, vars: &toml::Table, env: Env) -> Result<(), String> {
+    match value {
+        toml::Value::String(text) => {
+            let whole = text.strip_prefix("${").and_then(|rest| rest.strip_suffix('}'));
+            if let Some(var) = whole.and_then(|name| vars.get(name)) {
+                *value = var.clone();
+                return Ok(());
+            }
+            let mut out = String::new();
+            let mut rest = text.as_str();
+            while let Some(start) = rest.find('$') {
+                out.push_str(&rest[..start]);
+                if env != Env::Off && rest[start + 1..].starts_with('$') {
+                    out.push('$');
+                    rest = &rest[start + 2..];
+                    continue;
+                }
+                // ${name} or $name; `len` covers the `$` and any braces
+                let after = &rest[start + 1..];
+                let (name, len, braced) = match after.strip_prefix('{').and_then(|inner| inner.find('}')) {
+                    Some(end) => (&after[1..1 + end], end + 3, true),
+                    None => {
+                        let end = after.find(|c: char| !c.is_ascii_alphanumeric() && c != '_');
+                        let end = end.unwrap_or(after.len());
+                        (&after[..end], end + 1, false)
+                    }
+                };
+                let allowed = env == Env::All || (braced && env == Env::Braced);
+                let from_env = || std::env::var(name).ok().filter(|_| allowed && !name.is_empty());
+                match vars.get(name).filter(|_| braced) {
+                    Some(toml::Value::String(s)) => out.push_str(s),
+                    Some(toml::Value::Array(_) | toml::Value::Table(_)) => {
+                        return Err(format!("${{{}}} is a list and has to be the whole value", name));
+                    }
+                    Some(other) => out.push_str(&other.to_string()),
+                    None => match from_env() {
+                        Some(s) => out.push_str(&s),
+                        None => out.push_str(&rest[start..start + len]),
+                    },
+                }
+                rest = &rest[start + len..];
+            }
+            out.push_str(rest);
+            *text = out;
+        }
+        toml::Value::Array(items) => {
+            for item in items {
+                interpolate(item, vars, env)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (key, item) in table.iter_mut() {
+                interpolate(item, vars, env.under(key))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// Files one `include` entry names, sorted. A plain path has to exist; a
+// pattern may match nothing, and like the shell skips dotfiles unless it
+// starts with a dot itself
+fn expand_include(base: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let path = base.join(exec::expand_home(pattern));
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    if !name.contains(['*', '?', '[']) {
+        return Ok(vec![path]);
+    }
+    let dir = path.parent().unwrap_or(base);
+    if dir.to_string_lossy().contains(['*', '?', '[']) {
+        return Err(format!("include '{}': wildcards only work in the file name", pattern));
+    }
+    let re = regex::Regex::new(&rules::MatchMode::Glob.to_regex(name))
+        .map_err(|e| format!("include '{}': {}", pattern, e))?;
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("{}: {}", dir.display(), e)),
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| {
+            let file_name = file.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            (name.starts_with('.') || !file_name.starts_with('.')) && re.is_match(file_name) && file.is_file()
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
 
-    let config: Config = toml::from_str(&content).map_err(|e| {
-        format!("{}: {}", paths.config_file.display(), e)
-    })?;
+// A config from TOML text rather than a file, with the same checks
+pub fn parse(content: &str) -> Result<Config, String> {
+    let config: Config = toml::from_str(content).map_err(|e| e.to_string())?;
+    validate(config)
+}
+
+fn validate(config: Config) -> Result<Config, String> {
+    if let Some(ref mode) = config.settings.match_mode {
+        validate_match_mode(mode, "settings")?;
+    }
+    validate_command_env(&config.settings.env, &config.settings.shell, "settings")?;
+    if let Some(ref placement) = config.settings.placement {
+        validate_placement(placement, "settings")?;
+    }
+    for name in config.session_restore.suppress.iter().flatten() {
+        if !rules::ACTION_NAMES.contains(&name.as_str()) {
+            return Err(format!(
+                "session_restore: unknown action '{}' (expected one of: {})",
+                name,
+                rules::ACTION_NAMES.join(", ")
+            ));
+        }
+    }
+    if let Some(&i) = config
+        .startup
+        .rules
+        .iter()
+        .flatten()
+        .find(|&&i| i >= config.rule.len())
+    {
+        return Err(format!("startup: rules: no rule[{}]", i));
+    }
+    if let Some(ref grid) = config.grid
+        && (grid.cols == 0 || grid.rows == 0)
+    {
+        return Err("grid: cols and rows must be at least 1".into());
+    }
 
     for (i, rule) in config.rule.iter().enumerate() {
-        if rule.class.is_none()
-            && rule.title.is_none()
-            && rule.role.is_none()
-            && rule.process.is_none()
-            && rule.window_type.is_none()
+        if !rule.has_matcher() {
+            return Err(format!(
+                "rule[{}]: no matcher (need one of: {})",
+                i,
+                MATCHER_KEYS.join(", ")
+            ));
+        }
+        validate_rule(&config, i, rule).map_err(|e| blame_defaults(e, rule))?;
+        if let Some(ref name) = rule.name
+            && let Some(first) = config.rule[..i].iter().position(|r| r.name.as_ref() == Some(name))
         {
+            return Err(format!("rule[{}]: name '{}' is already used by rule[{}]", i, name, first));
+        }
+    }
+
+    if config.defaults.as_ref().is_some_and(Rule::has_matcher) {
+        return Err("defaults: takes no matchers; its keys go into every rule".into());
+    }
+
+    for (i, default) in config.monitor_defaults.iter().enumerate() {
+        if let MonitorValue::Name(ref name) = default.output
+            && name.is_empty()
+        {
+            return Err(format!("monitor_defaults[{}]: output is empty", i));
+        }
+        if default.rule.has_matcher() {
             return Err(format!(
-                "rule[{}]: no matcher (need class, title, role, process, or type)",
+                "monitor_defaults[{}]: takes no matchers; it applies to every window on its output",
                 i
             ));
         }
+        // Same checks as a rule, reported against the default
+        validate_rule(&config, i, &default.rule).map_err(|e| match e.strip_prefix(&format!("rule[{}]", i)) {
+            Some(rest) => format!("monitor_defaults[{}]{}", i, rest),
+            None => e,
+        })?;
+    }
+
+    Ok(config)
+}
 
-        if let Some(ref pos) = rule.position {
-            validate_position(pos, i)?;
+// Point at [defaults] when the error names a key the rule took from it
+fn blame_defaults(error: String, rule: &Rule) -> String {
+    let words: Vec<&str> = error.split(|c: char| !c.is_ascii_alphanumeric() && c != '_').collect();
+    let from: Vec<&str> =
+        rule.defaulted.iter().map(String::as_str).filter(|key| words.contains(key)).collect();
+    if from.is_empty() {
+        return error;
+    }
+    format!("{} ({} from [defaults])", error, from.join(", "))
+}
+
+fn validate_rule(config: &Config, i: usize, rule: &Rule) -> Result<(), String> {
+    let patterns = [
+        ("class", &rule.class),
+        ("title", &rule.title),
+        ("role", &rule.role),
+        ("process", &rule.process),
+        ("type", &rule.window_type),
+        ("class_not", &rule.class_not),
+        ("title_not", &rule.title_not),
+        ("role_not", &rule.role_not),
+        ("process_not", &rule.process_not),
+        ("type_not", &rule.window_type_not),
+        ("startup_id", &rule.startup_id),
+        ("launched_by", &rule.launched_by),
+        ("group_of", &rule.group_of),
+    ];
+    for (key, pat) in patterns {
+        if pat.as_ref().is_some_and(|p| p.patterns().is_empty()) {
+            return Err(format!("rule[{}]: {} is an empty list", i, key));
         }
-        if let Some(ref sz) = rule.size {
-            validate_size(sz, i)?;
+    }
+
+    if let Some(ref on) = rule.on
+        && !rules::TRIGGERS.contains(&on.as_str())
+    {
+        return Err(format!(
+            "rule[{}]: invalid trigger '{}' (expected one of: {})",
+            i,
+            on,
+            rules::TRIGGERS.join(", ")
+        ));
+    }
+
+    if let Some(ref apply) = rule.apply
+        && !rules::APPLY_MODES.contains(&apply.as_str())
+    {
+        return Err(format!(
+            "rule[{}]: invalid apply '{}' (expected one of: {})",
+            i,
+            apply,
+            rules::APPLY_MODES.join(", ")
+        ));
+    }
+
+    if let Some(ref group) = rule.group
+        && group != "move-with-main"
+    {
+        return Err(format!("rule[{}]: invalid group '{}' (expected move-with-main)", i, group));
+    }
+
+    if let Some(ref scope) = rule.scope
+        && !rules::SCOPES.contains(&scope.as_str())
+    {
+        return Err(format!(
+            "rule[{}]: invalid scope '{}' (expected one of: {})",
+            i,
+            scope,
+            rules::SCOPES.join(", ")
+        ));
+    }
+
+    if let Some(ref mode) = rule.match_mode {
+        validate_match_mode(mode, &format!("rule[{}]", i))?;
+    }
+
+    validate_command_env(&rule.env, &rule.shell, &format!("rule[{}]", i))?;
+    if let Some(ref placement) = rule.placement {
+        validate_placement(placement, &format!("rule[{}]", i))?;
+    }
+
+    if let Some(ref target) = rule.target
+        && !rules::GEOMETRY_TARGETS.contains(&target.as_str())
+    {
+        return Err(format!(
+            "rule[{}]: invalid target '{}' (expected one of: {})",
+            i,
+            target,
+            rules::GEOMETRY_TARGETS.join(", ")
+        ));
+    }
+
+    if rule.max_matches == Some(0) {
+        return Err(format!("rule[{}]: max_matches must be at least 1", i));
+    }
+    if rule.once_per_session == Some(true) && rule.max_matches.is_some() {
+        return Err(format!(
+            "rule[{}]: once_per_session and max_matches cannot be combined",
+            i
+        ));
+    }
+
+    validate_bounds(rule.min_width, rule.max_width, i, "width")?;
+    validate_bounds(rule.min_height, rule.max_height, i, "height")?;
+
+    for state in rule.state.iter().flatten() {
+        let name = state.strip_prefix('!').unwrap_or(state);
+        if !matchers::WINDOW_STATES.contains(&name) {
+            return Err(format!(
+                "rule[{}]: invalid state '{}' (expected one of: {})",
+                i,
+                state,
+                matchers::WINDOW_STATES.join(", ")
+            ));
         }
     }
 
-    Ok(config)
+    // Any string other than a balancing target names a desktop
+    let balanced = matches!(
+        &rule.workspace,
+        Some(WorkspaceValue::Named(name)) if rules::WORKSPACE_TARGETS.contains(&name.as_str())
+    );
+    match (&rule.workspace, &rule.workspace_among) {
+        (Some(WorkspaceValue::Named(name)), _) if name.is_empty() => {
+            return Err(format!("rule[{}]: workspace name is empty", i));
+        }
+        (_, Some(_)) if !balanced => {
+            return Err(format!(
+                "rule[{}]: workspace_among requires workspace = \"least-populated\"",
+                i
+            ));
+        }
+        (_, Some(among)) if among.is_empty() => {
+            return Err(format!("rule[{}]: workspace_among is an empty list", i));
+        }
+        _ => {}
+    }
+
+    match rule.when {
+        Some(WhenValue::Table(When { monitors: Some(CountValue::Compare(ref cmp)), .. })) => {
+            rules::Comparison::parse(cmp)
+                .map_err(|e| format!("rule[{}]: when.monitors: {}", i, e))?;
+        }
+        Some(WhenValue::Named(ref name)) if !config.conditions.contains_key(name) => {
+            return Err(format!("rule[{}]: unknown condition '{}'", i, name));
+        }
+        _ => {}
+    }
+
+    if rule.move_group == Some(true) && rule.workspace.is_none() && rule.monitor.is_none() {
+        return Err(format!("rule[{}]: move_group requires a workspace or monitor action", i));
+    }
+
+    if let Some(ref name) = rule.name
+        && (name.is_empty() || name.contains(char::is_whitespace))
+    {
+        return Err(format!("rule[{}]: invalid name '{}' (one word expected)", i, name));
+    }
+    if let Some(ref name) = rule.scratchpad {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("rule[{}]: invalid scratchpad name '{}' (one word expected)", i, name));
+        }
+        if rule.minimize.is_some() {
+            return Err(format!("rule[{}]: scratchpad hides the window itself; remove minimize", i));
+        }
+        if rule.on.as_deref().is_some_and(|on| on != "map") {
+            return Err(format!("rule[{}]: scratchpad only works with on = \"map\"", i));
+        }
+    }
+
+    if rule.follow.is_some() && rule.workspace.is_none() {
+        return Err(format!("rule[{}]: follow needs workspace", i));
+    }
+
+    if rule.remember == Some(true) && rule.on.as_deref().is_some_and(|on| on != "map") {
+        return Err(format!("rule[{}]: remember only works with on = \"map\"", i));
+    }
+
+    if rule.enforce == Some(true) {
+        if rule.size.is_none() && rule.position.is_none() && rule.cell.is_none() && rule.monitor.is_none() {
+            return Err(format!("rule[{}]: enforce needs monitor, size, position, or cell", i));
+        }
+        if rule.on.as_deref().is_some_and(|on| on != "map") {
+            return Err(format!("rule[{}]: enforce only works with on = \"map\"", i));
+        }
+    }
+
+    if rule.wait_for_title != Some(true) {
+        let timing = [("title_settle_ms", rule.title_settle_ms), ("title_timeout_ms", rule.title_timeout_ms)];
+        for (key, set) in timing {
+            if set.is_some() {
+                return Err(format!("rule[{}]: {} requires wait_for_title = true", i, key));
+            }
+        }
+    }
+
+    let hooks = [("exec", &rule.exec), ("exec_before", &rule.exec_before), ("exec_after", &rule.exec_after)];
+    for (key, line) in hooks {
+        if line.as_ref().is_some_and(|line| line.trim().is_empty()) {
+            return Err(format!("rule[{}]: {} is empty", i, key));
+        }
+    }
+
+    if rule.icon.as_ref().is_some_and(|path| path.trim().is_empty()) {
+        return Err(format!("rule[{}]: icon is empty", i));
+    }
+    if let Some(MaximizeValue::Axis(ref axis)) = rule.maximize
+        && axis != "both"
+        && rules::Axis::parse(axis).is_none()
+    {
+        return Err(format!(
+            "rule[{}]: invalid maximize '{}' (expected horizontal, vertical, or both)",
+            i, axis
+        ));
+    }
+    if let Some(ref strut) = rule.strut {
+        if rules::Edge::parse(&strut.edge).is_none() {
+            return Err(format!(
+                "rule[{}]: invalid strut edge '{}' (expected top, bottom, left, or right)",
+                i, strut.edge
+            ));
+        }
+        if strut.size == 0 {
+            return Err(format!("rule[{}]: strut size must be at least 1", i));
+        }
+    }
+    if let Some(ref border) = rule.border {
+        match border.color {
+            None if border.width.is_none() => return Err(format!("rule[{}]: border needs width or color", i)),
+            Some(ref color) if rules::parse_color(color).is_none() => {
+                return Err(format!("rule[{}]: invalid border color '{}' (expected #rrggbb)", i, color));
+            }
+            _ => {}
+        }
+    }
+
+    let prop_names = [
+        ("set_prop", rule.set_prop.as_ref().map(|p| p.name.as_str())),
+        ("del_prop", rule.del_prop.as_deref()),
+    ];
+    for (key, name) in prop_names {
+        if name.is_some_and(|n| n.trim().is_empty()) {
+            return Err(format!("rule[{}]: {} needs a property name", i, key));
+        }
+    }
+
+    if let Some(ref pos) = rule.position {
+        validate_position(pos, i)?;
+        if let PositionValue::Named(name) = pos
+            && rules::tile(name).is_some()
+            && rule.size.is_some()
+        {
+            return Err(format!("rule[{}]: position '{}' sets the size too; remove size", i, name));
+        }
+    }
+    for (key, sz) in [("size", &rule.size), ("min_size", &rule.min_size), ("max_size", &rule.max_size)] {
+        if let Some(sz) = sz {
+            validate_size(sz, i, key)?;
+        }
+    }
+    if let (Some(SizeValue::Absolute([min_w, min_h])), Some(SizeValue::Absolute([max_w, max_h]))) =
+        (&rule.min_size, &rule.max_size)
+        && (min_w > max_w || min_h > max_h)
+    {
+        return Err(format!("rule[{}]: min_size is larger than max_size", i));
+    }
+    if rule.size_hints == Some(true) && rule.min_size.is_none() && rule.max_size.is_none() {
+        return Err(format!("rule[{}]: size_hints requires min_size or max_size", i));
+    }
+    if let Some(ref ratio) = rule.aspect
+        && rules::parse_aspect(ratio).is_none()
+    {
+        return Err(format!("rule[{}]: invalid aspect '{}' (expected W:H, e.g. 16:9)", i, ratio));
+    }
+    if rule.move_by.is_some() && rule.position.is_some() {
+        return Err(format!("rule[{}]: move_by and position both set the position; use one", i));
+    }
+    let tiled = matches!(&rule.position, Some(PositionValue::Named(name)) if rules::tile(name).is_some());
+    if rule.resize_by.is_some() && (rule.size.is_some() || tiled) {
+        return Err(format!("rule[{}]: resize_by and size both set the size; use one", i));
+    }
+    if let Some([col, row, cols, rows]) = rule.cell {
+        let Some(ref grid) = config.grid else {
+            return Err(format!("rule[{}]: cell needs a [grid] table", i));
+        };
+        rules::Grid { cols: grid.cols, rows: grid.rows }
+            .span(col, row, cols, rows)
+            .map_err(|e| format!("rule[{}]: {}", i, e))?;
+        let conflicts = [
+            ("position", rule.position.is_some()),
+            ("size", rule.size.is_some()),
+            ("move_by", rule.move_by.is_some()),
+            ("resize_by", rule.resize_by.is_some()),
+        ];
+        if let Some((key, _)) = conflicts.iter().find(|(_, set)| *set) {
+            return Err(format!("rule[{}]: cell sets the position and size; remove {}", i, key));
+        }
+    }
+
+    Ok(())
 }
 
 const NAMED_POSITIONS: &[&str] = &[
@@ -140,11 +1317,34 @@ const NAMED_POSITIONS: &[&str] = &[
 fn validate_position(pos: &PositionValue, rule_idx: usize) -> Result<(), String> {
     match pos {
         PositionValue::Named(name) => {
-            if !NAMED_POSITIONS.contains(&name.as_str()) {
+            if let Some(Err(e)) = rules::parse_anchor_offset(name) {
+                return Err(format!("rule[{}]: invalid position '{}': {}", rule_idx, name, e));
+            }
+            if !NAMED_POSITIONS.contains(&name.as_str())
+                && !["parent-center", "smart", "pointer"].contains(&name.as_str())
+                && rules::tile(name).is_none()
+                && rules::parse_anchor_offset(name).is_none()
+            {
+                let expected: Vec<&str> = NAMED_POSITIONS
+                    .iter()
+                    .copied()
+                    .chain(["parent-center", "smart", "pointer"])
+                    .chain(rules::tile_names())
+                    .collect();
                 return Err(format!(
                     "rule[{}]: invalid position '{}' (expected one of: {})",
                     rule_idx,
                     name,
+                    expected.join(", ")
+                ));
+            }
+        }
+        PositionValue::Anchored { anchor, .. } => {
+            if !NAMED_POSITIONS.contains(&anchor.as_str()) && anchor != "pointer" {
+                return Err(format!(
+                    "rule[{}]: invalid position anchor '{}' (expected one of: {}, pointer)",
+                    rule_idx,
+                    anchor,
                     NAMED_POSITIONS.join(", ")
                 ));
             }
@@ -159,18 +1359,75 @@ fn validate_position(pos: &PositionValue, rule_idx: usize) -> Result<(), String>
     Ok(())
 }
 
-fn validate_size(sz: &SizeValue, rule_idx: usize) -> Result<(), String> {
+fn validate_size(sz: &SizeValue, rule_idx: usize, key: &str) -> Result<(), String> {
     match sz {
         SizeValue::Absolute(_) => {}
         SizeValue::Flexible(parts) => {
             for (j, part) in parts.iter().enumerate() {
-                validate_dimension_string(part, rule_idx, "size", j)?;
+                validate_dimension_string(part, rule_idx, key, j)?;
             }
         }
     }
     Ok(())
 }
 
+fn validate_match_mode(mode: &str, context: &str) -> Result<(), String> {
+    if !rules::MATCH_MODES.contains(&mode) {
+        return Err(format!(
+            "{}: invalid match_mode '{}' (expected one of: {})",
+            context,
+            mode,
+            rules::MATCH_MODES.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+fn validate_placement(placement: &str, context: &str) -> Result<(), String> {
+    if !rules::PLACEMENTS.contains(&placement) {
+        return Err(format!(
+            "{}: invalid placement '{}' (expected one of: {})",
+            context,
+            placement,
+            rules::PLACEMENTS.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+fn validate_command_env(
+    env: &Option<BTreeMap<String, String>>,
+    shell: &Option<String>,
+    context: &str,
+) -> Result<(), String> {
+    for name in env.iter().flat_map(|e| e.keys()) {
+        if name.is_empty() || name.contains(['=', '\0']) {
+            return Err(format!("{}: invalid env variable name '{}'", context, name));
+        }
+    }
+    if shell.as_ref().is_some_and(|s| s.is_empty()) {
+        return Err(format!("{}: shell is empty", context));
+    }
+    Ok(())
+}
+
+fn validate_bounds(
+    min: Option<u32>,
+    max: Option<u32>,
+    rule_idx: usize,
+    axis: &str,
+) -> Result<(), String> {
+    if let (Some(lo), Some(hi)) = (min, max)
+        && lo > hi
+    {
+        return Err(format!(
+            "rule[{}]: min_{} {} is greater than max_{} {}",
+            rule_idx, axis, lo, axis, hi
+        ));
+    }
+    Ok(())
+}
+
 fn validate_dimension_string(
     s: &str,
     rule_idx: usize,
@@ -182,6 +1439,10 @@ fn validate_dimension_string(
         pct.parse::<f64>().map_err(|_| {
             format!("rule[{}]: invalid {} {} percentage '{}'", rule_idx, field, axis_name, s)
         })?;
+    } else if let Some(mm) = s.strip_suffix("mm") {
+        mm.parse::<f64>().map_err(|_| {
+            format!("rule[{}]: invalid {} {} length '{}'", rule_idx, field, axis_name, s)
+        })?;
     } else {
         s.parse::<i64>().map_err(|_| {
             format!("rule[{}]: invalid {} {} value '{}'", rule_idx, field, axis_name, s)
@@ -1,8 +1,25 @@
-use std::path::Path;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::backend::WindowManager;
+use crate::backend::{WindowId, WindowManager};
 use crate::config;
-use crate::rules::{self, CompiledRule};
+use crate::dump;
+use crate::exec;
+use crate::exit::Exit;
+use crate::ipc::{self, Failure, Request, RuleRef, ScratchpadOp, WindowSpec};
+use crate::memory::{self, Memory};
+use crate::metrics::{self, Health};
+use crate::rules::{self, Action, CompiledRule, Trigger, WorkspaceTarget};
+use crate::stats::{self, Stats};
+
+// Pacing for `cherrypie reapply`: windows per batch, and the pause between
+// batches, so hundreds of windows don't turn into one request storm
+const REAPPLY_BATCH: usize = 32;
+const REAPPLY_INTERVAL: Duration = Duration::from_millis(25);
+
+// How often new usage statistics are written out, at most
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
 
 pub fn setup_signalfd() -> i32 {
     unsafe {
@@ -15,14 +32,33 @@ pub fn setup_signalfd() -> i32 {
     }
 }
 
-pub fn run(wm: WindowManager, config_path: &Path, dry_run: bool, signal_fd: i32) {
-    let compiled = match load_rules(config_path) {
-        Some(r) => r,
-        None => return,
+pub fn run(
+    wm: WindowManager,
+    paths: &config::Paths,
+    dry_run: bool,
+    metrics_file: Option<&Path>,
+    signal_fd: i32,
+) -> Result<(), (Exit, String)> {
+    let memory = load_memory(&wm);
+    let mut files = Files { health: HealthFile::new(metrics_file), stats: StatsFile::new(), memory };
+
+    let inotify_fd = setup_inotify(&paths.config_file);
+    let compiled = load_rules(paths, inotify_fd);
+    files.health.reloaded(compiled.as_ref().ok().map(|(rules, _)| rules.len()));
+    let (compiled, keep_stats) = match compiled {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            files.health.shutdown(true);
+            return Err((Exit::Config, e));
+        }
     };
+    files.stats.enable(keep_stats);
 
-    let inotify_fd = setup_inotify(config_path);
-    let x11_fd = wm.connection_fd();
+    report_warnings(&wm, &compiled);
+    report_support(&wm, &compiled);
+    if let Some(ref profile) = paths.profile {
+        eprintln!("[cherrypie] profile: {}", profile);
+    }
 
     eprintln!(
         "[cherrypie] daemon started (backend: {}, rules: {}, dry_run: {})",
@@ -31,7 +67,9 @@ pub fn run(wm: WindowManager, config_path: &Path, dry_run: bool, signal_fd: i32)
         dry_run,
     );
 
-    event_loop(wm, compiled, x11_fd, signal_fd, inotify_fd, config_path, dry_run);
+    let connected = event_loop(wm, compiled, signal_fd, inotify_fd, paths, dry_run, &mut files);
+    files.health.shutdown(connected);
+    files.stats.flush(true);
 
     // Cleanup
     if signal_fd >= 0 {
@@ -42,17 +80,23 @@ pub fn run(wm: WindowManager, config_path: &Path, dry_run: bool, signal_fd: i32)
     }
 
     eprintln!("[cherrypie] shutdown");
+    if !connected {
+        return Err((Exit::Backend, "lost the connection to the X server".into()));
+    }
+    Ok(())
 }
 
 fn event_loop(
     wm: WindowManager,
     mut rules: Vec<CompiledRule>,
-    x11_fd: i32,
     signal_fd: i32,
     inotify_fd: i32,
-    config_path: &Path,
+    paths: &config::Paths,
     dry_run: bool,
-) {
+    files: &mut Files,
+) -> bool {
+    let Files { health, stats, memory } = files;
+    let x11_fd = wm.connection_fd();
     let mut fds = Vec::with_capacity(3);
 
     // X11 connection fd
@@ -80,11 +124,51 @@ fn event_loop(
         });
     }
 
+    // Control socket for commands like grid-place
+    let ipc_path = ipc::socket_path();
+    let server = match ipc::Server::bind(&ipc_path) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            eprintln!("[cherrypie] control socket {}: {}", ipc_path.display(), e);
+            None
+        }
+    };
+    let ipc_idx = fds.len();
+    if let Some(server) = &server {
+        fds.push(libc::pollfd {
+            fd: server.fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        });
+    }
+
+    // Wakes the loop when a delay_ms rule is due
+    let timer_fd = setup_timerfd();
+    let timer_idx = fds.len();
+    if timer_fd >= 0 {
+        fds.push(libc::pollfd {
+            fd: timer_fd,
+            events: libc::POLLIN,
+            revents: 0,
+        });
+    }
+
+    let mut reapply = Reapply { windows: VecDeque::new(), rule: None, next_batch: Instant::now() };
+
     // Apply rules to windows that already existed at startup
-    wm.process_events(&rules, dry_run);
+    handle_events(&wm, &rules, dry_run, health, stats, memory.as_deref());
 
+    let mut connected = true;
     loop {
-        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+        if timer_fd >= 0 {
+            arm_timerfd(timer_fd, wm.next_delayed());
+        }
+        let timeout = if reapply.windows.is_empty() {
+            -1
+        } else {
+            reapply.next_batch.saturating_duration_since(Instant::now()).as_millis() as i32
+        };
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
         if ret < 0 {
             let errno = unsafe { *libc::__errno_location() };
             if errno == libc::EINTR {
@@ -94,6 +178,12 @@ fn event_loop(
             break;
         }
 
+        // The server is gone, and every window with it
+        if fds[0].revents & (libc::POLLHUP | libc::POLLERR) != 0 || !wm.connected() {
+            connected = false;
+            break;
+        }
+
         // Check signal fd (clean shutdown)
         if signal_fd >= 0 {
             let sig_idx = 1;
@@ -108,40 +198,371 @@ fn event_loop(
             let ino_idx = if signal_fd >= 0 { 2 } else { 1 };
             if ino_idx < fds.len() && fds[ino_idx].revents & libc::POLLIN != 0 {
                 drain_inotify(inotify_fd);
-                if let Some(new_rules) = load_rules(config_path) {
-                    eprintln!(
-                        "[cherrypie] config reloaded ({} rules)",
-                        new_rules.len()
-                    );
-                    rules = new_rules;
+                let loaded = load_rules(paths, inotify_fd);
+                health.reloaded(loaded.as_ref().ok().map(|(rules, _)| rules.len()));
+                match loaded {
+                    Ok((new_rules, keep_stats)) => {
+                        eprintln!(
+                            "[cherrypie] config reloaded ({} rules)",
+                            new_rules.len()
+                        );
+                        report_warnings(&wm, &new_rules);
+                        stats.enable(keep_stats);
+                        rules = new_rules;
+                    }
+                    Err(e) => eprintln!("[cherrypie] {}", e),
                 }
             }
         }
 
         // Check X11 fd (window events)
         if fds[0].revents & libc::POLLIN != 0 {
-            wm.process_events(&rules, dry_run);
+            handle_events(&wm, &rules, dry_run, health, stats, memory.as_deref());
         }
+
+        // Check control socket
+        if let Some(server) = &server
+            && fds[ipc_idx].revents & libc::POLLIN != 0
+        {
+            while let Some(mut conn) = server.accept() {
+                let result = conn
+                    .request()
+                    .map_err(Failure::from)
+                    .and_then(|req| handle_request(&wm, &rules, &mut reapply, &req, dry_run));
+                conn.reply(result);
+            }
+            // Requests that move or query windows round-trip like rules do
+            handle_events(&wm, &rules, dry_run, health, stats, memory.as_deref());
+        }
+
+        // delay_ms rules that are due
+        if timer_fd >= 0 && fds[timer_idx].revents & libc::POLLIN != 0 {
+            drain_timerfd(timer_fd);
+            wm.run_delayed(&rules, dry_run);
+            // Its round-trips may have queued events the X fd won't report
+            handle_events(&wm, &rules, dry_run, health, stats, memory.as_deref());
+        }
+
+        // Next batch of a reapply, once its pause is over
+        if !reapply.windows.is_empty() && Instant::now() >= reapply.next_batch {
+            let count = reapply.windows.len().min(REAPPLY_BATCH);
+            let batch: Vec<WindowId> = reapply.windows.drain(..count).collect();
+            wm.reapply(&batch, &rules, reapply.rule, dry_run);
+            reapply.next_batch = Instant::now() + REAPPLY_INTERVAL;
+            handle_events(&wm, &rules, dry_run, health, stats, memory.as_deref());
+        }
+    }
+
+    if timer_fd >= 0 {
+        unsafe { libc::close(timer_fd); }
     }
+    connected
 }
 
-fn load_rules(config_path: &Path) -> Option<Vec<CompiledRule>> {
-    let paths = config::Paths::with_config(config_path.to_path_buf());
-    match config::load(&paths) {
-        Ok(cfg) => match rules::compile(&cfg) {
-            Ok(compiled) => Some(compiled),
-            Err(e) => {
-                eprintln!("[cherrypie] rule compile error: {}", e);
-                None
-            }
-        },
+// Window events and what follows from them. Also called after anything
+// else that talks to the server: x11rb moves events that arrive during a
+// round-trip into its own queue, where poll() on the fd never sees them
+fn handle_events(
+    wm: &WindowManager,
+    rules: &[CompiledRule],
+    dry_run: bool,
+    health: &mut HealthFile,
+    stats: &mut StatsFile,
+    memory: Option<&Path>,
+) {
+    wm.process_events(rules, dry_run);
+    health.failed_at(wm.last_failure());
+    health.applied(wm.take_latencies());
+    send_alerts(wm);
+    stats.record(wm.take_fired());
+    save_memory(wm, memory);
+}
+
+// Files the daemon keeps in step with what it does
+struct Files {
+    health: HealthFile,
+    stats: StatsFile,
+    // Geometry of remember rules' windows; None if it couldn't be read
+    memory: Option<PathBuf>,
+}
+
+// A file that doesn't parse is left alone rather than overwritten
+fn load_memory(wm: &WindowManager) -> Option<PathBuf> {
+    let path = memory::default_path()?;
+    match Memory::load(&path) {
+        Ok(loaded) => {
+            wm.set_memory(loaded);
+            Some(path)
+        }
         Err(e) => {
-            eprintln!("[cherrypie] config error: {}", e);
+            eprintln!("[cherrypie] remember: {}", e);
             None
         }
     }
 }
 
+// Written as windows close, which is rare enough to not need batching
+fn save_memory(wm: &WindowManager, path: Option<&Path>) {
+    if let Some(path) = path
+        && let Some(memory) = wm.take_memory()
+        && let Err(e) = memory.save(path)
+    {
+        eprintln!("[cherrypie] remember: {}: {}", path.display(), e);
+    }
+}
+
+// Keeps the metrics textfile in step with the daemon; a no-op without
+// --metrics-file
+struct HealthFile {
+    path: Option<PathBuf>,
+    health: Health,
+}
+
+impl HealthFile {
+    fn new(path: Option<&Path>) -> Self {
+        Self {
+            path: path.map(Path::to_path_buf),
+            health: Health::new(metrics::unix_seconds(SystemTime::now())),
+        }
+    }
+
+    fn reloaded(&mut self, rules: Option<usize>) {
+        self.health.reloaded(metrics::unix_seconds(SystemTime::now()), rules);
+        self.write();
+    }
+
+    fn failed_at(&mut self, when: Option<SystemTime>) {
+        let secs = when.map(metrics::unix_seconds).unwrap_or(0);
+        if secs != self.health.last_error_time {
+            self.health.last_error_time = secs;
+            self.write();
+        }
+    }
+
+    fn applied(&mut self, latencies: Vec<(usize, Duration)>) {
+        if latencies.is_empty() {
+            return;
+        }
+        for (rule, took) in latencies {
+            self.health.applied(rule, took);
+        }
+        self.write();
+    }
+
+    // The last textfile tells a lost backend apart from a clean stop
+    fn shutdown(&mut self, connected: bool) {
+        self.health.up = false;
+        self.health.backend_connected = connected;
+        self.write();
+    }
+
+    fn write(&self) {
+        if let Some(path) = &self.path
+            && let Err(e) = self.health.write_textfile(path)
+        {
+            eprintln!("[cherrypie] metrics: {}: {}", path.display(), e);
+        }
+    }
+}
+
+// Usage statistics while the config asks for them (`stats = true`); new
+// counts are written at most once a minute, and on shutdown
+struct StatsFile {
+    // Set while enabled
+    path: Option<PathBuf>,
+    stats: Stats,
+    dirty: bool,
+    written: Instant,
+}
+
+impl StatsFile {
+    fn new() -> Self {
+        Self { path: None, stats: Stats::default(), dirty: false, written: Instant::now() }
+    }
+
+    fn enable(&mut self, on: bool) {
+        match (on, &self.path) {
+            (true, None) => {
+                let Some(path) = stats::default_path() else {
+                    eprintln!("[cherrypie] stats: HOME not set");
+                    return;
+                };
+                // A file that doesn't parse is left alone rather than overwritten
+                match Stats::load(&path) {
+                    Ok(loaded) => {
+                        self.stats = loaded;
+                        self.path = Some(path);
+                    }
+                    Err(e) => eprintln!("[cherrypie] stats: {}", e),
+                }
+            }
+            (false, Some(_)) => {
+                self.flush(true);
+                self.path = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn record(&mut self, fired: Vec<(usize, String)>) {
+        if self.path.is_none() || fired.is_empty() {
+            return;
+        }
+        let today = stats::today();
+        for (rule, class) in fired {
+            self.stats.record(&today, rule, &class);
+        }
+        self.dirty = true;
+        self.flush(false);
+    }
+
+    fn flush(&mut self, force: bool) {
+        if let Some(path) = &self.path
+            && self.dirty
+            && (force || self.written.elapsed() >= STATS_INTERVAL)
+        {
+            if let Err(e) = self.stats.save(path) {
+                eprintln!("[cherrypie] stats: {}: {}", path.display(), e);
+            }
+            self.dirty = false;
+            self.written = Instant::now();
+        }
+    }
+}
+
+// The compiled rules, and whether the config opts in to usage statistics.
+// Directories the config includes from join the reload watch
+fn load_rules(paths: &config::Paths, inotify_fd: i32) -> Result<(Vec<CompiledRule>, bool), String> {
+    let cfg = config::load(paths).map_err(|e| format!("config error: {}", e))?;
+    if inotify_fd >= 0 {
+        for dir in cfg.include_dirs(&paths.config_file) {
+            watch_dir(inotify_fd, &dir);
+        }
+    }
+    let keep_stats = cfg.settings.stats == Some(true);
+    let rules = rules::compile(&cfg).map_err(|e| format!("rule compile error: {}", e))?;
+    Ok((rules, keep_stats))
+}
+
+// Windows still waiting for a paced reapply, and the rule it is limited to
+struct Reapply {
+    windows: VecDeque<WindowId>,
+    rule: Option<usize>,
+    next_batch: Instant,
+}
+
+fn handle_request(
+    wm: &WindowManager,
+    rules: &[CompiledRule],
+    reapply: &mut Reapply,
+    request: &Request,
+    dry_run: bool,
+) -> Result<String, Failure> {
+    eprintln!("[cherrypie] control: {}", request);
+    match request {
+        Request::GridPlace { window, grid, cell } => {
+            let window = match window {
+                WindowSpec::Active => wm.active_window().ok_or("no active window")?,
+                WindowSpec::Id(id) => *id,
+            };
+            let (size, position) = grid.cell(*cell)?;
+            // Checked here so only rejected actions count as a partial failure
+            if !wm.windows_on(None).contains(&window) {
+                return Err(format!("no client window 0x{:x}", window).into());
+            }
+            wm.place(window, vec![Action::Size(size), Action::Position(position)], dry_run)
+                .map_err(Failure::Partial)?;
+            Ok(String::new())
+        }
+        Request::Reapply { rule, workspace } => {
+            let rule = match rule {
+                Some(wanted) => {
+                    let found = rules.iter().find(|r| match wanted {
+                        RuleRef::Index(index) => r.index == *index,
+                        RuleRef::Name(name) => r.name.as_ref() == Some(name),
+                    });
+                    match found {
+                        None => {
+                            return Err(match wanted {
+                                RuleRef::Index(index) => format!("no rule[{}]", index),
+                                RuleRef::Name(name) => format!("no rule named '{}'", name),
+                            }
+                            .into());
+                        }
+                        Some(r) if r.trigger != Trigger::Map => {
+                            return Err(format!("{} does not run when windows appear", r.label()).into());
+                        }
+                        Some(r) => Some(r.index),
+                    }
+                }
+                None => None,
+            };
+            for r in rules.iter().filter(|r| rule.is_none_or(|i| r.index == i)) {
+                r.reset_matches();
+            }
+
+            // Replaces any reapply still in progress
+            let windows = wm.windows_on(*workspace);
+            let count = windows.len();
+            *reapply = Reapply { windows: windows.into(), rule, next_batch: Instant::now() };
+            Ok(format!("reapplying to {} windows\n", count))
+        }
+        Request::DebugDump => Ok(format!("{}\n", dump::render(wm, rules))),
+        Request::Scratchpad { op, name } => {
+            let (window, index) =
+                wm.scratchpad(name).ok_or_else(|| format!("no window in scratchpad '{}'", name))?;
+            let show = match op {
+                ScratchpadOp::Show => true,
+                ScratchpadOp::Hide => false,
+                ScratchpadOp::Toggle => wm.out_of_sight(window),
+            };
+            let actions = if show {
+                // Back where the rule placed it, on the workspace in view
+                let placement = rules.iter().filter(|r| r.index == index).flat_map(|r| &r.actions);
+                let mut actions: Vec<Action> = placement
+                    .filter(|a| matches!(a, Action::Monitor(_) | Action::Size(_) | Action::Position(_)))
+                    .cloned()
+                    .collect();
+                if let Some(current) = wm.current_workspace() {
+                    actions.push(Action::Workspace(WorkspaceTarget::Index(current)));
+                }
+                actions.push(Action::Focus(true));
+                actions
+            } else {
+                vec![Action::Minimize(true)]
+            };
+            wm.place(window, actions, dry_run).map_err(Failure::Partial)?;
+            Ok(String::new())
+        }
+    }
+}
+
+fn send_alerts(wm: &WindowManager) {
+    for alert in wm.take_alerts() {
+        exec::notify(&alert.summary(), &alert.body());
+    }
+}
+
+// Once at startup, so users on minimal window managers learn which parts
+// of the config will do nothing
+fn report_support(wm: &WindowManager, rules: &[CompiledRule]) {
+    let report = wm.support_report(rules);
+    if report.is_empty() {
+        return;
+    }
+    let width = report.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    eprintln!("[cherrypie] window manager support for configured actions:");
+    for (name, support) in report {
+        eprintln!("[cherrypie]   {:width$}  {}", name, support, width = width);
+    }
+}
+
+fn report_warnings(wm: &WindowManager, rules: &[CompiledRule]) {
+    for warning in wm.rule_warnings(rules) {
+        eprintln!("[cherrypie] warning: {}", warning);
+    }
+}
+
 fn setup_inotify(config_path: &Path) -> i32 {
     let parent = match config_path.parent() {
         Some(p) => p,
@@ -169,6 +590,40 @@ fn setup_inotify(config_path: &Path) -> i32 {
     }
 }
 
+// Watching a directory twice is harmless, so every reload can add them
+fn watch_dir(fd: i32, dir: &Path) {
+    if let Ok(dir_str) = std::ffi::CString::new(dir.to_string_lossy().as_bytes()) {
+        unsafe {
+            libc::inotify_add_watch(fd, dir_str.as_ptr(), libc::IN_CLOSE_WRITE);
+        }
+    }
+}
+
+fn setup_timerfd() -> i32 {
+    unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC) }
+}
+
+// Fire once at `at`, or not at all
+fn arm_timerfd(fd: i32, at: Option<Instant>) {
+    let mut spec: libc::itimerspec = unsafe { std::mem::zeroed() };
+    if let Some(at) = at {
+        // A zero value would disarm the timer; due now means 1 ns
+        let wait = at.saturating_duration_since(Instant::now()).max(Duration::from_nanos(1));
+        spec.it_value.tv_sec = wait.as_secs() as libc::time_t;
+        spec.it_value.tv_nsec = wait.subsec_nanos() as libc::c_long;
+    }
+    unsafe {
+        libc::timerfd_settime(fd, 0, &spec, std::ptr::null_mut());
+    }
+}
+
+fn drain_timerfd(fd: i32) {
+    unsafe {
+        let mut expirations = 0u64;
+        libc::read(fd, &mut expirations as *mut u64 as *mut libc::c_void, 8);
+    }
+}
+
 fn drain_signalfd(fd: i32) {
     unsafe {
         let mut buf = [0u8; 128];
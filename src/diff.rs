@@ -0,0 +1,231 @@
+use std::fmt;
+use std::path::Path;
+
+use toml::Value;
+use toml::value::Table;
+
+use crate::config::{self, MATCHER_KEYS};
+use crate::rules;
+
+// Semantic differences between two configs, for reviewing changes to
+// window rules. Rules are paired by their matchers first, then by their
+// actions, so reordering or editing one side of a rule reads as a change
+// to that rule rather than a removal plus an addition.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added { index: usize, summary: String },
+    Removed { index: usize, summary: String },
+    // Same matchers, different actions or options
+    Changed { old: usize, new: usize, keys: Vec<KeyChange> },
+    // Same actions, different matchers
+    Rematched { old: usize, new: usize, keys: Vec<KeyChange> },
+    // Same rule at a different position
+    Moved { old: usize, new: usize },
+    // A top-level table other than the rules
+    Section { name: String, keys: Vec<KeyChange> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyChange {
+    pub key: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+// The config at `path` as raw TOML, once it has loaded and compiled like
+// the daemon would load it
+pub fn load(path: &Path) -> Result<Value, String> {
+    let cfg = config::load(&config::Paths::with_config(path.to_path_buf()))?;
+    rules::compile(&cfg).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut value: Value = toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if let Some(top) = value.as_table_mut() {
+        config::expand_groups(top).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+
+    // Included rules follow the main config's, as the daemon numbers them
+    for file in &cfg.included {
+        let content = std::fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+        let mut included: Table = toml::from_str(&content).map_err(|e| format!("{}: {}", file.display(), e))?;
+        config::expand_groups(&mut included).map_err(|e| format!("{}: {}", file.display(), e))?;
+        let Some(top) = value.as_table_mut() else { break };
+        for (key, item) in included {
+            match (top.get_mut(&key), item) {
+                (Some(Value::Array(list)), Value::Array(more)) => list.extend(more),
+                (Some(Value::Table(table)), Value::Table(more)) => table.extend(more),
+                (_, item) => {
+                    top.insert(key, item);
+                }
+            }
+        }
+    }
+    Ok(value)
+}
+
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    let empty = Table::new();
+    let old_top = old.as_table().unwrap_or(&empty);
+    let new_top = new.as_table().unwrap_or(&empty);
+
+    let mut sections: Vec<&String> = old_top.keys().chain(new_top.keys()).filter(|k| *k != "rule").collect();
+    sections.sort();
+    sections.dedup();
+    for name in sections {
+        let keys = diff_tables(
+            old_top.get(name).and_then(Value::as_table),
+            new_top.get(name).and_then(Value::as_table),
+            |_| true,
+        );
+        if !keys.is_empty() {
+            changes.push(Change::Section { name: name.clone(), keys });
+        }
+    }
+
+    let old_rules = rules(old_top);
+    let new_rules = rules(new_top);
+    let mut old_paired = vec![false; old_rules.len()];
+    let mut new_paired = vec![false; new_rules.len()];
+    let mut pairs = Vec::new();
+
+    // Matchers identify a rule; failing that, identical actions do
+    let is_matcher = |k: &str| MATCHER_KEYS.contains(&k);
+    let passes: [&dyn Fn(&str) -> bool; 2] = [&is_matcher, &|k| !is_matcher(k)];
+    for same in passes {
+        for (j, new_rule) in new_rules.iter().enumerate() {
+            if new_paired[j] {
+                continue;
+            }
+            let found = old_rules.iter().enumerate().position(|(i, old_rule)| {
+                !old_paired[i] && diff_tables(Some(old_rule), Some(new_rule), same).is_empty()
+            });
+            if let Some(i) = found {
+                old_paired[i] = true;
+                new_paired[j] = true;
+                pairs.push((i, j));
+            }
+        }
+    }
+    pairs.sort_by_key(|&(_, j)| j);
+    let in_order = longest_increasing(&pairs.iter().map(|&(i, _)| i).collect::<Vec<_>>());
+
+    for (i, old_rule) in old_rules.iter().enumerate().filter(|(i, _)| !old_paired[*i]) {
+        changes.push(Change::Removed { index: i, summary: summary(old_rule) });
+    }
+    for (k, (old_i, new_i)) in pairs.into_iter().enumerate() {
+        let (old_rule, new_rule) = (Some(old_rules[old_i]), Some(new_rules[new_i]));
+        let matchers = diff_tables(old_rule, new_rule, is_matcher);
+        let others = diff_tables(old_rule, new_rule, |k| !is_matcher(k));
+        let change = if !matchers.is_empty() {
+            Change::Rematched { old: old_i, new: new_i, keys: matchers }
+        } else if !others.is_empty() {
+            Change::Changed { old: old_i, new: new_i, keys: others }
+        } else if !in_order[k] {
+            Change::Moved { old: old_i, new: new_i }
+        } else {
+            continue;
+        };
+        changes.push(change);
+    }
+    for (j, new_rule) in new_rules.iter().enumerate().filter(|(j, _)| !new_paired[*j]) {
+        changes.push(Change::Added { index: j, summary: summary(new_rule) });
+    }
+
+    changes
+}
+
+// Marks the longest increasing run of old positions. Rules outside it
+// changed their order relative to the others; indices merely shifted by an
+// insertion or removal don't count as moves.
+fn longest_increasing(seq: &[usize]) -> Vec<bool> {
+    let n = seq.len();
+    let mut len = vec![1; n];
+    let mut prev = vec![None; n];
+    for i in 0..n {
+        for j in 0..i {
+            if seq[j] < seq[i] && len[j] + 1 > len[i] {
+                len[i] = len[j] + 1;
+                prev[i] = Some(j);
+            }
+        }
+    }
+
+    let mut keep = vec![false; n];
+    let mut at = (0..n).max_by_key(|&i| len[i]);
+    while let Some(i) = at {
+        keep[i] = true;
+        at = prev[i];
+    }
+    keep
+}
+
+fn rules(top: &Table) -> Vec<&Table> {
+    match top.get("rule").and_then(Value::as_array) {
+        Some(list) => list.iter().filter_map(Value::as_table).collect(),
+        None => Vec::new(),
+    }
+}
+
+// Keys accepted by `select` whose values differ, in key order. A missing
+// table counts as empty.
+fn diff_tables(old: Option<&Table>, new: Option<&Table>, select: impl Fn(&str) -> bool) -> Vec<KeyChange> {
+    let empty = Table::new();
+    let (old, new) = (old.unwrap_or(&empty), new.unwrap_or(&empty));
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).filter(|k| select(k)).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter(|k| old.get(*k) != new.get(*k))
+        .map(|k| KeyChange {
+            key: k.clone(),
+            old: old.get(k).map(Value::to_string),
+            new: new.get(k).map(Value::to_string),
+        })
+        .collect()
+}
+
+// The rule's matchers on one line, to recognize it by
+fn summary(rule: &Table) -> String {
+    let parts: Vec<String> = rule
+        .iter()
+        .filter(|(k, _)| MATCHER_KEYS.contains(&k.as_str()))
+        .map(|(k, v)| format!("{} = {}", k, v))
+        .collect();
+    parts.join(", ")
+}
+
+impl fmt::Display for KeyChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, "  ~ {}: {} -> {}", self.key, old, new),
+            (Some(old), None) => write!(f, "  - {} = {}", self.key, old),
+            (None, Some(new)) => write!(f, "  + {} = {}", self.key, new),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (header, keys) = match self {
+            Change::Added { index, summary } => return writeln!(f, "rule[{}] added ({})", index, summary),
+            Change::Removed { index, summary } => return writeln!(f, "rule[{}] removed ({})", index, summary),
+            Change::Moved { old, new } => return writeln!(f, "rule[{}] moved to rule[{}]", old, new),
+            Change::Changed { old, new, keys } if old == new => (format!("rule[{}] changed", new), keys),
+            Change::Changed { old, new, keys } => (format!("rule[{}] -> rule[{}] changed", old, new), keys),
+            Change::Rematched { old, new, keys } if old == new => (format!("rule[{}] matchers changed", new), keys),
+            Change::Rematched { old, new, keys } => {
+                (format!("rule[{}] -> rule[{}] matchers changed", old, new), keys)
+            }
+            Change::Section { name, keys } => (format!("[{}] changed", name), keys),
+        };
+        writeln!(f, "{}", header)?;
+        for key in keys {
+            writeln!(f, "{}", key)?;
+        }
+        Ok(())
+    }
+}
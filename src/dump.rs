@@ -0,0 +1,73 @@
+use crate::backend::{MonitorInfo, WindowManager, WindowState};
+use crate::json;
+use crate::rules::CompiledRule;
+
+// State of a running daemon as one JSON document, for attaching to bug
+// reports: versions, the compiled rules, the monitor layout, every client
+// window, and the recent event history. Window titles, process
+// environments and startup IDs are left out, since they may be private;
+// classes and process names are kept, as rules are keyed on them.
+
+const FEATURES: &[(&str, bool)] = &[
+    ("x11", cfg!(feature = "x11")),
+    ("schema", cfg!(feature = "schema")),
+    ("capture", cfg!(feature = "capture")),
+];
+
+pub fn render(wm: &WindowManager, rules: &[CompiledRule]) -> String {
+    let features = FEATURES.iter().filter(|(_, on)| *on).map(|(name, _)| json::string(name));
+    let windows = wm.windows_on(None).into_iter().map(|w| window(&wm.window_state(w)));
+    json::object([
+        ("version", json::string(env!("CARGO_PKG_VERSION"))),
+        ("features", json::array(features)),
+        ("backend", json::string(wm.backend_name())),
+        ("rules", json::array(rules.iter().map(rule))),
+        ("monitors", json::array(wm.monitors().iter().map(monitor))),
+        ("active", json::option(wm.active_window().map(|w| json::string(&hex(w))))),
+        ("windows", json::array(windows)),
+        ("history", json::array(wm.history().iter().map(|e| json::string(e)))),
+    ])
+}
+
+fn rule(rule: &CompiledRule) -> String {
+    json::object([
+        ("index", rule.index.to_string()),
+        ("name", json::option(rule.name.as_deref().map(json::string))),
+        ("priority", rule.priority.to_string()),
+        ("trigger", json::string(rule.trigger.name())),
+        ("matchers", rule.matchers.len().to_string()),
+        ("actions", json::array(rule.actions.iter().map(|a| json::string(&a.to_string())))),
+        ("stop", rule.stop.to_string()),
+        ("matches", rule.match_count().to_string()),
+        ("max_matches", json::option(rule.max_matches.map(|n| n.to_string()))),
+    ])
+}
+
+fn monitor(m: &MonitorInfo) -> String {
+    json::object([
+        ("name", json::string(&m.name)),
+        ("geometry", geometry((m.x, m.y, m.width, m.height))),
+        ("primary", m.primary.to_string()),
+    ])
+}
+
+fn window(w: &WindowState) -> String {
+    json::object([
+        ("id", json::string(&hex(w.id))),
+        ("class", json::string(&w.class)),
+        ("process", json::string(&w.process)),
+        ("type", json::string(&w.window_type)),
+        ("workspace", json::option(w.workspace.map(|ws| ws.to_string()))),
+        ("monitor", json::option(w.monitor.as_ref().map(|m| json::string(&m.name)))),
+        ("geometry", json::option(w.geometry.map(geometry))),
+        ("states", json::array(w.states.iter().map(|s| json::string(s)))),
+    ])
+}
+
+fn geometry((x, y, width, height): (i32, i32, u32, u32)) -> String {
+    format!("[{},{},{},{}]", x, y, width, height)
+}
+
+fn hex(id: u32) -> String {
+    format!("0x{:x}", id)
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::config::{Rule, Settings};
+
+const DEFAULT_SHELL: &str = "/bin/sh";
+
+// Context that commands launched on behalf of a rule run in: the global
+// [settings.env] table overlaid with the rule's own, a working directory,
+// and the shell that interprets the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandEnv {
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+    // Path to a shell, or "user" for the login shell in $SHELL
+    pub shell: String,
+}
+
+impl Default for CommandEnv {
+    fn default() -> Self {
+        Self {
+            env: BTreeMap::new(),
+            cwd: None,
+            shell: DEFAULT_SHELL.into(),
+        }
+    }
+}
+
+impl CommandEnv {
+    // Rule values win over settings; env tables merge key by key
+    pub fn resolve(rule: &Rule, settings: &Settings) -> Self {
+        let mut env = settings.env.clone().unwrap_or_default();
+        env.extend(rule.env.clone().unwrap_or_default());
+
+        let cwd = rule.cwd.as_deref().or(settings.cwd.as_deref()).map(expand_home);
+        let shell = rule
+            .shell
+            .as_deref()
+            .or(settings.shell.as_deref())
+            .unwrap_or(DEFAULT_SHELL)
+            .to_string();
+
+        Self { env, cwd, shell }
+    }
+
+    pub fn shell_path(&self) -> String {
+        if self.shell == "user" {
+            std::env::var("SHELL").unwrap_or_else(|_| DEFAULT_SHELL.into())
+        } else {
+            self.shell.clone()
+        }
+    }
+
+    // `<shell> -c <line>` with the environment and directory applied. The
+    // daemon's own environment is inherited underneath.
+    pub fn command(&self, line: &str) -> Command {
+        let mut cmd = Command::new(self.shell_path());
+        cmd.arg("-c").arg(line).envs(&self.env);
+        if let Some(ref dir) = self.cwd {
+            cmd.current_dir(dir);
+        }
+        cmd
+    }
+
+    // A rule's `exec` command, told about the window it matched through
+    // CHERRYPIE_WINDOW_ID (hex, as xprop and wmctrl take it),
+    // CHERRYPIE_CLASS, and CHERRYPIE_TITLE
+    pub fn hook(&self, line: &str, window: u32, class: &str, title: &str) -> Command {
+        let mut cmd = self.command(line);
+        cmd.env("CHERRYPIE_WINDOW_ID", format!("0x{:x}", window))
+            .env("CHERRYPIE_CLASS", class)
+            .env("CHERRYPIE_TITLE", title);
+        cmd
+    }
+}
+
+// Start `cmd` without waiting on it; it is reaped off the event loop
+pub fn spawn(mut cmd: Command) -> std::io::Result<()> {
+    let mut child = cmd.spawn()?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+// How long an exec_before hook may hold up the rest of the rule
+pub const BEFORE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Run `cmd` and wait up to `timeout` for it, so what comes next sees its
+// effects; a command still running then is left to finish on its own
+pub fn run(mut cmd: Command, timeout: Duration) -> Result<(), String> {
+    let mut child = cmd.spawn().map_err(|e| e.to_string())?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(status) if status.success() => return Ok(()),
+            Some(status) => return Err(status.to_string()),
+            None if Instant::now() >= deadline => {
+                std::thread::spawn(move || child.wait());
+                return Err(format!("still running after {} ms, not waiting", timeout.as_millis()));
+            }
+            None => std::thread::sleep(Duration::from_millis(10)),
+        }
+    }
+}
+
+// Desktop notification through notify-send, without waiting on it
+pub fn notify(summary: &str, body: &str) {
+    let mut cmd = Command::new("notify-send");
+    cmd.args(["--app-name=cherrypie", "--urgency=critical", summary, body]);
+    if let Err(e) = spawn(cmd) {
+        eprintln!("[cherrypie] notify-send: {}", e);
+    }
+}
+
+pub(crate) fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ if path == "~" => std::env::var("HOME").map(PathBuf::from).unwrap_or_else(|_| path.into()),
+        _ => PathBuf::from(path),
+    }
+}
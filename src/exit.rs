@@ -0,0 +1,67 @@
+use crate::json;
+
+// Exit statuses shared by every subcommand. The numbers are stable, so
+// scripts and service managers can tell failures apart without grepping
+// stderr; `--error-format json` reports the same kind on one line.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    // Anything not covered below; for `diff`, that the configs differ, for
+    // `lint`, that it found something
+    Failure = 1,
+    // Bad command line
+    Usage = 2,
+    // The config is missing, unreadable, or doesn't compile
+    Config = 3,
+    // No X display to connect to, or the daemon lost its connection
+    Backend = 4,
+    // No daemon answered on the control socket
+    NotRunning = 5,
+    // The daemon ran the request but some actions failed
+    Partial = 6,
+}
+
+impl Exit {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    pub fn kind(self) -> &'static str {
+        match self {
+            Exit::Failure => "failure",
+            Exit::Usage => "usage",
+            Exit::Config => "config",
+            Exit::Backend => "backend",
+            Exit::NotRunning => "not_running",
+            Exit::Partial => "partial",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    Text,
+    Json,
+}
+
+impl ErrorFormat {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            other => Err(format!("unknown error format '{}' (expected text or json)", other)),
+        }
+    }
+
+    // The line written to stderr before exiting with `exit`
+    pub fn message(self, exit: Exit, message: &str) -> String {
+        match self {
+            ErrorFormat::Text => format!("[cherrypie] {}", message),
+            ErrorFormat::Json => json::object([
+                ("error", json::string(exit.kind())),
+                ("code", exit.code().to_string()),
+                ("message", json::string(message)),
+            ]),
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::path::Path;
+
+// PNG decoding for the `icon` action, which hands the image to the window
+// manager as _NET_WM_ICON. Like capture, this gets by without an image or
+// compression crate, so it reads what icon files use: 8-bit grayscale,
+// RGB, and palette images, with or without alpha, not interlaced.
+
+// Icons larger than this are refused; window managers scale them down
+// anyway, and the property has to fit in one request
+pub const MAX_SIDE: u32 = 512;
+
+// _NET_WM_ICON data for the PNG at `path`
+pub fn load(path: &Path) -> Result<Vec<u32>, String> {
+    let png = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    decode_png(&png).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Width, height, then one ARGB value per pixel, row by row
+pub fn decode_png(png: &[u8]) -> Result<Vec<u32>, String> {
+    let mut rest = png.strip_prefix(b"\x89PNG\r\n\x1a\n").ok_or("not a PNG file")?;
+    let (mut header, mut palette, mut alphas, mut zlib) = (None, &[][..], &[][..], Vec::new());
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let data = rest.get(8..8 + len).ok_or("truncated chunk")?;
+        match &rest[4..8] {
+            b"IHDR" => header = Some(data),
+            b"PLTE" => palette = data,
+            b"tRNS" => alphas = data,
+            b"IDAT" => zlib.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        rest = rest.get(12 + len..).ok_or("truncated chunk")?;
+    }
+
+    let h = header.filter(|h| h.len() == 13).ok_or("missing IHDR chunk")?;
+    let width = u32::from_be_bytes([h[0], h[1], h[2], h[3]]);
+    let height = u32::from_be_bytes([h[4], h[5], h[6], h[7]]);
+    let (depth, color, interlace) = (h[8], h[9], h[12]);
+    if depth != 8 || interlace != 0 {
+        return Err(format!(
+            "unsupported PNG (bit depth {}, interlace {}); save it as 8-bit, not interlaced",
+            depth, interlace
+        ));
+    }
+    let channels = match color {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        other => return Err(format!("unsupported PNG color type {}", other)),
+    };
+    if width == 0 || height == 0 || width > MAX_SIDE || height > MAX_SIDE {
+        return Err(format!("{}x{} is not an icon size (at most {} pixels a side)", width, height, MAX_SIDE));
+    }
+
+    // Two bytes of zlib header; the Adler-32 at the end goes unchecked
+    let raw = inflate(zlib.get(2..).ok_or("no image data")?)?;
+    let pixels = unfilter(&raw, width as usize * channels, height as usize, channels)?;
+
+    let mut icon = Vec::with_capacity(2 + (width * height) as usize);
+    icon.extend([width, height]);
+    for px in pixels.chunks_exact(channels) {
+        let [r, g, b, a] = match color {
+            0 => [px[0], px[0], px[0], 255],
+            4 => [px[0], px[0], px[0], px[1]],
+            2 => [px[0], px[1], px[2], 255],
+            3 => {
+                let i = px[0] as usize;
+                let rgb = palette.get(i * 3..i * 3 + 3).ok_or("palette index out of range")?;
+                [rgb[0], rgb[1], rgb[2], alphas.get(i).copied().unwrap_or(255)]
+            }
+            _ => [px[0], px[1], px[2], px[3]],
+        };
+        icon.push(u32::from_be_bytes([a, r, g, b]));
+    }
+    Ok(icon)
+}
+
+// Undo the per-scanline filters; `bpp` is bytes per pixel
+fn unfilter(raw: &[u8], stride: usize, height: usize, bpp: usize) -> Result<Vec<u8>, String> {
+    if raw.len() < (stride + 1) * height {
+        return Err("image data is truncated".into());
+    }
+    let mut out = vec![0u8; stride * height];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        for x in 0..stride {
+            let left = if x >= bpp { out[y * stride + x - bpp] } else { 0 };
+            let up = if y > 0 { out[(y - 1) * stride + x] } else { 0 };
+            let up_left = if x >= bpp && y > 0 { out[(y - 1) * stride + x - bpp] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                other => return Err(format!("invalid filter type {}", other)),
+            };
+            out[y * stride + x] = line[x].wrapping_add(predicted);
+        }
+    }
+    Ok(out)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// DEFLATE (RFC 1951)
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195,
+    227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073,
+    4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// Order the code length code lengths are stored in
+const CODE_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Reads bits least significant first
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Bits<'_> {
+    fn bits(&mut self, n: u8) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..n {
+            let byte = self.data.get(self.pos / 8).ok_or("image data is truncated")?;
+            value |= (((byte >> (self.pos % 8)) & 1) as u32) << i;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+}
+
+// Canonical Huffman code: how many codes have each length, and the
+// symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate().filter(|(_, len)| **len != 0) {
+            symbols[offsets[len as usize] as usize] = symbol as u16;
+            offsets[len as usize] += 1;
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".into())
+    }
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut bits = Bits { data, pos: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                let at = bits.pos.div_ceil(8);
+                let header = data.get(at..at + 4).ok_or("image data is truncated")?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                out.extend_from_slice(data.get(at + 4..at + 4 + len).ok_or("image data is truncated")?);
+                bits.pos = (at + 4 + len) * 8;
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                codes(&mut bits, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (literals, distances) = dynamic(&mut bits)?;
+                codes(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => return Err("invalid deflate block".into()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+// The literal/length and distance codes of a dynamic block
+fn dynamic(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let stored = bits.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &i in &CODE_ORDER[..stored] {
+        code_lengths[i] = bits.bits(3)? as u8;
+    }
+    let code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (len, repeat) = match code.decode(bits)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => (*lengths.last().ok_or("repeat of no code length")?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths.len() != literals + distances {
+        return Err("code lengths overrun".into());
+    }
+    Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+// Symbols of one compressed block, up to its end-of-block code
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let i = symbol - 257;
+        let base = *LENGTH_BASE.get(i).ok_or("invalid length code")? as usize;
+        let len = base + bits.bits(LENGTH_EXTRA[i])? as usize;
+        let d = distances.decode(bits)? as usize;
+        let base = *DIST_BASE.get(d).ok_or("invalid distance code")? as usize;
+        let distance = base + bits.bits(DIST_EXTRA[d])? as usize;
+        let start = out.len().checked_sub(distance).ok_or("distance reaches before the start")?;
+        for k in 0..len {
+            out.push(out[start + k]);
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::backend::WindowId;
+use crate::rules::Grid;
+
+// Control socket of a running daemon. A client connects, writes one
+// request line, and reads until the daemon closes the connection. The
+// reply starts with "ok" (further lines are output), "error: <message>"
+// for a refused request, or "partial: <message>" when some actions failed.
+
+// How long a client gets to send its request line
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub fn socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("cherrypie.sock"),
+        None => std::env::temp_dir().join(format!("cherrypie-{}.sock", unsafe { libc::getuid() })),
+    }
+}
+
+// Why a request didn't fully succeed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    // The daemon refused the request
+    Error(String),
+    // The daemon ran it, but some actions failed
+    Partial(String),
+    // Nothing answered on the socket
+    NotRunning(String),
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure::Error(message)
+    }
+}
+
+impl From<&str> for Failure {
+    fn from(message: &str) -> Self {
+        Failure::Error(message.to_string())
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Error(e) | Failure::Partial(e) | Failure::NotRunning(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowSpec {
+    // The window that last gained focus
+    Active,
+    Id(WindowId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    // grid-place <window> <cols>x<rows> <cell>
+    GridPlace { window: WindowSpec, grid: Grid, cell: u32 },
+    // reapply [--rule <index|name>] [--workspace <n>]
+    Reapply { rule: Option<RuleRef>, workspace: Option<u32> },
+    // The daemon's state as JSON, for bug reports
+    DebugDump,
+    // scratchpad toggle|show|hide <name>
+    Scratchpad { op: ScratchpadOp, name: String },
+}
+
+// A rule by its position in the config, or by its `name`
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleRef {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScratchpadOp {
+    Toggle,
+    Show,
+    Hide,
+}
+
+impl Request {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["grid-place", window, grid, cell] => Ok(Request::GridPlace {
+                window: parse_window(window)?,
+                grid: Grid::parse(grid)?,
+                cell: cell.parse().map_err(|_| format!("invalid cell '{}'", cell))?,
+            }),
+            ["grid-place", ..] => Err("usage: grid-place <window> <cols>x<rows> <cell>".into()),
+            ["reapply", flags @ ..] => parse_reapply(flags),
+            ["debug-dump"] => Ok(Request::DebugDump),
+            ["scratchpad", op, name] => {
+                let op = match *op {
+                    "toggle" => ScratchpadOp::Toggle,
+                    "show" => ScratchpadOp::Show,
+                    "hide" => ScratchpadOp::Hide,
+                    _ => {
+                        let expected = "expected toggle, show, or hide";
+                        return Err(format!("invalid scratchpad command '{}' ({})", op, expected));
+                    }
+                };
+                Ok(Request::Scratchpad { op, name: name.to_string() })
+            }
+            ["scratchpad", ..] => Err("usage: scratchpad toggle|show|hide <name>".into()),
+            [other, ..] => Err(format!("unknown command '{}'", other)),
+            [] => Err("empty request".into()),
+        }
+    }
+}
+
+fn parse_reapply(flags: &[&str]) -> Result<Request, String> {
+    let (mut rule, mut workspace) = (None, None);
+    let mut rest = flags.iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next().ok_or_else(|| format!("{} requires a value", flag))?;
+        let invalid = || format!("invalid {} '{}'", flag, value);
+        match *flag {
+            // Anything that isn't a number is a name
+            "--rule" => {
+                rule = Some(value.parse().map_or_else(|_| RuleRef::Name(value.to_string()), RuleRef::Index))
+            }
+            "--workspace" => workspace = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err("usage: reapply [--rule <index|name>] [--workspace <n>]".into()),
+        }
+    }
+    Ok(Request::Reapply { rule, workspace })
+}
+
+// "active", or a window id in hex (0x...) or decimal
+fn parse_window(s: &str) -> Result<WindowSpec, String> {
+    if s == "active" {
+        return Ok(WindowSpec::Active);
+    }
+    let id = match s.strip_prefix("0x") {
+        Some(hex) => WindowId::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    id.map(WindowSpec::Id).map_err(|_| format!("invalid window '{}'", s))
+}
+
+impl fmt::Display for WindowSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowSpec::Active => write!(f, "active"),
+            WindowSpec::Id(id) => write!(f, "0x{:x}", id),
+        }
+    }
+}
+
+impl fmt::Display for RuleRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleRef::Index(index) => write!(f, "{}", index),
+            RuleRef::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Request::GridPlace { window, grid, cell } => {
+                write!(f, "grid-place {} {}x{} {}", window, grid.cols, grid.rows, cell)
+            }
+            Request::Reapply { rule, workspace } => {
+                write!(f, "reapply")?;
+                if let Some(rule) = rule {
+                    write!(f, " --rule {}", rule)?;
+                }
+                if let Some(ws) = workspace {
+                    write!(f, " --workspace {}", ws)?;
+                }
+                Ok(())
+            }
+            Request::DebugDump => write!(f, "debug-dump"),
+            Request::Scratchpad { op, name } => {
+                let op = match op {
+                    ScratchpadOp::Toggle => "toggle",
+                    ScratchpadOp::Show => "show",
+                    ScratchpadOp::Hide => "hide",
+                };
+                write!(f, "scratchpad {} {}", op, name)
+            }
+        }
+    }
+}
+
+// Listening end, owned by the daemon. The socket file is removed on drop.
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl Server {
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        // A socket nobody answers on is left over from a daemon that died
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "another daemon is listening"));
+        }
+        let _ = std::fs::remove_file(path);
+
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn fd(&self) -> i32 {
+        self.listener.as_raw_fd()
+    }
+
+    // Next waiting client, if any
+    pub fn accept(&self) -> Option<Connection> {
+        let (stream, _) = self.listener.accept().ok()?;
+        stream.set_read_timeout(Some(READ_TIMEOUT)).ok()?;
+        Some(Connection { stream })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+pub struct Connection {
+    stream: UnixStream,
+}
+
+impl Connection {
+    pub fn request(&mut self) -> Result<Request, String> {
+        let mut line = String::new();
+        BufReader::new(&self.stream)
+            .read_line(&mut line)
+            .map_err(|e| format!("read: {}", e))?;
+        Request::parse(&line)
+    }
+
+    pub fn reply(mut self, result: Result<String, Failure>) {
+        let text = match result {
+            Ok(output) if output.is_empty() => "ok\n".to_string(),
+            Ok(output) => format!("ok\n{}", output),
+            Err(Failure::Partial(e)) => format!("partial: {}\n", e),
+            Err(e) => format!("error: {}\n", e),
+        };
+        // The client may have given up already
+        let _ = self.stream.write_all(text.as_bytes());
+    }
+}
+
+// Send a request to the daemon listening at `path` and return its output
+pub fn send(path: &Path, request: &Request) -> Result<String, Failure> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|e| Failure::NotRunning(format!("{}: {} (is the daemon running?)", path.display(), e)))?;
+    stream
+        .write_all(format!("{}\n", request).as_bytes())
+        .map_err(|e| format!("send: {}", e))?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).map_err(|e| format!("receive: {}", e))?;
+    if let Some(("ok", output)) = reply.split_once('\n') {
+        return Ok(output.to_string());
+    }
+    let reply = reply.trim_end();
+    match (reply.strip_prefix("error: "), reply.strip_prefix("partial: ")) {
+        (Some(e), _) => Err(Failure::Error(e.to_string())),
+        (_, Some(e)) => Err(Failure::Partial(e.to_string())),
+        _ => Err(Failure::Error(format!("unexpected reply '{}'", reply))),
+    }
+}
@@ -0,0 +1,39 @@
+use std::fmt::Write;
+
+// Just enough JSON output for error reports and debug dumps, so the
+// format doesn't depend on the optional serde_json.
+
+// `s` as a quoted JSON string
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// A JSON object of already-encoded values, in the given order
+pub fn object<'a>(fields: impl IntoIterator<Item = (&'a str, String)>) -> String {
+    let fields: Vec<String> = fields.into_iter().map(|(k, v)| format!("{}:{}", string(k), v)).collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+// A JSON array of already-encoded values
+pub fn array(values: impl IntoIterator<Item = String>) -> String {
+    format!("[{}]", values.into_iter().collect::<Vec<_>>().join(","))
+}
+
+pub fn option(value: Option<String>) -> String {
+    value.unwrap_or_else(|| "null".into())
+}
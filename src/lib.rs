@@ -1,4 +1,23 @@
 pub mod backend;
+pub mod bench;
+pub mod capture;
+pub mod condition;
 pub mod config;
 pub mod daemon;
+pub mod diff;
+pub mod dump;
+pub mod exec;
+pub mod exit;
+pub mod icon;
+pub mod ipc;
+pub mod json;
+pub mod lint;
+pub mod matchers;
+pub mod memory;
+pub mod metrics;
+pub mod process;
 pub mod rules;
+pub mod session;
+pub mod share;
+pub mod snapshot;
+pub mod stats;
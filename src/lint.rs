@@ -0,0 +1,234 @@
+use std::fmt::Debug;
+
+use regex::RegexBuilder;
+
+use crate::config::{Config, MonitorValue, PatternValue, Rule};
+use crate::rules::{Action, CompiledRule, MatchMode, MonitorTarget, WorkspaceTarget};
+
+// Checks for `cherrypie lint`: mistakes that compile but make rules do
+// less than they appear to. Matchers are compared as written, so two
+// patterns that happen to match the same windows count as different;
+// what is reported is certain, not everything that could be.
+
+// What the display has, for checking references to it
+pub struct Display {
+    // Output names, in index order
+    pub monitors: Vec<String>,
+    // One name per workspace, empty where unnamed
+    pub workspaces: Option<Vec<String>>,
+}
+
+// Actions that add up rather than override, so two rules setting them
+// differently do not conflict
+const CUMULATIVE: &[&str] =
+    &["exec", "exec_before", "exec_after", "set_prop", "del_prop", "capture", "move_by", "resize_by"];
+
+// A pattern matching all of these is taken to match anything
+const SAMPLES: &[&str] = &["", "x", "Firefox", "Inbox - 3 unread"];
+
+// Warnings, one line each, in rule order per check
+pub fn lint(config: &Config, compiled: &[CompiledRule], display: Option<&Display>) -> Vec<String> {
+    // In evaluation order, with what decides the windows each one matches
+    let rules: Vec<(&CompiledRule, &Rule, Scope)> = compiled
+        .iter()
+        .filter(|r| !r.fallback)
+        .map(|r| (r, &config.rule[r.index], scope(config, &config.rule[r.index], r)))
+        .collect();
+    let mut warnings = Vec::new();
+
+    for (rule, raw, _) in &rules {
+        warnings.extend(match_anything(config, rule, raw));
+    }
+
+    let mut shadowed = vec![false; rules.len()];
+    for (j, (later, _, later_scope)) in rules.iter().enumerate() {
+        let blocker = rules[..j].iter().find(|(earlier, _, earlier_scope)| {
+            earlier.stop && earlier.max_matches.is_none() && covers(earlier_scope, later_scope)
+        });
+        if let Some((earlier, _, _)) = blocker {
+            shadowed[j] = true;
+            warnings.push(format!(
+                "{}: never reached; {} matches all of its windows first and has stop = true",
+                later.label(),
+                earlier.label()
+            ));
+        }
+    }
+
+    for (j, (later, _, later_scope)) in rules.iter().enumerate().filter(|(j, _)| !shadowed[*j]) {
+        for (earlier, _, earlier_scope) in &rules[..j] {
+            if !(covers(earlier_scope, later_scope) && covers(later_scope, earlier_scope)) {
+                continue;
+            }
+            for first in earlier.actions.iter().filter(|a| !CUMULATIVE.contains(&a.name())) {
+                let conflict = later.actions.iter().find(|a| a.name() == first.name() && *a != first);
+                if let Some(second) = conflict {
+                    warnings.push(format!(
+                        "{} and {} match the same windows with conflicting actions: {}, then {}",
+                        earlier.label(),
+                        later.label(),
+                        first,
+                        second
+                    ));
+                }
+            }
+        }
+    }
+
+    if let Some(display) = display {
+        for rule in compiled {
+            // Monitor defaults are numbered after the rules
+            let (label, on_monitor, on_workspace) = if rule.fallback {
+                let i = rule.index - config.rule.len();
+                (format!("monitor_defaults[{}]", i), Some(&config.monitor_defaults[i].output), None)
+            } else {
+                let raw = &config.rule[rule.index];
+                (rule.label(), raw.on_monitor.as_ref(), raw.on_workspace)
+            };
+            warnings.extend(missing(&label, display, rule, on_monitor, on_workspace));
+        }
+    }
+    warnings
+}
+
+type Scope = Vec<(&'static str, String)>;
+
+// Every matcher the rule sets, plus what changes how they read or when
+// they are checked, as comparable text
+fn scope(config: &Config, rule: &Rule, compiled: &CompiledRule) -> Scope {
+    let mode = rule.match_mode.as_ref().or(config.settings.match_mode.as_ref());
+    let icase = rule.case_insensitive.or(config.settings.case_insensitive).unwrap_or(false);
+    let keys = [
+        ("class", text(&rule.class)),
+        ("title", text(&rule.title)),
+        ("role", text(&rule.role)),
+        ("process", text(&rule.process)),
+        ("type", text(&rule.window_type)),
+        ("class_not", text(&rule.class_not)),
+        ("title_not", text(&rule.title_not)),
+        ("role_not", text(&rule.role_not)),
+        ("process_not", text(&rule.process_not)),
+        ("type_not", text(&rule.window_type_not)),
+        ("min_width", text(&rule.min_width)),
+        ("max_width", text(&rule.max_width)),
+        ("min_height", text(&rule.min_height)),
+        ("max_height", text(&rule.max_height)),
+        ("state", text(&rule.state)),
+        ("on_workspace", text(&rule.on_workspace)),
+        ("on_monitor", text(&rule.on_monitor)),
+        ("process_env", text(&rule.process_env)),
+        ("uid", text(&rule.uid)),
+        ("user", text(&rule.user)),
+        ("startup_id", text(&rule.startup_id)),
+        ("launched_by", text(&rule.launched_by)),
+        ("group_of", text(&rule.group_of)),
+        ("when", text(&rule.when)),
+        ("if_compositor", text(&rule.if_compositor)),
+        ("profile", text(&rule.profile)),
+        ("on", Some(format!("{:?}", compiled.trigger))),
+        ("match_mode", Some(format!("{:?}", mode))),
+        ("case_insensitive", Some(icase.to_string())),
+    ];
+    keys.into_iter().filter_map(|(key, value)| Some((key, value?))).collect()
+}
+
+fn text<T: Debug>(value: &Option<T>) -> Option<String> {
+    value.as_ref().map(|v| format!("{:?}", v))
+}
+
+// Whether every window `narrow` matches is one `wide` matches too
+fn covers(wide: &Scope, narrow: &Scope) -> bool {
+    wide.iter().all(|key| narrow.contains(key))
+}
+
+// Patterns that match any window, and `_not` patterns that rule out all
+fn match_anything(config: &Config, rule: &CompiledRule, raw: &Rule) -> Vec<String> {
+    let name = raw.match_mode.as_deref().or(config.settings.match_mode.as_deref());
+    let mode = name.and_then(|n| MatchMode::parse(n).ok()).unwrap_or(MatchMode::Regex);
+    let icase = raw.case_insensitive.or(config.settings.case_insensitive).unwrap_or(false);
+    let fields: [(&str, &Option<PatternValue>); 8] = [
+        ("class", &raw.class),
+        ("title", &raw.title),
+        ("role", &raw.role),
+        ("process", &raw.process),
+        ("class_not", &raw.class_not),
+        ("title_not", &raw.title_not),
+        ("role_not", &raw.role_not),
+        ("process_not", &raw.process_not),
+    ];
+
+    let mut warnings = Vec::new();
+    for (key, value) in fields {
+        for pattern in value.iter().flat_map(PatternValue::patterns) {
+            let re = RegexBuilder::new(&mode.to_regex(pattern)).case_insensitive(icase).build();
+            if !re.is_ok_and(|re| SAMPLES.iter().all(|s| re.is_match(s))) {
+                continue;
+            }
+            let effect = if key.ends_with("_not") {
+                "excludes every window, so the rule never matches"
+            } else {
+                "matches every window"
+            };
+            warnings.push(format!("{}: {} '{}' {}", rule.label(), key, pattern, effect));
+        }
+    }
+    warnings
+}
+
+// Monitors and workspaces the rule names that the display doesn't have
+fn missing(
+    label: &str,
+    display: &Display,
+    rule: &CompiledRule,
+    on_monitor: Option<&MonitorValue>,
+    on_workspace: Option<u32>,
+) -> Vec<String> {
+    let mut monitors: Vec<MonitorValue> = on_monitor.into_iter().cloned().collect();
+    let mut workspaces: Vec<WorkspaceTarget> = on_workspace.map(WorkspaceTarget::Index).into_iter().collect();
+    for action in &rule.actions {
+        match action {
+            Action::Monitor(MonitorTarget::Index(i)) => monitors.push(MonitorValue::Index(*i)),
+            Action::Monitor(MonitorTarget::Name(name)) => monitors.push(MonitorValue::Name(name.clone())),
+            Action::Workspace(target) => workspaces.push(target.clone()),
+            _ => {}
+        }
+    }
+
+    let mut warnings = Vec::new();
+    let connected = display.monitors.join(", ");
+    for monitor in monitors.iter().filter(|_| !display.monitors.is_empty()) {
+        match monitor {
+            MonitorValue::Index(i) if *i as usize >= display.monitors.len() => warnings.push(format!(
+                "{}: no monitor {} ({} connected: {})",
+                label,
+                i,
+                display.monitors.len(),
+                connected
+            )),
+            MonitorValue::Name(name) if !display.monitors.contains(name) => {
+                warnings.push(format!("{}: no monitor '{}' (connected: {})", label, name, connected))
+            }
+            _ => {}
+        }
+    }
+    let Some(ref names) = display.workspaces else {
+        return warnings;
+    };
+    for workspace in workspaces {
+        match workspace {
+            WorkspaceTarget::Index(ws) if ws as usize >= names.len() => {
+                warnings.push(format!("{}: no workspace {} ({} in all)", label, ws, names.len()))
+            }
+            WorkspaceTarget::Name(name) if !names.contains(&name) => {
+                warnings.push(format!("{}: no workspace named '{}'", label, name))
+            }
+            WorkspaceTarget::LeastPopulated(among) => {
+                if let Some(ws) = among.iter().find(|&&ws| ws as usize >= names.len()) {
+                    warnings.push(format!("{}: no workspace {} ({} in all)", label, ws, names.len()));
+                }
+            }
+            _ => {}
+        }
+    }
+    warnings
+}
@@ -1,43 +1,235 @@
 use cherrypie::backend;
+use cherrypie::bench;
 use cherrypie::config;
 use cherrypie::daemon;
+use cherrypie::diff;
+use cherrypie::exit::{ErrorFormat, Exit};
+use cherrypie::ipc;
+use cherrypie::lint;
+use cherrypie::process;
+use cherrypie::rules;
+use cherrypie::session::{self, SessionUser};
+use cherrypie::share;
+use cherrypie::snapshot;
+use cherrypie::stats::{self, Stats};
+
+use std::sync::OnceLock;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+// Set once from --error-format, before anything can fail
+static ERROR_FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+
+fn fail(exit: Exit, message: impl std::fmt::Display) -> ! {
+    let format = ERROR_FORMAT.get().copied().unwrap_or(ErrorFormat::Text);
+    eprintln!("{}", format.message(exit, &message.to_string()));
+    std::process::exit(exit.code());
+}
+
 enum Command {
-    Daemon { config: Option<String>, dry_run: bool },
+    Daemon {
+        config: Option<String>,
+        // [profile.<name>] to use instead of picking by `when`
+        profile: Option<String>,
+        dry_run: bool,
+        metrics_file: Option<String>,
+        // Where process details come from, for process.rs's provider()
+        process_info: Option<String>,
+        // Serve the session owner (--user, else from the environment)
+        system: bool,
+        user: Option<String>,
+    },
+    BenchConfig { config: Option<String>, profile: Option<String>, windows: usize, rounds: usize },
+    Diff { old: String, new: String },
+    RuleEncode { config: Option<String>, indices: Vec<usize> },
+    RuleDecode { config: Option<String>, code: String },
+    Stats { config: Option<String>, days: usize },
+    Lint { config: Option<String>, profile: Option<String> },
+    Schema,
+    Snapshot,
+    // Request for the running daemon
+    Control(ipc::Request),
     Help,
     Version,
 }
 
 fn parse_args() -> Command {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let format = match args.iter().position(|a| a == "--error-format") {
+        Some(at) if at + 1 < args.len() => {
+            let value = args.drain(at..at + 2).nth(1).unwrap_or_default();
+            ErrorFormat::parse(&value)
+        }
+        Some(_) => Err("--error-format requires text or json".into()),
+        None => Ok(ErrorFormat::Text),
+    };
+    match format {
+        Ok(format) => {
+            let _ = ERROR_FORMAT.set(format);
+        }
+        Err(e) => fail(Exit::Usage, e),
+    }
     let mut config = None;
+    let mut profile = None;
     let mut dry_run = false;
+    let mut metrics_file = None;
+    let mut process_info = None;
+    let mut system = false;
+    let mut user = None;
+    let mut bench = false;
+    let mut windows = 1000;
+    let mut rounds = 10;
     let mut i = 1;
 
+    if args.get(1).map(String::as_str) == Some("diff") {
+        return match &args[2..] {
+            [old, new] => Command::Diff { old: old.clone(), new: new.clone() },
+            _ => fail(Exit::Usage, "diff requires two config paths"),
+        };
+    }
+
+    if args.get(1).map(String::as_str) == Some("rule") {
+        return parse_rule_args(&args[2..]);
+    }
+
+    let control = ["grid-place", "reapply", "debug-dump", "scratchpad"];
+    if args.get(1).is_some_and(|cmd| control.contains(&cmd.as_str())) {
+        return match ipc::Request::parse(&args[1..].join(" ")) {
+            Ok(request) => Command::Control(request),
+            Err(e) => fail(Exit::Usage, e),
+        };
+    }
+
+    if args.get(1).map(String::as_str) == Some("stats") {
+        let (mut config, mut days) = (None, 7);
+        let mut i = 2;
+        while i < args.len() {
+            match args[i].as_str() {
+                "--config" | "-c" => config = Some(path_arg(&args, &mut i, "--config")),
+                "--days" => days = count_arg(&args, &mut i, "--days"),
+                other => fail(Exit::Usage, format!("unknown argument: {}", other)),
+            }
+            i += 1;
+        }
+        return Command::Stats { config, days };
+    }
+
+    if args.get(1).map(String::as_str) == Some("lint") {
+        let (mut config, mut profile) = (None, None);
+        let mut i = 2;
+        while i < args.len() {
+            match args[i].as_str() {
+                "--config" | "-c" => config = Some(path_arg(&args, &mut i, "--config")),
+                "--profile" => profile = Some(profile_arg(&args, &mut i)),
+                other => fail(Exit::Usage, format!("unknown argument: {}", other)),
+            }
+            i += 1;
+        }
+        return Command::Lint { config, profile };
+    }
+
+    if args.get(1).map(String::as_str) == Some("schema") {
+        return Command::Schema;
+    }
+
+    if args.get(1).map(String::as_str) == Some("snapshot") {
+        return Command::Snapshot;
+    }
+
+    if args.get(1).map(String::as_str) == Some("bench-config") {
+        bench = true;
+        i = 2;
+    }
+
     while i < args.len() {
         match args[i].as_str() {
             "--help" | "-h" => return Command::Help,
             "--version" | "-V" => return Command::Version,
             "--dry-run" => dry_run = true,
-            "--config" | "-c" => {
+            "--windows" if bench => windows = count_arg(&args, &mut i, "--windows"),
+            "--rounds" if bench => rounds = count_arg(&args, &mut i, "--rounds"),
+            "--config" | "-c" => config = Some(path_arg(&args, &mut i, "--config")),
+            "--profile" => profile = Some(profile_arg(&args, &mut i)),
+            "--metrics-file" if !bench => metrics_file = Some(path_arg(&args, &mut i, "--metrics-file")),
+            "--process-info" if !bench => {
                 i += 1;
-                if i >= args.len() {
-                    eprintln!("--config requires a path");
-                    std::process::exit(1);
+                match args.get(i) {
+                    Some(spec) => process_info = Some(spec.clone()),
+                    None => fail(Exit::Usage, "--process-info requires procfs, pidfd, or cgroup:<path>"),
                 }
-                config = Some(args[i].clone());
             }
-            other => {
-                eprintln!("unknown argument: {}", other);
-                std::process::exit(1);
+            "--system" if !bench => system = true,
+            "--user" if !bench => {
+                i += 1;
+                match args.get(i) {
+                    Some(name) => user = Some(name.clone()),
+                    None => fail(Exit::Usage, "--user requires a user name"),
+                }
             }
+            other => fail(Exit::Usage, format!("unknown argument: {}", other)),
         }
         i += 1;
     }
 
-    Command::Daemon { config, dry_run }
+    if bench {
+        Command::BenchConfig { config, profile, windows, rounds }
+    } else {
+        if user.is_some() && !system {
+            fail(Exit::Usage, "--user only applies with --system");
+        }
+        Command::Daemon { config, profile, dry_run, metrics_file, process_info, system, user }
+    }
+}
+
+// rule encode <INDEX>... | rule decode <CODE>, either with -c <PATH>
+fn parse_rule_args(args: &[String]) -> Command {
+    let usage = "usage: rule encode <INDEX>... | rule decode <CODE> [-c <PATH>]";
+    let mut config = None;
+    let mut words = Vec::new();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--config" | "-c" => config = Some(path_arg(args, &mut i, "--config")),
+            word => words.push(word.to_string()),
+        }
+        i += 1;
+    }
+
+    match (args.first().map(String::as_str), words.as_slice()) {
+        (Some("encode"), [_, ..]) => {
+            let indices = words
+                .iter()
+                .map(|w| w.parse().unwrap_or_else(|_| fail(Exit::Usage, format!("invalid rule index '{}'", w))))
+                .collect();
+            Command::RuleEncode { config, indices }
+        }
+        (Some("decode"), [code]) => Command::RuleDecode { config, code: code.clone() },
+        _ => fail(Exit::Usage, usage),
+    }
+}
+
+fn path_arg(args: &[String], i: &mut usize, flag: &str) -> String {
+    *i += 1;
+    match args.get(*i) {
+        Some(path) => path.clone(),
+        None => fail(Exit::Usage, format!("{} requires a path", flag)),
+    }
+}
+
+fn profile_arg(args: &[String], i: &mut usize) -> String {
+    *i += 1;
+    match args.get(*i) {
+        Some(name) => name.clone(),
+        None => fail(Exit::Usage, "--profile requires a profile name"),
+    }
+}
+
+fn count_arg(args: &[String], i: &mut usize, flag: &str) -> usize {
+    *i += 1;
+    match args.get(*i).and_then(|s| s.parse().ok()) {
+        Some(n) if n > 0 => n,
+        _ => fail(Exit::Usage, format!("{} requires a positive number", flag)),
+    }
 }
 
 fn print_help() {
@@ -45,12 +237,76 @@ fn print_help() {
     println!();
     println!("USAGE:");
     println!("    cherrypie [OPTIONS]");
+    println!("    cherrypie bench-config [-c <PATH>] [--profile <NAME>] [--windows <N>] [--rounds <N>]");
+    println!("    cherrypie diff <OLD> <NEW>");
+    println!("    cherrypie lint [-c <PATH>] [--profile <NAME>]");
+    println!("    cherrypie schema                 Print the config's JSON Schema");
+    println!("    cherrypie snapshot               Print rules that put open windows back where they are");
+    println!("    cherrypie rule encode <INDEX>... [-c <PATH>]");
+    println!("    cherrypie rule decode <CODE> [-c <PATH>]");
+    println!("    cherrypie grid-place <WINDOW> <COLS>x<ROWS> <CELL>");
+    println!("    cherrypie reapply [--rule <INDEX|NAME>] [--workspace <N>]");
+    println!("    cherrypie debug-dump             Print the daemon's state as JSON for bug reports");
+    println!("    cherrypie scratchpad toggle|show|hide <NAME>");
+    println!("    cherrypie stats [--days <N>] [-c <PATH>]");
     println!();
     println!("OPTIONS:");
     println!("    -c, --config <PATH>    Config file (default: ~/.config/cherrypie/config.toml)");
     println!("    --dry-run              Log matches without applying actions");
+    println!("    --profile <NAME>       Use [profile.NAME] (default: $CHERRYPIE_PROFILE, else by `when`)");
+    println!("    --metrics-file <PATH>  Write Prometheus health gauges to PATH");
+    println!("    --process-info <FROM>  Process details from procfs (default), pidfd, or cgroup:<PATH>");
+    println!("    --system               Start as root and serve the display's session owner");
+    println!("    --user <NAME>          Session owner for --system (default: $PAM_USER, $USER)");
+    println!("    --error-format <FMT>   Report errors as text (default) or json, any command");
     println!("    -h, --help             Show this help");
     println!("    -V, --version          Show version");
+    println!();
+    println!("BENCH-CONFIG:");
+    println!("    Match the config's rules against synthetic windows and report");
+    println!("    throughput and the slowest rules.");
+    println!("    --windows <N>          Synthetic windows to generate (default: 1000)");
+    println!("    --rounds <N>           Passes over every window (default: 10)");
+    println!();
+    println!("DIFF:");
+    println!("    Compare the rules of two configs: rules added, removed, moved,");
+    println!("    or with changed matchers or actions. Exits 1 if they differ.");
+    println!();
+    println!("LINT:");
+    println!("    Check the config for rules an earlier stop rule never lets match,");
+    println!("    rules matching the same windows with conflicting actions, patterns");
+    println!("    that match any window, and, on a display, monitors and workspaces");
+    println!("    it doesn't have. Exits 1 if anything was found.");
+    println!();
+    println!("RULE:");
+    println!("    encode prints rules of the config, by index, as one line to share;");
+    println!("    decode checks such a line against the config and prints the rules");
+    println!("    as TOML to append to it.");
+    println!();
+    println!("GRID-PLACE:");
+    println!("    Ask the running daemon to move and resize a window into one cell");
+    println!("    of a grid over its monitor. WINDOW is 'active' or an id (0x...);");
+    println!("    cells count from 0, left to right, then top to bottom.");
+    println!();
+    println!("REAPPLY:");
+    println!("    Ask the running daemon to run its rules again over every open");
+    println!("    window, in small batches. --rule limits it to one rule, by index");
+    println!("    or name (which also starts its max_matches count over),");
+    println!("    --workspace to the windows on one workspace.");
+    println!();
+    println!("STATS:");
+    println!("    Summarize the local usage statistics kept with stats = true under");
+    println!("    [settings]: rules fired over the last N days (default: 7), the");
+    println!("    busiest rules and window classes, and rules that never fired.");
+    println!();
+    println!("EXIT STATUS:");
+    println!("    0  success");
+    println!("    1  other failure (diff: the configs differ; lint: warnings)");
+    println!("    2  invalid arguments");
+    println!("    3  config missing, invalid, or failing to compile");
+    println!("    4  no X display");
+    println!("    5  daemon not running");
+    println!("    6  daemon ran the request but some actions failed");
 }
 
 fn main() {
@@ -61,39 +317,196 @@ fn main() {
         Command::Version => {
             println!("cherrypie {}", VERSION);
         }
-        Command::Daemon { config, dry_run } => {
-            let paths = match config {
-                Some(path) => config::Paths::with_config(path.into()),
-                None => match config::Paths::init() {
-                    Ok(p) => p,
-                    Err(e) => {
-                        eprintln!("[cherrypie] {}", e);
-                        std::process::exit(1);
-                    }
-                },
+        Command::BenchConfig { config, profile, windows, rounds } => {
+            let mut paths = config_paths(config);
+            paths.profile = profile.or(paths.profile);
+            let compiled = match config::load(&paths).and_then(|cfg| rules::compile(&cfg)) {
+                Ok(r) => r,
+                Err(e) => fail(Exit::Config, e),
             };
 
-            if !paths.config_file.exists() {
-                eprintln!(
-                    "[cherrypie] config not found: {}",
-                    paths.config_file.display()
+            let props = bench::synthetic_windows(windows);
+            print!("{}", bench::run(&compiled, &props, rounds));
+        }
+        #[cfg(feature = "schema")]
+        Command::Schema => {
+            println!("{}", config::schema());
+        }
+        #[cfg(not(feature = "schema"))]
+        Command::Schema => {
+            fail(Exit::Failure, "built without the schema feature");
+        }
+        Command::Snapshot => {
+            let wm = backend::WindowManager::init(-1).unwrap_or_else(|e| fail(Exit::Backend, e));
+            print!("{}", snapshot::render(&wm));
+        }
+        Command::Control(request) => match ipc::send(&ipc::socket_path(), &request) {
+            Ok(output) => print!("{}", output),
+            Err(e @ ipc::Failure::NotRunning(_)) => fail(Exit::NotRunning, e),
+            Err(e @ ipc::Failure::Partial(_)) => fail(Exit::Partial, e),
+            Err(e) => fail(Exit::Failure, e),
+        },
+        Command::Lint { config, profile } => {
+            let mut paths = config_paths(config);
+            paths.profile = profile.or(paths.profile);
+            let cfg = config::load(&paths).unwrap_or_else(|e| fail(Exit::Config, e));
+            let compiled = rules::compile(&cfg).unwrap_or_else(|e| fail(Exit::Config, e));
+            // Monitors and workspaces are only checked with a display to ask
+            let display = std::env::var_os("DISPLAY")
+                .and_then(|_| backend::WindowManager::init(-1).ok())
+                .map(|wm| lint::Display {
+                    monitors: wm.monitors().into_iter().map(|m| m.name).collect(),
+                    workspaces: wm.workspaces(),
+                });
+            if display.is_none() {
+                eprintln!("[cherrypie] no display; monitor and workspace references not checked");
+            }
+            let warnings = lint::lint(&cfg, &compiled, display.as_ref());
+            for warning in &warnings {
+                println!("{}", warning);
+            }
+            if !warnings.is_empty() {
+                std::process::exit(Exit::Failure.code());
+            }
+        }
+        Command::Diff { old, new } => {
+            let load = |path: &str| {
+                diff::load(std::path::Path::new(path)).unwrap_or_else(|e| fail(Exit::Config, e))
+            };
+            let changes = diff::diff(&load(&old), &load(&new));
+            for change in &changes {
+                print!("{}", change);
+            }
+            if !changes.is_empty() {
+                std::process::exit(Exit::Failure.code());
+            }
+        }
+        Command::RuleEncode { config, indices } => {
+            let paths = config_paths(config);
+            let raw = diff::load(&paths.config_file).unwrap_or_else(|e| fail(Exit::Config, e));
+            match share::encode(&raw, &indices) {
+                Ok(code) => println!("{}", code),
+                Err(e) => fail(Exit::Usage, e),
+            }
+        }
+        Command::RuleDecode { config, code } => {
+            let rules = share::decode(&code).unwrap_or_else(|e| fail(Exit::Usage, e));
+            // Checked against the user's config when there is one, for its
+            // aliases and conditions
+            let path = match config {
+                Some(path) => Some(std::path::PathBuf::from(path)),
+                None => config::Paths::init().ok().map(|p| p.config_file).filter(|p| p.exists()),
+            };
+            let raw = path.map(|p| diff::load(&p).unwrap_or_else(|e| fail(Exit::Config, e)));
+            if let Err(e) = share::check(raw.as_ref(), &rules) {
+                fail(Exit::Config, e);
+            }
+            match share::to_toml(rules) {
+                Ok(text) => print!("{}", text),
+                Err(e) => fail(Exit::Failure, e),
+            }
+        }
+        Command::Stats { config, days } => {
+            let path = stats::default_path().unwrap_or_else(|| fail(Exit::Failure, "HOME not set"));
+            if !path.exists() {
+                fail(
+                    Exit::Failure,
+                    format!("no statistics at {} (set stats = true under [settings] to keep them)", path.display()),
                 );
-                eprintln!("[cherrypie] create it and add rules, then restart");
-                std::process::exit(1);
             }
+            let recorded = Stats::load(&path).unwrap_or_else(|e| fail(Exit::Failure, e));
+            // With the config, rules that never fired can be listed too
+            let paths = match config {
+                Some(path) => Some(config::Paths::with_config(path.into())),
+                None => config::Paths::init().ok().filter(|p| p.config_file.exists()),
+            };
+            let cfg = paths.map(|p| config::load(&p).unwrap_or_else(|e| fail(Exit::Config, e)));
+            // Included and profile rules are in `rule`; monitor defaults are
+            // counted after them, as they are numbered
+            let rule_count = cfg.as_ref().map(|c| c.rule.len() + c.monitor_defaults.len());
+            print!("{}", recorded.report(days, rule_count));
+        }
+        Command::Daemon { config, profile, dry_run, metrics_file, process_info, system, user } => {
+            let process_info =
+                process_info.map(|spec| process::provider(&spec).unwrap_or_else(|e| fail(Exit::Usage, e)));
+            // In system mode the config is picked, and read, as the session owner
+            let owner = system.then(|| session_owner(user));
+            let early = match owner {
+                None => Some(config_paths(config.clone())),
+                Some(_) => None,
+            };
 
             // Signal handling before anything else so shutdown works during init
             let signal_fd = daemon::setup_signalfd();
 
             let wm = match backend::WindowManager::init(signal_fd) {
                 Ok(wm) => wm,
-                Err(e) => {
-                    eprintln!("[cherrypie] {}", e);
-                    std::process::exit(1);
-                }
+                Err(e) => fail(Exit::Backend, e),
+            };
+            let wm = match process_info {
+                Some(provider) => wm.with_process_info(provider),
+                None => wm,
+            };
+
+            let mut paths = match owner {
+                Some(owner) => become_owner(&owner, config),
+                None => early.unwrap_or_else(|| config_paths(config)),
             };
+            paths.profile = profile.or(paths.profile);
 
-            daemon::run(wm, &paths.config_file, dry_run, signal_fd);
+            let metrics_file = metrics_file.map(std::path::PathBuf::from);
+            if let Err((exit, e)) = daemon::run(wm, &paths, dry_run, metrics_file.as_deref(), signal_fd) {
+                fail(exit, e);
+            }
         }
     }
 }
+
+// Who a --system daemon works for; it has to start as root to switch
+fn session_owner(user: Option<String>) -> SessionUser {
+    if !session::running_as_root() {
+        fail(Exit::Usage, "--system must be started as root");
+    }
+    let name = user
+        .or_else(session::owner_from_env)
+        .unwrap_or_else(|| fail(Exit::Usage, "--system needs --user, PAM_USER, or USER to name the session owner"));
+    SessionUser::lookup(&name).unwrap_or_else(|e| fail(Exit::Usage, e))
+}
+
+// Drop to the session owner, then pick their config: --config, their
+// own, or the system-wide one
+fn become_owner(owner: &SessionUser, config: Option<String>) -> config::Paths {
+    if let Err(e) = owner.drop_privileges() {
+        fail(Exit::Failure, e);
+    }
+    owner.export_env();
+    eprintln!("[cherrypie] serving session of {} (uid {})", owner.name, owner.uid);
+
+    let file = match config {
+        Some(path) => path.into(),
+        None => owner.config_file(std::path::Path::new(session::SYSTEM_CONFIG)),
+    };
+    if !file.exists() {
+        fail(Exit::Config, format!("config not found: {}", file.display()));
+    }
+    config::Paths::with_config(file)
+}
+
+fn config_paths(config: Option<String>) -> config::Paths {
+    let paths = match config {
+        Some(path) => config::Paths::with_config(path.into()),
+        None => match config::Paths::init() {
+            Ok(p) => p,
+            Err(e) => fail(Exit::Config, e),
+        },
+    };
+
+    if !paths.config_file.exists() {
+        fail(
+            Exit::Config,
+            format!("config not found: {} (create it and add rules, then restart)", paths.config_file.display()),
+        );
+    }
+
+    paths
+}
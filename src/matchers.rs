@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use regex::Regex;
+
+use crate::rules::{Comparison, MonitorTarget};
+
+// Everything a matcher may look at for one window. Backends fill in the
+// string properties unconditionally; the optional extras are only fetched
+// when some rule's matchers ask for them through Needs.
+#[derive(Debug, Clone, Default)]
+pub struct WindowProps {
+    pub class: String,
+    pub title: String,
+    pub role: String,
+    pub process: String,
+    pub window_type: String,
+    pub size: Option<(u32, u32)>,
+    pub states: Vec<String>,
+    pub workspace: Option<u32>,
+    pub monitor: Option<MonitorId>,
+    pub process_env: Option<HashMap<String, String>>,
+    pub uid: Option<u32>,
+    pub startup_id: Option<String>,
+    // Class of the group leader, if the window belongs to a group
+    pub group_class: Option<String>,
+    pub compositor: Option<bool>,
+    pub monitor_count: Option<u32>,
+    // Local time when the window is evaluated, minutes since midnight
+    pub minute_of_day: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorId {
+    pub index: u32,
+    pub name: String,
+}
+
+// Properties beyond the basic strings that a matcher reads. Lets the
+// backend skip round-trips nobody will look at.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Needs {
+    pub geometry: bool,
+    pub states: bool,
+    pub workspace: bool,
+    pub monitor: bool,
+    pub process_env: bool,
+    pub uid: bool,
+    pub startup_id: bool,
+    pub group: bool,
+    pub compositor: bool,
+    pub monitor_count: bool,
+    pub time: bool,
+}
+
+impl Needs {
+    pub fn union(self, other: Needs) -> Needs {
+        Needs {
+            geometry: self.geometry || other.geometry,
+            states: self.states || other.states,
+            workspace: self.workspace || other.workspace,
+            monitor: self.monitor || other.monitor,
+            process_env: self.process_env || other.process_env,
+            uid: self.uid || other.uid,
+            startup_id: self.startup_id || other.startup_id,
+            group: self.group || other.group,
+            compositor: self.compositor || other.compositor,
+            monitor_count: self.monitor_count || other.monitor_count,
+            time: self.time || other.time,
+        }
+    }
+}
+
+// A single condition on a window. A rule matches when all of its matchers
+// do. Library users can attach their own through CompiledRule::add_matcher.
+pub trait Matcher {
+    fn matches(&self, props: &WindowProps) -> bool;
+
+    fn needs(&self) -> Needs {
+        Needs::default()
+    }
+
+    // Whether the title takes part, which wait_for_title checks last
+    fn reads_title(&self) -> bool {
+        false
+    }
+}
+
+// Inverts another matcher (`class_not`, `type_not`, ...)
+pub struct Not(pub Box<dyn Matcher>);
+
+impl Matcher for Not {
+    fn matches(&self, props: &WindowProps) -> bool {
+        !self.0.matches(props)
+    }
+
+    fn needs(&self) -> Needs {
+        self.0.needs()
+    }
+
+    fn reads_title(&self) -> bool {
+        self.0.reads_title()
+    }
+}
+
+// Every inner matcher must match (`&&` in a condition)
+pub struct AllOf(pub Vec<Box<dyn Matcher>>);
+
+impl Matcher for AllOf {
+    fn matches(&self, props: &WindowProps) -> bool {
+        self.0.iter().all(|m| m.matches(props))
+    }
+
+    fn needs(&self) -> Needs {
+        self.0.iter().fold(Needs::default(), |acc, m| acc.union(m.needs()))
+    }
+
+    fn reads_title(&self) -> bool {
+        self.0.iter().any(|m| m.reads_title())
+    }
+}
+
+// Any inner matcher may match (`||` in a condition)
+pub struct AnyOf(pub Vec<Box<dyn Matcher>>);
+
+impl Matcher for AnyOf {
+    fn matches(&self, props: &WindowProps) -> bool {
+        self.0.iter().any(|m| m.matches(props))
+    }
+
+    fn needs(&self) -> Needs {
+        self.0.iter().fold(Needs::default(), |acc, m| acc.union(m.needs()))
+    }
+
+    fn reads_title(&self) -> bool {
+        self.0.iter().any(|m| m.reads_title())
+    }
+}
+
+// Settled when the config was compiled (a condition's host terms)
+pub struct Constant(pub bool);
+
+impl Matcher for Constant {
+    fn matches(&self, _props: &WindowProps) -> bool {
+        self.0
+    }
+}
+
+// A named condition shared by every rule that references it
+impl Matcher for Rc<dyn Matcher> {
+    fn matches(&self, props: &WindowProps) -> bool {
+        (**self).matches(props)
+    }
+
+    fn needs(&self) -> Needs {
+        (**self).needs()
+    }
+
+    fn reads_title(&self) -> bool {
+        (**self).reads_title()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Class,
+    Title,
+    Role,
+    Process,
+}
+
+// Regexes against one of the string properties; any one matching is enough
+pub struct PatternMatcher {
+    pub field: Field,
+    pub regexes: Vec<Regex>,
+    // [class_aliases]: raw WM_CLASS -> name class patterns are written against
+    pub class_aliases: Rc<HashMap<String, String>>,
+}
+
+impl Matcher for PatternMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        let value = match self.field {
+            Field::Class => self.class_aliases.get(&props.class).unwrap_or(&props.class),
+            Field::Title => &props.title,
+            Field::Role => &props.role,
+            Field::Process => &props.process,
+        };
+        self.regexes.iter().any(|re| re.is_match(value))
+    }
+
+    fn reads_title(&self) -> bool {
+        self.field == Field::Title
+    }
+}
+
+// Case-insensitive comparison against the window type name; any listed
+// type matching is enough
+pub struct TypeMatcher(pub Vec<String>);
+
+impl Matcher for TypeMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        self.0.iter().any(|t| t.eq_ignore_ascii_case(&props.window_type))
+    }
+}
+
+// Inclusive bounds on the window's initial size. Never matches a window
+// whose geometry could not be read.
+#[derive(Debug, Clone, Default)]
+pub struct SizeMatcher {
+    pub min_width: Option<u32>,
+    pub max_width: Option<u32>,
+    pub min_height: Option<u32>,
+    pub max_height: Option<u32>,
+}
+
+impl Matcher for SizeMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        let Some((width, height)) = props.size else {
+            return false;
+        };
+        self.min_width.is_none_or(|min| width >= min)
+            && self.max_width.is_none_or(|max| width <= max)
+            && self.min_height.is_none_or(|min| height >= min)
+            && self.max_height.is_none_or(|max| height <= max)
+    }
+
+    fn needs(&self) -> Needs {
+        Needs { geometry: true, ..Needs::default() }
+    }
+}
+
+// Window states a `state` matcher can name, as reported by backends.
+// "maximized" is set only when both axes are maximized.
+pub const WINDOW_STATES: &[&str] = &[
+    "maximized",
+    "maximized_vert",
+    "maximized_horz",
+    "fullscreen",
+    "above",
+    "below",
+    "sticky",
+    "shaded",
+    "hidden",
+    "modal",
+    "skip_taskbar",
+    "skip_pager",
+    "demands_attention",
+];
+
+// One entry of a `state` matcher: "fullscreen" requires the state,
+// "!fullscreen" requires its absence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateMatcher {
+    pub name: String,
+    pub present: bool,
+}
+
+impl StateMatcher {
+    pub fn parse(s: &str) -> Self {
+        match s.strip_prefix('!') {
+            Some(name) => Self { name: name.to_string(), present: false },
+            None => Self { name: s.to_string(), present: true },
+        }
+    }
+}
+
+impl Matcher for StateMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        props.states.contains(&self.name) == self.present
+    }
+
+    fn needs(&self) -> Needs {
+        Needs { states: true, ..Needs::default() }
+    }
+}
+
+// Sentinel _NET_WM_DESKTOP value for windows shown on every workspace
+pub const ALL_WORKSPACES: u32 = 0xFFFFFFFF;
+
+// Workspace the window appeared on. Sticky windows are on every workspace;
+// windows with an unknown workspace never match.
+pub struct WorkspaceMatcher(pub u32);
+
+impl Matcher for WorkspaceMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        props
+            .workspace
+            .is_some_and(|ws| ws == self.0 || ws == ALL_WORKSPACES)
+    }
+
+    fn needs(&self) -> Needs {
+        Needs { workspace: true, ..Needs::default() }
+    }
+}
+
+// Output the window initially appears on, by index or RandR name
+pub struct MonitorMatcher(pub MonitorTarget);
+
+impl Matcher for MonitorMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        let Some(ref mon) = props.monitor else {
+            return false;
+        };
+        match &self.0 {
+            MonitorTarget::Index(i) => mon.index == *i,
+            MonitorTarget::Name(n) => mon.name == *n,
+            // on_monitor compiles names literally, never to keywords
+            _ => false,
+        }
+    }
+
+    fn needs(&self) -> Needs {
+        Needs { monitor: true, ..Needs::default() }
+    }
+}
+
+// Variables in the environment of the window's process; every listed
+// variable must be set and match its pattern. Never matches a window whose
+// environment could not be read.
+pub struct ProcessEnvMatcher(pub Vec<(String, Regex)>);
+
+impl Matcher for ProcessEnvMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        let Some(ref env) = props.process_env else {
+            return false;
+        };
+        self.0
+            .iter()
+            .all(|(name, re)| env.get(name).is_some_and(|v| re.is_match(v)))
+    }
+
+    fn needs(&self) -> Needs {
+        Needs { process_env: true, ..Needs::default() }
+    }
+}
+
+// Owner of the window's process. Never matches a window whose process is
+// not visible here (no _NET_WM_PID, or remote over X forwarding).
+pub struct UidMatcher(pub u32);
+
+impl Matcher for UidMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        props.uid == Some(self.0)
+    }
+
+    fn needs(&self) -> Needs {
+        Needs { uid: true, ..Needs::default() }
+    }
+}
+
+// Startup-notification ID the window was launched with, or the launcher
+// named at its front. Never matches a window launched without one.
+pub struct StartupMatcher {
+    pub launcher: bool,
+    pub regexes: Vec<Regex>,
+}
+
+impl Matcher for StartupMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        let Some(ref id) = props.startup_id else {
+            return false;
+        };
+        let value = if self.launcher { launcher_of(id) } else { id };
+        self.regexes.iter().any(|re| re.is_match(value))
+    }
+
+    fn needs(&self) -> Needs {
+        Needs { startup_id: true, ..Needs::default() }
+    }
+}
+
+// Class of the window's group leader. Never matches a window outside a
+// group.
+pub struct GroupMatcher(pub Vec<Regex>);
+
+impl Matcher for GroupMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        props
+            .group_class
+            .as_ref()
+            .is_some_and(|class| self.0.iter().any(|re| re.is_match(class)))
+    }
+
+    fn needs(&self) -> Needs {
+        Needs { group: true, ..Needs::default() }
+    }
+}
+
+// The launcher part of a startup ID. IDs conventionally start with it:
+// "gnome-shell/firefox/1234-0-host_TIME56" or "rofi-1234-host_TIME56".
+pub fn launcher_of(id: &str) -> &str {
+    let end = id
+        .find('/')
+        .or_else(|| id.find('-'))
+        .or_else(|| id.find("_TIME"))
+        .unwrap_or(id.len());
+    &id[..end]
+}
+
+// Whether a compositor is running when the rule is evaluated. Never
+// matches if the backend cannot tell.
+pub struct CompositorMatcher(pub bool);
+
+impl Matcher for CompositorMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        props.compositor == Some(self.0)
+    }
+
+    fn needs(&self) -> Needs {
+        Needs { compositor: true, ..Needs::default() }
+    }
+}
+
+// Local time of day between start (inclusive) and end (exclusive), in
+// minutes since midnight; wraps past midnight when start > end
+pub struct TimeMatcher {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Matcher for TimeMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        let Some(now) = props.minute_of_day else {
+            return false;
+        };
+        if self.start <= self.end {
+            now >= self.start && now < self.end
+        } else {
+            now >= self.start || now < self.end
+        }
+    }
+
+    fn needs(&self) -> Needs {
+        Needs { time: true, ..Needs::default() }
+    }
+}
+
+// Number of connected outputs (`when.monitors`). Never matches if the
+// backend cannot tell.
+pub struct MonitorCountMatcher(pub Comparison);
+
+impl Matcher for MonitorCountMatcher {
+    fn matches(&self, props: &WindowProps) -> bool {
+        props.monitor_count.is_some_and(|n| self.0.holds(n))
+    }
+
+    fn needs(&self) -> Needs {
+        Needs { monitor_count: true, ..Needs::default() }
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::stats;
+
+// Where windows of `remember = true` rules were when they closed, so the
+// next window of the same kind opens there again. Windows are told apart
+// by class, instance, and the rule's title patterns.
+
+// Client area in root coordinates, and the workspace
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Remembered {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub workspace: Option<u32>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Memory {
+    // key() -> last geometry
+    #[serde(default)]
+    pub windows: BTreeMap<String, Remembered>,
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    Some(stats::state_dir()?.join("geometry.toml"))
+}
+
+pub fn key(class: &str, instance: &str, title_patterns: &str) -> String {
+    format!("{}/{}/{}", class, instance, title_patterns)
+}
+
+impl Memory {
+    // Nothing remembered if the file doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(self).map_err(io::Error::other)?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, text)?;
+        std::fs::rename(&tmp, path)
+    }
+}
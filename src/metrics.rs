@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Health gauges in the Prometheus text exposition format, written as a
+// node_exporter textfile so an existing scrape setup can alert on them
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Health {
+    pub up: bool,
+    pub backend_connected: bool,
+    pub last_reload_success: bool,
+    pub rules: usize,
+    pub start_time: u64,
+    pub last_reload_time: u64,
+    // Zero until an action has failed
+    pub last_error_time: u64,
+    // Rule index -> time from new windows appearing to the rule applied,
+    // since the rules were last loaded
+    pub latency: BTreeMap<usize, Latency>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Latency {
+    pub count: u64,
+    pub sum: Duration,
+    pub max: Duration,
+}
+
+impl Health {
+    pub fn new(now: u64) -> Self {
+        Self {
+            up: true,
+            backend_connected: true,
+            last_reload_success: true,
+            rules: 0,
+            start_time: now,
+            last_reload_time: now,
+            last_error_time: 0,
+            latency: BTreeMap::new(),
+        }
+    }
+
+    pub fn reloaded(&mut self, now: u64, rules: Option<usize>) {
+        self.last_reload_time = now;
+        self.last_reload_success = rules.is_some();
+        if let Some(n) = rules {
+            self.rules = n;
+            // Indices may now name other rules
+            self.latency.clear();
+        }
+    }
+
+    pub fn applied(&mut self, rule: usize, took: Duration) {
+        let latency = self.latency.entry(rule).or_default();
+        latency.count += 1;
+        latency.sum += took;
+        latency.max = latency.max.max(took);
+    }
+
+    pub fn write_textfile(&self, path: &Path) -> io::Result<()> {
+        // Rename over the old file so a scrape never sees a partial write
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, self.to_string())?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gauges: [(&str, &str, u64); 7] = [
+            ("cherrypie_up", "Whether the daemon is running.", self.up as u64),
+            ("cherrypie_backend_connected", "Whether the window system backend is connected.", self.backend_connected as u64),
+            ("cherrypie_last_reload_success", "Whether the last config load succeeded.", self.last_reload_success as u64),
+            ("cherrypie_rules", "Rules currently loaded.", self.rules as u64),
+            ("cherrypie_start_time_seconds", "Unix time the daemon started.", self.start_time),
+            ("cherrypie_last_reload_timestamp_seconds", "Unix time of the last config load attempt.", self.last_reload_time),
+            ("cherrypie_last_error_timestamp_seconds", "Unix time of the last failed action, 0 if none.", self.last_error_time),
+        ];
+
+        for (name, help, value) in gauges {
+            writeln!(f, "# HELP {} {}", name, help)?;
+            writeln!(f, "# TYPE {} gauge", name)?;
+            writeln!(f, "{} {}", name, value)?;
+        }
+
+        if self.latency.is_empty() {
+            return Ok(());
+        }
+        let name = "cherrypie_apply_latency_seconds";
+        writeln!(f, "# HELP {} Time from a window appearing to a rule applied to it.", name)?;
+        writeln!(f, "# TYPE {} summary", name)?;
+        for (rule, latency) in &self.latency {
+            writeln!(f, "{}_sum{{rule=\"{}\"}} {}", name, rule, latency.sum.as_secs_f64())?;
+            writeln!(f, "{}_count{{rule=\"{}\"}} {}", name, rule, latency.count)?;
+        }
+        let name = "cherrypie_apply_latency_max_seconds";
+        writeln!(f, "# HELP {} Slowest application of each rule to a new window.", name)?;
+        writeln!(f, "# TYPE {} gauge", name)?;
+        for (rule, latency) in &self.latency {
+            writeln!(f, "{}{{rule=\"{}\"}} {}", name, rule, latency.max.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+pub fn unix_seconds(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Where details about a window's process come from, given the pid its
+// backend reports. The default reads the local /proc; others can stand in
+// for systems or sandboxes where that doesn't work, and tests use them to
+// fake processes.
+pub trait ProcessInfoProvider {
+    // Short command name, as in `ps -o comm`
+    fn name(&self, pid: u32) -> Option<String>;
+
+    // Environment, if readable (usually only for the same user)
+    fn env(&self, _pid: u32) -> Option<HashMap<String, String>> {
+        None
+    }
+
+    // Uid owning the process
+    fn uid(&self, _pid: u32) -> Option<u32> {
+        None
+    }
+}
+
+// A procfs mount: /proc by default, or another root such as the host's
+// /proc bind-mounted into a container
+pub struct ProcFs {
+    root: PathBuf,
+}
+
+impl ProcFs {
+    pub fn new() -> Self {
+        Self::at("/proc")
+    }
+
+    pub fn at(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl Default for ProcFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProcessInfoProvider for ProcFs {
+    fn name(&self, pid: u32) -> Option<String> {
+        let comm = std::fs::read_to_string(self.root.join(pid.to_string()).join("comm")).ok()?;
+        Some(comm.trim().to_string())
+    }
+
+    // NUL-separated KEY=VALUE pairs from <root>/<pid>/environ
+    fn env(&self, pid: u32) -> Option<HashMap<String, String>> {
+        let raw = std::fs::read(self.root.join(pid.to_string()).join("environ")).ok()?;
+        let env = raw
+            .split(|&b| b == 0)
+            .filter_map(|entry| {
+                let entry = String::from_utf8_lossy(entry);
+                let (key, value) = entry.split_once('=')?;
+                Some((key.to_string(), value.to_string()))
+            })
+            .collect();
+        Some(env)
+    }
+
+    fn uid(&self, pid: u32) -> Option<u32> {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata(self.root.join(pid.to_string())).ok().map(|m| m.uid())
+    }
+}
+
+// ProcFs that holds the process by a pidfd while reading it, so a process
+// that exits partway, its pid free for another to take, gives nothing
+// rather than a mix of the two
+pub struct PidFd {
+    procfs: ProcFs,
+}
+
+impl PidFd {
+    pub fn new() -> Self {
+        Self::at("/proc")
+    }
+
+    pub fn at(root: impl Into<PathBuf>) -> Self {
+        Self { procfs: ProcFs::at(root) }
+    }
+
+    fn pinned<T>(&self, pid: u32, read: impl FnOnce(&ProcFs) -> Option<T>) -> Option<T> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+        if fd < 0 {
+            return None;
+        }
+        let fd = fd as libc::c_int;
+        let value = read(&self.procfs);
+        // A pidfd turns readable once its process exits. Unlike signal 0,
+        // this needs no permission over processes of other users
+        let mut poll = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        let alive = unsafe { libc::poll(&mut poll, 1, 0) } == 0;
+        unsafe { libc::close(fd); }
+        value.filter(|_| alive)
+    }
+}
+
+impl Default for PidFd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProcessInfoProvider for PidFd {
+    fn name(&self, pid: u32) -> Option<String> {
+        self.pinned(pid, |procfs| procfs.name(pid))
+    }
+
+    fn env(&self, pid: u32) -> Option<HashMap<String, String>> {
+        self.pinned(pid, |procfs| procfs.env(pid))
+    }
+
+    fn uid(&self, pid: u32) -> Option<u32> {
+        self.pinned(pid, |procfs| procfs.uid(pid))
+    }
+}
+
+// Clients in a container set _NET_WM_PID to their pid inside its pid
+// namespace. Given the container's cgroup (v2), such pids are mapped to
+// the host's through the NSpid line of each member's status; pids no
+// member has are looked up as they are
+pub struct Cgroup {
+    procfs: ProcFs,
+    cgroup: PathBuf,
+}
+
+impl Cgroup {
+    pub fn new(cgroup: impl Into<PathBuf>) -> Self {
+        Self::at("/proc", cgroup)
+    }
+
+    pub fn at(root: impl Into<PathBuf>, cgroup: impl Into<PathBuf>) -> Self {
+        Self { procfs: ProcFs::at(root), cgroup: cgroup.into() }
+    }
+
+    fn host_pid(&self, pid: u32) -> u32 {
+        members(&self.cgroup).into_iter().find(|&host| self.inner_pid(host) == Some(pid)).unwrap_or(pid)
+    }
+
+    // Last NSpid entry: the pid in the innermost namespace
+    fn inner_pid(&self, host: u32) -> Option<u32> {
+        let status = std::fs::read_to_string(self.procfs.root.join(host.to_string()).join("status")).ok()?;
+        let nspid = status.lines().find_map(|line| line.strip_prefix("NSpid:"))?;
+        nspid.split_whitespace().last()?.parse().ok()
+    }
+}
+
+// Pids in a cgroup and the cgroups below it
+fn members(cgroup: &Path) -> Vec<u32> {
+    let procs = std::fs::read_to_string(cgroup.join("cgroup.procs")).unwrap_or_default();
+    let mut pids: Vec<u32> = procs.lines().filter_map(|line| line.trim().parse().ok()).collect();
+    let children = std::fs::read_dir(cgroup).into_iter().flatten().flatten();
+    for child in children.filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir())) {
+        pids.extend(members(&child.path()));
+    }
+    pids
+}
+
+impl ProcessInfoProvider for Cgroup {
+    fn name(&self, pid: u32) -> Option<String> {
+        self.procfs.name(self.host_pid(pid))
+    }
+
+    fn env(&self, pid: u32) -> Option<HashMap<String, String>> {
+        self.procfs.env(self.host_pid(pid))
+    }
+
+    fn uid(&self, pid: u32) -> Option<u32> {
+        self.procfs.uid(self.host_pid(pid))
+    }
+}
+
+// The provider `--process-info` names: "procfs", "pidfd", or
+// "cgroup:<path>"
+pub fn provider(spec: &str) -> Result<Box<dyn ProcessInfoProvider>, String> {
+    match spec.split_once(':') {
+        None if spec == "procfs" => Ok(Box::new(ProcFs::new())),
+        None if spec == "pidfd" => Ok(Box::new(PidFd::new())),
+        Some(("cgroup", path)) if !path.is_empty() => Ok(Box::new(Cgroup::new(path))),
+        _ => Err(format!("invalid process info '{}' (expected procfs, pidfd, or cgroup:<path>)", spec)),
+    }
+}
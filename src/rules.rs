@@ -1,46 +1,693 @@
-use regex::Regex;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::Duration;
 
-use crate::config::{Config, MonitorValue, PositionValue, Rule, SizeValue};
+use regex::{Regex, RegexBuilder};
+
+use crate::config::{
+    Config, CountValue, MarginValue, MaximizeValue, MonitorValue, PatternValue, PositionValue, PropValue, Rule,
+    SessionRestore, Settings, SizeValue, Startup, WhenValue, WorkspaceValue,
+};
+use crate::condition;
+use crate::exec::CommandEnv;
+use crate::matchers::{
+    CompositorMatcher, Field, GroupMatcher, Matcher, MonitorCountMatcher, MonitorMatcher, Needs, Not,
+    PatternMatcher, ProcessEnvMatcher, SizeMatcher, StartupMatcher, StateMatcher, TypeMatcher, UidMatcher,
+    WindowProps, WorkspaceMatcher,
+};
 
 pub struct CompiledRule {
-    // Matchers
-    pub class: Option<Regex>,
-    pub title: Option<Regex>,
-    pub role: Option<Regex>,
-    pub process: Option<Regex>,
-    pub window_type: Option<String>,
-
-    // Actions
-    pub workspace: Option<u32>,
-    pub monitor: Option<MonitorTarget>,
-    pub position: Option<PositionTarget>,
-    pub size: Option<SizeTarget>,
-    pub maximize: Option<bool>,
-    pub fullscreen: Option<bool>,
-    pub pin: Option<bool>,
-    pub minimize: Option<bool>,
-    pub shade: Option<bool>,
-    pub above: Option<bool>,
-    pub below: Option<bool>,
-    pub decorate: Option<bool>,
-    pub focus: Option<bool>,
-    pub opacity: Option<f64>,
-}
-
-#[derive(Debug, Clone)]
+    // Position in the config file, for messages; evaluation order is by
+    // priority
+    pub index: usize,
+    pub name: Option<String>,
+    pub priority: i32,
+    pub trigger: Trigger,
+    pub apply: Apply,
+    pub placement: Placement,
+    pub target: Option<GeometryTarget>,
+    // Kept free along the monitor edges
+    pub margin: Margin,
+    // No later rule is evaluated for the window once this one matched
+    pub stop: bool,
+    // From [[monitor_defaults]]: only applies if no other rule matched
+    pub fallback: bool,
+    // Desktop notification when an action fails
+    pub alert_on_failure: bool,
+    // Workspace and monitor moves also apply to the window's group
+    pub move_group: bool,
+    // Follow the group's main window to its workspace and monitor
+    pub move_with_main: bool,
+    pub scope: Scope,
+    // Scratchpad the matched window is hidden into
+    pub scratchpad: Option<String>,
+    // Treatment of windows that existed at startup, from [startup]
+    pub startup: StartupMode,
+    pub session_restore: Option<RestoreSuppression>,
+
+    // All must match
+    pub matchers: Vec<Box<dyn Matcher>>,
+
+    // Actions, in application order
+    pub actions: Vec<Action>,
+
+    // Environment for any commands the rule runs
+    pub command_env: CommandEnv,
+    // Wait this long after the match before applying
+    pub delay: Option<Duration>,
+    // Put the window back when it moves or resizes itself
+    pub enforce: bool,
+    // With remember = true: the title patterns, part of the memory key
+    pub remember: Option<String>,
+    // Match a new window only once its title has been unchanged for the
+    // first duration, or the second has passed since it appeared
+    pub wait_for_title: Option<(Duration, Duration)>,
+    // Some(true) rounds sizes to the window's resize increments, Some(false)
+    // clears them for the resize; None leaves them to the window manager
+    pub honor_size_hints: Option<bool>,
+    // Latency from a window appearing to the rule applied that is logged
+    // as slow, from settings.slow_apply_ms
+    pub slow_apply: Option<Duration>,
+
+    // Stop applying after this many windows; counted since the rule set
+    // was loaded
+    pub max_matches: Option<u32>,
+    match_count: Cell<u32>,
+}
+
+// Default for settings.slow_apply_ms
+pub const DEFAULT_SLOW_APPLY: Duration = Duration::from_millis(100);
+
+// Defaults for title_settle_ms and title_timeout_ms
+pub const DEFAULT_TITLE_SETTLE: Duration = Duration::from_millis(250);
+pub const DEFAULT_TITLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// How string matcher patterns are interpreted. Whatever the mode, a
+// pattern starting with '=' is an exact match on the rest of the string.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchMode {
+    Regex,
+    // '*' any run, '?' one character, [...] a class; anchored both ends
+    Glob,
+    Exact,
+    Substring,
+}
+
+pub const MATCH_MODES: &[&str] = &["regex", "glob", "exact", "substring"];
+
+impl MatchMode {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "regex" => Ok(MatchMode::Regex),
+            "glob" => Ok(MatchMode::Glob),
+            "exact" => Ok(MatchMode::Exact),
+            "substring" => Ok(MatchMode::Substring),
+            _ => Err(format!("unknown match mode '{}'", name)),
+        }
+    }
+
+    // Regex source equivalent to `pattern` under this mode
+    pub fn to_regex(self, pattern: &str) -> String {
+        if let Some(exact) = pattern.strip_prefix('=') {
+            return format!("^{}$", regex::escape(exact));
+        }
+        match self {
+            MatchMode::Regex => pattern.to_string(),
+            MatchMode::Glob => glob_to_regex(pattern),
+            MatchMode::Exact => format!("^{}$", regex::escape(pattern)),
+            MatchMode::Substring => regex::escape(pattern),
+        }
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut out = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => out.push_str(".*"),
+            '?' => out.push('.'),
+            '[' => {
+                // Character class, copied through; [!...] negates as in sh
+                out.push('[');
+                if chars.next_if_eq(&'!').is_some() {
+                    out.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == '\\' {
+                        out.push('\\');
+                    }
+                    out.push(c);
+                    if c == ']' {
+                        break;
+                    }
+                }
+            }
+            _ => out.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    out.push('$');
+    out
+}
+
+// When a rule is evaluated against a window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    // Once, when the window appears
+    Map,
+    // Each time the window gains focus; reversible actions are undone when
+    // it loses focus again
+    Focus,
+    // Each time the current workspace changes, for every window on the
+    // workspace switched to
+    WorkspaceSwitch,
+}
+
+pub const TRIGGERS: &[&str] = &["map", "focus", "workspace-switch"];
+
+impl Trigger {
+    // Config value the trigger was compiled from
+    pub fn name(self) -> &'static str {
+        match self {
+            Trigger::Map => "map",
+            Trigger::Focus => "focus",
+            Trigger::WorkspaceSwitch => "workspace-switch",
+        }
+    }
+}
+
+// `<op> <n>` condition on a count, e.g. ">= 2"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comparison {
+    pub op: CmpOp,
+    pub value: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        // Two-character operators first so ">=" isn't read as ">"
+        let ops = [
+            (">=", CmpOp::Ge),
+            ("<=", CmpOp::Le),
+            ("==", CmpOp::Eq),
+            ("!=", CmpOp::Ne),
+            (">", CmpOp::Gt),
+            ("<", CmpOp::Lt),
+            ("=", CmpOp::Eq),
+        ];
+        let (op, rest) = ops
+            .iter()
+            .find_map(|(sym, op)| s.strip_prefix(sym).map(|rest| (*op, rest)))
+            .unwrap_or((CmpOp::Eq, s));
+        let value = rest
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid comparison '{}' (expected e.g. \">= 2\")", s))?;
+        Ok(Self { op, value })
+    }
+
+    pub fn holds(&self, n: u32) -> bool {
+        match self.op {
+            CmpOp::Eq => n == self.value,
+            CmpOp::Ne => n != self.value,
+            CmpOp::Lt => n < self.value,
+            CmpOp::Le => n <= self.value,
+            CmpOp::Gt => n > self.value,
+            CmpOp::Ge => n >= self.value,
+        }
+    }
+}
+
+// Whether a map rule re-fires as the window's properties change after it
+// appeared. Rules that did not match at first get another chance too.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Apply {
+    Once,
+    // On any change to title, class, role, or window type
+    Always,
+    OnTitleChange,
+}
+
+pub const APPLY_MODES: &[&str] = &["once", "always", "on_title_change"];
+
+// Which windows a matching rule acts on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    // The matched window alone
+    Window,
+    // Also every other open window of the same class or process
+    Application,
+}
+
+pub const SCOPES: &[&str] = &["window", "application"];
+
+// How position and size actions reach the window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    // Move/resize requests to the window manager
+    Configure,
+    // Program-specified position/size in WM_NORMAL_HINTS, for WMs that
+    // only honor hints
+    Hints,
+}
+
+pub const PLACEMENTS: &[&str] = &["configure", "hints"];
+
+// Pixels kept free along each edge of the monitor. Named anchors,
+// percentages, and millimeters resolve within the remaining area;
+// absolute pixel values are left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Margin {
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub left: u32,
+}
+
+impl Margin {
+    fn from_value(value: &MarginValue) -> Self {
+        match *value {
+            MarginValue::Uniform(m) => Self { top: m, right: m, bottom: m, left: m },
+            MarginValue::Sides([top, right, bottom, left]) => Self { top, right, bottom, left },
+        }
+    }
+
+    // The monitor area (x, y, width, height) inside the margin, never
+    // narrower than one pixel
+    pub fn inset(&self, x: i32, y: i32, width: u32, height: u32) -> (i32, i32, u32, u32) {
+        (
+            x + self.left as i32,
+            y + self.top as i32,
+            width.saturating_sub(self.left + self.right).max(1),
+            height.saturating_sub(self.top + self.bottom).max(1),
+        )
+    }
+}
+
+// What position and size coordinates describe. Unset, they are passed to
+// the window manager as-is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeometryTarget {
+    // The decorated frame, including _NET_FRAME_EXTENTS
+    Frame,
+    // The client area inside the decorations
+    Client,
+}
+
+pub const GEOMETRY_TARGETS: &[&str] = &["frame", "client"];
+
+// Actions held back while a session manager restores the previous
+// session after login, from [session_restore]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestoreSuppression {
+    // Counted from daemon start
+    pub window: Duration,
+    // Action names
+    pub actions: Vec<String>,
+}
+
+impl RestoreSuppression {
+    fn resolve(restore: &SessionRestore) -> Option<Self> {
+        let seconds = restore.seconds.filter(|&s| s > 0)?;
+        let actions = match restore.suppress {
+            Some(ref list) => list.clone(),
+            None => vec!["focus".into(), "above".into()],
+        };
+        Some(Self { window: Duration::from_secs(seconds), actions })
+    }
+}
+
+// What the pass over windows that existed at startup does with a map rule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StartupMode {
+    Apply,
+    // Log the match without applying it
+    DryRun,
+    Skip,
+}
+
+impl StartupMode {
+    fn resolve(startup: &Startup, index: usize) -> Self {
+        let listed = startup.rules.as_ref().is_none_or(|list| list.contains(&index));
+        if startup.enabled == Some(false) || !listed {
+            StartupMode::Skip
+        } else if startup.dry_run == Some(true) {
+            StartupMode::DryRun
+        } else {
+            StartupMode::Apply
+        }
+    }
+}
+
+// A single thing a rule does to a window. Backends consume these in order;
+// compile() emits Monitor first and Size before Position so that named
+// anchors can be resolved against the final window size.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    // Hook waited on (up to exec::BEFORE_TIMEOUT) before anything else
+    ExecBefore(String),
+    Monitor(MonitorTarget),
+    Size(SizeTarget),
+    // Relative to the current geometry
+    ResizeBy(i32, i32),
+    // Clamp the size into a bound; with true, also write the bound to
+    // WM_NORMAL_HINTS so the window can't be resized past it later
+    MinSize(SizeTarget, bool),
+    MaxSize(SizeTarget, bool),
+    // Width:height ratio to reshape the window to
+    Aspect(u32, u32),
+    Position(PositionTarget),
+    MoveBy(i32, i32),
+    Workspace(WorkspaceTarget),
+    // Switch the current workspace to the one Workspace moved the window to
+    Follow(bool),
+    Maximize(bool),
+    // Maximized along one axis only
+    MaximizeAxis(Axis),
+    Fullscreen(bool),
+    Pin(bool),
+    Sticky(bool),
+    // Pager hints, ahead of Minimize so the animation can use them
+    IconGeometry([u32; 4]),
+    HandledIcons(bool),
+    Minimize(bool),
+    Shade(bool),
+    Above(bool),
+    Below(bool),
+    Decorate(bool),
+    // X border width and pixel (0xRRGGBB); unset parts are left alone
+    Border(Option<u32>, Option<u32>),
+    // Pixels reserved along an edge of the window's monitor
+    Strut(Edge, u32),
+    // PNG path for _NET_WM_ICON
+    Icon(String),
+    Focus(bool),
+    Opacity(f64),
+    // Compositor hints: blur behind the window, draw a shadow
+    Blur(bool),
+    Shadow(bool),
+    // Arbitrary properties, for other tools to read
+    SetProperty(String, PropValue),
+    DeleteProperty(String),
+    // Screenshot to a path template, after the window is placed
+    Capture(String),
+    // Shell command told about the window (CommandEnv::hook)
+    Exec(String),
+    // Politely, via the window manager, or by force; always last
+    Close(bool),
+    Kill(bool),
+    // Hook started once every other action has been sent
+    ExecAfter(String),
+}
+
+// Config keys of every action, as returned by Action::name
+pub const ACTION_NAMES: &[&str] = &[
+    "exec_before",
+    "monitor",
+    "size",
+    "resize_by",
+    "min_size",
+    "max_size",
+    "aspect",
+    "position",
+    "move_by",
+    "workspace",
+    "follow",
+    "maximize",
+    "fullscreen",
+    "pin",
+    "sticky",
+    "icon_geometry",
+    "handled_icons",
+    "minimize",
+    "shade",
+    "above",
+    "below",
+    "decorate",
+    "border",
+    "strut",
+    "icon",
+    "focus",
+    "opacity",
+    "blur",
+    "shadow",
+    "set_prop",
+    "del_prop",
+    "capture",
+    "exec",
+    "close",
+    "kill",
+    "exec_after",
+];
+
+impl Action {
+    // Config key the action was compiled from
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Monitor(_) => "monitor",
+            Action::Size(_) => "size",
+            Action::Position(_) => "position",
+            Action::ResizeBy(..) => "resize_by",
+            Action::MinSize(..) => "min_size",
+            Action::MaxSize(..) => "max_size",
+            Action::Aspect(..) => "aspect",
+            Action::MoveBy(..) => "move_by",
+            Action::Workspace(_) => "workspace",
+            Action::Follow(_) => "follow",
+            Action::Maximize(_) | Action::MaximizeAxis(_) => "maximize",
+            Action::Fullscreen(_) => "fullscreen",
+            Action::Pin(_) => "pin",
+            Action::Sticky(_) => "sticky",
+            Action::IconGeometry(_) => "icon_geometry",
+            Action::HandledIcons(_) => "handled_icons",
+            Action::Minimize(_) => "minimize",
+            Action::Shade(_) => "shade",
+            Action::Above(_) => "above",
+            Action::Below(_) => "below",
+            Action::Decorate(_) => "decorate",
+            Action::Border(..) => "border",
+            Action::Strut(..) => "strut",
+            Action::Icon(_) => "icon",
+            Action::Focus(_) => "focus",
+            Action::Opacity(_) => "opacity",
+            Action::Blur(_) => "blur",
+            Action::Shadow(_) => "shadow",
+            Action::SetProperty(..) => "set_prop",
+            Action::DeleteProperty(_) => "del_prop",
+            Action::Capture(_) => "capture",
+            Action::Exec(_) => "exec",
+            Action::ExecBefore(_) => "exec_before",
+            Action::ExecAfter(_) => "exec_after",
+            Action::Close(_) => "close",
+            Action::Kill(_) => "kill",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Monitor(MonitorTarget::Index(i)) => write!(f, "monitor -> {}", i),
+            Action::Monitor(MonitorTarget::Name(n)) => write!(f, "monitor -> '{}'", n),
+            Action::Monitor(MonitorTarget::LeastPopulated) => write!(f, "monitor -> least-populated"),
+            Action::Monitor(MonitorTarget::Emptiest) => write!(f, "monitor -> emptiest"),
+            Action::Monitor(MonitorTarget::Primary) => write!(f, "monitor -> primary"),
+            Action::Monitor(MonitorTarget::Current) => write!(f, "monitor -> current"),
+            Action::Monitor(MonitorTarget::Largest) => write!(f, "monitor -> largest"),
+            Action::Monitor(MonitorTarget::External) => write!(f, "monitor -> external"),
+            Action::Size(sz) => write!(f, "size -> {:?}", sz),
+            Action::Position(pos) => write!(f, "position -> {:?}", pos),
+            Action::ResizeBy(dw, dh) => write!(f, "resize_by -> {:+},{:+}", dw, dh),
+            Action::MinSize(sz, hints) => write!(f, "min_size -> {:?}{}", sz, if *hints { " (hinted)" } else { "" }),
+            Action::MaxSize(sz, hints) => write!(f, "max_size -> {:?}{}", sz, if *hints { " (hinted)" } else { "" }),
+            Action::Aspect(w, h) => write!(f, "aspect -> {}:{}", w, h),
+            Action::MoveBy(dx, dy) => write!(f, "move_by -> {:+},{:+}", dx, dy),
+            Action::Workspace(WorkspaceTarget::Index(ws)) => write!(f, "workspace -> {}", ws),
+            Action::Workspace(WorkspaceTarget::Name(name)) => write!(f, "workspace -> '{}'", name),
+            Action::Workspace(WorkspaceTarget::LeastPopulated(among)) if among.is_empty() => {
+                write!(f, "workspace -> least-populated")
+            }
+            Action::Workspace(WorkspaceTarget::LeastPopulated(among)) => {
+                write!(f, "workspace -> least-populated of {:?}", among)
+            }
+            Action::Pin(true) => write!(f, "pin (all workspaces)"),
+            Action::Opacity(o) => write!(f, "opacity -> {}", o),
+            Action::Decorate(d) => write!(f, "decorate -> {}", d),
+            Action::Border(width, color) => {
+                write!(f, "border ->")?;
+                if let Some(width) = width {
+                    write!(f, " {}px", width)?;
+                }
+                if let Some(color) = color {
+                    write!(f, " #{:06x}", color)?;
+                }
+                Ok(())
+            }
+            Action::Strut(edge, size) => write!(f, "strut -> {}px {:?}", size, edge),
+            Action::Blur(b) => write!(f, "blur -> {}", b),
+            Action::Shadow(s) => write!(f, "shadow -> {}", s),
+            Action::SetProperty(name, PropValue::Cardinal(n)) => write!(f, "set_prop -> {} = {}", name, n),
+            Action::SetProperty(name, PropValue::Text(s)) => write!(f, "set_prop -> {} = '{}'", name, s),
+            Action::DeleteProperty(name) => write!(f, "del_prop -> {}", name),
+            Action::Icon(path) => write!(f, "icon -> '{}'", path),
+            Action::Capture(path) => write!(f, "capture -> '{}'", path),
+            Action::Exec(line) | Action::ExecBefore(line) | Action::ExecAfter(line) => {
+                write!(f, "{} -> '{}'", self.name(), line)
+            }
+            Action::MaximizeAxis(Axis::Horizontal) => write!(f, "maximize -> horizontal"),
+            Action::MaximizeAxis(Axis::Vertical) => write!(f, "maximize -> vertical"),
+            Action::IconGeometry([x, y, width, height]) => {
+                write!(f, "icon_geometry -> {}x{} at {},{}", width, height, x, y)
+            }
+            Action::Follow(true)
+            | Action::Maximize(true)
+            | Action::Fullscreen(true)
+            | Action::Sticky(true)
+            | Action::HandledIcons(true)
+            | Action::Minimize(true)
+            | Action::Shade(true)
+            | Action::Above(true)
+            | Action::Below(true)
+            | Action::Focus(true)
+            | Action::Close(true)
+            | Action::Kill(true) => write!(f, "{}", self.name()),
+            Action::Follow(false)
+            | Action::Maximize(false)
+            | Action::Fullscreen(false)
+            | Action::Pin(false)
+            | Action::Sticky(false)
+            | Action::HandledIcons(false)
+            | Action::Minimize(false)
+            | Action::Shade(false)
+            | Action::Above(false)
+            | Action::Below(false)
+            | Action::Focus(false)
+            | Action::Close(false)
+            | Action::Kill(false) => write!(f, "{} -> false", self.name()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkspaceTarget {
+    Index(u32),
+    // Looked up in the desktop names (_NET_DESKTOP_NAMES) when applied
+    Name(String),
+    // Candidate workspaces; empty means all of them
+    LeastPopulated(Vec<u32>),
+}
+
+pub const WORKSPACE_TARGETS: &[&str] = &["least-populated"];
+
+impl WorkspaceTarget {
+    // Workspace to send a window to, given how many workspaces exist, their
+    // names, and the workspace of every other window. Ties go to the
+    // earliest candidate.
+    pub fn resolve(&self, count: u32, names: &[String], occupied: &[u32]) -> Option<u32> {
+        match self {
+            WorkspaceTarget::Index(ws) => Some(*ws),
+            WorkspaceTarget::Name(name) => names.iter().position(|n| n == name).map(|i| i as u32),
+            WorkspaceTarget::LeastPopulated(among) => {
+                let all: Vec<u32> = (0..count).collect();
+                let candidates = if among.is_empty() { &all } else { among };
+                candidates
+                    .iter()
+                    .copied()
+                    .min_by_key(|ws| occupied.iter().filter(|&&o| o == *ws).count())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MonitorTarget {
     Index(u32),
     Name(String),
+    // Output with the fewest windows on it
+    LeastPopulated,
+    // Output with no windows on it; none if every output has one
+    Emptiest,
+    // RandR primary output
+    Primary,
+    // Output under the pointer
+    Current,
+    // Output with the most pixels
+    Largest,
+    // First output that isn't a built-in laptop panel
+    External,
 }
 
-#[derive(Debug, Clone)]
+pub const MONITOR_TARGETS: &[&str] = &["least-populated", "emptiest", "primary", "current", "largest", "external"];
+
+// What the layout keywords know about an output
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputInfo<'a> {
+    pub name: &'a str,
+    pub width: u32,
+    pub height: u32,
+    pub primary: bool,
+}
+
+// Built-in panels, by the RandR output name drivers give them
+pub fn is_builtin_output(name: &str) -> bool {
+    ["eDP", "LVDS", "DSI"].iter().any(|prefix| name.starts_with(prefix))
+}
+
+impl MonitorTarget {
+    // For the population targets, the monitor to use given how many other
+    // windows each monitor holds. Ties go to the lowest index.
+    pub fn pick_by_population(&self, counts: &[usize]) -> Option<usize> {
+        let least = (0..counts.len()).min_by_key(|&i| counts[i]);
+        match self {
+            MonitorTarget::LeastPopulated => least,
+            MonitorTarget::Emptiest => least.filter(|&i| counts[i] == 0),
+            _ => None,
+        }
+    }
+
+    // For primary, largest and external, the monitor to use given the
+    // outputs. Ties go to the lowest index.
+    pub fn pick_by_layout(&self, outputs: &[OutputInfo]) -> Option<usize> {
+        match self {
+            MonitorTarget::Primary => outputs.iter().position(|o| o.primary),
+            MonitorTarget::Largest => (0..outputs.len())
+                .rev()
+                .max_by_key(|&i| outputs[i].width as u64 * outputs[i].height as u64),
+            MonitorTarget::External => outputs.iter().position(|o| !is_builtin_output(o.name)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum PositionTarget {
     Absolute(i32, i32),
     Named(NamedPosition),
+    // A named anchor moved by a pixel offset
+    Anchored(NamedPosition, i32, i32),
     Flexible(DimensionVal, DimensionVal),
+    // Centered over the WM_TRANSIENT_FOR parent's frame, else the monitor
+    ParentCenter,
+    // Where it overlaps the other visible windows least (smart_position)
+    Smart,
+    // Top-left corner at the pointer, moved by a pixel offset (at_pointer)
+    Pointer(i32, i32),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum NamedPosition {
     Center,
     TopLeft,
@@ -53,73 +700,807 @@ pub enum NamedPosition {
     Bottom,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SizeTarget {
     Absolute(u32, u32),
     Flexible(DimensionVal, DimensionVal),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DimensionVal {
     Pixels(i32),
     Percent(f64),
+    Millimeters(f64),
+}
+
+// Equal cells over a monitor, numbered from 0 left to right, then top to
+// bottom
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grid {
+    pub cols: u32,
+    pub rows: u32,
+}
+
+impl Grid {
+    // "<cols>x<rows>", e.g. "3x2"
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid grid '{}' (expected <cols>x<rows>)", s);
+        let (cols, rows) = s.split_once('x').ok_or_else(invalid)?;
+        let cols: u32 = cols.parse().map_err(|_| invalid())?;
+        let rows: u32 = rows.parse().map_err(|_| invalid())?;
+        if cols == 0 || rows == 0 {
+            return Err(invalid());
+        }
+        // Cells are numbered with a u32
+        if cols.checked_mul(rows).is_none() {
+            return Err(format!("grid '{}' has too many cells", s));
+        }
+        Ok(Self { cols, rows })
+    }
+
+    // Size and position of one cell, relative to the monitor
+    pub fn cell(&self, cell: u32) -> Result<(SizeTarget, PositionTarget), String> {
+        let cells = self.cols as u64 * self.rows as u64;
+        if cell as u64 >= cells {
+            return Err(format!(
+                "cell {} is outside a {}x{} grid (0-{})",
+                cell,
+                self.cols,
+                self.rows,
+                cells.saturating_sub(1)
+            ));
+        }
+        self.span(cell % self.cols, cell / self.cols, 1, 1)
+    }
+
+    // Size and position of `cols` x `rows` cells from column `col`, row
+    // `row` (both from 0), relative to the monitor
+    pub fn span(
+        &self,
+        col: u32,
+        row: u32,
+        cols: u32,
+        rows: u32,
+    ) -> Result<(SizeTarget, PositionTarget), String> {
+        if cols == 0 || rows == 0 {
+            return Err(format!("cell [{}, {}, {}, {}] spans no cells", col, row, cols, rows));
+        }
+        if col.checked_add(cols).is_none_or(|end| end > self.cols)
+            || row.checked_add(rows).is_none_or(|end| end > self.rows)
+        {
+            return Err(format!(
+                "cell [{}, {}, {}, {}] is outside the {}x{} grid",
+                col, row, cols, rows, self.cols, self.rows
+            ));
+        }
+        let frac = |n: u32, of: u32| DimensionVal::Percent(n as f64 / of as f64);
+        Ok((
+            SizeTarget::Flexible(frac(cols, self.cols), frac(rows, self.rows)),
+            PositionTarget::Flexible(frac(col, self.cols), frac(row, self.rows)),
+        ))
+    }
+}
+
+// Named positions that also set the size, for tiling on stacking window
+// managers: (x, y, width, height) as fractions of the monitor
+const TILES: &[(&str, [f64; 4])] = &[
+    ("left-half", [0.0, 0.0, 0.5, 1.0]),
+    ("right-half", [0.5, 0.0, 0.5, 1.0]),
+    ("top-half", [0.0, 0.0, 1.0, 0.5]),
+    ("bottom-half", [0.0, 0.5, 1.0, 0.5]),
+    ("top-left-quarter", [0.0, 0.0, 0.5, 0.5]),
+    ("top-right-quarter", [0.5, 0.0, 0.5, 0.5]),
+    ("bottom-left-quarter", [0.0, 0.5, 0.5, 0.5]),
+    ("bottom-right-quarter", [0.5, 0.5, 0.5, 0.5]),
+    ("left-third", [0.0, 0.0, 1.0 / 3.0, 1.0]),
+    ("center-third", [1.0 / 3.0, 0.0, 1.0 / 3.0, 1.0]),
+    ("right-third", [2.0 / 3.0, 0.0, 1.0 / 3.0, 1.0]),
+    ("left-two-thirds", [0.0, 0.0, 2.0 / 3.0, 1.0]),
+    ("right-two-thirds", [1.0 / 3.0, 0.0, 2.0 / 3.0, 1.0]),
+];
+
+pub fn tile_names() -> impl Iterator<Item = &'static str> {
+    TILES.iter().map(|(name, _)| *name)
+}
+
+// Size and position of a named tile, relative to the monitor
+pub fn tile(name: &str) -> Option<(SizeTarget, PositionTarget)> {
+    let (_, [x, y, w, h]) = TILES.iter().find(|(n, _)| *n == name)?;
+    Some((
+        SizeTarget::Flexible(DimensionVal::Percent(*w), DimensionVal::Percent(*h)),
+        PositionTarget::Flexible(DimensionVal::Percent(*x), DimensionVal::Percent(*y)),
+    ))
+}
+
+// Offset between cascaded windows when smart placement finds no free spot
+pub const CASCADE_STEP: i32 = 32;
+
+// Top-left corner for a window of `size` inside `area` (x, y, width,
+// height) that overlaps the `occupied` rectangles least. The spots tried
+// are the area's corners and the edges of each occupied rectangle; the
+// topmost, then leftmost, wins a tie. When every spot overlaps something,
+// windows cascade from the area's corner instead, taking the first step
+// no window's corner is already near.
+pub fn smart_position(
+    area: (i32, i32, u32, u32),
+    size: (u32, u32),
+    occupied: &[(i32, i32, u32, u32)],
+) -> (i32, i32) {
+    let (ax, ay, aw, ah) = (area.0, area.1, area.2 as i32, area.3 as i32);
+    let (w, h) = (size.0 as i32, size.1 as i32);
+    if w >= aw || h >= ah {
+        return (ax, ay);
+    }
+
+    let mut xs = vec![ax, ax + aw - w];
+    let mut ys = vec![ay, ay + ah - h];
+    for &(x, y, rw, rh) in occupied {
+        xs.extend([x + rw as i32, x - w]);
+        ys.extend([y + rh as i32, y - h]);
+    }
+    let overlap = |x: i32, y: i32| -> i64 {
+        occupied
+            .iter()
+            .map(|&(ox, oy, ow, oh)| {
+                let dx = (x + w).min(ox + ow as i32) - x.max(ox);
+                let dy = (y + h).min(oy + oh as i32) - y.max(oy);
+                dx.max(0) as i64 * dy.max(0) as i64
+            })
+            .sum()
+    };
+    let best = ys
+        .iter()
+        .flat_map(|&y| xs.iter().map(move |&x| (x, y)))
+        .filter(|&(x, y)| x >= ax && y >= ay && x + w <= ax + aw && y + h <= ay + ah)
+        .min_by_key(|&(x, y)| (overlap(x, y), y, x))
+        .unwrap_or((ax, ay));
+    if overlap(best.0, best.1) == 0 {
+        return best;
+    }
+
+    let taken = |x: i32, y: i32| {
+        occupied
+            .iter()
+            .any(|&(ox, oy, _, _)| (ox - x).abs() < CASCADE_STEP / 2 && (oy - y).abs() < CASCADE_STEP / 2)
+    };
+    (0..)
+        .map(|k| (ax + k * CASCADE_STEP, ay + k * CASCADE_STEP))
+        .take_while(|&(x, y)| x + w <= ax + aw && y + h <= ay + ah)
+        .find(|&(x, y)| !taken(x, y))
+        .unwrap_or(best)
+}
+
+// Top-left corner for a window of `size` at `pointer` plus `offset`,
+// pulled back inside `area` (x, y, width, height) where it would run off
+// the right or bottom edge
+pub fn at_pointer(
+    pointer: (i32, i32),
+    offset: (i32, i32),
+    size: (u32, u32),
+    area: (i32, i32, u32, u32),
+) -> (i32, i32) {
+    let (ax, ay, aw, ah) = (area.0, area.1, area.2 as i32, area.3 as i32);
+    let x = (pointer.0 + offset.0).min(ax + aw - size.0 as i32).max(ax);
+    let y = (pointer.1 + offset.1).min(ay + ah - size.1 as i32).max(ay);
+    (x, y)
+}
+
+// `size` rounded to the nearest base + n * increment in each dimension,
+// the sizes ICCCM size hints allow; an increment of 0 or 1 leaves it be
+pub fn snap_to_increments(size: (u32, u32), base: (u32, u32), inc: (u32, u32)) -> (u32, u32) {
+    let snap = |len: u32, base: u32, inc: u32| {
+        if inc <= 1 || len <= base {
+            return len.max(base);
+        }
+        base + (len - base + inc / 2) / inc * inc
+    };
+    (snap(size.0, base.0, inc.0), snap(size.1, base.1, inc.1))
+}
+
+// One direction of _NET_WM_STATE_MAXIMIZED_*
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+impl Axis {
+    pub fn parse(s: &str) -> Option<Axis> {
+        match s {
+            "horizontal" => Some(Axis::Horizontal),
+            "vertical" => Some(Axis::Vertical),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Edge {
+    pub fn parse(s: &str) -> Option<Edge> {
+        match s {
+            "left" => Some(Edge::Left),
+            "right" => Some(Edge::Right),
+            "top" => Some(Edge::Top),
+            "bottom" => Some(Edge::Bottom),
+            _ => None,
+        }
+    }
+}
+
+// _NET_WM_STRUT_PARTIAL reserving `size` pixels along one edge of
+// `monitor`, over the stretch of that edge the window covers. Struts count
+// from the edge of the whole screen, so a monitor away from it adds its
+// distance. All rectangles are (x, y, width, height).
+pub fn strut_partial(
+    edge: Edge,
+    size: u32,
+    window: (i32, i32, u32, u32),
+    monitor: (i32, i32, u32, u32),
+    screen: (u32, u32),
+) -> [u32; 12] {
+    let (x, y, w, h) = window;
+    let (mx, my, mw, mh) = monitor;
+    let span = |start: i32, len: u32| (start.max(0) as u32, (start + len as i32 - 1).max(0) as u32);
+    let mut strut = [0; 12];
+    match edge {
+        Edge::Left => {
+            strut[0] = mx.max(0) as u32 + size;
+            (strut[4], strut[5]) = span(y, h);
+        }
+        Edge::Right => {
+            strut[1] = screen.0.saturating_sub((mx + mw as i32).max(0) as u32) + size;
+            (strut[6], strut[7]) = span(y, h);
+        }
+        Edge::Top => {
+            strut[2] = my.max(0) as u32 + size;
+            (strut[8], strut[9]) = span(x, w);
+        }
+        Edge::Bottom => {
+            strut[3] = screen.1.saturating_sub((my + mh as i32).max(0) as u32) + size;
+            (strut[10], strut[11]) = span(x, w);
+        }
+    }
+    strut
+}
+
+// A "#rrggbb" color as 0xRRGGBB, the pixel value on TrueColor visuals
+pub fn parse_color(s: &str) -> Option<u32> {
+    let hex = s.strip_prefix('#').filter(|h| h.len() == 6)?;
+    u32::from_str_radix(hex, 16).ok()
+}
+
+// An aspect ratio such as "16:9"
+pub fn parse_aspect(s: &str) -> Option<(u32, u32)> {
+    let (w, h) = s.split_once(':')?;
+    let (w, h) = (w.trim().parse().ok()?, h.trim().parse().ok()?);
+    (w > 0 && h > 0).then_some((w, h))
+}
+
+// The size with ratio `w:h` nearest to `size`: whichever of its width or
+// height needs the smaller change is kept, then the result is scaled down
+// to fit `bounds`
+pub fn fit_aspect(size: (u32, u32), (w, h): (u32, u32), bounds: (u32, u32)) -> (u32, u32) {
+    let ratio = w as f64 / h as f64;
+    let (width, height) = (size.0 as f64, size.1 as f64);
+    let by_width = (width, width / ratio);
+    let by_height = (height * ratio, height);
+    let (mut fw, mut fh) = if (by_width.1 - height).abs() <= (by_height.0 - width).abs() {
+        by_width
+    } else {
+        by_height
+    };
+    let scale = (bounds.0 as f64 / fw).min(bounds.1 as f64 / fh).min(1.0);
+    fw *= scale;
+    fh *= scale;
+    ((fw.round() as u32).max(1), (fh.round() as u32).max(1))
+}
+
+// Pixel density assumed when an output doesn't report its physical size
+const FALLBACK_PX_PER_MM: f64 = 96.0 / 25.4;
+
+impl DimensionVal {
+    // Pixel value along one monitor axis that is `total` pixels and
+    // `total_mm` millimeters long (0 if unknown).
+    pub fn resolve(self, total: i32, total_mm: u32) -> i32 {
+        match self {
+            DimensionVal::Pixels(px) => px,
+            DimensionVal::Percent(pct) => (total as f64 * pct) as i32,
+            DimensionVal::Millimeters(mm) => {
+                let px_per_mm = if total_mm > 0 {
+                    total as f64 / total_mm as f64
+                } else {
+                    FALLBACK_PX_PER_MM
+                };
+                (mm * px_per_mm).round() as i32
+            }
+        }
+    }
 }
 
 impl CompiledRule {
-    fn compile(rule: &Rule) -> Result<Self, String> {
-        let compile_pat = |pat: &Option<String>| -> Result<Option<Regex>, String> {
-            match pat {
-                Some(s) => Regex::new(s)
-                    .map(Some)
-                    .map_err(|e| format!("bad regex '{}': {}", s, e)),
-                None => Ok(None),
+    fn compile(
+        index: usize,
+        rule: &Rule,
+        settings: &Settings,
+        grid: Option<Grid>,
+        class_aliases: &Rc<HashMap<String, String>>,
+        conditions: &HashMap<String, Rc<dyn Matcher>>,
+    ) -> Result<Self, String> {
+        let mut matchers: Vec<Box<dyn Matcher>> = Vec::new();
+
+        let mode = match rule.match_mode.as_deref().or(settings.match_mode.as_deref()) {
+            None => MatchMode::Regex,
+            Some(name) => MatchMode::parse(name)?,
+        };
+        let icase = rule
+            .case_insensitive
+            .or(settings.case_insensitive)
+            .unwrap_or(false);
+
+        let patterns = [
+            (Field::Class, &rule.class),
+            (Field::Title, &rule.title),
+            (Field::Role, &rule.role),
+            (Field::Process, &rule.process),
+        ];
+        for (field, pat) in patterns {
+            if let Some(s) = pat {
+                matchers.push(Box::new(PatternMatcher {
+                    field,
+                    regexes: compile_patterns(s, mode, icase)?,
+                    class_aliases: class_aliases.clone(),
+                }));
+            }
+        }
+
+        if let Some(ref t) = rule.window_type {
+            matchers.push(Box::new(TypeMatcher(t.patterns().to_vec())));
+        }
+
+        let negated = [
+            (Field::Class, &rule.class_not),
+            (Field::Title, &rule.title_not),
+            (Field::Role, &rule.role_not),
+            (Field::Process, &rule.process_not),
+        ];
+        for (field, pat) in negated {
+            if let Some(s) = pat {
+                let inner = PatternMatcher {
+                    field,
+                    regexes: compile_patterns(s, mode, icase)?,
+                    class_aliases: class_aliases.clone(),
+                };
+                matchers.push(Box::new(Not(Box::new(inner))));
+            }
+        }
+
+        if let Some(ref t) = rule.window_type_not {
+            matchers.push(Box::new(Not(Box::new(TypeMatcher(t.patterns().to_vec())))));
+        }
+
+        let size = SizeMatcher {
+            min_width: rule.min_width,
+            max_width: rule.max_width,
+            min_height: rule.min_height,
+            max_height: rule.max_height,
+        };
+        if size.min_width.is_some()
+            || size.max_width.is_some()
+            || size.min_height.is_some()
+            || size.max_height.is_some()
+        {
+            matchers.push(Box::new(size));
+        }
+
+        for s in rule.state.iter().flatten() {
+            matchers.push(Box::new(StateMatcher::parse(s)));
+        }
+
+        if let Some(ws) = rule.on_workspace {
+            matchers.push(Box::new(WorkspaceMatcher(ws)));
+        }
+
+        if let Some(ref mon) = rule.on_monitor {
+            matchers.push(Box::new(MonitorMatcher(compile_monitor(mon))));
+        }
+
+        if let Some(ref vars) = rule.process_env {
+            let compiled = vars
+                .iter()
+                .map(|(name, pat)| Ok((name.clone(), compile_pattern(pat, mode, icase)?)))
+                .collect::<Result<_, String>>()?;
+            matchers.push(Box::new(ProcessEnvMatcher(compiled)));
+        }
+
+        let startup = [(false, &rule.startup_id), (true, &rule.launched_by)];
+        for (launcher, pat) in startup {
+            if let Some(s) = pat {
+                matchers.push(Box::new(StartupMatcher {
+                    launcher,
+                    regexes: compile_patterns(s, mode, icase)?,
+                }));
             }
+        }
+
+        if let Some(ref pat) = rule.group_of {
+            matchers.push(Box::new(GroupMatcher(compile_patterns(pat, mode, icase)?)));
+        }
+
+        if let Some(uid) = rule.uid {
+            matchers.push(Box::new(UidMatcher(uid)));
+        }
+        if let Some(ref user) = rule.user {
+            let uid = user_uid(user).ok_or_else(|| format!("unknown user '{}'", user))?;
+            matchers.push(Box::new(UidMatcher(uid)));
+        }
+
+        if let Some(count) = rule.when.as_ref().and_then(WhenValue::table).and_then(|w| w.monitors.as_ref()) {
+            let cmp = match count {
+                CountValue::Exact(n) => Comparison { op: CmpOp::Eq, value: *n },
+                CountValue::Compare(s) => Comparison::parse(s).map_err(|e| format!("when.monitors: {}", e))?,
+            };
+            matchers.push(Box::new(MonitorCountMatcher(cmp)));
+        }
+
+        if let Some(WhenValue::Named(ref name)) = rule.when {
+            let cond = conditions
+                .get(name)
+                .ok_or_else(|| format!("unknown condition '{}'", name))?;
+            matchers.push(Box::new(cond.clone()));
+        }
+
+        if let Some(want) = rule.if_compositor {
+            matchers.push(Box::new(CompositorMatcher(want)));
+        }
+
+        let trigger = match rule.on.as_deref() {
+            None | Some("map") => Trigger::Map,
+            Some("focus") => Trigger::Focus,
+            Some("workspace-switch") => Trigger::WorkspaceSwitch,
+            Some(other) => return Err(format!("unknown trigger '{}'", other)),
+        };
+
+        let apply = match rule.apply.as_deref() {
+            None | Some("once") => Apply::Once,
+            Some("always") => Apply::Always,
+            Some("on_title_change") => Apply::OnTitleChange,
+            Some(other) => return Err(format!("unknown apply mode '{}'", other)),
+        };
+
+        let scope = match rule.scope.as_deref() {
+            None | Some("window") => Scope::Window,
+            Some("application") => Scope::Application,
+            Some(other) => return Err(format!("unknown scope '{}'", other)),
+        };
+
+        let placement = match rule.placement.as_deref().or(settings.placement.as_deref()) {
+            None | Some("configure") => Placement::Configure,
+            Some("hints") => Placement::Hints,
+            Some(other) => return Err(format!("unknown placement '{}'", other)),
+        };
+        let margin = rule.margin.as_ref().or(settings.margin.as_ref()).map(Margin::from_value).unwrap_or_default();
+
+        let target = match rule.target.as_deref() {
+            None => None,
+            Some("frame") => Some(GeometryTarget::Frame),
+            Some("client") => Some(GeometryTarget::Client),
+            Some(other) => return Err(format!("unknown target '{}'", other)),
         };
 
         Ok(Self {
-            class: compile_pat(&rule.class)?,
-            title: compile_pat(&rule.title)?,
-            role: compile_pat(&rule.role)?,
-            process: compile_pat(&rule.process)?,
-            window_type: rule.window_type.clone(),
-
-            workspace: rule.workspace,
-            monitor: rule.monitor.as_ref().map(compile_monitor),
-            position: rule.position.as_ref().map(compile_position).transpose()?,
-            size: rule.size.as_ref().map(compile_size).transpose()?,
-            maximize: rule.maximize,
-            fullscreen: rule.fullscreen,
-            pin: rule.pin,
-            minimize: rule.minimize,
-            shade: rule.shade,
-            above: rule.above,
-            below: rule.below,
-            decorate: rule.decorate,
-            focus: rule.focus,
-            opacity: rule.opacity,
+            index,
+            name: rule.name.clone(),
+            priority: rule.priority.unwrap_or(0),
+            trigger,
+            apply,
+            placement,
+            target,
+            margin,
+            stop: rule.stop.or(settings.first_match).unwrap_or(false),
+            fallback: false,
+            alert_on_failure: rule.alert_on_failure.unwrap_or(false),
+            move_group: rule.move_group.unwrap_or(false),
+            move_with_main: rule.group.as_deref() == Some("move-with-main"),
+            scope,
+            scratchpad: rule.scratchpad.clone(),
+            startup: StartupMode::Apply,
+            session_restore: None,
+            matchers,
+            actions: compile_actions(rule, grid)?,
+            command_env: CommandEnv::resolve(rule, settings),
+            delay: rule.delay_ms.filter(|&ms| ms > 0).map(Duration::from_millis),
+            enforce: rule.enforce.unwrap_or(false),
+            remember: (rule.remember == Some(true))
+                .then(|| rule.title.as_ref().map(|t| t.patterns().join("|")).unwrap_or_default()),
+            wait_for_title: match rule.wait_for_title {
+                Some(true) => Some((
+                    rule.title_settle_ms.map_or(DEFAULT_TITLE_SETTLE, Duration::from_millis),
+                    rule.title_timeout_ms.map_or(DEFAULT_TITLE_TIMEOUT, Duration::from_millis),
+                )),
+                _ => None,
+            },
+            honor_size_hints: rule.honor_size_hints.or(settings.honor_size_hints),
+            slow_apply: match settings.slow_apply_ms {
+                Some(0) => None,
+                Some(ms) => Some(Duration::from_millis(ms)),
+                None => Some(DEFAULT_SLOW_APPLY),
+            },
+            max_matches: match rule.once_per_session {
+                Some(true) => Some(1),
+                _ => rule.max_matches,
+            },
+            match_count: Cell::new(0),
         })
     }
 
-    pub fn matches(
-        &self,
-        class: &str,
-        title: &str,
-        role: &str,
-        process: &str,
-        window_type: &str,
-    ) -> bool {
-        let class_ok = self.class.as_ref().is_none_or(|re| re.is_match(class));
-        let title_ok = self.title.as_ref().is_none_or(|re| re.is_match(title));
-        let role_ok = self.role.as_ref().is_none_or(|re| re.is_match(role));
-        let process_ok = self.process.as_ref().is_none_or(|re| re.is_match(process));
-        let type_ok = self
-            .window_type
-            .as_ref()
-            .is_none_or(|t| t.eq_ignore_ascii_case(window_type));
-        class_ok && title_ok && role_ok && process_ok && type_ok
+    // "rule[3]", or "rule[3] (slack-ws2)" for a named rule
+    pub fn label(&self) -> String {
+        match self.name {
+            Some(ref name) => format!("rule[{}] ({})", self.index, name),
+            None => format!("rule[{}]", self.index),
+        }
+    }
+
+    pub fn matches(&self, props: &WindowProps) -> bool {
+        self.matchers.iter().all(|m| m.matches(props))
+    }
+
+    // Every matcher but those on the title, for a window whose title
+    // hasn't settled yet
+    pub fn matches_besides_title(&self, props: &WindowProps) -> bool {
+        self.matchers.iter().filter(|m| !m.reads_title()).all(|m| m.matches(props))
+    }
+
+    // Windows this rule has been applied to so far
+    pub fn match_count(&self) -> u32 {
+        self.match_count.get()
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.max_matches.is_some_and(|max| self.match_count.get() >= max)
+    }
+
+    pub fn record_match(&self) {
+        self.match_count.set(self.match_count.get() + 1);
+    }
+
+    // Start max_matches over, as on reload
+    pub fn reset_matches(&self) {
+        self.match_count.set(0);
+    }
+
+    // A copy with only the actions `keep` accepts, for applying part of
+    // the rule. Matchers are not carried over.
+    pub fn with_actions(&self, keep: impl Fn(&Action) -> bool) -> CompiledRule {
+        CompiledRule {
+            index: self.index,
+            name: self.name.clone(),
+            priority: self.priority,
+            trigger: self.trigger,
+            apply: self.apply,
+            placement: self.placement,
+            target: self.target,
+            margin: self.margin,
+            stop: self.stop,
+            fallback: self.fallback,
+            alert_on_failure: self.alert_on_failure,
+            move_group: self.move_group,
+            move_with_main: self.move_with_main,
+            scope: self.scope,
+            scratchpad: self.scratchpad.clone(),
+            startup: self.startup,
+            session_restore: self.session_restore.clone(),
+            matchers: Vec::new(),
+            actions: self.actions.iter().filter(|a| keep(a)).cloned().collect(),
+            command_env: self.command_env.clone(),
+            delay: self.delay,
+            enforce: self.enforce,
+            remember: self.remember.clone(),
+            wait_for_title: self.wait_for_title,
+            honor_size_hints: self.honor_size_hints,
+            slow_apply: self.slow_apply,
+            max_matches: self.max_matches,
+            match_count: Cell::new(self.match_count.get()),
+        }
+    }
+
+    // A rule that matches nothing and only carries actions, for placing a
+    // window on request. Coordinates describe the frame.
+    pub fn from_actions(actions: Vec<Action>) -> CompiledRule {
+        CompiledRule {
+            index: 0,
+            name: None,
+            priority: 0,
+            trigger: Trigger::Map,
+            apply: Apply::Once,
+            placement: Placement::Configure,
+            target: Some(GeometryTarget::Frame),
+            margin: Margin::default(),
+            stop: false,
+            fallback: false,
+            alert_on_failure: false,
+            move_group: false,
+            move_with_main: false,
+            scope: Scope::Window,
+            scratchpad: None,
+            startup: StartupMode::Apply,
+            session_restore: None,
+            matchers: Vec::new(),
+            actions,
+            command_env: CommandEnv::default(),
+            delay: None,
+            enforce: false,
+            remember: None,
+            wait_for_title: None,
+            honor_size_hints: None,
+            slow_apply: None,
+            max_matches: None,
+            match_count: Cell::new(0),
+        }
+    }
+
+    pub fn add_matcher(&mut self, matcher: Box<dyn Matcher>) {
+        self.matchers.push(matcher);
+    }
+
+    pub fn needs(&self) -> Needs {
+        self.matchers
+            .iter()
+            .fold(Needs::default(), |acc, m| acc.union(m.needs()))
+    }
+
+    pub fn monitor(&self) -> Option<&MonitorTarget> {
+        self.actions.iter().find_map(|a| match a {
+            Action::Monitor(m) => Some(m),
+            _ => None,
+        })
     }
 }
 
+fn compile_patterns(
+    pat: &PatternValue,
+    mode: MatchMode,
+    case_insensitive: bool,
+) -> Result<Vec<Regex>, String> {
+    pat.patterns()
+        .iter()
+        .map(|s| compile_pattern(s, mode, case_insensitive))
+        .collect()
+}
+
+fn compile_pattern(pattern: &str, mode: MatchMode, case_insensitive: bool) -> Result<Regex, String> {
+    RegexBuilder::new(&mode.to_regex(pattern))
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|e| format!("bad regex '{}': {}", pattern, e))
+}
+
+fn compile_actions(rule: &Rule, grid: Option<Grid>) -> Result<Vec<Action>, String> {
+    let mut actions = Vec::new();
+
+    if cfg!(not(feature = "capture")) && rule.capture.is_some() {
+        return Err("capture needs cherrypie built with the capture feature".into());
+    }
+
+    if let Some(ref line) = rule.exec_before {
+        actions.push(Action::ExecBefore(line.clone()));
+    }
+    if let Some(ref mon) = rule.monitor {
+        actions.push(Action::Monitor(compile_monitor_action(mon)));
+    }
+    // A tile or grid cell sets the size too; it goes in before the bounds
+    // are applied
+    let placed = match (&rule.position, rule.cell) {
+        (Some(PositionValue::Named(name)), _) if let Some(tiled) = tile(name) => Some(tiled),
+        (_, Some([col, row, cols, rows])) => {
+            let grid = grid.ok_or("cell needs a [grid] table")?;
+            Some(grid.span(col, row, cols, rows)?)
+        }
+        _ => None,
+    };
+    if let Some(ref sz) = rule.size {
+        actions.push(Action::Size(compile_size(sz)?));
+    }
+    if let Some((size, _)) = &placed {
+        actions.push(Action::Size(size.clone()));
+    }
+    if let Some([dw, dh]) = rule.resize_by {
+        actions.push(Action::ResizeBy(dw, dh));
+    }
+    let hints = rule.size_hints.unwrap_or(false);
+    if let Some(ref sz) = rule.min_size {
+        actions.push(Action::MinSize(compile_size(sz)?, hints));
+    }
+    if let Some(ref sz) = rule.max_size {
+        actions.push(Action::MaxSize(compile_size(sz)?, hints));
+    }
+    if let Some(ref ratio) = rule.aspect {
+        let (w, h) = parse_aspect(ratio).ok_or_else(|| format!("invalid aspect '{}'", ratio))?;
+        actions.push(Action::Aspect(w, h));
+    }
+    match (&rule.position, placed) {
+        (_, Some((_, position))) => actions.push(Action::Position(position)),
+        (Some(pos), None) => actions.push(Action::Position(compile_position(pos)?)),
+        (None, None) => {}
+    }
+    if let Some([dx, dy]) = rule.move_by {
+        actions.push(Action::MoveBy(dx, dy));
+    }
+
+    match &rule.workspace {
+        Some(WorkspaceValue::Index(ws)) => actions.push(Action::Workspace(WorkspaceTarget::Index(*ws))),
+        Some(WorkspaceValue::Named(name)) if name == "least-populated" => {
+            let among = rule.workspace_among.clone().unwrap_or_default();
+            actions.push(Action::Workspace(WorkspaceTarget::LeastPopulated(among)));
+        }
+        Some(WorkspaceValue::Named(name)) => actions.push(Action::Workspace(WorkspaceTarget::Name(name.clone()))),
+        None => {}
+    }
+
+    let simple = [
+        rule.follow.map(Action::Follow),
+        match rule.maximize {
+            Some(MaximizeValue::Toggle(on)) => Some(Action::Maximize(on)),
+            Some(MaximizeValue::Axis(ref axis)) if axis == "both" => Some(Action::Maximize(true)),
+            Some(MaximizeValue::Axis(ref axis)) => Some(Action::MaximizeAxis(
+                Axis::parse(axis).ok_or_else(|| format!("invalid maximize '{}'", axis))?,
+            )),
+            None => None,
+        },
+        rule.fullscreen.map(Action::Fullscreen),
+        rule.pin.map(Action::Pin),
+        rule.sticky.map(Action::Sticky),
+        rule.icon_geometry.map(Action::IconGeometry),
+        rule.handled_icons.map(Action::HandledIcons),
+        rule.minimize.or(rule.scratchpad.as_ref().map(|_| true)).map(Action::Minimize),
+        rule.shade.map(Action::Shade),
+        rule.above.map(Action::Above),
+        rule.below.map(Action::Below),
+        rule.decorate.map(Action::Decorate),
+        match rule.border {
+            Some(ref b) => {
+                let color = match b.color {
+                    Some(ref c) => {
+                        Some(parse_color(c).ok_or_else(|| format!("invalid border color '{}'", c))?)
+                    }
+                    None => None,
+                };
+                Some(Action::Border(b.width, color))
+            }
+            None => None,
+        },
+        match rule.strut {
+            Some(ref s) => {
+                let edge = Edge::parse(&s.edge).ok_or_else(|| format!("invalid strut edge '{}'", s.edge))?;
+                Some(Action::Strut(edge, s.size))
+            }
+            None => None,
+        },
+        rule.icon.clone().map(Action::Icon),
+        rule.focus.map(Action::Focus),
+        rule.opacity.map(Action::Opacity),
+        rule.blur.map(Action::Blur),
+        rule.shadow.map(Action::Shadow),
+        rule.set_prop.clone().map(|p| Action::SetProperty(p.name, p.value)),
+        rule.del_prop.clone().map(Action::DeleteProperty),
+        rule.capture.clone().map(Action::Capture),
+        rule.exec.clone().map(Action::Exec),
+        rule.close.map(Action::Close),
+        rule.kill.map(Action::Kill),
+        rule.exec_after.clone().map(Action::ExecAfter),
+    ];
+    actions.extend(simple.into_iter().flatten());
+
+    Ok(actions)
+}
+
 fn compile_monitor(val: &MonitorValue) -> MonitorTarget {
     match val {
         MonitorValue::Index(i) => MonitorTarget::Index(*i),
@@ -127,23 +1508,77 @@ fn compile_monitor(val: &MonitorValue) -> MonitorTarget {
     }
 }
 
+// As compile_monitor, plus the keyword targets only the action takes
+fn compile_monitor_action(val: &MonitorValue) -> MonitorTarget {
+    match val {
+        MonitorValue::Name(n) if n == "least-populated" => MonitorTarget::LeastPopulated,
+        MonitorValue::Name(n) if n == "emptiest" => MonitorTarget::Emptiest,
+        MonitorValue::Name(n) if n == "primary" => MonitorTarget::Primary,
+        MonitorValue::Name(n) if n == "current" => MonitorTarget::Current,
+        MonitorValue::Name(n) if n == "largest" => MonitorTarget::Largest,
+        MonitorValue::Name(n) if n == "external" => MonitorTarget::External,
+        other => compile_monitor(other),
+    }
+}
+
+impl NamedPosition {
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "center" => NamedPosition::Center,
+            "top-left" => NamedPosition::TopLeft,
+            "top-right" => NamedPosition::TopRight,
+            "bottom-left" => NamedPosition::BottomLeft,
+            "bottom-right" => NamedPosition::BottomRight,
+            "left" => NamedPosition::Left,
+            "right" => NamedPosition::Right,
+            "top" => NamedPosition::Top,
+            "bottom" => NamedPosition::Bottom,
+            _ => return None,
+        })
+    }
+}
+
+// "<anchor>+X,-Y", e.g. "top-right-20,+20". None if `s` carries no offset
+// (the first '+' or '-' followed by a digit starts it).
+pub fn parse_anchor_offset(s: &str) -> Option<Result<PositionTarget, String>> {
+    let bytes = s.as_bytes();
+    let at = (0..bytes.len().saturating_sub(1))
+        .find(|&i| matches!(bytes[i], b'+' | b'-') && bytes[i + 1].is_ascii_digit())?;
+    let (name, offset) = s.split_at(at);
+    Some(anchored(name, offset))
+}
+
+fn anchored(name: &str, offset: &str) -> Result<PositionTarget, String> {
+    let invalid = || format!("invalid offset '{}' (expected +X,+Y)", offset);
+    let (dx, dy) = offset.split_once(',').ok_or_else(invalid)?;
+    let dx = dx.parse().map_err(|_| invalid())?;
+    let dy = dy.parse().map_err(|_| invalid())?;
+    if name == "pointer" {
+        return Ok(PositionTarget::Pointer(dx, dy));
+    }
+    let anchor = NamedPosition::parse(name).ok_or_else(|| format!("unknown anchor '{}'", name))?;
+    Ok(PositionTarget::Anchored(anchor, dx, dy))
+}
+
 fn compile_position(val: &PositionValue) -> Result<PositionTarget, String> {
     match val {
+        PositionValue::Named(name) if name == "parent-center" => Ok(PositionTarget::ParentCenter),
+        PositionValue::Named(name) if name == "smart" => Ok(PositionTarget::Smart),
+        PositionValue::Named(name) if name == "pointer" => Ok(PositionTarget::Pointer(0, 0)),
         PositionValue::Named(name) => {
-            let named = match name.as_str() {
-                "center" => NamedPosition::Center,
-                "top-left" => NamedPosition::TopLeft,
-                "top-right" => NamedPosition::TopRight,
-                "bottom-left" => NamedPosition::BottomLeft,
-                "bottom-right" => NamedPosition::BottomRight,
-                "left" => NamedPosition::Left,
-                "right" => NamedPosition::Right,
-                "top" => NamedPosition::Top,
-                "bottom" => NamedPosition::Bottom,
-                _ => return Err(format!("unknown position '{}'", name)),
-            };
+            if let Some(anchored) = parse_anchor_offset(name) {
+                return anchored;
+            }
+            let named = NamedPosition::parse(name).ok_or_else(|| format!("unknown position '{}'", name))?;
             Ok(PositionTarget::Named(named))
         }
+        PositionValue::Anchored { anchor, offset } if anchor == "pointer" => {
+            Ok(PositionTarget::Pointer(offset[0], offset[1]))
+        }
+        PositionValue::Anchored { anchor, offset } => {
+            let named = NamedPosition::parse(anchor).ok_or_else(|| format!("unknown anchor '{}'", anchor))?;
+            Ok(PositionTarget::Anchored(named, offset[0], offset[1]))
+        }
         PositionValue::Absolute(coords) => Ok(PositionTarget::Absolute(coords[0], coords[1])),
         PositionValue::Flexible(parts) => {
             let x = parse_dimension(&parts[0])?;
@@ -170,6 +1605,11 @@ fn parse_dimension(s: &str) -> Result<DimensionVal, String> {
             .parse()
             .map_err(|_| format!("invalid percentage '{}'", s))?;
         Ok(DimensionVal::Percent(val / 100.0))
+    } else if let Some(mm) = s.strip_suffix("mm") {
+        let val: f64 = mm
+            .parse()
+            .map_err(|_| format!("invalid length '{}'", s))?;
+        Ok(DimensionVal::Millimeters(val))
     } else {
         let val: i32 = s.parse().map_err(|_| format!("invalid dimension '{}'", s))?;
         Ok(DimensionVal::Pixels(val))
@@ -177,10 +1617,98 @@ fn parse_dimension(s: &str) -> Result<DimensionVal, String> {
 }
 
 pub fn compile(config: &Config) -> Result<Vec<CompiledRule>, String> {
-    config
-        .rule
+    compile_for_host(config, &hostname())
+}
+
+// Compile the rules whose load-time `when` conditions hold on `host`
+pub fn compile_for_host(config: &Config, host: &str) -> Result<Vec<CompiledRule>, String> {
+    let class_aliases = Rc::new(config.class_aliases.clone().into_iter().collect());
+    let conditions = config
+        .conditions
         .iter()
-        .enumerate()
-        .map(|(i, r)| CompiledRule::compile(r).map_err(|e| format!("rule[{}]: {}", i, e)))
-        .collect()
+        .map(|(name, expr)| {
+            let cond = condition::compile(expr, host).map_err(|e| format!("conditions.{}: {}", name, e))?;
+            Ok((name.clone(), cond))
+        })
+        .collect::<Result<HashMap<_, _>, String>>()?;
+    let profiles = config
+        .profile
+        .iter()
+        .filter_map(|(name, profile)| Some((name, profile.when.as_ref()?)))
+        .map(|(name, expr)| {
+            let cond = condition::compile(expr, host).map_err(|e| format!("profile.{}.when: {}", name, e))?;
+            Ok((name.clone(), cond))
+        })
+        .collect::<Result<HashMap<_, _>, String>>()?;
+    let mut compiled = Vec::new();
+    let grid = config.grid.as_ref().map(|g| Grid { cols: g.cols, rows: g.rows });
+    for (i, r) in config.rule.iter().enumerate() {
+        let in_scope = host_matches(r, host).map_err(|e| format!("rule[{}]: {}", i, e))?;
+        if in_scope {
+            let mut rule = CompiledRule::compile(i, r, &config.settings, grid, &class_aliases, &conditions)
+                .map_err(|e| format!("rule[{}]: {}", i, e))?;
+            // Compiled all the same, so mistakes show before it is enabled
+            if r.enabled == Some(false) {
+                continue;
+            }
+            if let Some(cond) = r.profile.as_ref().and_then(|name| profiles.get(name)) {
+                rule.add_matcher(Box::new(cond.clone()));
+            }
+            rule.startup = StartupMode::resolve(&config.startup, i);
+            rule.session_restore = RestoreSuppression::resolve(&config.session_restore);
+            compiled.push(rule);
+        }
+    }
+
+    // Higher priority first; file order among equals
+    compiled.sort_by_key(|r| std::cmp::Reverse(r.priority));
+
+    // Monitor defaults come last, in file order, numbered after the rules
+    for (i, d) in config.monitor_defaults.iter().enumerate() {
+        let index = config.rule.len() + i;
+        if host_matches(&d.rule, host).map_err(|e| format!("monitor_defaults[{}]: {}", i, e))? {
+            let mut rule =
+                CompiledRule::compile(index, &d.rule, &config.settings, grid, &class_aliases, &conditions)
+                    .map_err(|e| format!("monitor_defaults[{}]: {}", i, e))?;
+            if d.rule.enabled == Some(false) {
+                continue;
+            }
+            rule.add_matcher(Box::new(MonitorMatcher(compile_monitor(&d.output))));
+            rule.fallback = true;
+            rule.startup = StartupMode::resolve(&config.startup, index);
+            rule.session_restore = RestoreSuppression::resolve(&config.session_restore);
+            compiled.push(rule);
+        }
+    }
+    Ok(compiled)
+}
+
+fn host_matches(rule: &Rule, host: &str) -> Result<bool, String> {
+    match rule.when.as_ref().and_then(WhenValue::table).and_then(|w| w.hostname.as_deref()) {
+        None => Ok(true),
+        Some(pattern) => {
+            let re = Regex::new(pattern).map_err(|e| format!("bad when.hostname '{}': {}", pattern, e))?;
+            Ok(re.is_match(host))
+        }
+    }
+}
+
+// Uid of a local user, from the passwd database
+pub fn user_uid(name: &str) -> Option<u32> {
+    let cname = std::ffi::CString::new(name).ok()?;
+    let pw = unsafe { libc::getpwnam(cname.as_ptr()) };
+    if pw.is_null() {
+        return None;
+    }
+    Some(unsafe { (*pw).pw_uid })
+}
+
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ret != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
 }
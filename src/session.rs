@@ -0,0 +1,97 @@
+use std::ffi::{CStr, CString};
+use std::path::{Path, PathBuf};
+
+// System mode: one root-started instance per X display, e.g. from a
+// display manager's session-setup hook, that serves whoever owns the
+// session. It connects to the display while it can still read the
+// server's authority file, then drops to the session owner for good, so
+// /proc lookups and everything the rules run happen with that user's
+// privileges only.
+
+// Config used when the session owner has none of their own
+pub const SYSTEM_CONFIG: &str = "/etc/cherrypie/config.toml";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionUser {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+}
+
+impl SessionUser {
+    // From the passwd database
+    pub fn lookup(name: &str) -> Result<Self, String> {
+        let cname = CString::new(name).map_err(|_| format!("invalid user name '{}'", name))?;
+        let pw = unsafe { libc::getpwnam(cname.as_ptr()) };
+        if pw.is_null() {
+            return Err(format!("no user '{}'", name));
+        }
+        let pw = unsafe { &*pw };
+        let home = unsafe { CStr::from_ptr(pw.pw_dir) }.to_string_lossy().into_owned();
+        Ok(Self {
+            name: name.to_string(),
+            uid: pw.pw_uid,
+            gid: pw.pw_gid,
+            home: PathBuf::from(home),
+        })
+    }
+
+    // The owner's own config if they have one, else `fallback`
+    pub fn config_file(&self, fallback: &Path) -> PathBuf {
+        let own = self.home.join(".config/cherrypie/config.toml");
+        if own.exists() { own } else { fallback.to_path_buf() }
+    }
+
+    // Become the session owner: supplementary groups, then gid, then uid,
+    // and make sure root can't be regained
+    pub fn drop_privileges(&self) -> Result<(), String> {
+        let cname = CString::new(self.name.as_str()).map_err(|_| format!("invalid user name '{}'", self.name))?;
+        let failed = |call: &str| format!("{}: {}", call, std::io::Error::last_os_error());
+        unsafe {
+            if libc::initgroups(cname.as_ptr(), self.gid as libc::gid_t) != 0 {
+                return Err(failed("initgroups"));
+            }
+            if libc::setgid(self.gid) != 0 {
+                return Err(failed("setgid"));
+            }
+            if libc::setuid(self.uid) != 0 {
+                return Err(failed("setuid"));
+            }
+            if self.uid != 0 && libc::setuid(0) == 0 {
+                return Err("privileges could not be dropped".into());
+            }
+        }
+        Ok(())
+    }
+
+    // HOME, USER, and XDG_RUNTIME_DIR of the owner, for the commands rules
+    // run and the control socket
+    pub fn export_env(&self) {
+        let runtime = PathBuf::from(format!("/run/user/{}", self.uid));
+        // SAFETY: called before the daemon starts any threads
+        unsafe {
+            std::env::set_var("HOME", &self.home);
+            std::env::set_var("USER", &self.name);
+            std::env::set_var("LOGNAME", &self.name);
+            if runtime.is_dir() {
+                std::env::set_var("XDG_RUNTIME_DIR", runtime);
+            } else {
+                std::env::remove_var("XDG_RUNTIME_DIR");
+            }
+        }
+    }
+}
+
+// The session owner named by the environment display managers give their
+// hooks: PAM_USER, else USER
+pub fn owner_from_env() -> Option<String> {
+    ["PAM_USER", "USER"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|name| !name.is_empty() && name != "root")
+}
+
+pub fn running_as_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
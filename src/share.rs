@@ -0,0 +1,104 @@
+use toml::Value;
+use toml::value::{Array, Table};
+
+use crate::config;
+use crate::rules;
+
+// Rules as one line of text, for pasting into chat or an issue: the
+// selected rules as a `[[rule]]` TOML snippet, base64url-encoded without
+// padding behind a versioned prefix.
+
+pub const PREFIX: &str = "cherrypie1:";
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// rule[i] of `config` (raw TOML, e.g. from diff::load) for each index
+pub fn encode(config: &Value, indices: &[usize]) -> Result<String, String> {
+    let all = config.get("rule").and_then(Value::as_array);
+    let mut picked = Array::new();
+    for &i in indices {
+        let rule = all.and_then(|rules| rules.get(i)).ok_or_else(|| format!("no rule[{}]", i))?;
+        picked.push(rule.clone());
+    }
+    let text = to_toml(picked)?;
+    Ok(format!("{}{}", PREFIX, base64_encode(text.as_bytes())))
+}
+
+// The rules in a code from `encode`, checked to compile on their own
+pub fn decode(code: &str) -> Result<Array, String> {
+    let payload = code
+        .trim()
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| format!("not a rule code (expected it to start with '{}')", PREFIX))?;
+    let bytes = base64_decode(payload)?;
+    let text = String::from_utf8(bytes).map_err(|_| "rule code is not valid text".to_string())?;
+    let value: Value = toml::from_str(&text).map_err(|e| format!("rule code: {}", e))?;
+    match value.get("rule").and_then(Value::as_array) {
+        Some(rules) if !rules.is_empty() => Ok(rules.clone()),
+        _ => Err("rule code holds no rules".into()),
+    }
+}
+
+// Check `rules` compile alongside `config` (raw TOML), so rules that
+// refer to its aliases or conditions are accepted
+pub fn check(config: Option<&Value>, rules: &Array) -> Result<(), String> {
+    let mut merged = config.and_then(Value::as_table).cloned().unwrap_or_default();
+    let existing = match merged.remove("rule") {
+        Some(Value::Array(list)) => list,
+        _ => Array::new(),
+    };
+    let offset = existing.len();
+    merged.insert("rule".into(), Value::Array(existing.into_iter().chain(rules.iter().cloned()).collect()));
+
+    let text = toml::to_string(&merged).map_err(|e| e.to_string())?;
+    let cfg = config::parse(&text).map_err(|e| renumber(&e, offset))?;
+    rules::compile(&cfg).map(|_| ()).map_err(|e| renumber(&e, offset))
+}
+
+// Errors about the appended rules, numbered as in the code
+fn renumber(error: &str, offset: usize) -> String {
+    if let Some((n, rest)) = error.strip_prefix("rule[").and_then(|rest| rest.split_once(']'))
+        && let Ok(n) = n.parse::<usize>()
+        && n >= offset
+    {
+        return format!("rule[{}]{}", n - offset, rest);
+    }
+    error.to_string()
+}
+
+// `rules` as `[[rule]]` tables, ready to append to a config
+pub fn to_toml(rules: Array) -> Result<String, String> {
+    let mut top = Table::new();
+    top.insert("rule".into(), Value::Array(rules));
+    toml::to_string(&top).map_err(|e| e.to_string())
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+        }
+    }
+    out
+}
+
+fn base64_decode(s: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut bits, mut n) = (0u32, 0u32);
+    for c in s.bytes() {
+        let v = ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or_else(|| format!("invalid character '{}' in rule code", c as char))?;
+        bits = bits << 6 | v as u32;
+        n += 6;
+        if n >= 8 {
+            n -= 8;
+            out.push((bits >> n) as u8);
+            bits &= (1 << n) - 1;
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,88 @@
+use crate::backend::{WindowManager, WindowState};
+use crate::matchers::ALL_WORKSPACES;
+
+// `cherrypie snapshot`: the open windows as [[rule]] blocks that put them
+// back where they are now. Classes and titles are matched exactly ("="),
+// titles only where a class has more than one window.
+
+// Window types worth a rule; docks, menus, dialogs and the like come and go
+const TYPES: &[&str] = &["normal", "utility"];
+
+pub fn render(wm: &WindowManager) -> String {
+    let windows: Vec<(WindowState, String)> = wm
+        .windows_on(None)
+        .into_iter()
+        .map(|w| (wm.window_state(w), wm.title(w)))
+        .filter(|(w, _)| !w.class.is_empty() && TYPES.contains(&w.window_type.as_str()))
+        .collect();
+
+    let mut out = String::new();
+    let mut seen = Vec::new();
+    for (w, title) in &windows {
+        let shared = windows.iter().filter(|(other, _)| other.class == w.class).count() > 1;
+        // A second window with the same class and title would get the same rule
+        let key = (&w.class, shared.then_some(title));
+        if seen.contains(&key) {
+            continue;
+        }
+        seen.push(key);
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&rule(w, key.1));
+    }
+    out
+}
+
+fn rule(w: &WindowState, title: Option<&String>) -> String {
+    let mut lines = vec!["[[rule]]".to_string(), format!("class = {}", quote(&format!("={}", w.class)))];
+    if let Some(title) = title {
+        lines.push(format!("title = {}", quote(&format!("={}", title))));
+    }
+    let has = |state: &str| w.states.iter().any(|s| s == state);
+
+    match w.workspace {
+        Some(ALL_WORKSPACES) => lines.push("pin = true".into()),
+        Some(ws) => lines.push(format!("workspace = {}", ws)),
+        None => {}
+    }
+    if let Some(ref monitor) = w.monitor {
+        lines.push(format!("monitor = {}", quote(&monitor.name)));
+    }
+    // Fullscreen and maximized windows get their geometry from the state
+    if has("fullscreen") {
+        lines.push("fullscreen = true".into());
+    } else if has("maximized") {
+        lines.push("maximize = true".into());
+    } else {
+        if has("maximized_vert") {
+            lines.push("maximize = \"vertical\"".into());
+        } else if has("maximized_horz") {
+            lines.push("maximize = \"horizontal\"".into());
+        }
+        if let Some((x, y, width, height)) = w.geometry {
+            lines.push(format!("position = [{}, {}]", x, y));
+            lines.push(format!("size = [{}, {}]", width, height));
+        }
+    }
+    let flags = [
+        ("above", "above"),
+        ("below", "below"),
+        ("shaded", "shade"),
+        ("hidden", "minimize"),
+    ];
+    for (state, key) in flags {
+        if has(state) {
+            lines.push(format!("{} = true", key));
+        }
+    }
+    if has("sticky") && w.workspace != Some(ALL_WORKSPACES) {
+        lines.push("sticky = true".into());
+    }
+
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+fn quote(text: &str) -> String {
+    toml::Value::String(text.to_string()).to_string()
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+// Local usage statistics, kept only with `stats = true` under [settings]:
+// how often each rule fired per day and which window classes matched most,
+// so rules that never fire can be pruned. The file stays on this machine;
+// nothing reads it but `cherrypie stats`.
+
+// Days kept in the file; older ones are dropped as new ones start
+pub const KEEP_DAYS: usize = 90;
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    // Date (YYYY-MM-DD) -> rule index -> times the rule fired that day
+    #[serde(default)]
+    pub days: BTreeMap<String, BTreeMap<String, u64>>,
+    // Window class -> matches over all kept days
+    #[serde(default)]
+    pub classes: BTreeMap<String, u64>,
+}
+
+// $XDG_STATE_HOME/cherrypie, else under ~/.local/state
+pub fn state_dir() -> Option<PathBuf> {
+    let state = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state.join("cherrypie"))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    Some(state_dir()?.join("stats.toml"))
+}
+
+impl Stats {
+    // An empty record if the file doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(self).map_err(io::Error::other)?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, text)?;
+        std::fs::rename(&tmp, path)
+    }
+
+    pub fn record(&mut self, date: &str, rule: usize, class: &str) {
+        *self.days.entry(date.to_string()).or_default().entry(rule.to_string()).or_default() += 1;
+        if !class.is_empty() {
+            *self.classes.entry(class.to_string()).or_default() += 1;
+        }
+        while self.days.len() > KEEP_DAYS {
+            self.days.pop_first();
+        }
+    }
+
+    // Times each rule fired over the kept days
+    pub fn totals(&self) -> BTreeMap<usize, u64> {
+        let mut totals = BTreeMap::new();
+        for rules in self.days.values() {
+            for (rule, n) in rules {
+                if let Ok(rule) = rule.parse() {
+                    *totals.entry(rule).or_default() += n;
+                }
+            }
+        }
+        totals
+    }
+
+    // The last `days` days, the busiest rules and classes, and (given the
+    // config's rule count) the rules that never fired
+    pub fn report(&self, days: usize, rule_count: Option<usize>) -> String {
+        let mut out = String::new();
+        let Some(first) = self.days.keys().next() else {
+            return "no rules have fired yet\n".into();
+        };
+        out.push_str(&format!("Since {} ({} days recorded)\n\nPer day:\n", first, self.days.len()));
+        for (date, rules) in self.days.iter().rev().take(days) {
+            let fired: Vec<String> = rules.iter().map(|(rule, n)| format!("rule[{}] {}", rule, n)).collect();
+            out.push_str(&format!("  {}  {}\n", date, fired.join(", ")));
+        }
+
+        let mut totals: Vec<(usize, u64)> = self.totals().into_iter().collect();
+        totals.sort_by_key(|&(rule, n)| (std::cmp::Reverse(n), rule));
+        out.push_str("\nRules:\n");
+        for (rule, n) in &totals {
+            out.push_str(&format!("  rule[{}]  {}\n", rule, n));
+        }
+
+        let mut classes: Vec<(&String, &u64)> = self.classes.iter().collect();
+        classes.sort_by_key(|&(class, n)| (std::cmp::Reverse(*n), class));
+        out.push_str("\nClasses:\n");
+        for (class, n) in classes.iter().take(10) {
+            out.push_str(&format!("  {}  {}\n", class, n));
+        }
+
+        if let Some(count) = rule_count {
+            let idle: Vec<String> = (0..count)
+                .filter(|i| !totals.iter().any(|(rule, _)| rule == i))
+                .map(|i| format!("rule[{}]", i))
+                .collect();
+            if !idle.is_empty() {
+                out.push_str(&format!("\nNever fired: {}\n", idle.join(", ")));
+            }
+        }
+        out
+    }
+}
+
+// Today's local date as YYYY-MM-DD
+pub fn today() -> String {
+    unsafe {
+        let mut t: libc::time_t = 0;
+        libc::time(&mut t);
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&t, &mut tm);
+        format!("{:04}-{:02}-{:02}", tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday)
+    }
+}
@@ -0,0 +1,1184 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use cherrypie::backend::{
+    ApplyReport, ChangedProperty, Registry, Support, WindowBackend, WindowEvent, WindowId,
+    WindowManager, WriteAccess,
+};
+use cherrypie::config::Config;
+use cherrypie::matchers::MonitorId;
+use cherrypie::memory::Remembered;
+use cherrypie::process::ProcessInfoProvider;
+use cherrypie::rules::{self, Action, CompiledRule, WorkspaceTarget};
+
+struct FakeWindow {
+    id: WindowId,
+    class: &'static str,
+    title: &'static str,
+    workspace: u32,
+}
+
+// Shared with the test after the backend moves into the WindowManager
+#[derive(Clone, Default)]
+struct Handle {
+    queue: Rc<RefCell<Vec<WindowEvent>>>,
+    log: Rc<RefCell<Vec<String>>>,
+    compositor: Rc<Cell<Option<bool>>>,
+    titles: Rc<RefCell<HashMap<WindowId, &'static str>>>,
+    // Window -> group leader
+    groups: Rc<RefCell<HashMap<WindowId, WindowId>>>,
+    // Report pid 1000 + window id for every window
+    pids: Rc<Cell<bool>>,
+    // _NET_SUPPORTED
+    hints: Rc<RefCell<Option<Vec<String>>>>,
+    // Window -> output it is on
+    outputs: Rc<RefCell<HashMap<WindowId, &'static str>>>,
+    // Window -> client geometry
+    geometry: Rc<RefCell<HashMap<WindowId, Geometry>>>,
+}
+
+type Geometry = (i32, i32, u32, u32);
+
+impl Handle {
+    fn push(&self, event: WindowEvent) {
+        self.queue.borrow_mut().push(event);
+    }
+
+    fn log(&self) -> Vec<String> {
+        self.log.borrow().clone()
+    }
+
+    // Change a window's title and report it like a backend would
+    fn retitle(&self, window: WindowId, title: &'static str) {
+        self.titles.borrow_mut().insert(window, title);
+        self.push(WindowEvent::PropertyChanged(window, ChangedProperty::Title));
+    }
+}
+
+// Test double: reports its windows as created on the first poll, then any
+// events queued through the handle, and logs every apply/revert as
+// "<verb> <window> <first action>".
+struct FakeBackend {
+    windows: Vec<FakeWindow>,
+    handle: Handle,
+}
+
+impl FakeBackend {
+    fn new(windows: Vec<FakeWindow>) -> (Self, Handle) {
+        let handle = Handle::default();
+        for w in &windows {
+            handle.push(WindowEvent::Created(w.id));
+        }
+        let backend = Self {
+            windows,
+            handle: handle.clone(),
+        };
+        (backend, handle)
+    }
+
+    fn find<T>(&self, window: WindowId, f: impl Fn(&FakeWindow) -> T) -> T {
+        f(self.windows.iter().find(|w| w.id == window).unwrap())
+    }
+
+    fn record(&self, verb: &str, window: WindowId, rule: &CompiledRule) {
+        let first = rule.actions.first().map(|a| a.to_string()).unwrap_or_default();
+        self.handle.log.borrow_mut().push(format!("{} {} {}", verb, window, first));
+    }
+}
+
+impl WindowBackend for FakeBackend {
+    fn name(&self) -> &str {
+        "fake"
+    }
+
+    fn connection_fd(&self) -> i32 {
+        -1
+    }
+
+    fn poll_events(&self) -> Vec<WindowEvent> {
+        self.handle.queue.take()
+    }
+
+    fn get_class(&self, window: WindowId) -> String {
+        self.find(window, |w| w.class.to_string())
+    }
+
+    fn get_title(&self, window: WindowId) -> String {
+        match self.handle.titles.borrow().get(&window) {
+            Some(title) => title.to_string(),
+            None => self.find(window, |w| w.title.to_string()),
+        }
+    }
+
+    fn list_windows(&self) -> Vec<WindowId> {
+        self.windows.iter().map(|w| w.id).collect()
+    }
+
+    fn get_group_leader(&self, window: WindowId) -> Option<WindowId> {
+        self.handle.groups.borrow().get(&window).copied()
+    }
+
+    fn get_workspace(&self, window: WindowId) -> Option<u32> {
+        Some(self.find(window, |w| w.workspace))
+    }
+
+    fn compositor_running(&self) -> Option<bool> {
+        self.handle.compositor.get()
+    }
+
+    fn get_geometry(&self, window: WindowId) -> Option<Geometry> {
+        self.handle.geometry.borrow().get(&window).copied()
+    }
+
+    fn get_monitor(&self, window: WindowId) -> Option<MonitorId> {
+        let name = self.handle.outputs.borrow().get(&window)?.to_string();
+        Some(MonitorId { index: 0, name })
+    }
+
+    fn supported_hints(&self) -> Option<Vec<String>> {
+        self.handle.hints.borrow().clone()
+    }
+
+    fn get_role(&self, _window: WindowId) -> String {
+        String::new()
+    }
+
+    fn get_pid(&self, window: WindowId) -> Option<u32> {
+        self.handle.pids.get().then_some(1000 + window)
+    }
+
+    fn get_window_type(&self, _window: WindowId) -> String {
+        "normal".into()
+    }
+
+    fn apply_rule(&self, window: WindowId, rule: &CompiledRule, _: &WriteAccess) -> ApplyReport {
+        self.record("apply", window, rule);
+
+        // Pretend the server rejects anything sent to workspace 99
+        let mut report = ApplyReport::default();
+        let rejected = Action::Workspace(WorkspaceTarget::Index(99));
+        if rule.actions.contains(&rejected) {
+            report.fail(&rejected, "BadValue".into());
+        }
+        report
+    }
+
+    fn revert_rule(&self, window: WindowId, rule: &CompiledRule, _: &WriteAccess) -> ApplyReport {
+        self.record("revert", window, rule);
+        ApplyReport::default()
+    }
+}
+
+fn fake_manager(windows: Vec<FakeWindow>) -> (WindowManager, Handle) {
+    let (backend, handle) = FakeBackend::new(windows);
+    (WindowManager::from_backend(Box::new(backend)), handle)
+}
+
+// Pid -> (command name, $PROJECT), standing in for /proc
+struct FakeProcesses(HashMap<u32, (&'static str, &'static str)>);
+
+impl ProcessInfoProvider for FakeProcesses {
+    fn name(&self, pid: u32) -> Option<String> {
+        self.0.get(&pid).map(|(name, _)| name.to_string())
+    }
+
+    fn env(&self, pid: u32) -> Option<HashMap<String, String>> {
+        let (_, project) = self.0.get(&pid)?;
+        Some(HashMap::from([("PROJECT".to_string(), project.to_string())]))
+    }
+}
+
+fn compile(toml_str: &str) -> Vec<CompiledRule> {
+    let cfg: Config = toml::from_str(toml_str).unwrap();
+    rules::compile(&cfg).unwrap()
+}
+
+#[test]
+fn process_matchers_use_the_process_provider() {
+    let rules = compile(r#"
+        [[rule]]
+        process = "^nvim$"
+        process_env = { PROJECT = "^work" }
+        workspace = 2
+    "#);
+    let (backend, handle) = FakeBackend::new(vec![
+        FakeWindow { id: 10, class: "kitty", title: "a", workspace: 0 },
+        FakeWindow { id: 11, class: "kitty", title: "b", workspace: 0 },
+        FakeWindow { id: 12, class: "kitty", title: "c", workspace: 0 },
+    ]);
+    handle.pids.set(true);
+    let processes = FakeProcesses(HashMap::from([(1010, ("nvim", "work/api")), (1011, ("nvim", "home"))]));
+    let wm = WindowManager::from_backend(Box::new(backend)).with_process_info(Box::new(processes));
+
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log(), ["apply 10 workspace -> 2"]);
+}
+
+#[test]
+fn support_report_checks_advertised_hints() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "a"
+        size = [800, 600]
+        workspace = 2
+        shade = true
+        opacity = 0.9
+
+        [[rule]]
+        class = "b"
+        workspace = 3
+        decorate = false
+    "#);
+    let (wm, handle) = fake_manager(Vec::new());
+    handle.compositor.set(Some(false));
+
+    // A window manager that doesn't publish _NET_SUPPORTED
+    let report = wm.support_report(&rules);
+    assert_eq!(
+        report,
+        [
+            ("size", Support::Supported),
+            ("workspace", Support::Unknown),
+            ("shade", Support::Unknown),
+            ("opacity", Support::Unsupported),
+            ("decorate", Support::Unknown),
+        ]
+    );
+
+    *handle.hints.borrow_mut() = Some(vec!["_NET_WM_DESKTOP".into(), "_NET_WM_STATE".into()]);
+    let report = wm.support_report(&rules);
+    assert_eq!(report[1], ("workspace", Support::Supported));
+    assert_eq!(report[2], ("shade", Support::Unsupported));
+    assert_eq!(report[2].1.to_string(), "unsupported");
+}
+
+#[test]
+fn from_backend_reports_name() {
+    let (wm, _) = fake_manager(Vec::new());
+    assert_eq!(wm.backend_name(), "fake");
+    assert_eq!(wm.connection_fd(), -1);
+}
+
+#[test]
+fn matching_rules_applied_to_new_windows() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+
+        [[rule]]
+        title = ".*YouTube.*"
+        workspace = 3
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "kitty", title: "zsh", workspace: 0 },
+        FakeWindow { id: 11, class: "firefox", title: "YouTube - Firefox", workspace: 0 },
+        FakeWindow { id: 12, class: "thunar", title: "Home", workspace: 0 },
+    ]);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 1", "apply 11 workspace -> 3"]);
+}
+
+#[test]
+fn failed_action_does_not_stop_later_rules() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 99
+
+        [[rule]]
+        class = "kitty"
+        workspace = 2
+    "#);
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 }]);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 99", "apply 10 workspace -> 2"]);
+}
+
+#[test]
+fn failed_action_records_last_failure() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 99
+    "#);
+    let (wm, _) = fake_manager(vec![FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 }]);
+    assert!(wm.last_failure().is_none());
+
+    wm.process_events(&rules, false);
+
+    assert!(wm.last_failure().is_some());
+}
+
+#[test]
+fn failures_raise_alerts_only_for_opted_in_rules() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 99
+        alert_on_failure = true
+
+        [[rule]]
+        class = "kitty"
+        title = "zsh"
+        workspace = 99
+
+        [[rule]]
+        class = "kitty"
+        workspace = 2
+        alert_on_failure = true
+    "#);
+    let (wm, _) = fake_manager(vec![FakeWindow { id: 10, class: "kitty", title: "zsh", workspace: 0 }]);
+
+    wm.process_events(&rules, false);
+
+    let alerts = wm.take_alerts();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].rule, 0);
+    assert_eq!(alerts[0].summary(), "cherrypie: rule[0] failed on kitty");
+    assert_eq!(alerts[0].body(), "window 0xa 'zsh'\nworkspace: BadValue");
+    assert!(wm.take_alerts().is_empty());
+}
+
+#[test]
+fn history_and_debug_dump_leave_out_titles() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 99
+    "#);
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "kitty", title: "bank statement", workspace: 0 }]);
+    wm.process_events(&rules, false);
+    handle.retitle(10, "private chat");
+    wm.process_events(&rules, false);
+
+    let history: Vec<String> = wm.history().iter().map(|e| e.split_once(' ').unwrap().1.to_string()).collect();
+    assert_eq!(
+        history,
+        vec![
+            "created 0xa",
+            "rule[0] applied to 0xa",
+            "rule[0] workspace failed on 0xa: BadValue",
+            "title changed on 0xa",
+        ]
+    );
+    assert_eq!(wm.take_fired(), [(0, "kitty".to_string())]);
+
+    let dump = cherrypie::dump::render(&wm, &rules);
+    assert!(dump.starts_with(&format!(r#"{{"version":"{}","features":["#, env!("CARGO_PKG_VERSION"))));
+    assert!(dump.contains(r#""backend":"fake","rules":[{"index":0,"name":null,"priority":0,"trigger":"map","matchers":1,"actions":["workspace -> 99"],"stop":false,"matches":1,"max_matches":null}]"#));
+    assert!(dump.contains(r#""monitors":[],"active":null,"windows":[{"id":"0xa","class":"kitty","process":"","type":"normal","workspace":0,"monitor":null,"geometry":null,"states":[]}]"#));
+    assert!(!dump.contains("bank") && !dump.contains("private"));
+}
+
+#[test]
+fn snapshot_prints_a_rule_per_window() {
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "kitty", title: "build", workspace: 1 },
+        FakeWindow { id: 11, class: "kitty", title: "\"logs\"", workspace: 2 },
+        FakeWindow { id: 12, class: "firefox", title: "Inbox", workspace: 0 },
+        FakeWindow { id: 13, class: "kitty", title: "build", workspace: 3 },
+    ]);
+    handle.outputs.borrow_mut().insert(12, "DP-1");
+    handle.geometry.borrow_mut().insert(12, (1920, 0, 1280, 1400));
+
+    let snapshot = cherrypie::snapshot::render(&wm);
+    assert_eq!(snapshot, concat!(
+        "[[rule]]\nclass = \"=kitty\"\ntitle = \"=build\"\nworkspace = 1\n",
+        "\n[[rule]]\nclass = \"=kitty\"\ntitle = '=\"logs\"'\nworkspace = 2\n",
+        "\n[[rule]]\nclass = \"=firefox\"\nworkspace = 0\nmonitor = \"DP-1\"\n",
+        "position = [1920, 0]\nsize = [1280, 1400]\n",
+    ));
+    assert_eq!(compile(&snapshot).len(), 3);
+}
+
+#[test]
+fn place_applies_actions_to_known_windows() {
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 }]);
+    let to = |ws| vec![Action::Workspace(WorkspaceTarget::Index(ws))];
+
+    assert_eq!(wm.place(10, to(2), false), Ok(()));
+    assert_eq!(wm.place(10, to(99), false), Err("workspace: BadValue".into()));
+    assert_eq!(wm.place(11, to(2), false), Err("no client window 0xb".into()));
+    assert_eq!(wm.place(10, to(3), true), Ok(()));
+
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 2", "apply 10 workspace -> 99"]);
+    assert!(wm.last_failure().is_some());
+}
+
+#[test]
+fn move_group_moves_the_rest_of_the_group() {
+    let rules = compile(r#"
+        [[rule]]
+        title = "main"
+        move_group = true
+        workspace = 3
+        maximize = true
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "gimp", title: "main", workspace: 0 },
+        FakeWindow { id: 11, class: "gimp", title: "toolbox", workspace: 0 },
+        FakeWindow { id: 12, class: "kitty", title: "zsh", workspace: 0 },
+    ]);
+    handle.groups.borrow_mut().extend([(10, 10), (11, 10)]);
+    // Only the main window appears; the rest of its group is already open
+    handle.queue.borrow_mut().truncate(1);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 3", "apply 11 workspace -> 3"]);
+}
+
+#[test]
+fn reapply_reruns_map_rules_on_selected_windows() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+
+        [[rule]]
+        class = "kitty"
+        once_per_session = true
+        workspace = 2
+
+        [[rule]]
+        class = "kitty"
+        on = "focus"
+        above = true
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 },
+        FakeWindow { id: 11, class: "kitty", title: "", workspace: 3 },
+        FakeWindow { id: 12, class: "kitty", title: "", workspace: 0xFFFFFFFF },
+    ]);
+    handle.queue.borrow_mut().clear();
+
+    let on_three = wm.windows_on(Some(3));
+    assert_eq!(on_three, vec![11, 12]);
+    wm.reapply(&on_three, &rules, None, false);
+    assert_eq!(
+        handle.log(),
+        vec!["apply 11 workspace -> 1", "apply 11 workspace -> 2", "apply 12 workspace -> 1"]
+    );
+
+    // Just the once-per-session rule, with its count started over
+    handle.log.borrow_mut().clear();
+    rules[1].reset_matches();
+    wm.reapply(&wm.windows_on(None), &rules, Some(1), false);
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 2"]);
+}
+
+#[test]
+fn stop_ends_evaluation_for_the_window() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        title = "scratch"
+        stop = true
+        workspace = 9
+
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "kitty", title: "scratch", workspace: 0 },
+        FakeWindow { id: 11, class: "kitty", title: "zsh", workspace: 0 },
+    ]);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 9", "apply 11 workspace -> 1"]);
+}
+
+#[test]
+fn monitor_defaults_apply_to_windows_no_rule_matched() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "mpv"
+        workspace = 2
+
+        [[monitor_defaults]]
+        output = "HDMI-1"
+        fullscreen = true
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "mpv", title: "film", workspace: 0 },
+        FakeWindow { id: 11, class: "kodi", title: "Kodi", workspace: 0 },
+        FakeWindow { id: 12, class: "kitty", title: "zsh", workspace: 0 },
+    ]);
+    handle.outputs.borrow_mut().extend([(10, "HDMI-1"), (11, "HDMI-1"), (12, "eDP-1")]);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 2", "apply 11 fullscreen"]);
+    assert_eq!(wm.take_fired(), vec![(0, "mpv".to_string()), (1, "kodi".to_string())]);
+}
+
+#[test]
+fn apply_latency_is_recorded_for_new_windows_only() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+    "#);
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "kitty", title: "zsh", workspace: 0 }]);
+
+    wm.process_events(&rules, false);
+    let latencies = wm.take_latencies();
+    assert_eq!(latencies.len(), 1);
+    assert_eq!(latencies[0].0, 0);
+    assert!(latencies[0].1 < rules[0].slow_apply.unwrap());
+
+    wm.reapply(&[10], &rules, None, false);
+    handle.push(WindowEvent::Existing(10));
+    wm.process_events(&rules, false);
+    assert!(wm.take_latencies().is_empty());
+}
+
+#[test]
+fn delayed_rules_apply_once_their_delay_is_over() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "slack"
+        delay_ms = 50
+        workspace = 3
+
+        [[rule]]
+        class = "slack"
+        workspace = 1
+    "#);
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "slack", title: "", workspace: 0 }]);
+
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 1"]);
+    let due = wm.next_delayed().unwrap();
+
+    // Nothing is due yet
+    wm.run_delayed(&rules, false);
+    assert_eq!(handle.log().len(), 1);
+
+    std::thread::sleep(due.saturating_duration_since(std::time::Instant::now()));
+    wm.run_delayed(&rules, false);
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 1", "apply 10 workspace -> 3"]);
+    assert_eq!(wm.next_delayed(), None);
+}
+
+#[test]
+fn wait_for_title_matches_once_the_title_settles() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "firefox"
+        title = "YouTube"
+        wait_for_title = true
+        title_settle_ms = 40
+        workspace = 4
+    "#);
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "firefox", title: "", workspace: 0 }]);
+
+    wm.process_events(&rules, false);
+    assert!(handle.log().is_empty());
+
+    // A title change restarts the wait
+    let first = wm.next_delayed().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    handle.retitle(10, "YouTube - Firefox");
+    wm.process_events(&rules, false);
+    let due = wm.next_delayed().unwrap();
+    assert!(due > first);
+
+    std::thread::sleep(due.saturating_duration_since(std::time::Instant::now()));
+    wm.run_delayed(&rules, false);
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 4"]);
+    assert_eq!(wm.next_delayed(), None);
+}
+
+#[test]
+fn wait_for_title_with_stop_holds_back_later_rules() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "firefox"
+        title = "YouTube"
+        wait_for_title = true
+        title_settle_ms = 20
+        stop = true
+        workspace = 4
+
+        [[rule]]
+        class = "firefox|xterm"
+        workspace = 1
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "firefox", title: "", workspace: 0 },
+        FakeWindow { id: 11, class: "xterm", title: "", workspace: 0 },
+        FakeWindow { id: 12, class: "firefox", title: "", workspace: 0 },
+    ]);
+
+    // Only the firefox windows wait
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log(), vec!["apply 11 workspace -> 1"]);
+
+    handle.retitle(10, "YouTube - Firefox");
+    handle.retitle(12, "Mozilla Firefox");
+    wm.process_events(&rules, false);
+    // Past both settle times
+    std::thread::sleep(std::time::Duration::from_millis(40));
+    wm.run_delayed(&rules, false);
+    // A title that doesn't match lets the later rules run after all
+    assert_eq!(
+        handle.log(),
+        vec!["apply 11 workspace -> 1", "apply 10 workspace -> 4", "apply 12 workspace -> 1"]
+    );
+    assert_eq!(wm.next_delayed(), None);
+}
+
+#[test]
+fn enforce_puts_back_windows_that_move_themselves() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "steam"
+        enforce = true
+        max_matches = 1
+        size = [1200, 800]
+        workspace = 2
+    "#);
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "steam", title: "", workspace: 0 }]);
+    let settle = || std::thread::sleep(std::time::Duration::from_millis(120));
+
+    let placed = "apply 10 size -> Absolute(1200, 800)";
+
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log(), vec![placed]);
+
+    // The answer to our own request
+    handle.push(WindowEvent::GeometryChanged(10));
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log().len(), 1);
+
+    // Only the placement is applied again, past max_matches
+    settle();
+    handle.push(WindowEvent::GeometryChanged(10));
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log(), vec![placed, placed]);
+
+    // A window that keeps fighting is left alone
+    for _ in 0..5 {
+        settle();
+        handle.push(WindowEvent::GeometryChanged(10));
+        wm.process_events(&rules, false);
+    }
+    assert_eq!(handle.log().len(), 6);
+    assert!(wm.history().iter().any(|e| e.ends_with("gave up enforcing on 0xa")));
+}
+
+#[test]
+fn scratchpad_rules_hide_the_window_and_remember_it() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        title = "dropdown"
+        scratchpad = "term"
+        size = [1920, 400]
+        position = "top"
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "kitty", title: "dropdown", workspace: 0 },
+        FakeWindow { id: 11, class: "kitty", title: "shell", workspace: 0 },
+    ]);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 10 size -> Absolute(1920, 400)"]);
+    assert!(rules[0].actions.contains(&Action::Minimize(true)));
+    assert_eq!(wm.scratchpad("term"), Some((10, 0)));
+    assert_eq!(wm.scratchpad("notes"), None);
+}
+
+#[test]
+fn remember_restores_where_the_last_window_closed() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "gimp"
+        remember = true
+        size = [800, 600]
+        opacity = 0.9
+    "#);
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "gimp", title: "", workspace: 2 }]);
+
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log(), vec!["apply 10 size -> Absolute(800, 600)"]);
+
+    // Moved by the user, then closed
+    handle.geometry.borrow_mut().insert(10, (100, 50, 1024, 768));
+    handle.push(WindowEvent::GeometryChanged(10));
+    handle.push(WindowEvent::Closed(10));
+    wm.process_events(&rules, false);
+    let memory = wm.take_memory().unwrap();
+    let last = Remembered { x: 100, y: 50, width: 1024, height: 768, workspace: Some(2) };
+    assert_eq!(memory.windows.get("gimp//"), Some(&last));
+    assert_eq!(wm.take_memory(), None);
+
+    // The next one opens there, with the rule's other actions
+    handle.push(WindowEvent::Created(10));
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log()[1], "apply 10 size -> Absolute(1024, 768)");
+
+    // Also across restarts
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "gimp", title: "", workspace: 0 }]);
+    wm.set_memory(memory);
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log(), vec!["apply 10 size -> Absolute(1024, 768)"]);
+}
+
+#[test]
+fn application_scope_reaches_the_other_windows_of_the_class() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "thunderbird"
+        title = "Inbox"
+        scope = "application"
+        workspace = 2
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "thunderbird", title: "Inbox", workspace: 0 },
+        FakeWindow { id: 11, class: "thunderbird", title: "Compose", workspace: 0 },
+        FakeWindow { id: 12, class: "firefox", title: "Inbox", workspace: 0 },
+    ]);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 2", "apply 11 workspace -> 2"]);
+}
+
+#[test]
+fn move_with_main_follows_the_group_leader() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "gimp"
+        title = "Toolbox"
+        group = "move-with-main"
+        above = true
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "gimp", title: "GNU Image Manipulation Program", workspace: 3 },
+        FakeWindow { id: 11, class: "gimp", title: "Toolbox", workspace: 0 },
+    ]);
+    handle.groups.borrow_mut().insert(11, 10);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 11 above", "apply 11 workspace -> 3"]);
+}
+
+#[test]
+fn first_match_setting_with_rule_opt_out() {
+    let rules = compile(r#"
+        [settings]
+        first_match = true
+
+        [[rule]]
+        class = "kitty"
+        stop = false
+        opacity = 0.9
+
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+
+        [[rule]]
+        class = "kitty"
+        workspace = 2
+    "#);
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 }]);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 10 opacity -> 0.9", "apply 10 workspace -> 1"]);
+}
+
+#[test]
+fn max_matches_limits_windows_per_rule() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        max_matches = 1
+        maximize = true
+
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 },
+        FakeWindow { id: 11, class: "kitty", title: "", workspace: 0 },
+    ]);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(
+        handle.log(),
+        vec!["apply 10 maximize", "apply 10 workspace -> 1", "apply 11 workspace -> 1"]
+    );
+    assert_eq!(rules[0].match_count(), 1);
+    assert!(rules[0].exhausted());
+}
+
+#[test]
+fn once_per_session_applies_to_first_window_until_reload() {
+    let config = r#"
+        [[rule]]
+        class = "firefox"
+        once_per_session = true
+        workspace = 2
+    "#;
+    let rules = compile(config);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "firefox", title: "", workspace: 0 },
+        FakeWindow { id: 11, class: "firefox", title: "", workspace: 0 },
+    ]);
+
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 2"]);
+
+    // A reload compiles fresh rules with fresh counters
+    let reloaded = compile(config);
+    handle.push(WindowEvent::Created(11));
+    wm.process_events(&reloaded, false);
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 2", "apply 11 workspace -> 2"]);
+}
+
+#[test]
+fn apply_report_records_action_name() {
+    let mut report = ApplyReport::default();
+    assert!(report.is_ok());
+    report.fail(&Action::Opacity(0.5), "BadWindow".into());
+    assert!(!report.is_ok());
+    assert_eq!(report.failures, vec![("opacity", "BadWindow".to_string())]);
+}
+
+#[test]
+fn dry_run_applies_nothing() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+    "#);
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 }]);
+
+    wm.process_events(&rules, true);
+
+    assert!(handle.log().is_empty());
+}
+
+// STARTUP SWEEP
+
+#[test]
+fn disabled_startup_sweep_leaves_existing_windows() {
+    let rules = compile(r#"
+        [startup]
+        enabled = false
+
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 },
+        FakeWindow { id: 11, class: "kitty", title: "", workspace: 0 },
+    ]);
+    handle.queue.take();
+    handle.push(WindowEvent::Existing(10));
+    handle.push(WindowEvent::Created(11));
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 11 workspace -> 1"]);
+}
+
+#[test]
+fn startup_whitelist_and_dry_run() {
+    let rules = compile(r#"
+        [startup]
+        rules = [1]
+        dry_run = true
+
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+
+        [[rule]]
+        class = "kitty"
+        once_per_session = true
+        above = true
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 },
+        FakeWindow { id: 11, class: "kitty", title: "", workspace: 0 },
+    ]);
+    handle.queue.take();
+    handle.push(WindowEvent::Existing(10));
+
+    wm.process_events(&rules, false);
+    assert!(handle.log().is_empty());
+
+    // The previewed match did not use up the once-per-session rule
+    handle.push(WindowEvent::Created(11));
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log(), vec!["apply 11 workspace -> 1", "apply 11 above"]);
+}
+
+// SESSION RESTORE
+
+#[test]
+fn session_restore_holds_back_listed_actions() {
+    let rules = compile(r#"
+        [session_restore]
+        seconds = 3600
+
+        [[rule]]
+        class = "kitty"
+        focus = true
+        opacity = 0.9
+    "#);
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 }]);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 10 opacity -> 0.9"]);
+}
+
+#[test]
+fn session_restore_covers_remembered_windows_and_alerts() {
+    let rules = compile(r#"
+        [session_restore]
+        seconds = 3600
+
+        [[rule]]
+        class = "gimp"
+        remember = true
+        focus = true
+
+        [[rule]]
+        class = "kitty"
+        workspace = 99
+        alert_on_failure = true
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "gimp", title: "", workspace: 0 },
+        FakeWindow { id: 11, class: "kitty", title: "", workspace: 0 },
+    ]);
+    let mut memory = cherrypie::memory::Memory::default();
+    let last = Remembered { x: 100, y: 50, width: 1024, height: 768, workspace: None };
+    memory.windows.insert("gimp//".into(), last);
+    wm.set_memory(memory);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 10 size -> Absolute(1024, 768)", "apply 11 workspace -> 99"]);
+    assert!(wm.last_failure().is_some());
+    assert!(wm.take_alerts().is_empty());
+}
+
+#[test]
+fn session_restore_is_off_by_default() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        focus = true
+        opacity = 0.9
+    "#);
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 }]);
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 10 focus"]);
+}
+
+// FOCUS TRIGGER
+
+#[test]
+fn focus_rules_apply_on_focus_and_revert_on_blur() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        on = "focus"
+        opacity = 1.0
+
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 },
+        FakeWindow { id: 11, class: "firefox", title: "", workspace: 0 },
+    ]);
+
+    // Creation only fires map rules
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 1"]);
+
+    handle.push(WindowEvent::FocusChanged { old: Some(11), new: Some(10) });
+    handle.push(WindowEvent::FocusChanged { old: Some(10), new: Some(11) });
+    wm.process_events(&rules, false);
+
+    assert_eq!(
+        handle.log(),
+        vec![
+            "apply 10 workspace -> 1",
+            "apply 10 opacity -> 1",
+            "revert 10 opacity -> 1",
+        ]
+    );
+}
+
+// RE-APPLY
+
+#[test]
+fn apply_modes_control_refiring_on_property_changes() {
+    let rules = compile(r#"
+        [[rule]]
+        title = "YouTube"
+        workspace = 3
+
+        [[rule]]
+        title = "YouTube"
+        apply = "on_title_change"
+        workspace = 4
+
+        [[rule]]
+        class = "firefox"
+        apply = "always"
+        workspace = 5
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "firefox", title: "New Tab", workspace: 0 },
+    ]);
+
+    wm.process_events(&rules, false);
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 5"]);
+
+    handle.retitle(10, "YouTube - Firefox");
+    handle.push(WindowEvent::PropertyChanged(10, ChangedProperty::Other));
+    wm.process_events(&rules, false);
+
+    assert_eq!(
+        handle.log(),
+        vec![
+            "apply 10 workspace -> 5",
+            "apply 10 workspace -> 4",
+            "apply 10 workspace -> 5",
+            "apply 10 workspace -> 5",
+        ]
+    );
+}
+
+// WORKSPACE SWITCH TRIGGER
+
+#[test]
+fn workspace_switch_rules_run_on_windows_of_new_workspace() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        on = "workspace-switch"
+        above = true
+    "#);
+    let (wm, handle) = fake_manager(vec![
+        FakeWindow { id: 10, class: "kitty", title: "", workspace: 1 },
+        FakeWindow { id: 11, class: "kitty", title: "", workspace: 2 },
+        FakeWindow { id: 12, class: "kitty", title: "", workspace: 0xFFFFFFFF },
+    ]);
+
+    // Creation does not fire workspace-switch rules
+    wm.process_events(&rules, false);
+    assert!(handle.log().is_empty());
+
+    handle.push(WindowEvent::WorkspaceChanged(2));
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 11 above", "apply 12 above"]);
+}
+
+// COMPOSITOR
+
+#[test]
+fn if_compositor_gates_rules() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        if_compositor = true
+        workspace = 1
+
+        [[rule]]
+        class = "kitty"
+        if_compositor = false
+        workspace = 2
+    "#);
+    let (wm, handle) = fake_manager(vec![FakeWindow { id: 10, class: "kitty", title: "", workspace: 0 }]);
+    handle.compositor.set(Some(false));
+
+    wm.process_events(&rules, false);
+
+    assert_eq!(handle.log(), vec!["apply 10 workspace -> 2"]);
+}
+
+#[test]
+fn compositor_actions_without_compositor_warn() {
+    let rules = compile(r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+
+        [[rule]]
+        class = "kitty"
+        opacity = 0.9
+
+        [[rule]]
+        class = "kitty"
+        if_compositor = true
+        opacity = 0.8
+
+        [[rule]]
+        class = "polybar"
+        shadow = false
+    "#);
+    let (wm, handle) = fake_manager(Vec::new());
+
+    // Unknown compositor state is not worth a warning
+    assert!(wm.rule_warnings(&rules).is_empty());
+
+    handle.compositor.set(Some(true));
+    assert!(wm.rule_warnings(&rules).is_empty());
+
+    handle.compositor.set(Some(false));
+    assert_eq!(
+        wm.rule_warnings(&rules),
+        vec![
+            "rule[1]: opacity has no effect without a running compositor",
+            "rule[3]: shadow has no effect without a running compositor",
+        ]
+    );
+}
+
+// REGISTRY
+
+#[test]
+fn registry_tries_backends_in_order() {
+    let mut registry = Registry::empty();
+    registry
+        .register("broken", |_| Err("unavailable".into()))
+        .register("fake", |_| Ok(Box::new(FakeBackend::new(Vec::new()).0)));
+
+    assert_eq!(registry.names(), vec!["broken", "fake"]);
+    let wm = WindowManager::init_from(&registry, -1).unwrap();
+    assert_eq!(wm.backend_name(), "fake");
+}
+
+#[test]
+fn empty_registry_fails() {
+    assert!(WindowManager::init_from(&Registry::empty(), -1).is_err());
+}
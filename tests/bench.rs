@@ -0,0 +1,35 @@
+use cherrypie::bench;
+use cherrypie::config::Config;
+use cherrypie::rules;
+
+#[test]
+fn synthetic_windows_are_reproducible() {
+    let a = bench::synthetic_windows(50);
+    let b = bench::synthetic_windows(50);
+    assert_eq!(a.len(), 50);
+    assert!(a.iter().zip(&b).all(|(x, y)| x.class == y.class && x.title == y.title));
+    assert!(a.iter().all(|w| w.size.is_some() && w.monitor.is_some()));
+}
+
+#[test]
+fn report_covers_every_rule() {
+    let cfg: Config = toml::from_str(r#"
+        [[rule]]
+        class = ".*"
+        workspace = 1
+
+        [[rule]]
+        class = "^no-such-class$"
+        workspace = 2
+    "#).unwrap();
+    let compiled = rules::compile(&cfg).unwrap();
+    let windows = bench::synthetic_windows(20);
+
+    let report = bench::run(&compiled, &windows, 2);
+
+    assert_eq!(report.windows, 20);
+    assert_eq!(report.rules.len(), 2);
+    assert_eq!(report.rules[0].matched, 20);
+    assert_eq!(report.rules[1].matched, 0);
+    assert!(report.to_string().contains("2 rules x 20 windows x 2 rounds"));
+}
@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+
+use cherrypie::capture;
+
+#[test]
+fn expands_path_template() {
+    let path = capture::path("/srv/shots/%c-%t-%w-%T%%.png", "Zenity", "Update a/b", 0x1a00004, 1700000000);
+    assert_eq!(path, PathBuf::from("/srv/shots/Zenity-Update a_b-0x1a00004-1700000000%.png"));
+    assert_eq!(capture::path("/tmp/%x%", "c", "t", 1, 0), PathBuf::from("/tmp/%x%"));
+}
+
+#[test]
+fn encodes_png() {
+    let rgb = capture::bgrx_to_rgb(&[1, 2, 3, 0, 4, 5, 6, 0]);
+    assert_eq!(rgb, vec![3, 2, 1, 6, 5, 4]);
+
+    let png = capture::encode_png(2, 1, &rgb);
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    // IHDR: length, type, 2x1, 8-bit RGB, then its CRC
+    assert_eq!(&png[8..16], b"\0\0\0\x0dIHDR");
+    assert_eq!(&png[16..29], &[0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+    assert_eq!(&png[29..33], &[0x7b, 0x40, 0xe8, 0xdd]);
+    // IDAT: zlib header, one final stored block of 7 bytes (filter byte + 2 pixels), Adler-32
+    let idat = &png[33..];
+    assert_eq!(&idat[4..8], b"IDAT");
+    assert_eq!(&idat[8..15], &[0x78, 0x01, 1, 7, 0, 0xf8, 0xff]);
+    assert_eq!(&idat[15..22], &[0, 3, 2, 1, 6, 5, 4]);
+    assert_eq!(&idat[22..26], &[0x00, 0x47, 0x00, 0x16]);
+    assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+}
@@ -1,7 +1,9 @@
 use std::fs;
 use std::path::PathBuf;
 
-use cherrypie::config;
+use cherrypie::config::{self, MaximizeValue, PatternValue, WorkspaceValue};
+use cherrypie::matchers::WindowProps;
+use cherrypie::rules;
 
 fn temp_config(content: &str) -> (tempfile::TempDir, config::Paths) {
     let dir = tempfile::tempdir().unwrap();
@@ -26,9 +28,9 @@ fn parse_single_rule() {
 
     let cfg = config::load(&paths).unwrap();
     assert_eq!(cfg.rule.len(), 1);
-    assert_eq!(cfg.rule[0].class.as_deref(), Some("kitty"));
-    assert_eq!(cfg.rule[0].workspace, Some(1));
-    assert_eq!(cfg.rule[0].maximize, Some(true));
+    assert_eq!(cfg.rule[0].class, Some(PatternValue::One("kitty".into())));
+    assert_eq!(cfg.rule[0].workspace, Some(WorkspaceValue::Index(1)));
+    assert_eq!(cfg.rule[0].maximize, Some(MaximizeValue::Toggle(true)));
     assert!(cfg.rule[0].title.is_none());
     assert!(cfg.rule[0].position.is_none());
 }
@@ -58,6 +60,245 @@ fn parse_multiple_rules() {
     assert_eq!(cfg.rule.len(), 3);
 }
 
+#[test]
+fn includes_rule_files() {
+    let (dir, paths) = temp_config(
+        "include = [\"rules.d/*.toml\", \"extra.toml\"]\n[[rule]]\nclass = \"main\"\nmaximize = true\n",
+    );
+    let rules_d = dir.path().join("rules.d");
+    fs::create_dir(&rules_d).unwrap();
+    fs::write(rules_d.join("b.toml"), "[[rule]]\nclass = \"b\"\nmaximize = true\n").unwrap();
+    let a = concat!(
+        "[[rule]]\nclass = \"a\"\nwhen = \"docked\"\nmaximize = true\n",
+        "[conditions]\ndocked = \"monitors >= 2\"\n",
+    );
+    fs::write(rules_d.join("a.toml"), a).unwrap();
+    fs::write(rules_d.join(".a.toml.swp"), "not toml").unwrap();
+    fs::write(rules_d.join("notes.txt"), "not toml").unwrap();
+    fs::write(dir.path().join("extra.toml"), "[[rule]]\nclass = \"extra\"\nmaximize = true\n").unwrap();
+
+    let cfg = config::load(&paths).unwrap();
+    let classes: Vec<_> = cfg.rule.iter().map(|r| r.class.clone().unwrap()).collect();
+    let expected = ["main", "a", "b", "extra"].map(|c| PatternValue::One(c.into()));
+    assert_eq!(classes, expected);
+    assert!(cfg.conditions.contains_key("docked"));
+    let included = vec![rules_d.join("a.toml"), rules_d.join("b.toml"), dir.path().join("extra.toml")];
+    assert_eq!(cfg.included, included);
+    assert_eq!(cfg.include_dirs(&paths.config_file), vec![rules_d, dir.path().to_path_buf()]);
+}
+
+#[test]
+fn reject_bad_include() {
+    let (dir, paths) = temp_config("include = [\"missing.toml\"]\n");
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.starts_with(&format!("{}: ", dir.path().join("missing.toml").display())), "{}", err);
+
+    // An empty conf.d is fine; settings stay in the main config
+    let (dir, paths) = temp_config("include = [\"rules.d/*.toml\"]\n");
+    assert_eq!(config::load(&paths).unwrap().rule.len(), 0);
+    fs::create_dir(dir.path().join("rules.d")).unwrap();
+    fs::write(dir.path().join("rules.d/x.toml"), "[settings]\nstats = true\n").unwrap();
+    assert!(config::load(&paths).unwrap_err().contains("unknown field `settings`"));
+}
+
+#[test]
+fn substitutes_vars() {
+    let (_dir, paths) = temp_config(r#"
+        [vars]
+        left = "DP-1"
+        mail = 3
+        editor = ["60%", "100%"]
+
+        [[rule]]
+        class = "thunderbird"
+        workspace = "${mail}"
+        monitor = "${left}"
+
+        [[rule]]
+        title = "${left} - ${mail} - ${unknown}"
+        size = "${editor}"
+        exec = "echo ${HOME}"
+    "#);
+    let cfg = config::load(&paths).unwrap();
+    assert_eq!(cfg.rule[0].workspace, Some(WorkspaceValue::Index(3)));
+    assert!(matches!(cfg.rule[0].monitor, Some(config::MonitorValue::Name(ref n)) if n == "DP-1"));
+    assert_eq!(cfg.rule[1].title, Some(PatternValue::One("DP-1 - 3 - ${unknown}".into())));
+    assert!(matches!(cfg.rule[1].size, Some(config::SizeValue::Flexible(ref s)) if s == &["60%", "100%"]));
+    assert_eq!(cfg.rule[1].exec.as_deref(), Some("echo ${HOME}"));
+}
+
+#[test]
+fn substitutes_environment() {
+    let (_dir, paths) = temp_config(r#"
+        [vars]
+        dir = "$HOME/src"
+
+        [[rule]]
+        class = "kitty"
+        title = "^${HOME}/x $HOME ${CHERRYPIE_UNSET_FOR_TEST} 5$$"
+        cwd = "${dir}/$CHERRYPIE_UNSET_FOR_TEST/$$HOME"
+        exec = "cd $HOME"
+    "#);
+    let home = std::env::var("HOME").unwrap();
+    let cfg = config::load(&paths).unwrap();
+    // Bare $NAME is a regex anchor in patterns
+    let title = format!("^{}/x $HOME ${{CHERRYPIE_UNSET_FOR_TEST}} 5$", home);
+    assert_eq!(cfg.rule[0].title, Some(PatternValue::One(title)));
+    assert_eq!(cfg.rule[0].cwd, Some(format!("{}/src/$CHERRYPIE_UNSET_FOR_TEST/$HOME", home)));
+    assert_eq!(cfg.rule[0].exec.as_deref(), Some("cd $HOME"));
+}
+
+#[test]
+fn groups_share_keys_with_their_rules() {
+    let (_dir, paths) = temp_config(r#"
+        [[group]]
+        class = "jetbrains-idea"
+        decorate = false
+
+        [[group.rule]]
+        title = "^Welcome"
+        position = "center"
+
+        [[group.rule]]
+        title = "^Settings"
+        decorate = true
+
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+    "#);
+    let cfg = config::load(&paths).unwrap();
+    assert_eq!(cfg.rule.len(), 3);
+    assert_eq!(cfg.rule[0].class, Some(PatternValue::One("kitty".into())));
+    assert_eq!(cfg.rule[1].class, Some(PatternValue::One("jetbrains-idea".into())));
+    assert_eq!(cfg.rule[1].title, Some(PatternValue::One("^Welcome".into())));
+    assert_eq!(cfg.rule[1].decorate, Some(false));
+    assert_eq!(cfg.rule[2].class, Some(PatternValue::One("jetbrains-idea".into())));
+    assert_eq!(cfg.rule[2].decorate, Some(true));
+
+    let (_dir, paths) = temp_config("[[group]]\nclass = \"x\"\n");
+    assert!(config::load(&paths).unwrap_err().ends_with("group[0]: no [[group.rule]] entries"));
+}
+
+#[test]
+fn reject_list_var_inside_text() {
+    let toml = "[vars]\nsize = [800, 600]\n[[rule]]\nclass = \"x\"\nexec = \"echo ${size}\"\n";
+    let (_dir, paths) = temp_config(toml);
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.ends_with(": ${size} is a list and has to be the whole value"), "{}", err);
+}
+
+#[test]
+fn defaults_fill_in_every_rule() {
+    let (dir, paths) = temp_config(r#"
+        include = ["more.toml"]
+
+        [vars]
+        dim = 0.9
+
+        [defaults]
+        decorate = true
+        opacity = "${dim}"
+
+        [[rule]]
+        class = "kitty"
+
+        [[rule]]
+        class = "mpv"
+        decorate = false
+    "#);
+    fs::write(dir.path().join("more.toml"), "[[rule]]\nclass = \"feh\"\n").unwrap();
+
+    let cfg = config::load(&paths).unwrap();
+    let keys: Vec<_> = cfg.rule.iter().map(|r| (r.decorate, r.opacity)).collect();
+    assert_eq!(keys, vec![(Some(true), Some(0.9)), (Some(false), Some(0.9)), (Some(true), Some(0.9))]);
+
+    let (_dir, paths) = temp_config("[defaults]\nclass = \"kitty\"\n[[rule]]\nclass = \"a\"\nfocus = true\n");
+    assert_eq!(config::load(&paths).unwrap_err(), "defaults: takes no matchers; its keys go into every rule");
+}
+
+#[test]
+fn defaults_leave_layout_and_identity_to_the_rule() {
+    let load = |content: &str| {
+        let (_dir, paths) = temp_config(content);
+        config::load(&paths)
+    };
+    let cfg = load(r#"
+        [defaults]
+        size = [800, 600]
+        position = [10, 10]
+
+        [[rule]]
+        class = "a"
+        position = "left-half"
+
+        [[rule]]
+        class = "b"
+        move_by = [0, 20]
+
+        [[rule]]
+        class = "c"
+    "#).unwrap();
+    let sizes: Vec<_> = cfg.rule.iter().map(|r| (r.size.is_some(), r.position.is_some())).collect();
+    assert_eq!(sizes, [(false, true), (false, false), (true, true)]);
+
+    let err = load("[defaults]\nname = \"x\"\n[[rule]]\nclass = \"a\"\n").unwrap_err();
+    assert!(err.ends_with(": defaults: name is set per rule, not in [defaults]"), "{}", err);
+    assert_eq!(
+        load("[defaults]\nmove_by = [0, 20]\n[[rule]]\nclass = \"a\"\nposition = [0, 0]\n").unwrap_err(),
+        "rule[0]: move_by and position both set the position; use one (move_by from [defaults])"
+    );
+}
+
+#[test]
+fn profiles_add_rules_by_name_or_condition() {
+    let (_dir, mut paths) = temp_config(r#"
+        [[rule]]
+        class = "kitty"
+        focus = true
+
+        [profile.mobile]
+        when = "monitors == 1"
+        [[profile.mobile.rule]]
+        class = "firefox"
+        maximize = true
+
+        [profile.docked]
+        when = "monitors >= 2"
+        [[profile.docked.rule]]
+        class = "firefox"
+        monitor = 1
+
+        [profile.demo]
+        [[profile.demo.rule]]
+        class = "firefox"
+        fullscreen = true
+    "#);
+    let firefox = |monitors: u32| WindowProps {
+        class: "firefox".into(),
+        monitor_count: Some(monitors),
+        ..WindowProps::default()
+    };
+
+    // By name order: demo (rule[1]), docked (rule[2]), mobile (rule[3])
+    paths.profile = None;
+    let compiled = rules::compile(&config::load(&paths).unwrap()).unwrap();
+    assert_eq!(compiled.iter().map(|r| r.index).collect::<Vec<_>>(), vec![0, 2, 3]);
+    assert!(compiled[1].matches(&firefox(2)) && !compiled[1].matches(&firefox(1)));
+    assert!(compiled[2].matches(&firefox(1)) && !compiled[2].matches(&firefox(2)));
+
+    paths.profile = Some("demo".into());
+    let compiled = rules::compile(&config::load(&paths).unwrap()).unwrap();
+    assert_eq!(compiled.iter().map(|r| r.index).collect::<Vec<_>>(), vec![0, 1]);
+    assert!(compiled[1].matches(&firefox(2)));
+
+    paths.profile = Some("office".into());
+    assert_eq!(
+        config::load(&paths).unwrap_err(),
+        "unknown profile 'office' (defined: demo, docked, mobile)"
+    );
+}
+
 #[test]
 fn empty_rules_array() {
     let (_dir, paths) = temp_config("rule = []");
@@ -79,7 +320,7 @@ fn parse_process_matcher() {
     );
 
     let cfg = config::load(&paths).unwrap();
-    assert_eq!(cfg.rule[0].process.as_deref(), Some("montauk"));
+    assert_eq!(cfg.rule[0].process, Some(PatternValue::One("montauk".into())));
 }
 
 #[test]
@@ -94,7 +335,7 @@ fn parse_type_matcher() {
     );
 
     let cfg = config::load(&paths).unwrap();
-    assert_eq!(cfg.rule[0].window_type.as_deref(), Some("dialog"));
+    assert_eq!(cfg.rule[0].window_type, Some(PatternValue::One("dialog".into())));
     assert_eq!(cfg.rule[0].above, Some(true));
 }
 
@@ -183,6 +424,203 @@ fn reject_invalid_position_name() {
     assert!(err.contains("invalid position"), "got: {}", err);
 }
 
+#[test]
+fn reject_relative_and_absolute_geometry_together() {
+    let load = |keys: &str| {
+        let (_dir, paths) = temp_config(&format!("[[rule]]\nclass = \"test\"\n{}\n", keys));
+        config::load(&paths)
+    };
+
+    assert_eq!(
+        load("move_by = [10, 0]\nposition = \"center\"").unwrap_err(),
+        "rule[0]: move_by and position both set the position; use one"
+    );
+    assert_eq!(
+        load("resize_by = [10, 0]\nposition = \"left-half\"").unwrap_err(),
+        "rule[0]: resize_by and size both set the size; use one"
+    );
+    assert!(load("resize_by = [10, 0]\nposition = \"center\"").is_ok());
+}
+
+#[test]
+fn monitor_defaults_take_actions_but_no_matchers() {
+    let load = |content: &str| {
+        let (_dir, paths) = temp_config(content);
+        config::load(&paths)
+    };
+    let rule = "[[rule]]\nclass = \"a\"\nmaximize = true\n";
+
+    let cfg = load(&format!("{}[[monitor_defaults]]\noutput = \"HDMI-1\"\nfullscreen = true\n", rule)).unwrap();
+    assert_eq!(cfg.monitor_defaults.len(), 1);
+    assert_eq!(cfg.monitor_defaults[0].rule.fullscreen, Some(true));
+
+    assert_eq!(
+        load(&format!("{}[[monitor_defaults]]\noutput = \"HDMI-1\"\nclass = \"mpv\"\n", rule)).unwrap_err(),
+        "monitor_defaults[0]: takes no matchers; it applies to every window on its output"
+    );
+    assert_eq!(
+        load(&format!("{}[[monitor_defaults]]\noutput = 1\nmax_matches = 0\n", rule)).unwrap_err(),
+        "monitor_defaults[0]: max_matches must be at least 1"
+    );
+    assert!(load(&format!("{}[[monitor_defaults]]\nfullscreen = true\n", rule)).is_err());
+}
+
+#[test]
+fn reject_bad_grid_cells() {
+    let load = |content: &str| {
+        let (_dir, paths) = temp_config(content);
+        config::load(&paths)
+    };
+    let grid = "[grid]\ncols = 12\nrows = 6\n";
+
+    assert_eq!(
+        load("[[rule]]\nclass = \"a\"\ncell = [0, 0, 6, 6]\n").unwrap_err(),
+        "rule[0]: cell needs a [grid] table"
+    );
+    assert_eq!(
+        load(&format!("{}[[rule]]\nclass = \"a\"\ncell = [8, 0, 6, 6]\n", grid)).unwrap_err(),
+        "rule[0]: cell [8, 0, 6, 6] is outside the 12x6 grid"
+    );
+    assert_eq!(
+        load(&format!("{}[[rule]]\nclass = \"a\"\ncell = [4294967295, 0, 1, 1]\n", grid)).unwrap_err(),
+        "rule[0]: cell [4294967295, 0, 1, 1] is outside the 12x6 grid"
+    );
+    assert_eq!(
+        load(&format!("{}[[rule]]\nclass = \"a\"\ncell = [0, 0, 6, 6]\nposition = \"center\"\n", grid)).unwrap_err(),
+        "rule[0]: cell sets the position and size; remove position"
+    );
+    assert_eq!(
+        load("[grid]\ncols = 0\nrows = 6\n[[rule]]\nclass = \"a\"\nmaximize = true\n").unwrap_err(),
+        "grid: cols and rows must be at least 1"
+    );
+    assert!(load(&format!("{}[[rule]]\nclass = \"a\"\ncell = [6, 0, 6, 6]\n", grid)).is_ok());
+}
+
+#[test]
+fn reject_bad_border() {
+    let load = |keys: &str| {
+        let (_dir, paths) = temp_config(&format!("[[rule]]\nclass = \"test\"\n{}\n", keys));
+        config::load(&paths)
+    };
+
+    assert_eq!(load("border = {}").unwrap_err(), "rule[0]: border needs width or color");
+    assert_eq!(
+        load("border = { width = 1, color = \"red\" }").unwrap_err(),
+        "rule[0]: invalid border color 'red' (expected #rrggbb)"
+    );
+    assert!(load("border = { width = 0 }").is_ok());
+}
+
+#[test]
+fn reject_bad_strut() {
+    let load = |keys: &str| {
+        let (_dir, paths) = temp_config(&format!("[[rule]]\nclass = \"conky\"\n{}\n", keys));
+        config::load(&paths)
+    };
+
+    assert_eq!(
+        load("strut = { edge = \"middle\", size = 40 }").unwrap_err(),
+        "rule[0]: invalid strut edge 'middle' (expected top, bottom, left, or right)"
+    );
+    assert_eq!(
+        load("strut = { edge = \"top\", size = 0 }").unwrap_err(),
+        "rule[0]: strut size must be at least 1"
+    );
+    assert!(load("strut = { edge = \"left\", size = 300 }").is_ok());
+}
+
+#[test]
+fn reject_bad_maximize_axis() {
+    let load = |keys: &str| {
+        let (_dir, paths) = temp_config(&format!("[[rule]]\nclass = \"emacs\"\n{}\n", keys));
+        config::load(&paths)
+    };
+
+    assert_eq!(
+        load("maximize = \"diagonal\"").unwrap_err(),
+        "rule[0]: invalid maximize 'diagonal' (expected horizontal, vertical, or both)"
+    );
+    assert!(load("maximize = \"both\"").is_ok());
+    assert!(load("maximize = \"vertical\"").is_ok());
+}
+
+#[test]
+fn reject_bad_rule_names() {
+    let load = |toml: &str| {
+        let (_dir, paths) = temp_config(toml);
+        config::load(&paths)
+    };
+
+    assert_eq!(
+        load("[[rule]]\nclass = \"a\"\nname = \"slack ws2\"\nfocus = true\n").unwrap_err(),
+        "rule[0]: invalid name 'slack ws2' (one word expected)"
+    );
+    let twice = "[[rule]]\nclass = \"a\"\nname = \"chat\"\nfocus = true\n".repeat(2);
+    assert_eq!(load(&twice).unwrap_err(), "rule[1]: name 'chat' is already used by rule[0]");
+}
+
+#[test]
+fn reject_empty_icon() {
+    let (_dir, paths) = temp_config("[[rule]]\nclass = \"wine\"\nicon = \" \"\n");
+    assert_eq!(config::load(&paths).unwrap_err(), "rule[0]: icon is empty");
+}
+
+#[test]
+fn reject_bad_size_bounds() {
+    let load = |keys: &str| {
+        let (_dir, paths) = temp_config(&format!("[[rule]]\nclass = \"test\"\n{}\n", keys));
+        config::load(&paths)
+    };
+
+    assert_eq!(
+        load("min_size = [800, 300]\nmax_size = [640, 480]").unwrap_err(),
+        "rule[0]: min_size is larger than max_size"
+    );
+    assert_eq!(
+        load("max_size = [\"90%\", \"big\"]").unwrap_err(),
+        "rule[0]: invalid max_size y/height value 'big'"
+    );
+    assert_eq!(
+        load("size_hints = true").unwrap_err(),
+        "rule[0]: size_hints requires min_size or max_size"
+    );
+    assert!(load("min_size = [\"50%\", \"50%\"]\nmax_size = [640, 480]\nsize_hints = true").is_ok());
+    assert_eq!(
+        load("aspect = \"16/9\"").unwrap_err(),
+        "rule[0]: invalid aspect '16/9' (expected W:H, e.g. 16:9)"
+    );
+}
+
+#[test]
+fn reject_bad_anchor_offsets() {
+    let load = |position: &str| {
+        let (_dir, paths) = temp_config(&format!("[[rule]]\nclass = \"test\"\nposition = {}\n", position));
+        config::load(&paths).unwrap_err()
+    };
+
+    assert_eq!(load(r#""middle+10,10""#), "rule[0]: invalid position 'middle+10,10': unknown anchor 'middle'");
+    assert_eq!(
+        load(r#""center+10""#),
+        "rule[0]: invalid position 'center+10': invalid offset '+10' (expected +X,+Y)"
+    );
+    assert!(load(r#"{ anchor = "middle", offset = [0, 0] }"#).starts_with("rule[0]: invalid position anchor 'middle'"));
+}
+
+#[test]
+fn reject_size_alongside_tile_position() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "test"
+        position = "left-half"
+        size = [800, 600]
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert_eq!(err, "rule[0]: position 'left-half' sets the size too; remove size");
+}
+
 #[test]
 fn reject_invalid_position_percentage() {
     let (_dir, paths) = temp_config(
@@ -419,3 +857,523 @@ fn parse_full_example_config() {
     let cfg = config::load(&paths).unwrap();
     assert_eq!(cfg.rule.len(), 8);
 }
+
+// GEOMETRY MATCHERS
+
+#[test]
+fn size_bound_alone_is_valid_matcher() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        min_width = 1000
+        maximize = true
+        "#,
+    );
+
+    let cfg = config::load(&paths).unwrap();
+    assert_eq!(cfg.rule[0].min_width, Some(1000));
+}
+
+#[test]
+fn reject_inverted_size_bounds() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        min_height = 800
+        max_height = 400
+        maximize = true
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("min_height"), "got: {}", err);
+}
+
+// STATE MATCHER
+
+#[test]
+fn parse_state_matcher() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        state = ["maximized", "!fullscreen"]
+        size = ["60%", "60%"]
+        "#,
+    );
+
+    let cfg = config::load(&paths).unwrap();
+    assert_eq!(
+        cfg.rule[0].state.as_deref(),
+        Some(&["maximized".to_string(), "!fullscreen".to_string()][..])
+    );
+}
+
+#[test]
+fn reject_unknown_state() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "test"
+        state = ["!floating"]
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("invalid state '!floating'"), "got: {}", err);
+}
+
+#[test]
+fn on_workspace_alone_is_valid_matcher() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        on_workspace = 8
+        position = "center"
+        "#,
+    );
+
+    let cfg = config::load(&paths).unwrap();
+    assert_eq!(cfg.rule[0].on_workspace, Some(8));
+}
+
+#[test]
+fn parse_on_monitor_matcher() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        on_monitor = "HDMI-1"
+        monitor = "eDP-1"
+        "#,
+    );
+
+    let cfg = config::load(&paths).unwrap();
+    match &cfg.rule[0].on_monitor {
+        Some(config::MonitorValue::Name(n)) => assert_eq!(n, "HDMI-1"),
+        _ => panic!("expected Name on_monitor"),
+    }
+}
+
+#[test]
+fn negated_matcher_alone_is_valid() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class_not = "kitty"
+        decorate = false
+        "#,
+    );
+
+    let cfg = config::load(&paths).unwrap();
+    assert_eq!(cfg.rule[0].class_not, Some(PatternValue::One("kitty".into())));
+}
+
+// PATTERN LISTS
+
+#[test]
+fn parse_pattern_list() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = ["kitty", "alacritty", "foot"]
+        workspace = 1
+        "#,
+    );
+
+    let cfg = config::load(&paths).unwrap();
+    let class = cfg.rule[0].class.as_ref().unwrap();
+    assert_eq!(class.patterns(), &["kitty", "alacritty", "foot"]);
+}
+
+#[test]
+fn reject_empty_pattern_list() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = []
+        workspace = 1
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("class is an empty list"), "got: {}", err);
+}
+
+// PHYSICAL UNITS
+
+#[test]
+fn parse_size_millimeters() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "zathura"
+        size = ["300mm", "200mm"]
+        position = ["10mm", "10%"]
+        "#,
+    );
+
+    config::load(&paths).unwrap(); // should not error
+}
+
+#[test]
+fn reject_invalid_millimeters() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "zathura"
+        size = ["wide mm", "200mm"]
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("invalid size x/width length"), "got: {}", err);
+}
+
+// TRIGGERS
+
+#[test]
+fn reject_unknown_trigger() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "kitty"
+        on = "hover"
+        opacity = 0.9
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("invalid trigger 'hover'"), "got: {}", err);
+}
+
+// MATCH MODE
+
+#[test]
+fn reject_unknown_match_mode() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [settings]
+        match_mode = "fuzzy"
+
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("settings: invalid match_mode 'fuzzy'"), "got: {}", err);
+}
+
+// COMMAND ENVIRONMENT
+
+#[test]
+fn reject_invalid_env_name() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "kitty"
+        env = { "A=B" = "x" }
+        workspace = 1
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: invalid env variable name 'A=B'"), "got: {}", err);
+}
+
+#[test]
+fn reject_zero_max_matches() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "kitty"
+        max_matches = 0
+        maximize = true
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: max_matches must be at least 1"), "got: {}", err);
+}
+
+#[test]
+fn reject_unknown_apply_mode() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "kitty"
+        apply = "twice"
+        workspace = 1
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: invalid apply 'twice'"), "got: {}", err);
+}
+
+#[test]
+fn reject_unknown_group_mode() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "gimp"
+        group = "follow"
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: invalid group 'follow' (expected move-with-main)"), "got: {}", err);
+}
+
+#[test]
+fn reject_unknown_scope() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "thunderbird"
+        scope = "group"
+        workspace = 2
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    let expected = "rule[0]: invalid scope 'group' (expected one of: window, application)";
+    assert!(err.contains(expected), "got: {}", err);
+}
+
+#[test]
+fn reject_follow_without_workspace() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "thunderbird"
+        follow = true
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: follow needs workspace"), "got: {}", err);
+}
+
+#[test]
+fn reject_scratchpad_with_minimize() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "kitty"
+        scratchpad = "term"
+        minimize = false
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: scratchpad hides the window itself; remove minimize"), "got: {}", err);
+}
+
+#[test]
+fn reject_enforce_without_placement() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "steam"
+        enforce = true
+        workspace = 2
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: enforce needs monitor, size, position, or cell"), "got: {}", err);
+}
+
+#[test]
+fn reject_title_timing_without_wait_for_title() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "firefox"
+        title_settle_ms = 500
+        workspace = 2
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: title_settle_ms requires wait_for_title = true"), "got: {}", err);
+}
+
+#[test]
+fn parse_and_validate_geometry_target() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "kitty"
+        target = "frame"
+        size = ["50%", "100%"]
+        "#,
+    );
+    let cfg = config::load(&paths).unwrap();
+    assert_eq!(cfg.rule[0].target.as_deref(), Some("frame"));
+
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "kitty"
+        target = "border"
+        size = ["50%", "100%"]
+        "#,
+    );
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: invalid target 'border'"), "got: {}", err);
+}
+
+#[test]
+fn workspace_among_requires_least_populated() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+        workspace_among = [1, 2]
+        "#,
+    );
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: workspace_among requires"), "got: {}", err);
+
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "kitty"
+        workspace = "mail"
+        workspace_among = [1, 2]
+        "#,
+    );
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: workspace_among requires"), "got: {}", err);
+
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "kitty"
+        workspace = ""
+        "#,
+    );
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: workspace name is empty"), "got: {}", err);
+}
+
+// STARTUP
+
+#[test]
+fn startup_rules_must_exist() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [startup]
+        rules = [0, 3]
+
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("startup: rules: no rule[3]"), "got: {}", err);
+}
+
+// CONDITIONS
+
+#[test]
+fn when_names_must_exist_in_conditions() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [conditions]
+        docked = "monitors >= 2"
+
+        [[rule]]
+        class = "kitty"
+        when = "docked"
+        workspace = 1
+
+        [[rule]]
+        class = "kitty"
+        when = "dockd"
+        workspace = 2
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[1]: unknown condition 'dockd'"), "got: {}", err);
+}
+
+// PROPERTY ACTIONS
+
+#[test]
+fn reject_empty_property_name() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        class = "kitty"
+        del_prop = ""
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: del_prop needs a property name"), "got: {}", err);
+}
+
+// WINDOW GROUPS
+
+#[test]
+fn move_group_requires_a_move() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [[rule]]
+        group_of = "gimp"
+        move_group = true
+        maximize = true
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("rule[0]: move_group requires a workspace or monitor action"), "got: {}", err);
+}
+
+// SESSION RESTORE
+
+#[test]
+fn reject_unknown_suppressed_action() {
+    let (_dir, paths) = temp_config(
+        r#"
+        [session_restore]
+        seconds = 20
+        suppress = ["focus", "raise"]
+
+        [[rule]]
+        class = "kitty"
+        focus = true
+        "#,
+    );
+
+    let err = config::load(&paths).unwrap_err();
+    assert!(err.contains("session_restore: unknown action 'raise'"), "got: {}", err);
+}
+
+// SCHEMA
+
+#[cfg(feature = "schema")]
+#[test]
+fn schema_describes_every_rule_key() {
+    let schema: serde_json::Value = serde_json::from_str(&config::schema()).unwrap();
+    let rule = &schema["definitions"]["Rule"]["properties"];
+
+    let more = ["workspace", "when", "set_prop", "kill", "name", "enabled"];
+    for key in config::MATCHER_KEYS.iter().chain(&more) {
+        assert!(rule.get(key).is_some(), "schema lacks rule key '{}'", key);
+    }
+    for table in ["vars", "defaults", "group", "profile", "monitor_defaults", "conditions"] {
+        assert!(schema["properties"].get(table).is_some(), "schema lacks [{}]", table);
+    }
+    assert!(schema["definitions"]["Profile"]["properties"]["when"].is_object());
+    // A config may hold nothing but includes
+    assert!(schema["properties"]["include"].is_object());
+    assert!(schema.get("required").is_none());
+}
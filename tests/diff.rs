@@ -0,0 +1,96 @@
+use cherrypie::diff::{self, Change, KeyChange};
+
+fn changes(old: &str, new: &str) -> Vec<Change> {
+    diff::diff(&toml::from_str(old).unwrap(), &toml::from_str(new).unwrap())
+}
+
+const BASE: &str = r#"
+    [[rule]]
+    class = "kitty"
+    workspace = 1
+
+    [[rule]]
+    class = "firefox"
+    workspace = 2
+
+    [[rule]]
+    class = "mpv"
+    above = true
+"#;
+
+#[test]
+fn identical_configs_have_no_changes() {
+    assert!(changes(BASE, BASE).is_empty());
+}
+
+#[test]
+fn rules_pair_by_matchers_then_actions() {
+    let new = r#"
+        [[rule]]
+        class = "slack"
+        workspace = 4
+
+        [[rule]]
+        class = "kitty"
+        workspace = 3
+
+        [[rule]]
+        class = "firefox"
+        workspace = 2
+
+        [[rule]]
+        class = "vlc"
+        above = true
+    "#;
+
+    assert_eq!(
+        changes(BASE, new),
+        vec![
+            Change::Changed {
+                old: 0,
+                new: 1,
+                keys: vec![KeyChange { key: "workspace".into(), old: Some("1".into()), new: Some("3".into()) }],
+            },
+            Change::Rematched {
+                old: 2,
+                new: 3,
+                keys: vec![KeyChange {
+                    key: "class".into(),
+                    old: Some("\"mpv\"".into()),
+                    new: Some("\"vlc\"".into()),
+                }],
+            },
+            Change::Added { index: 0, summary: "class = \"slack\"".into() },
+        ]
+    );
+}
+
+#[test]
+fn reordering_and_sections_are_reported() {
+    let new = r#"
+        [settings]
+        first_match = true
+
+        [[rule]]
+        class = "mpv"
+        above = true
+
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+    "#;
+
+    let found = changes(BASE, new);
+    assert_eq!(
+        found,
+        vec![
+            Change::Section {
+                name: "settings".into(),
+                keys: vec![KeyChange { key: "first_match".into(), old: None, new: Some("true".into()) }],
+            },
+            Change::Removed { index: 1, summary: "class = \"firefox\"".into() },
+            Change::Moved { old: 2, new: 0 },
+        ]
+    );
+    assert_eq!(found[0].to_string(), "[settings] changed\n  + first_match = true\n");
+}
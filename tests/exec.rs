@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use cherrypie::config::Config;
+use cherrypie::exec::{self, CommandEnv};
+use cherrypie::rules;
+
+fn command_env(toml_str: &str) -> CommandEnv {
+    let cfg: Config = toml::from_str(toml_str).unwrap();
+    rules::compile(&cfg).unwrap().remove(0).command_env
+}
+
+#[test]
+fn defaults_to_sh_without_extra_env() {
+    let env = command_env(r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+    "#);
+    assert_eq!(env, CommandEnv::default());
+    assert_eq!(env.shell_path(), "/bin/sh");
+}
+
+#[test]
+fn rule_env_overlays_settings() {
+    let env = command_env(r#"
+        [settings]
+        cwd = "/srv"
+        shell = "/bin/bash"
+        env = { LANG = "C", PROJECT = "home" }
+
+        [[rule]]
+        class = "kitty"
+        cwd = "/tmp"
+        env = { PROJECT = "work" }
+        workspace = 1
+    "#);
+
+    let expected: BTreeMap<String, String> = [("LANG", "C"), ("PROJECT", "work")]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    assert_eq!(env.env, expected);
+    assert_eq!(env.cwd.as_deref(), Some(std::path::Path::new("/tmp")));
+    assert_eq!(env.shell, "/bin/bash");
+}
+
+#[test]
+fn exec_hook_describes_the_window() {
+    let line = "echo \"$CHERRYPIE_WINDOW_ID $CHERRYPIE_CLASS $CHERRYPIE_TITLE\"";
+    let out = CommandEnv::default()
+        .hook(line, 0x1a00004, "kitty", "zsh")
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "0x1a00004 kitty zsh\n");
+}
+
+#[test]
+fn run_waits_for_the_hook_up_to_a_timeout() {
+    let env = CommandEnv::default();
+    assert_eq!(exec::run(env.command("true"), Duration::from_secs(5)), Ok(()));
+    assert_eq!(exec::run(env.command("exit 3"), Duration::from_secs(5)), Err("exit status: 3".into()));
+    assert_eq!(
+        exec::run(env.command("sleep 5"), Duration::from_millis(50)),
+        Err("still running after 50 ms, not waiting".into())
+    );
+}
+
+#[test]
+fn command_runs_with_env_and_cwd() {
+    let dir = tempfile::tempdir().unwrap();
+    let env = CommandEnv {
+        env: [("CHERRYPIE_TEST".to_string(), "hello".to_string())].into(),
+        cwd: Some(dir.path().to_path_buf()),
+        ..CommandEnv::default()
+    };
+
+    let out = env.command("echo \"$CHERRYPIE_TEST\"; pwd").output().unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let canonical = dir.path().canonicalize().unwrap();
+    assert_eq!(stdout, format!("hello\n{}\n", canonical.display()));
+}
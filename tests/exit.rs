@@ -0,0 +1,30 @@
+use cherrypie::exit::{ErrorFormat, Exit};
+
+#[test]
+fn exit_codes_are_stable() {
+    let codes: Vec<(i32, &str)> = [Exit::Failure, Exit::Usage, Exit::Config, Exit::Backend, Exit::NotRunning, Exit::Partial]
+        .iter()
+        .map(|e| (e.code(), e.kind()))
+        .collect();
+    assert_eq!(
+        codes,
+        vec![(1, "failure"), (2, "usage"), (3, "config"), (4, "backend"), (5, "not_running"), (6, "partial")]
+    );
+}
+
+#[test]
+fn formats_errors_as_text_or_json() {
+    assert_eq!(ErrorFormat::parse("json"), Ok(ErrorFormat::Json));
+    assert!(ErrorFormat::parse("yaml").unwrap_err().contains("unknown error format 'yaml'"));
+
+    let msg = "rule[2]: bad pattern \"(\"\n\tunclosed";
+    assert_eq!(ErrorFormat::Text.message(Exit::Config, "config not found"), "[cherrypie] config not found");
+    assert_eq!(
+        ErrorFormat::Json.message(Exit::Config, msg),
+        r#"{"error":"config","code":3,"message":"rule[2]: bad pattern \"(\"\n\tunclosed"}"#
+    );
+    assert_eq!(
+        ErrorFormat::Json.message(Exit::NotRunning, "a\u{1}b"),
+        r#"{"error":"not_running","code":5,"message":"a\u0001b"}"#
+    );
+}
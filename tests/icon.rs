@@ -0,0 +1,42 @@
+use cherrypie::{capture, icon};
+
+// 3x2 RGBA written by zlib (fixed Huffman block); the second row uses the
+// Up filter to repeat the first
+const RGBA: [u8; 78] = [
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00,
+    0x00, 0x03, 0x00, 0x00, 0x00, 0x02, 0x08, 0x06, 0x00, 0x00, 0x00, 0x9d, 0x74, 0x66, 0x1a, 0x00, 0x00, 0x00,
+    0x15, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0xf8, 0xcf, 0xc0, 0xf0, 0x1f, 0x08, 0x1b, 0x40, 0x14, 0x13,
+    0x03, 0x12, 0x00, 0x00, 0x5a, 0xe2, 0x04, 0x7f, 0xdc, 0x45, 0x93, 0x3c, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45,
+    0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+];
+
+#[test]
+fn decodes_compressed_png() {
+    let row = [0xffff0000, 0x8000ff00, 0x000000ff];
+    let mut expected = vec![3, 2];
+    expected.extend(row);
+    expected.extend(row);
+    assert_eq!(icon::decode_png(&RGBA).unwrap(), expected);
+}
+
+#[test]
+fn decodes_captured_png() {
+    let png = capture::encode_png(2, 1, &[255, 128, 0, 1, 2, 3]);
+    assert_eq!(icon::decode_png(&png).unwrap(), vec![2, 1, 0xffff8000, 0xff010203]);
+}
+
+#[test]
+fn rejects_what_it_cannot_read() {
+    assert_eq!(icon::decode_png(b"GIF89a").unwrap_err(), "not a PNG file");
+
+    // 16-bit depth
+    let mut deep = RGBA;
+    deep[24] = 16;
+    assert!(icon::decode_png(&deep).unwrap_err().starts_with("unsupported PNG (bit depth 16"));
+
+    let huge = capture::encode_png(icon::MAX_SIDE + 1, 1, &vec![0; (icon::MAX_SIDE as usize + 1) * 3]);
+    assert_eq!(icon::decode_png(&huge).unwrap_err(), "513x1 is not an icon size (at most 512 pixels a side)");
+
+    let missing = icon::load(std::path::Path::new("/nonexistent/icon.png")).unwrap_err();
+    assert!(missing.starts_with("/nonexistent/icon.png: "));
+}
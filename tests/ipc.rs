@@ -0,0 +1,91 @@
+use cherrypie::ipc::{self, Failure, Request, RuleRef, ScratchpadOp, Server, WindowSpec};
+use cherrypie::rules::Grid;
+
+#[test]
+fn parses_grid_place() {
+    assert_eq!(
+        Request::parse("grid-place 0x1a00004 3x2 4\n"),
+        Ok(Request::GridPlace {
+            window: WindowSpec::Id(0x1a00004),
+            grid: Grid { cols: 3, rows: 2 },
+            cell: 4,
+        })
+    );
+
+    let active = Request::parse("grid-place active 2x1 0").unwrap();
+    assert_eq!(active.to_string(), "grid-place active 2x1 0");
+    assert_eq!(Request::parse(&active.to_string()), Ok(active));
+}
+
+#[test]
+fn parses_reapply_flags() {
+    assert_eq!(Request::parse("reapply"), Ok(Request::Reapply { rule: None, workspace: None }));
+    assert_eq!(Request::parse("debug-dump\n"), Ok(Request::DebugDump));
+    let both = Request::parse("reapply --workspace 2 --rule 5").unwrap();
+    assert_eq!(both, Request::Reapply { rule: Some(RuleRef::Index(5)), workspace: Some(2) });
+    assert_eq!(both.to_string(), "reapply --rule 5 --workspace 2");
+    let named = Request::parse("reapply --rule mpv-five").unwrap();
+    assert_eq!(named, Request::Reapply { rule: Some(RuleRef::Name("mpv-five".into())), workspace: None });
+    assert_eq!(named.to_string(), "reapply --rule mpv-five");
+
+    assert_eq!(Request::parse("reapply --rule"), Err("--rule requires a value".into()));
+    assert_eq!(Request::parse("reapply --workspace x"), Err("invalid --workspace 'x'".into()));
+    assert!(Request::parse("reapply --all 1").unwrap_err().starts_with("usage:"));
+}
+
+#[test]
+fn parses_scratchpad() {
+    let toggle = Request::parse("scratchpad toggle term\n").unwrap();
+    assert_eq!(toggle, Request::Scratchpad { op: ScratchpadOp::Toggle, name: "term".into() });
+    assert_eq!(Request::parse(&toggle.to_string()), Ok(toggle));
+    assert_eq!(Request::parse("scratchpad hide term").unwrap().to_string(), "scratchpad hide term");
+
+    assert!(Request::parse("scratchpad flip term").unwrap_err().starts_with("invalid scratchpad command 'flip'"));
+    assert!(Request::parse("scratchpad toggle").unwrap_err().starts_with("usage:"));
+}
+
+#[test]
+fn rejects_malformed_requests() {
+    assert!(Request::parse("grid-place active 3x2").unwrap_err().starts_with("usage:"));
+    assert!(Request::parse("grid-place focused 3x2 0").unwrap_err().contains("invalid window"));
+    assert!(Request::parse("grid-place active 0x2 0").unwrap_err().contains("invalid grid"));
+    assert_eq!(Request::parse("launch kitty"), Err("unknown command 'launch'".into()));
+    assert_eq!(Request::parse(""), Err("empty request".into()));
+}
+
+// Answer one request with `reply` and return what the client got back
+fn round_trip(path: &std::path::Path, request: &Request, reply: Result<String, Failure>) -> Result<String, Failure> {
+    let server = Server::bind(path).unwrap();
+    let client = {
+        let (path, request) = (path.to_path_buf(), request.clone());
+        std::thread::spawn(move || ipc::send(&path, &request))
+    };
+    let mut conn = loop {
+        if let Some(conn) = server.accept() {
+            break conn;
+        }
+        std::thread::yield_now();
+    };
+    assert_eq!(conn.request().as_ref(), Ok(request));
+    conn.reply(reply);
+    client.join().unwrap()
+}
+
+#[test]
+fn request_and_reply_over_socket() {
+    let path = std::env::temp_dir().join(format!("cherrypie-ipc-{}.sock", std::process::id()));
+    let request = Request::parse("grid-place active 2x2 3").unwrap();
+
+    assert_eq!(round_trip(&path, &request, Err("no active window".into())), Err("no active window".into()));
+    assert!(!path.exists());
+}
+
+#[test]
+fn partial_failures_and_missing_daemon() {
+    let path = std::env::temp_dir().join(format!("cherrypie-ipc-partial-{}.sock", std::process::id()));
+    let request = Request::parse("grid-place 0x400001 2x1 0").unwrap();
+
+    let partial = Failure::Partial("size: BadValue".into());
+    assert_eq!(round_trip(&path, &request, Err(partial.clone())), Err(partial));
+    assert!(matches!(ipc::send(&path, &request), Err(Failure::NotRunning(e)) if e.contains("is the daemon running?")));
+}
@@ -0,0 +1,133 @@
+use cherrypie::config::Config;
+use cherrypie::lint::{self, Display};
+use cherrypie::rules;
+
+fn lint(toml_str: &str, display: Option<&Display>) -> Vec<String> {
+    let cfg: Config = toml::from_str(toml_str).unwrap();
+    let compiled = rules::compile(&cfg).unwrap();
+    lint::lint(&cfg, &compiled, display)
+}
+
+#[test]
+fn finds_shadowed_and_conflicting_rules() {
+    let warnings = lint(r#"
+        [[rule]]
+        class = "kitty"
+        title = "scratch"
+        workspace = 9
+
+        [[rule]]
+        class = "kitty"
+        stop = true
+        priority = 5
+        workspace = 1
+
+        [[rule]]
+        class = "mpv"
+        workspace = 2
+        exec = "notify-send a"
+
+        [[rule]]
+        name = "mpv-five"
+        class = "mpv"
+        workspace = 5
+        exec = "notify-send b"
+
+        [[rule]]
+        class = "mpv"
+        title = "x"
+        workspace = 6
+    "#, None);
+    assert_eq!(warnings, [
+        "rule[0]: never reached; rule[1] matches all of its windows first and has stop = true",
+        "rule[2] and rule[3] (mpv-five) match the same windows with conflicting actions: \
+         workspace -> 2, then workspace -> 5",
+    ]);
+}
+
+#[test]
+fn finds_patterns_that_match_anything() {
+    let warnings = lint(r#"
+        [[rule]]
+        class = ["firefox", ".*"]
+        title_not = "^"
+        focus = true
+
+        [[rule]]
+        class = "*"
+        match_mode = "glob"
+        focus = true
+
+        [[rule]]
+        title = ".*"
+        match_mode = "glob"
+        focus = true
+    "#, None);
+    assert_eq!(warnings, [
+        "rule[0]: class '.*' matches every window",
+        "rule[0]: title_not '^' excludes every window, so the rule never matches",
+        "rule[1]: class '*' matches every window",
+    ]);
+}
+
+#[test]
+fn checks_references_against_the_display() {
+    let toml = r#"
+        [[rule]]
+        class = "a"
+        monitor = "DP-3"
+        workspace = 4
+
+        [[rule]]
+        class = "b"
+        on_monitor = 1
+        workspace = "mail"
+
+        [[monitor_defaults]]
+        output = "HDMI-1"
+        workspace = 0
+    "#;
+    let display = Display {
+        monitors: vec!["eDP-1".into()],
+        workspaces: Some(vec!["web".into(), "".into()]),
+    };
+    assert_eq!(lint(toml, Some(&display)), [
+        "rule[0]: no monitor 'DP-3' (connected: eDP-1)",
+        "rule[0]: no workspace 4 (2 in all)",
+        "rule[1]: no monitor 1 (1 connected: eDP-1)",
+        "rule[1]: no workspace named 'mail'",
+        "monitor_defaults[0]: no monitor 'HDMI-1' (connected: eDP-1)",
+    ]);
+    assert!(lint(toml, None).is_empty());
+}
+
+#[test]
+fn waiting_for_a_title_shadows_only_what_it_matches_once_settled() {
+    // The rules after a held stop rule still run when the settled title
+    // fails its title pattern; only one the stop rule matches whatever
+    // the title is never does
+    let warnings = lint(r#"
+        [[rule]]
+        class = "firefox"
+        wait_for_title = true
+        stop = true
+        workspace = 4
+
+        [[rule]]
+        class = "firefox"
+        title = "YouTube"
+        workspace = 1
+
+        [[rule]]
+        class = "mpv"
+        title = "^Music"
+        wait_for_title = true
+        stop = true
+        workspace = 2
+
+        [[rule]]
+        class = "mpv"
+        workspace = 3
+    "#, None);
+    assert_eq!(warnings, ["rule[1]: never reached; rule[0] matches all of its windows first and has stop = true"]);
+}
@@ -0,0 +1,18 @@
+use cherrypie::memory::{self, Memory, Remembered};
+
+#[test]
+fn keeps_geometry_across_save_and_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state/cherrypie/geometry.toml");
+    assert_eq!(Memory::load(&path).unwrap(), Memory::default());
+
+    let mut memory = Memory::default();
+    let key = memory::key("firefox", "Navigator", "YouTube|Twitch");
+    assert_eq!(key, "firefox/Navigator/YouTube|Twitch");
+    memory.windows.insert(key, Remembered { x: -8, y: 40, width: 1280, height: 720, workspace: None });
+    memory.save(&path).unwrap();
+    assert_eq!(Memory::load(&path).unwrap(), memory);
+
+    std::fs::write(&path, "windows = 3").unwrap();
+    assert!(Memory::load(&path).unwrap_err().contains("geometry.toml"));
+}
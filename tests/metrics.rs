@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use cherrypie::metrics::Health;
+
+#[test]
+fn renders_gauges_in_text_format() {
+    let mut health = Health::new(1000);
+    health.reloaded(1010, Some(4));
+    health.last_error_time = 1020;
+
+    let text = health.to_string();
+    assert!(text.contains("# TYPE cherrypie_up gauge\ncherrypie_up 1\n"));
+    assert!(text.contains("cherrypie_backend_connected 1\n"));
+    assert!(text.contains("cherrypie_last_reload_success 1\n"));
+    assert!(text.contains("cherrypie_rules 4\n"));
+    assert!(text.contains("cherrypie_start_time_seconds 1000\n"));
+    assert!(text.contains("cherrypie_last_reload_timestamp_seconds 1010\n"));
+    assert!(text.contains("cherrypie_last_error_timestamp_seconds 1020\n"));
+}
+
+#[test]
+fn renders_apply_latency_per_rule() {
+    let mut health = Health::new(1000);
+    assert!(!health.to_string().contains("latency"));
+
+    health.applied(2, Duration::from_millis(10));
+    health.applied(2, Duration::from_millis(30));
+    let text = health.to_string();
+    assert!(text.contains("# TYPE cherrypie_apply_latency_seconds summary\n"));
+    assert!(text.contains("cherrypie_apply_latency_seconds_sum{rule=\"2\"} 0.04\n"));
+    assert!(text.contains("cherrypie_apply_latency_seconds_count{rule=\"2\"} 2\n"));
+    assert!(text.contains("cherrypie_apply_latency_max_seconds{rule=\"2\"} 0.03\n"));
+
+    // A new rule set starts over
+    health.reloaded(1010, Some(3));
+    assert!(health.latency.is_empty());
+}
+
+#[test]
+fn failed_reload_keeps_previous_rule_count() {
+    let mut health = Health::new(1000);
+    health.reloaded(1000, Some(4));
+    health.reloaded(1050, None);
+
+    assert!(!health.last_reload_success);
+    assert_eq!(health.last_reload_time, 1050);
+    assert_eq!(health.rules, 4);
+}
+
+#[test]
+fn writes_textfile() {
+    let path = std::env::temp_dir().join(format!("cherrypie-metrics-{}.prom", std::process::id()));
+    let health = Health::new(1000);
+
+    health.write_textfile(&path).unwrap();
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(written, health.to_string());
+}
@@ -0,0 +1,65 @@
+use cherrypie::process::{self, Cgroup, PidFd, ProcFs, ProcessInfoProvider};
+
+#[test]
+fn procfs_reads_from_its_root() {
+    let root = tempfile::tempdir().unwrap();
+    let proc = root.path().join("42");
+    std::fs::create_dir(&proc).unwrap();
+    std::fs::write(proc.join("comm"), "firefox\n").unwrap();
+    std::fs::write(proc.join("environ"), b"LANG=C\0MOZ_ENABLE_WAYLAND=0\0broken\0").unwrap();
+
+    let procfs = ProcFs::at(root.path());
+    assert_eq!(procfs.name(42).as_deref(), Some("firefox"));
+    let env = procfs.env(42).unwrap();
+    assert_eq!(env.len(), 2);
+    assert_eq!(env["MOZ_ENABLE_WAYLAND"], "0");
+    assert!(procfs.uid(42).is_some());
+    assert_eq!(procfs.name(43), None);
+}
+
+#[test]
+fn pidfd_reads_only_live_processes() {
+    let root = tempfile::tempdir().unwrap();
+    let own = std::process::id();
+    let gone = i32::MAX as u32;
+    // init: someone else's process unless the tests run as root
+    let init = 1;
+    for pid in [own, gone, init] {
+        let proc = root.path().join(pid.to_string());
+        std::fs::create_dir(&proc).unwrap();
+        std::fs::write(proc.join("comm"), "cherrypie-test\n").unwrap();
+    }
+
+    let pidfd = PidFd::at(root.path());
+    assert_eq!(pidfd.name(own).as_deref(), Some("cherrypie-test"));
+    assert_eq!(pidfd.name(gone), None);
+    assert_eq!(pidfd.name(init).as_deref(), Some("cherrypie-test"));
+}
+
+#[test]
+fn cgroup_maps_container_pids_to_the_host() {
+    let root = tempfile::tempdir().unwrap();
+    let proc = root.path().join("proc");
+    for (host, inner, comm) in [(4100, 1, "init"), (4242, 7, "gimp"), (7, 7, "kthreadd")] {
+        let dir = proc.join(host.to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("comm"), format!("{}\n", comm)).unwrap();
+        std::fs::write(dir.join("status"), format!("Name:\t{}\nNSpid:\t{}\t{}\n", comm, host, inner)).unwrap();
+    }
+    // Members of the container's cgroup and of one below it
+    let cgroup = root.path().join("container");
+    std::fs::create_dir_all(cgroup.join("app")).unwrap();
+    std::fs::write(cgroup.join("cgroup.procs"), "4100\n").unwrap();
+    std::fs::write(cgroup.join("app").join("cgroup.procs"), "4242\n").unwrap();
+
+    let provider = Cgroup::at(&proc, &cgroup);
+    assert_eq!(provider.name(7).as_deref(), Some("gimp"));
+    assert_eq!(provider.name(1).as_deref(), Some("init"));
+    // Not in the container: looked up as given
+    assert_eq!(provider.name(4242).as_deref(), Some("gimp"));
+    assert_eq!(provider.name(9), None);
+
+    assert!(process::provider("cgroup:/sys/fs/cgroup/x").is_ok());
+    assert!(process::provider("cgroup:").is_err());
+    assert!(process::provider("sysctl").is_err());
+}
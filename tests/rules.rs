@@ -1,10 +1,36 @@
-use cherrypie::config::Config;
-use cherrypie::rules;
+use cherrypie::config::{Config, PropValue};
+use cherrypie::matchers::{Matcher, MonitorId, Needs, WindowProps, launcher_of};
+use cherrypie::rules::{self, Action, Axis, Edge, MonitorTarget, Placement, StartupMode, WorkspaceTarget};
 
 fn make_config(toml_str: &str) -> Config {
     toml::from_str(toml_str).unwrap()
 }
 
+fn win(class: &str, title: &str, role: &str, process: &str, window_type: &str) -> WindowProps {
+    WindowProps {
+        class: class.into(),
+        title: title.into(),
+        role: role.into(),
+        process: process.into(),
+        window_type: window_type.into(),
+        ..WindowProps::default()
+    }
+}
+
+fn sized(width: u32, height: u32) -> WindowProps {
+    WindowProps {
+        size: Some((width, height)),
+        ..WindowProps::default()
+    }
+}
+
+fn with_states(list: &[&str]) -> WindowProps {
+    WindowProps {
+        states: list.iter().map(|s| s.to_string()).collect(),
+        ..WindowProps::default()
+    }
+}
+
 // CLASS MATCHING
 
 #[test]
@@ -16,9 +42,9 @@ fn exact_class_match() {
     "#);
     let compiled = rules::compile(&cfg).unwrap();
 
-    assert!(compiled[0].matches("kitty", "", "", "", ""));
-    assert!(!compiled[0].matches("kitty-terminal", "", "", "", ""));
-    assert!(!compiled[0].matches("xkitty", "", "", "", ""));
+    assert!(compiled[0].matches(&win("kitty", "", "", "", "")));
+    assert!(!compiled[0].matches(&win("kitty-terminal", "", "", "", "")));
+    assert!(!compiled[0].matches(&win("xkitty", "", "", "", "")));
 }
 
 #[test]
@@ -30,9 +56,9 @@ fn regex_class_match() {
     "#);
     let compiled = rules::compile(&cfg).unwrap();
 
-    assert!(compiled[0].matches("chromium", "", "", "", ""));
-    assert!(compiled[0].matches("chromium-browser", "", "", "", ""));
-    assert!(!compiled[0].matches("firefox", "", "", "", ""));
+    assert!(compiled[0].matches(&win("chromium", "", "", "", "")));
+    assert!(compiled[0].matches(&win("chromium-browser", "", "", "", "")));
+    assert!(!compiled[0].matches(&win("firefox", "", "", "", "")));
 }
 
 // TITLE MATCHING
@@ -46,8 +72,8 @@ fn title_regex_match() {
     "#);
     let compiled = rules::compile(&cfg).unwrap();
 
-    assert!(compiled[0].matches("", "GIMP 2.10", "", "", ""));
-    assert!(!compiled[0].matches("", "gimp", "", "", ""));
+    assert!(compiled[0].matches(&win("", "GIMP 2.10", "", "", "")));
+    assert!(!compiled[0].matches(&win("", "gimp", "", "", "")));
 }
 
 #[test]
@@ -59,8 +85,8 @@ fn case_insensitive_regex() {
     "#);
     let compiled = rules::compile(&cfg).unwrap();
 
-    assert!(compiled[0].matches("", "GIMP", "", "", ""));
-    assert!(compiled[0].matches("", "gimp", "", "", ""));
+    assert!(compiled[0].matches(&win("", "GIMP", "", "", "")));
+    assert!(compiled[0].matches(&win("", "gimp", "", "", "")));
 }
 
 // ROLE MATCHING
@@ -74,8 +100,8 @@ fn role_match() {
     "#);
     let compiled = rules::compile(&cfg).unwrap();
 
-    assert!(compiled[0].matches("", "", "browser", "", ""));
-    assert!(!compiled[0].matches("", "", "editor", "", ""));
+    assert!(compiled[0].matches(&win("", "", "browser", "", "")));
+    assert!(!compiled[0].matches(&win("", "", "editor", "", "")));
 }
 
 // PROCESS MATCHING
@@ -89,8 +115,8 @@ fn process_match() {
     "#);
     let compiled = rules::compile(&cfg).unwrap();
 
-    assert!(compiled[0].matches("", "", "", "montauk", ""));
-    assert!(!compiled[0].matches("", "", "", "firefox", ""));
+    assert!(compiled[0].matches(&win("", "", "", "montauk", "")));
+    assert!(!compiled[0].matches(&win("", "", "", "firefox", "")));
 }
 
 #[test]
@@ -102,9 +128,9 @@ fn process_regex_match() {
     "#);
     let compiled = rules::compile(&cfg).unwrap();
 
-    assert!(compiled[0].matches("", "", "", "python3", ""));
-    assert!(compiled[0].matches("", "", "", "python", ""));
-    assert!(!compiled[0].matches("", "", "", "ruby", ""));
+    assert!(compiled[0].matches(&win("", "", "", "python3", "")));
+    assert!(compiled[0].matches(&win("", "", "", "python", "")));
+    assert!(!compiled[0].matches(&win("", "", "", "ruby", "")));
 }
 
 // WINDOW TYPE MATCHING
@@ -118,9 +144,9 @@ fn type_match() {
     "#);
     let compiled = rules::compile(&cfg).unwrap();
 
-    assert!(compiled[0].matches("", "", "", "", "dialog"));
-    assert!(compiled[0].matches("", "", "", "", "DIALOG")); // case insensitive
-    assert!(!compiled[0].matches("", "", "", "", "normal"));
+    assert!(compiled[0].matches(&win("", "", "", "", "dialog")));
+    assert!(compiled[0].matches(&win("", "", "", "", "DIALOG"))); // case insensitive
+    assert!(!compiled[0].matches(&win("", "", "", "", "normal")));
 }
 
 // COMBINED MATCHERS
@@ -135,9 +161,9 @@ fn combined_matchers_all_must_match() {
     "#);
     let compiled = rules::compile(&cfg).unwrap();
 
-    assert!(compiled[0].matches("firefox", "YouTube - Firefox", "", "", ""));
-    assert!(!compiled[0].matches("firefox", "Google - Firefox", "", "", ""));
-    assert!(!compiled[0].matches("chromium", "YouTube", "", "", ""));
+    assert!(compiled[0].matches(&win("firefox", "YouTube - Firefox", "", "", "")));
+    assert!(!compiled[0].matches(&win("firefox", "Google - Firefox", "", "", "")));
+    assert!(!compiled[0].matches(&win("chromium", "YouTube", "", "", "")));
 }
 
 #[test]
@@ -151,11 +177,11 @@ fn class_and_process_combined() {
     let compiled = rules::compile(&cfg).unwrap();
 
     // Both must match
-    assert!(compiled[0].matches("kitty", "", "", "montauk", ""));
+    assert!(compiled[0].matches(&win("kitty", "", "", "montauk", "")));
     // Only class
-    assert!(!compiled[0].matches("kitty", "", "", "htop", ""));
+    assert!(!compiled[0].matches(&win("kitty", "", "", "htop", "")));
     // Only process
-    assert!(!compiled[0].matches("alacritty", "", "", "montauk", ""));
+    assert!(!compiled[0].matches(&win("alacritty", "", "", "montauk", "")));
 }
 
 // NONE MATCHERS ARE PERMISSIVE
@@ -169,7 +195,7 @@ fn none_matchers_are_permissive() {
     "#);
     let compiled = rules::compile(&cfg).unwrap();
 
-    assert!(compiled[0].matches("kitty", "any title", "any role", "any process", "normal"));
+    assert!(compiled[0].matches(&win("kitty", "any title", "any role", "any process", "normal")));
 }
 
 // MULTIPLE RULES
@@ -187,10 +213,10 @@ fn multiple_rules_independent() {
     "#);
     let compiled = rules::compile(&cfg).unwrap();
 
-    assert!(compiled[0].matches("kitty", "", "", "", ""));
-    assert!(!compiled[0].matches("firefox", "", "", "", ""));
-    assert!(compiled[1].matches("firefox", "", "", "", ""));
-    assert!(!compiled[1].matches("kitty", "", "", "", ""));
+    assert!(compiled[0].matches(&win("kitty", "", "", "", "")));
+    assert!(!compiled[0].matches(&win("firefox", "", "", "", "")));
+    assert!(compiled[1].matches(&win("firefox", "", "", "", "")));
+    assert!(!compiled[1].matches(&win("kitty", "", "", "", "")));
 }
 
 // INVALID REGEX
@@ -229,6 +255,7 @@ fn all_actions_preserved() {
         maximize = true
         fullscreen = true
         pin = true
+        sticky = false
         minimize = false
         shade = true
         above = true
@@ -236,23 +263,248 @@ fn all_actions_preserved() {
         decorate = false
         focus = true
         opacity = 0.75
+        blur = true
+        shadow = false
+        close = true
+        kill = true
         position = [10, 20]
         size = [640, 480]
     "#);
     let compiled = rules::compile(&cfg).unwrap();
-    let r = &compiled[0];
 
-    assert_eq!(r.workspace, Some(5));
-    assert_eq!(r.maximize, Some(true));
-    assert_eq!(r.fullscreen, Some(true));
-    assert_eq!(r.pin, Some(true));
-    assert_eq!(r.minimize, Some(false));
-    assert_eq!(r.shade, Some(true));
-    assert_eq!(r.above, Some(true));
-    assert_eq!(r.below, Some(false));
-    assert_eq!(r.decorate, Some(false));
-    assert_eq!(r.focus, Some(true));
-    assert_eq!(r.opacity, Some(0.75));
+    assert_eq!(
+        compiled[0].actions,
+        vec![
+            Action::Size(rules::SizeTarget::Absolute(640, 480)),
+            Action::Position(rules::PositionTarget::Absolute(10, 20)),
+            Action::Workspace(WorkspaceTarget::Index(5)),
+            Action::Maximize(true),
+            Action::Fullscreen(true),
+            Action::Pin(true),
+            Action::Sticky(false),
+            Action::Minimize(false),
+            Action::Shade(true),
+            Action::Above(true),
+            Action::Below(false),
+            Action::Decorate(false),
+            Action::Focus(true),
+            Action::Opacity(0.75),
+            Action::Blur(true),
+            Action::Shadow(false),
+            Action::Close(true),
+            Action::Kill(true),
+        ]
+    );
+    assert!(compiled[0].actions.iter().all(|a| rules::ACTION_NAMES.contains(&a.name())));
+}
+
+#[test]
+fn compile_property_actions() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "kitty"
+        set_prop = { name = "_MY_MARKER", value = "work" }
+
+        [[rule]]
+        class = "kitty"
+        set_prop = { name = "_MY_LEVEL", value = 2 }
+        del_prop = "_MY_MARKER"
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert_eq!(
+        compiled[0].actions,
+        vec![Action::SetProperty("_MY_MARKER".into(), PropValue::Text("work".into()))]
+    );
+    assert_eq!(compiled[0].actions[0].to_string(), "set_prop -> _MY_MARKER = 'work'");
+    assert_eq!(
+        compiled[1].actions,
+        vec![
+            Action::SetProperty("_MY_LEVEL".into(), PropValue::Cardinal(2)),
+            Action::DeleteProperty("_MY_MARKER".into()),
+        ]
+    );
+}
+
+#[test]
+fn capture_needs_feature() {
+    let cfg = make_config(r#"
+        [[rule]]
+        type = "dialog"
+        capture = "~/screens/%c-%t.png"
+        close = true
+    "#);
+    let compiled = rules::compile(&cfg);
+    if cfg!(feature = "capture") {
+        assert_eq!(
+            compiled.unwrap()[0].actions,
+            vec![Action::Capture("~/screens/%c-%t.png".into()), Action::Close(true)]
+        );
+    } else {
+        assert_eq!(compiled.err(), Some("rule[0]: capture needs cherrypie built with the capture feature".into()));
+    }
+}
+
+#[test]
+fn compile_border() {
+    let cfg = make_config(r##"
+        [[rule]]
+        class = "a"
+        border = { width = 2, color = "#FF0000" }
+
+        [[rule]]
+        class = "b"
+        border = { color = "#00ff7f" }
+    "##);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(compiled[0].actions, vec![Action::Border(Some(2), Some(0xff0000))]);
+    assert_eq!(compiled[0].actions[0].to_string(), "border -> 2px #ff0000");
+    assert_eq!(compiled[1].actions[0].to_string(), "border -> #00ff7f");
+    assert_eq!(rules::parse_color("red"), None);
+    assert_eq!(rules::parse_color("#12345"), None);
+}
+
+#[test]
+fn follow_comes_right_after_workspace() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "thunderbird"
+        maximize = true
+        follow = true
+        workspace = 3
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(
+        compiled[0].actions,
+        vec![Action::Workspace(WorkspaceTarget::Index(3)), Action::Follow(true), Action::Maximize(true)]
+    );
+    assert_eq!(compiled[0].actions[1].to_string(), "follow");
+}
+
+#[test]
+fn compile_maximize_axis() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "emacs"
+        maximize = "vertical"
+
+        [[rule]]
+        class = "xterm"
+        maximize = "both"
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(compiled[0].actions, vec![Action::MaximizeAxis(Axis::Vertical)]);
+    assert_eq!(compiled[0].actions[0].to_string(), "maximize -> vertical");
+    assert_eq!(compiled[1].actions, vec![Action::Maximize(true)]);
+}
+
+#[test]
+fn compile_strut() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "conky"
+        strut = { edge = "top", size = 40 }
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(compiled[0].actions, vec![Action::Strut(Edge::Top, 40)]);
+    assert_eq!(compiled[0].actions[0].to_string(), "strut -> 40px Top");
+}
+
+#[test]
+fn disabled_rules_do_not_compile_in() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "slack"
+        name = "slack-ws2"
+        workspace = 2
+
+        [[rule]]
+        class = "discord"
+        enabled = false
+        workspace = 3
+
+        [[rule]]
+        class = "zoom"
+        enabled = true
+        workspace = 4
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(compiled.iter().map(|r| r.index).collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(compiled[0].label(), "rule[0] (slack-ws2)");
+    assert_eq!(compiled[1].label(), "rule[2]");
+
+    // Still compiled, so a broken disabled rule is reported
+    let cfg = make_config("[[rule]]\nclass = \"(\"\nenabled = false\nfocus = true\n");
+    assert!(rules::compile(&cfg).is_err_and(|e| e.starts_with("rule[0]: ")));
+}
+
+#[test]
+fn compile_icon() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "steam_app_.*"
+        icon = "~/.local/share/icons/game.png"
+        focus = true
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(compiled[0].actions[0], Action::Icon("~/.local/share/icons/game.png".into()));
+    assert_eq!(compiled[0].actions[0].to_string(), "icon -> '~/.local/share/icons/game.png'");
+    assert_eq!(compiled[0].actions[1], Action::Focus(true));
+}
+
+#[test]
+fn strut_counts_from_the_screen_edge() {
+    // Two 1920x1080 monitors side by side, the right one 120 px lower
+    let right = (1920, 120, 1920, 1080);
+    let screen = (3840, 1200);
+
+    // A 40 px bar along the top of the right monitor
+    let strut = rules::strut_partial(Edge::Top, 40, (1920, 120, 1920, 40), right, screen);
+    assert_eq!(strut, [0, 0, 160, 0, 0, 0, 0, 0, 1920, 3839, 0, 0]);
+
+    // A 300 px sidebar on its right edge, covering the upper half
+    let strut = rules::strut_partial(Edge::Right, 300, (3540, 120, 300, 540), right, screen);
+    assert_eq!(strut, [0, 300, 0, 0, 0, 0, 120, 659, 0, 0, 0, 0]);
+
+    // Along the bottom of the left monitor, 120 px above the screen's bottom
+    let left = (0, 0, 1920, 1080);
+    let strut = rules::strut_partial(Edge::Bottom, 30, (0, 1050, 1920, 30), left, screen);
+    assert_eq!(strut, [0, 0, 0, 150, 0, 0, 0, 0, 0, 0, 0, 1919]);
+}
+
+#[test]
+fn exec_runs_before_close() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "zoom"
+        close = true
+        exec = "notify-send closed"
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(compiled[0].actions, vec![Action::Exec("notify-send closed".into()), Action::Close(true)]);
+    assert_eq!(compiled[0].actions[0].to_string(), "exec -> 'notify-send closed'");
+}
+
+#[test]
+fn exec_hooks_surround_the_other_actions() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "zoom"
+        exec_after = "logger placed"
+        workspace = 2
+        exec_before = "wmctrl -s 1"
+        kill = true
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(
+        compiled[0].actions,
+        vec![
+            Action::ExecBefore("wmctrl -s 1".into()),
+            Action::Workspace(rules::WorkspaceTarget::Index(2)),
+            Action::Kill(true),
+            Action::ExecAfter("logger placed".into()),
+        ]
+    );
 }
 
 // POSITION COMPILATION
@@ -265,10 +517,10 @@ fn compile_named_position() {
         position = "center"
     "#);
     let compiled = rules::compile(&cfg).unwrap();
-    assert!(matches!(
-        compiled[0].position,
-        Some(rules::PositionTarget::Named(rules::NamedPosition::Center))
-    ));
+    assert_eq!(
+        compiled[0].actions,
+        vec![Action::Position(rules::PositionTarget::Named(rules::NamedPosition::Center))]
+    );
 }
 
 #[test]
@@ -279,10 +531,10 @@ fn compile_absolute_position() {
         position = [100, 200]
     "#);
     let compiled = rules::compile(&cfg).unwrap();
-    assert!(matches!(
-        compiled[0].position,
-        Some(rules::PositionTarget::Absolute(100, 200))
-    ));
+    assert_eq!(
+        compiled[0].actions,
+        vec![Action::Position(rules::PositionTarget::Absolute(100, 200))]
+    );
 }
 
 #[test]
@@ -293,8 +545,8 @@ fn compile_percentage_position() {
         position = ["25%", "50%"]
     "#);
     let compiled = rules::compile(&cfg).unwrap();
-    match &compiled[0].position {
-        Some(rules::PositionTarget::Flexible(x, y)) => {
+    match &compiled[0].actions[..] {
+        [Action::Position(rules::PositionTarget::Flexible(x, y))] => {
             assert!(matches!(x, rules::DimensionVal::Percent(p) if (*p - 0.25).abs() < 0.001));
             assert!(matches!(y, rules::DimensionVal::Percent(p) if (*p - 0.50).abs() < 0.001));
         }
@@ -302,6 +554,267 @@ fn compile_percentage_position() {
     }
 }
 
+#[test]
+fn compile_relative_geometry() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "a"
+        move_by = [0, -30]
+        resize_by = [-100, 40]
+        maximize = false
+    "#);
+    let actions = &rules::compile(&cfg).unwrap()[0].actions;
+    assert_eq!(actions[..2], [Action::ResizeBy(-100, 40), Action::MoveBy(0, -30)]);
+    assert_eq!(actions[0].to_string(), "resize_by -> -100,+40");
+}
+
+#[test]
+fn compile_size_bounds_after_tile_size() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "a"
+        position = "left-half"
+        max_size = ["1600", "90%"]
+        min_size = [400, 300]
+        size_hints = true
+    "#);
+    let actions = &rules::compile(&cfg).unwrap()[0].actions;
+    let names: Vec<&str> = actions.iter().map(Action::name).collect();
+    assert_eq!(names, ["size", "min_size", "max_size", "position"]);
+    assert_eq!(actions[1], Action::MinSize(rules::SizeTarget::Absolute(400, 300), true));
+    assert_eq!(actions[1].to_string(), "min_size -> Absolute(400, 300) (hinted)");
+}
+
+#[test]
+fn aspect_keeps_the_nearer_dimension_and_fits_the_monitor() {
+    assert_eq!(rules::parse_aspect("16:9"), Some((16, 9)));
+    assert_eq!(rules::parse_aspect("16x9"), None);
+    assert_eq!(rules::parse_aspect("0:9"), None);
+
+    // 800x500 is nearer 800x450 than 889x500
+    assert_eq!(rules::fit_aspect((800, 500), (16, 9), (1920, 1080)), (800, 450));
+    assert_eq!(rules::fit_aspect((400, 600), (3, 4), (1920, 1080)), (450, 600));
+    // Too tall for the monitor
+    assert_eq!(rules::fit_aspect((1920, 1200), (16, 10), (1920, 1080)), (1728, 1080));
+
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "mpv"
+        aspect = "16:9"
+        position = "bottom-right"
+        size = [480, 300]
+    "#);
+    let actions = &rules::compile(&cfg).unwrap()[0].actions;
+    assert_eq!(actions[1], Action::Aspect(16, 9));
+    assert_eq!(actions[1].to_string(), "aspect -> 16:9");
+    assert_eq!(actions[2].name(), "position");
+}
+
+#[test]
+fn compile_anchor_with_offset() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "a"
+        position = "top-right-20,+20"
+
+        [[rule]]
+        class = "b"
+        position = { anchor = "center", offset = [100, -50] }
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(
+        compiled[0].actions,
+        vec![Action::Position(rules::PositionTarget::Anchored(rules::NamedPosition::TopRight, -20, 20))]
+    );
+    assert_eq!(
+        compiled[1].actions,
+        vec![Action::Position(rules::PositionTarget::Anchored(rules::NamedPosition::Center, 100, -50))]
+    );
+    assert!(rules::parse_anchor_offset("bottom-left").is_none());
+    assert_eq!(
+        rules::parse_anchor_offset("center+100,-50"),
+        Some(Ok(rules::PositionTarget::Anchored(rules::NamedPosition::Center, 100, -50)))
+    );
+}
+
+#[test]
+fn compile_icon_hints_before_minimize() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "a"
+        minimize = true
+        icon_geometry = [0, 1040, 48, 40]
+
+        [[rule]]
+        class = "tint2"
+        handled_icons = true
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(compiled[0].actions, [Action::IconGeometry([0, 1040, 48, 40]), Action::Minimize(true)]);
+    assert_eq!(compiled[0].actions[0].to_string(), "icon_geometry -> 48x40 at 0,1040");
+    assert_eq!(compiled[1].actions[0].to_string(), "handled_icons");
+}
+
+#[test]
+fn smart_position_finds_free_space_then_cascades() {
+    let area = (0, 0, 1000, 800);
+    assert_eq!(rules::smart_position(area, (400, 300), &[]), (0, 0));
+    // Beside the first window, then below it
+    assert_eq!(rules::smart_position(area, (400, 300), &[(0, 0, 500, 400)]), (500, 0));
+    assert_eq!(rules::smart_position(area, (600, 300), &[(0, 0, 500, 400)]), (0, 400));
+    // No free spot: cascade past the windows already at the corner steps
+    let full = [(0, 0, 1000, 800), (32, 32, 500, 500)];
+    assert_eq!(rules::smart_position(area, (400, 300), &full), (64, 64));
+    // Too big for the area
+    assert_eq!(rules::smart_position(area, (1200, 300), &full), (0, 0));
+
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "a"
+        position = "smart"
+    "#);
+    assert_eq!(rules::compile(&cfg).unwrap()[0].actions, vec![Action::Position(rules::PositionTarget::Smart)]);
+}
+
+#[test]
+fn sizes_snap_to_resize_increments() {
+    // A terminal with 9x18 cells and 4 pixels of padding
+    assert_eq!(rules::snap_to_increments((800, 600), (4, 4), (9, 18)), (796, 598));
+    assert_eq!(rules::snap_to_increments((2, 2), (4, 4), (9, 18)), (4, 4));
+    assert_eq!(rules::snap_to_increments((800, 600), (0, 0), (1, 0)), (800, 600));
+
+    let cfg = make_config(r#"
+        [settings]
+        honor_size_hints = true
+
+        [[rule]]
+        class = "xterm"
+        size = [800, 600]
+
+        [[rule]]
+        class = "urxvt"
+        honor_size_hints = false
+        size = [800, 600]
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(compiled[0].honor_size_hints, Some(true));
+    assert_eq!(compiled[1].honor_size_hints, Some(false));
+}
+
+#[test]
+fn pointer_position_stays_on_the_monitor() {
+    let area = (1920, 0, 1920, 1080);
+    assert_eq!(rules::at_pointer((2000, 100), (10, 10), (400, 300), area), (2010, 110));
+    // Pulled back from the right and bottom edges
+    assert_eq!(rules::at_pointer((3800, 1000), (0, 0), (400, 300), area), (3440, 780));
+    assert_eq!(rules::at_pointer((1930, 5), (-50, -50), (400, 300), area), (1920, 0));
+
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "a"
+        position = "pointer"
+
+        [[rule]]
+        class = "b"
+        position = "pointer+16,-8"
+
+        [[rule]]
+        class = "c"
+        position = { anchor = "pointer", offset = [-20, 20] }
+    "#);
+    let positions: Vec<Action> = rules::compile(&cfg).unwrap().iter().map(|r| r.actions[0].clone()).collect();
+    assert_eq!(
+        positions,
+        [
+            Action::Position(rules::PositionTarget::Pointer(0, 0)),
+            Action::Position(rules::PositionTarget::Pointer(16, -8)),
+            Action::Position(rules::PositionTarget::Pointer(-20, 20)),
+        ]
+    );
+}
+
+#[test]
+fn compile_grid_cell() {
+    let cfg = make_config(r#"
+        [grid]
+        cols = 4
+        rows = 2
+
+        [[rule]]
+        class = "a"
+        cell = [1, 1, 3, 1]
+        max_size = [1600, 900]
+    "#);
+    let actions = &rules::compile(&cfg).unwrap()[0].actions;
+    let pct = |p: f64| rules::DimensionVal::Percent(p);
+    assert_eq!(
+        actions[..],
+        [
+            Action::Size(rules::SizeTarget::Flexible(pct(0.75), pct(0.5))),
+            Action::MaxSize(rules::SizeTarget::Absolute(1600, 900), false),
+            Action::Position(rules::PositionTarget::Flexible(pct(0.25), pct(0.5))),
+        ]
+    );
+}
+
+#[test]
+fn compile_parent_center() {
+    let cfg = make_config(r#"
+        [[rule]]
+        type = "dialog"
+        position = "parent-center"
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(compiled[0].actions, vec![Action::Position(rules::PositionTarget::ParentCenter)]);
+}
+
+#[test]
+fn compile_tile_sets_size_and_position() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "editor"
+        position = "right-two-thirds"
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    let pct = |p: f64| rules::DimensionVal::Percent(p);
+    assert_eq!(
+        compiled[0].actions,
+        vec![
+            Action::Size(rules::SizeTarget::Flexible(pct(2.0 / 3.0), pct(1.0))),
+            Action::Position(rules::PositionTarget::Flexible(pct(1.0 / 3.0), pct(0.0))),
+        ]
+    );
+    assert_eq!(rules::tile("bottom-right-quarter"), Some((
+        rules::SizeTarget::Flexible(pct(0.5), pct(0.5)),
+        rules::PositionTarget::Flexible(pct(0.5), pct(0.5)),
+    )));
+    assert_eq!(rules::tile("center"), None);
+}
+
+#[test]
+fn margin_from_rule_or_settings() {
+    let cfg = make_config(r#"
+        [settings]
+        margin = 12
+
+        [[rule]]
+        class = "a"
+        position = "top-right"
+
+        [[rule]]
+        class = "b"
+        position = "left-half"
+        margin = [30, 0, 0, 8]
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(compiled[0].margin, rules::Margin { top: 12, right: 12, bottom: 12, left: 12 });
+    let sides = rules::Margin { top: 30, right: 0, bottom: 0, left: 8 };
+    assert_eq!(compiled[1].margin, sides);
+
+    assert_eq!(sides.inset(1920, 0, 1920, 1080), (1928, 30, 1912, 1050));
+    assert_eq!(compiled[0].margin.inset(0, 0, 20, 20), (12, 12, 1, 1));
+}
+
 // SIZE COMPILATION
 
 #[test]
@@ -312,8 +825,8 @@ fn compile_percentage_size() {
         size = ["80%", "90%"]
     "#);
     let compiled = rules::compile(&cfg).unwrap();
-    match &compiled[0].size {
-        Some(rules::SizeTarget::Flexible(w, h)) => {
+    match &compiled[0].actions[..] {
+        [Action::Size(rules::SizeTarget::Flexible(w, h))] => {
             assert!(matches!(w, rules::DimensionVal::Percent(p) if (*p - 0.80).abs() < 0.001));
             assert!(matches!(h, rules::DimensionVal::Percent(p) if (*p - 0.90).abs() < 0.001));
         }
@@ -332,7 +845,7 @@ fn compile_monitor_by_name() {
     "#);
     let compiled = rules::compile(&cfg).unwrap();
     assert!(matches!(
-        &compiled[0].monitor,
+        compiled[0].monitor(),
         Some(rules::MonitorTarget::Name(n)) if n == "Z"
     ));
 }
@@ -345,10 +858,38 @@ fn compile_monitor_by_index() {
         monitor = 1
     "#);
     let compiled = rules::compile(&cfg).unwrap();
-    assert!(matches!(
-        compiled[0].monitor,
-        Some(rules::MonitorTarget::Index(1))
-    ));
+    assert_eq!(compiled[0].monitor(), Some(&rules::MonitorTarget::Index(1)));
+}
+
+#[test]
+fn size_compiled_before_position() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "test"
+        position = "center"
+        monitor = 0
+        size = [800, 600]
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    let names: Vec<_> = compiled[0].actions.iter().map(|a| a.name()).collect();
+    assert_eq!(names, vec!["monitor", "size", "position"]);
+}
+
+// ACTION DISPLAY
+
+#[test]
+fn action_display_for_dry_run() {
+    assert_eq!(Action::Workspace(WorkspaceTarget::Index(3)).to_string(), "workspace -> 3");
+    assert_eq!(Action::Maximize(true).to_string(), "maximize");
+    assert_eq!(Action::Pin(true).to_string(), "pin (all workspaces)");
+    assert_eq!(Action::Sticky(true).to_string(), "sticky");
+    assert_eq!(Action::Decorate(false).to_string(), "decorate -> false");
+    assert_eq!(Action::Close(true).to_string(), "close");
+    assert_eq!(Action::Shadow(false).to_string(), "shadow -> false");
+    assert_eq!(
+        Action::Monitor(rules::MonitorTarget::Name("HDMI-1".into())).to_string(),
+        "monitor -> 'HDMI-1'"
+    );
 }
 
 // EMPTY
@@ -359,3 +900,853 @@ fn compile_empty_rules() {
     let compiled = rules::compile(&cfg).unwrap();
     assert!(compiled.is_empty());
 }
+
+// GEOMETRY MATCHING
+
+#[test]
+fn geometry_bounds_match() {
+    let cfg = make_config(r#"
+        [[rule]]
+        max_width = 300
+        max_height = 300
+        position = "center"
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert!(compiled[0].matches(&sized(200, 150)));
+    assert!(compiled[0].matches(&sized(300, 300)));
+    assert!(!compiled[0].matches(&sized(301, 150)));
+    assert!(!compiled[0].matches(&WindowProps::default()));
+}
+
+#[test]
+fn needs_only_what_matchers_read() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+
+        [[rule]]
+        min_width = 1000
+        maximize = true
+
+        [[rule]]
+        state = ["!fullscreen"]
+        above = true
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert_eq!(compiled[0].needs(), Needs::default());
+    assert!(compiled[1].needs().geometry && !compiled[1].needs().states);
+    assert!(compiled[2].needs().states && !compiled[2].needs().geometry);
+}
+
+// STATE MATCHING
+
+#[test]
+fn state_required_and_negated() {
+    let cfg = make_config(r#"
+        [[rule]]
+        state = ["maximized", "!fullscreen"]
+        size = ["60%", "60%"]
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert!(compiled[0].matches(&with_states(&["maximized", "above"])));
+    assert!(!compiled[0].matches(&with_states(&["maximized", "fullscreen"])));
+    assert!(!compiled[0].matches(&with_states(&["maximized_vert"])));
+    assert!(!compiled[0].matches(&with_states(&[])));
+}
+
+// CUSTOM MATCHERS
+
+struct TitleLongerThan(usize);
+
+impl Matcher for TitleLongerThan {
+    fn matches(&self, props: &WindowProps) -> bool {
+        props.title.len() > self.0
+    }
+}
+
+#[test]
+fn custom_matcher_is_anded() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "firefox"
+        workspace = 2
+    "#);
+    let mut compiled = rules::compile(&cfg).unwrap();
+    compiled[0].add_matcher(Box::new(TitleLongerThan(5)));
+
+    assert!(compiled[0].matches(&win("firefox", "YouTube - Firefox", "", "", "")));
+    assert!(!compiled[0].matches(&win("firefox", "New", "", "", "")));
+    assert!(!compiled[0].matches(&win("chromium", "YouTube - Chromium", "", "", "")));
+}
+
+// WORKSPACE MATCHING
+
+#[test]
+fn on_workspace_match() {
+    let cfg = make_config(r#"
+        [[rule]]
+        on_workspace = 8
+        position = "center"
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    let on = |ws: Option<u32>| WindowProps { workspace: ws, ..WindowProps::default() };
+
+    assert!(compiled[0].needs().workspace);
+    assert!(compiled[0].matches(&on(Some(8))));
+    assert!(compiled[0].matches(&on(Some(0xFFFFFFFF)))); // sticky
+    assert!(!compiled[0].matches(&on(Some(1))));
+    assert!(!compiled[0].matches(&on(None)));
+}
+
+// MONITOR MATCHING
+
+#[test]
+fn on_monitor_by_name_and_index() {
+    let cfg = make_config(r#"
+        [[rule]]
+        type = "dialog"
+        on_monitor = "HDMI-1"
+        monitor = "eDP-1"
+
+        [[rule]]
+        on_monitor = 0
+        above = true
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    let on = |index: u32, name: &str| WindowProps {
+        window_type: "dialog".into(),
+        monitor: Some(MonitorId { index, name: name.into() }),
+        ..WindowProps::default()
+    };
+
+    assert!(compiled[0].needs().monitor);
+    assert!(compiled[0].matches(&on(1, "HDMI-1")));
+    assert!(!compiled[0].matches(&on(0, "eDP-1")));
+    assert!(compiled[1].matches(&on(0, "eDP-1")));
+    assert!(!compiled[1].matches(&WindowProps::default()));
+}
+
+// PROCESS ENVIRONMENT MATCHING
+
+#[test]
+fn process_env_requires_every_variable() {
+    let cfg = make_config(r#"
+        [[rule]]
+        process_env = { PROJECT = "^work", TMUX = "" }
+        workspace = 4
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    let with_env = |vars: &[(&str, &str)]| WindowProps {
+        process_env: Some(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
+        ..WindowProps::default()
+    };
+
+    assert!(compiled[0].needs().process_env);
+    assert!(compiled[0].matches(&with_env(&[("PROJECT", "work-api"), ("TMUX", "/tmp/t,1,0")])));
+    assert!(!compiled[0].matches(&with_env(&[("PROJECT", "work-api")])));
+    assert!(!compiled[0].matches(&with_env(&[("PROJECT", "home"), ("TMUX", "x")])));
+    assert!(!compiled[0].matches(&WindowProps::default()));
+}
+
+// PROCESS OWNER
+
+#[test]
+fn uid_and_user_matchers() {
+    let cfg = make_config(r#"
+        [[rule]]
+        uid = 0
+        above = true
+
+        [[rule]]
+        user = "root"
+        decorate = false
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    let owned_by = |uid: Option<u32>| WindowProps { uid, ..WindowProps::default() };
+
+    assert!(compiled[0].needs().uid);
+    assert!(compiled[0].matches(&owned_by(Some(0))));
+    assert!(!compiled[0].matches(&owned_by(Some(1000))));
+    assert!(!compiled[0].matches(&owned_by(None)));
+    assert!(compiled[1].matches(&owned_by(Some(0))));
+}
+
+#[test]
+fn unknown_user_is_an_error() {
+    let cfg = make_config(r#"
+        [[rule]]
+        user = "no-such-user-cherrypie"
+        above = true
+    "#);
+    let err = rules::compile(&cfg).err().unwrap();
+    assert_eq!(err, "rule[0]: unknown user 'no-such-user-cherrypie'");
+}
+
+// STARTUP SWEEP
+
+#[test]
+fn startup_table_sets_each_rules_mode() {
+    let modes = |toml_str: &str| -> Vec<StartupMode> {
+        rules::compile(&make_config(toml_str)).unwrap().iter().map(|r| r.startup).collect()
+    };
+    let two_rules = r#"
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+
+        [[rule]]
+        class = "firefox"
+        workspace = 2
+    "#;
+
+    assert_eq!(modes(two_rules), vec![StartupMode::Apply, StartupMode::Apply]);
+    assert_eq!(
+        modes(&format!("[startup]\nenabled = false\n{}", two_rules)),
+        vec![StartupMode::Skip, StartupMode::Skip]
+    );
+    assert_eq!(
+        modes(&format!("[startup]\nrules = [1]\ndry_run = true\n{}", two_rules)),
+        vec![StartupMode::Skip, StartupMode::DryRun]
+    );
+}
+
+// STARTUP NOTIFICATION
+
+#[test]
+fn launched_by_and_startup_id_matchers() {
+    let cfg = make_config(r#"
+        [[rule]]
+        launched_by = "rofi"
+        workspace = 2
+
+        [[rule]]
+        startup_id = "^layout-"
+        workspace = 3
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    let launched = |id: Option<&str>| WindowProps { startup_id: id.map(String::from), ..WindowProps::default() };
+
+    assert!(compiled[0].needs().startup_id);
+    assert!(compiled[0].matches(&launched(Some("rofi-4711-host-firefox-0_TIME1234"))));
+    assert!(!compiled[0].matches(&launched(Some("gnome-shell/rofi/4711-0-host_TIME1234"))));
+    assert!(!compiled[0].matches(&launched(None)));
+    assert!(compiled[1].matches(&launched(Some("layout-editor_TIME0"))));
+    assert!(!compiled[1].matches(&launched(Some("rofi-layout-1_TIME0"))));
+}
+
+#[test]
+fn group_of_matches_leader_class() {
+    let cfg = make_config(r#"
+        [[rule]]
+        group_of = "^Gimp"
+        workspace = 4
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    let grouped = |leader: Option<&str>| WindowProps { group_class: leader.map(String::from), ..WindowProps::default() };
+
+    assert!(compiled[0].needs().group);
+    assert!(compiled[0].matches(&grouped(Some("Gimp-2.10"))));
+    assert!(!compiled[0].matches(&grouped(Some("Inkscape"))));
+    assert!(!compiled[0].matches(&grouped(None)));
+}
+
+#[test]
+fn launcher_is_the_leading_component() {
+    assert_eq!(launcher_of("gnome-shell/firefox/1234-0-host_TIME56"), "gnome-shell");
+    assert_eq!(launcher_of("rofi-1234-host_TIME56"), "rofi");
+    assert_eq!(launcher_of("dmenu_TIME56"), "dmenu");
+    assert_eq!(launcher_of("plain"), "plain");
+}
+
+// CLASS ALIASES
+
+#[test]
+fn class_aliases_normalize_before_matching() {
+    let cfg = make_config(r#"
+        [class_aliases]
+        "Navigator" = "firefox"
+
+        [[rule]]
+        class = "^firefox$"
+        workspace = 2
+
+        [[rule]]
+        class = "kitty"
+        class_not = "^firefox$"
+        workspace = 1
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert!(compiled[0].matches(&win("Navigator", "", "", "", "")));
+    assert!(compiled[0].matches(&win("firefox", "", "", "", "")));
+    assert!(!compiled[0].matches(&win("Navigator2", "", "", "", "")));
+    assert!(compiled[1].matches(&win("kitty", "", "", "", "")));
+}
+
+// NEGATED MATCHERS
+
+#[test]
+fn class_with_process_not() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "kitty"
+        process_not = "^montauk$"
+        workspace = 1
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert!(compiled[0].matches(&win("kitty", "", "", "zsh", "")));
+    assert!(!compiled[0].matches(&win("kitty", "", "", "montauk", "")));
+    assert!(!compiled[0].matches(&win("alacritty", "", "", "zsh", "")));
+}
+
+#[test]
+fn type_not_alone() {
+    let cfg = make_config(r#"
+        [[rule]]
+        type_not = "dialog"
+        decorate = false
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert!(compiled[0].matches(&win("", "", "", "", "normal")));
+    assert!(!compiled[0].matches(&win("", "", "", "", "DIALOG")));
+}
+
+#[test]
+fn invalid_negated_regex_rejected() {
+    let cfg = make_config(r#"
+        [[rule]]
+        title_not = "(unclosed"
+        workspace = 1
+    "#);
+    assert!(rules::compile(&cfg).is_err());
+}
+
+// PATTERN LISTS
+
+#[test]
+fn pattern_list_any_matches() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = ["^kitty$", "^Alacritty$", "^foot$"]
+        type = ["normal", "dialog"]
+        workspace = 1
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert!(compiled[0].matches(&win("kitty", "", "", "", "normal")));
+    assert!(compiled[0].matches(&win("foot", "", "", "", "dialog")));
+    assert!(!compiled[0].matches(&win("xterm", "", "", "", "normal")));
+    assert!(!compiled[0].matches(&win("kitty", "", "", "", "splash")));
+}
+
+#[test]
+fn negated_pattern_list_excludes_all() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "kitty"
+        title_not = ["montauk", "htop"]
+        workspace = 1
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert!(compiled[0].matches(&win("kitty", "zsh", "", "", "")));
+    assert!(!compiled[0].matches(&win("kitty", "htop", "", "", "")));
+    assert!(!compiled[0].matches(&win("kitty", "montauk", "", "", "")));
+}
+
+#[test]
+fn bad_regex_in_list_rejected() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = ["kitty", "[invalid"]
+        workspace = 1
+    "#);
+    match rules::compile(&cfg) {
+        Err(e) => assert!(e.contains("[invalid"), "got: {}", e),
+        Ok(_) => panic!("expected error for invalid regex"),
+    }
+}
+
+// PHYSICAL UNITS
+
+#[test]
+fn compile_millimeter_size() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "zathura"
+        size = ["300mm", "200mm"]
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(
+        compiled[0].actions,
+        vec![Action::Size(rules::SizeTarget::Flexible(
+            rules::DimensionVal::Millimeters(300.0),
+            rules::DimensionVal::Millimeters(200.0),
+        ))]
+    );
+}
+
+#[test]
+fn millimeters_resolve_per_monitor_density() {
+    let mm = rules::DimensionVal::Millimeters(300.0);
+    // 27" 4K: 3840px over 597mm
+    assert_eq!(mm.resolve(3840, 597), 1930);
+    // 24" 1080p: 1920px over 531mm
+    assert_eq!(mm.resolve(1920, 531), 1085);
+    // Unknown physical size falls back to 96 DPI
+    assert_eq!(mm.resolve(1920, 0), 1134);
+}
+
+#[test]
+fn percent_and_pixels_resolve() {
+    assert_eq!(rules::DimensionVal::Percent(0.5).resolve(1920, 531), 960);
+    assert_eq!(rules::DimensionVal::Pixels(42).resolve(1920, 531), 42);
+}
+
+// MATCH MODES
+
+#[test]
+fn glob_match_mode_from_settings() {
+    let cfg = make_config(r#"
+        [settings]
+        match_mode = "glob"
+
+        [[rule]]
+        class = "chrom*"
+        title = "?im [!x]"
+        workspace = 2
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert!(compiled[0].matches(&win("chromium", "vim a", "", "", "")));
+    assert!(!compiled[0].matches(&win("xchromium", "vim a", "", "", "")));
+    assert!(!compiled[0].matches(&win("chromium", "vim x", "", "", "")));
+}
+
+#[test]
+fn rule_match_mode_overrides_settings() {
+    let cfg = make_config(r#"
+        [settings]
+        match_mode = "glob"
+
+        [[rule]]
+        class = "kit"
+        match_mode = "substring"
+        workspace = 1
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert!(compiled[0].matches(&win("kitty", "", "", "", "")));
+    assert!(!compiled[0].matches(&win("foot", "", "", "", "")));
+}
+
+#[test]
+fn exact_prefix_in_regex_mode() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "=kitty.bin"
+        workspace = 1
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert!(compiled[0].matches(&win("kitty.bin", "", "", "", "")));
+    assert!(!compiled[0].matches(&win("kittyXbin", "", "", "", "")));
+    assert!(!compiled[0].matches(&win("kitty.bin2", "", "", "", "")));
+}
+
+#[test]
+fn match_mode_to_regex() {
+    use rules::MatchMode;
+    assert_eq!(MatchMode::Exact.to_regex("a.b"), r"^a\.b$");
+    assert_eq!(MatchMode::Substring.to_regex("a+b"), r"a\+b");
+    assert_eq!(MatchMode::Glob.to_regex("*.txt"), r"^.*\.txt$");
+    assert_eq!(MatchMode::Regex.to_regex("^k.*"), "^k.*");
+}
+
+// CASE INSENSITIVITY
+
+#[test]
+fn case_insensitive_from_settings() {
+    let cfg = make_config(r#"
+        [settings]
+        case_insensitive = true
+
+        [[rule]]
+        class = "^firefox$"
+        title_not = "private"
+        workspace = 2
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert!(compiled[0].matches(&win("Firefox", "Home", "", "", "")));
+    assert!(!compiled[0].matches(&win("FIREFOX", "Private Browsing", "", "", "")));
+}
+
+#[test]
+fn rule_case_insensitive_overrides_settings() {
+    let cfg = make_config(r#"
+        [settings]
+        case_insensitive = true
+
+        [[rule]]
+        class = "kitty"
+        case_insensitive = false
+        workspace = 1
+
+        [[rule]]
+        class = "=kitty"
+        workspace = 2
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert!(!compiled[0].matches(&win("Kitty", "", "", "", "")));
+    assert!(compiled[1].matches(&win("KITTY", "", "", "", "")));
+}
+
+// PLACEMENT
+
+#[test]
+fn placement_from_settings_with_rule_override() {
+    let cfg = make_config(r#"
+        [settings]
+        placement = "hints"
+
+        [[rule]]
+        class = "xterm"
+        position = "center"
+
+        [[rule]]
+        class = "kitty"
+        placement = "configure"
+        size = [800, 600]
+
+        [[rule]]
+        class = "foot"
+        workspace = 1
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert_eq!(compiled[0].placement, Placement::Hints);
+    assert_eq!(compiled[1].placement, Placement::Configure);
+    assert_eq!(compiled[2].placement, Placement::Hints);
+
+    let defaults = rules::compile(&make_config(r#"
+        [[rule]]
+        class = "xterm"
+        position = "center"
+    "#)).unwrap();
+    assert_eq!(defaults[0].placement, Placement::Configure);
+}
+
+// PRIORITY
+
+#[test]
+fn rules_sorted_by_priority_then_file_order() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = ".*"
+        workspace = 0
+
+        [[rule]]
+        class = "firefox"
+        title = "YouTube"
+        priority = 10
+        workspace = 3
+
+        [[rule]]
+        class = "firefox"
+        workspace = 2
+
+        [[rule]]
+        class = "steam"
+        priority = -5
+        workspace = 8
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    let order: Vec<usize> = compiled.iter().map(|r| r.index).collect();
+    assert_eq!(order, vec![1, 0, 2, 3]);
+    assert_eq!(compiled[0].priority, 10);
+}
+
+// LOAD-TIME CONDITIONS
+
+#[test]
+fn when_hostname_filters_rules_at_load() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "firefox"
+        monitor = "eDP-1"
+        when.hostname = "^laptop"
+
+        [[rule]]
+        class = "firefox"
+        monitor = "DP-2"
+        when = { hostname = "^desktop$" }
+
+        [[rule]]
+        class = "kitty"
+        workspace = 1
+    "#);
+
+    let laptop = rules::compile_for_host(&cfg, "laptop-x1").unwrap();
+    assert_eq!(laptop.iter().map(|r| r.index).collect::<Vec<_>>(), vec![0, 2]);
+
+    let desktop = rules::compile_for_host(&cfg, "desktop").unwrap();
+    assert_eq!(desktop.iter().map(|r| r.index).collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[test]
+fn bad_hostname_regex_is_an_error() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "firefox"
+        when.hostname = "(unclosed"
+        workspace = 1
+    "#);
+
+    let err = rules::compile_for_host(&cfg, "laptop").err().unwrap();
+    assert!(err.starts_with("rule[0]: bad when.hostname"), "got: {}", err);
+}
+
+// WORKSPACE BALANCING
+
+#[test]
+fn least_populated_workspace() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "kitty"
+        workspace = "least-populated"
+
+        [[rule]]
+        class = "firefox"
+        workspace = "least-populated"
+        workspace_among = [4, 2, 3]
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    let target = |i: usize| match &compiled[i].actions[0] {
+        Action::Workspace(t) => t.clone(),
+        other => panic!("unexpected action {:?}", other),
+    };
+
+    // Workspaces 0 and 2 hold two windows each, 1 holds one, 3 is empty
+    let occupied = [0, 0, 1, 2, 2, 4];
+    assert_eq!(target(0).resolve(4, &[], &occupied), Some(3));
+    assert_eq!(target(0).resolve(3, &[], &occupied), Some(1));
+    assert_eq!(target(1).resolve(5, &[], &occupied), Some(3));
+    assert_eq!(target(1).resolve(5, &[], &[3]), Some(4));
+    assert_eq!(compiled[0].actions[0].to_string(), "workspace -> least-populated");
+    assert_eq!(
+        compiled[1].actions[0].to_string(),
+        "workspace -> least-populated of [4, 2, 3]"
+    );
+}
+
+#[test]
+fn workspace_by_name_resolves_against_desktop_names() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "thunderbird"
+        workspace = "mail"
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    let target = WorkspaceTarget::Name("mail".into());
+    assert_eq!(compiled[0].actions, vec![Action::Workspace(target.clone())]);
+    assert_eq!(compiled[0].actions[0].to_string(), "workspace -> 'mail'");
+
+    let names: Vec<String> = ["web", "code", "mail"].iter().map(|s| s.to_string()).collect();
+    assert_eq!(target.resolve(3, &names, &[]), Some(2));
+    assert_eq!(target.resolve(2, &names[..2], &[]), None);
+}
+
+// MONITOR BALANCING
+
+#[test]
+fn population_monitor_targets() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "kitty"
+        monitor = "least-populated"
+        position = "center"
+
+        [[rule]]
+        class = "foot"
+        monitor = "emptiest"
+        position = "center"
+
+        [[rule]]
+        on_monitor = "emptiest"
+        above = true
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+
+    assert_eq!(compiled[0].monitor(), Some(&MonitorTarget::LeastPopulated));
+    assert_eq!(compiled[1].monitor(), Some(&MonitorTarget::Emptiest));
+    // Only the action understands the population targets
+    let on = |name: &str| WindowProps {
+        monitor: Some(MonitorId { index: 0, name: name.into() }),
+        ..WindowProps::default()
+    };
+    assert!(compiled[2].matches(&on("emptiest")));
+
+    assert_eq!(MonitorTarget::LeastPopulated.pick_by_population(&[3, 1, 1]), Some(1));
+    assert_eq!(MonitorTarget::Emptiest.pick_by_population(&[3, 0, 1]), Some(1));
+    assert_eq!(MonitorTarget::Emptiest.pick_by_population(&[3, 2, 1]), None);
+    assert_eq!(MonitorTarget::Index(0).pick_by_population(&[3, 0]), None);
+}
+
+#[test]
+fn layout_monitor_keywords() {
+    use rules::OutputInfo;
+
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "mpv"
+        monitor = "external"
+        fullscreen = true
+
+        [[rule]]
+        class = "slack"
+        monitor = "current"
+        position = "center"
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    assert_eq!(compiled[0].monitor(), Some(&MonitorTarget::External));
+    assert_eq!(compiled[1].monitor(), Some(&MonitorTarget::Current));
+    assert_eq!(compiled[1].actions[0].to_string(), "monitor -> current");
+
+    let output = |name, width, height, primary| OutputInfo { name, width, height, primary };
+    let outputs = [
+        output("eDP-1", 2560, 1600, false),
+        output("HDMI-1", 1920, 1080, true),
+        output("DP-2", 2560, 1600, false),
+    ];
+    assert_eq!(MonitorTarget::Primary.pick_by_layout(&outputs), Some(1));
+    assert_eq!(MonitorTarget::Largest.pick_by_layout(&outputs), Some(0));
+    assert_eq!(MonitorTarget::External.pick_by_layout(&outputs), Some(1));
+    assert_eq!(MonitorTarget::External.pick_by_layout(&outputs[..1]), None);
+    assert!(rules::is_builtin_output("LVDS1"));
+    assert!(!rules::is_builtin_output("DP-1"));
+}
+
+#[test]
+fn when_monitors_checked_per_evaluation() {
+    let cfg = make_config(r#"
+        [[rule]]
+        class = "firefox"
+        monitor = 1
+        position = "center"
+        when.monitors = ">= 2"
+
+        [[rule]]
+        class = "firefox"
+        maximize = true
+        when.monitors = 1
+    "#);
+    let compiled = rules::compile(&cfg).unwrap();
+    let with_outputs = |n: u32| WindowProps {
+        class: "firefox".into(),
+        monitor_count: Some(n),
+        ..WindowProps::default()
+    };
+
+    assert!(compiled[0].needs().monitor_count);
+    assert!(!compiled[0].matches(&with_outputs(1)));
+    assert!(compiled[0].matches(&with_outputs(2)));
+    assert!(compiled[1].matches(&with_outputs(1)));
+    assert!(!compiled[1].matches(&with_outputs(3)));
+}
+
+// GRID CELLS
+
+#[test]
+fn grid_cells_count_across_then_down() {
+    use rules::{DimensionVal::Percent, Grid, PositionTarget, SizeTarget};
+
+    let grid = Grid::parse("3x2").unwrap();
+    assert_eq!(grid, Grid { cols: 3, rows: 2 });
+
+    let (size, position) = grid.cell(4).unwrap();
+    assert_eq!(size, SizeTarget::Flexible(Percent(1.0 / 3.0), Percent(0.5)));
+    assert_eq!(position, PositionTarget::Flexible(Percent(1.0 / 3.0), Percent(0.5)));
+
+    assert_eq!(grid.cell(0).unwrap().1, PositionTarget::Flexible(Percent(0.0), Percent(0.0)));
+    assert_eq!(grid.cell(6).unwrap_err(), "cell 6 is outside a 3x2 grid (0-5)");
+    assert!(Grid::parse("3by2").is_err());
+    assert!(Grid::parse("3x0").is_err());
+    assert_eq!(Grid::parse("65536x65536").unwrap_err(), "grid '65536x65536' has too many cells");
+    assert!(Grid { cols: 65536, rows: 65536 }.cell(u32::MAX).is_ok());
+}
+
+// NAMED CONDITIONS
+
+#[test]
+fn named_conditions_combine_terms() {
+    let cfg = make_config(r#"
+        [conditions]
+        work_hours = 'time within 09:00-18:00 && host == "desk"'
+        night_or_docked = "time within 22:00-06:00 || (monitors >= 2 && !compositor)"
+
+        [[rule]]
+        class = "slack"
+        when = "work_hours"
+        workspace = 3
+
+        [[rule]]
+        class = "slack"
+        when = "night_or_docked"
+        minimize = true
+    "#);
+    let at = |hour: u32, monitors: u32, compositor: bool| WindowProps {
+        class: "slack".into(),
+        minute_of_day: Some(hour * 60),
+        monitor_count: Some(monitors),
+        compositor: Some(compositor),
+        ..WindowProps::default()
+    };
+
+    let desk = rules::compile_for_host(&cfg, "desk").unwrap();
+    assert!(desk[0].needs().time);
+    assert!(desk[0].matches(&at(9, 1, true)));
+    assert!(!desk[0].matches(&at(18, 1, true)));
+    assert!(desk[1].matches(&at(23, 1, true)));
+    assert!(desk[1].matches(&at(3, 1, true)));
+    assert!(!desk[1].matches(&at(12, 1, true)));
+    assert!(desk[1].matches(&at(12, 2, false)));
+    assert!(!desk[1].matches(&at(12, 2, true)));
+
+    let laptop = rules::compile_for_host(&cfg, "laptop").unwrap();
+    assert!(!laptop[0].matches(&at(9, 1, true)));
+}
+
+#[test]
+fn condition_errors_name_the_condition() {
+    let bad = |expr: &str| {
+        let cfg = make_config(&format!("[conditions]\nc = '{}'\n[[rule]]\nclass = \"x\"\nabove = true\n", expr));
+        rules::compile_for_host(&cfg, "desk").err().unwrap()
+    };
+
+    assert_eq!(bad("time within 9:00"), "conditions.c: expected '-'");
+    assert_eq!(bad("weekday == 1"), "conditions.c: unknown term 'weekday' (expected time, host, monitors, or compositor)");
+    assert_eq!(bad("compositor &&"), "conditions.c: unexpected end of expression");
+    assert_eq!(bad("(compositor"), "conditions.c: expected ')'");
+    assert_eq!(bad("time within 25:00-01:00"), "conditions.c: invalid number or time '25:00'");
+    assert_eq!(bad("compositor compositor"), "conditions.c: unexpected 'compositor'");
+}
+
+#[test]
+fn comparison_parsing() {
+    use rules::{CmpOp, Comparison};
+    assert_eq!(Comparison::parse(">= 2"), Ok(Comparison { op: CmpOp::Ge, value: 2 }));
+    assert_eq!(Comparison::parse("<3"), Ok(Comparison { op: CmpOp::Lt, value: 3 }));
+    assert_eq!(Comparison::parse("!= 1"), Ok(Comparison { op: CmpOp::Ne, value: 1 }));
+    assert_eq!(Comparison::parse("2"), Ok(Comparison { op: CmpOp::Eq, value: 2 }));
+    assert!(Comparison::parse("=> 2").is_err());
+    assert!(Comparison::parse("lots").is_err());
+}
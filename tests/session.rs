@@ -0,0 +1,26 @@
+use std::path::Path;
+
+use cherrypie::session::SessionUser;
+
+#[test]
+fn looks_up_users() {
+    let root = SessionUser::lookup("root").unwrap();
+    assert_eq!((root.uid, root.gid), (0, 0));
+    assert!(root.home.is_absolute());
+
+    assert_eq!(SessionUser::lookup("no-such-user-here"), Err("no user 'no-such-user-here'".into()));
+    assert!(SessionUser::lookup("a\0b").unwrap_err().starts_with("invalid user name"));
+}
+
+#[test]
+fn prefers_the_owners_config() {
+    let home = tempfile::tempdir().unwrap();
+    let user = SessionUser { name: "kiosk".into(), uid: 1000, gid: 1000, home: home.path().to_path_buf() };
+    let fallback = Path::new("/etc/cherrypie/config.toml");
+
+    assert_eq!(user.config_file(fallback), fallback);
+    let own = home.path().join(".config/cherrypie/config.toml");
+    std::fs::create_dir_all(own.parent().unwrap()).unwrap();
+    std::fs::write(&own, "").unwrap();
+    assert_eq!(user.config_file(fallback), own);
+}
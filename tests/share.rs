@@ -0,0 +1,51 @@
+use cherrypie::share;
+use toml::Value;
+
+const CONFIG: &str = r#"
+[conditions]
+docked = "monitors >= 2"
+
+[[rule]]
+class = "kitty"
+workspace = 1
+
+[[rule]]
+class = "mpv"
+when = "docked"
+process_env = { PROJECT = "^work" }
+position = "left-half"
+"#;
+
+#[test]
+fn encode_decode_round_trip() {
+    let config: Value = toml::from_str(CONFIG).unwrap();
+    let code = share::encode(&config, &[1, 0]).unwrap();
+    assert!(code.starts_with(share::PREFIX));
+    assert!(!code.contains(char::is_whitespace));
+
+    let rules = share::decode(&format!("  {}\n", code)).unwrap();
+    let list = config["rule"].as_array().unwrap();
+    assert_eq!(rules, vec![list[1].clone(), list[0].clone()]);
+    assert!(share::to_toml(rules).unwrap().starts_with("[[rule]]\n"));
+
+    assert_eq!(share::encode(&config, &[2]), Err("no rule[2]".into()));
+}
+
+#[test]
+fn decode_rejects_foreign_text() {
+    assert!(share::decode("W1tydWxlXV0K").unwrap_err().starts_with("not a rule code"));
+    assert_eq!(share::decode("cherrypie1:a*b"), Err("invalid character '*' in rule code".into()));
+    // "x = 1": valid TOML without rules
+    assert_eq!(share::decode("cherrypie1:eCA9IDE"), Err("rule code holds no rules".into()));
+}
+
+#[test]
+fn check_resolves_conditions_from_the_config() {
+    let config: Value = toml::from_str(CONFIG).unwrap();
+    let rules = share::decode(&share::encode(&config, &[1]).unwrap()).unwrap();
+
+    assert_eq!(share::check(Some(&config), &rules), Ok(()));
+    let err = share::check(None, &rules).unwrap_err();
+    assert!(err.starts_with("rule[0]: "), "got: {}", err);
+    assert!(err.contains("docked"), "got: {}", err);
+}
@@ -0,0 +1,39 @@
+use cherrypie::stats::{KEEP_DAYS, Stats};
+
+#[test]
+fn counts_rules_per_day_and_classes() {
+    let mut stats = Stats::default();
+    stats.record("2026-03-01", 0, "kitty");
+    stats.record("2026-03-01", 0, "kitty");
+    stats.record("2026-03-02", 2, "firefox");
+    stats.record("2026-03-02", 0, "");
+
+    assert_eq!(stats.totals().into_iter().collect::<Vec<_>>(), [(0, 3), (2, 1)]);
+    assert_eq!(stats.classes.get("kitty"), Some(&2));
+    assert_eq!(stats.classes.len(), 2);
+
+    let report = stats.report(1, Some(4));
+    assert!(report.starts_with("Since 2026-03-01 (2 days recorded)\n"));
+    assert!(report.contains("  2026-03-02  rule[0] 1, rule[2] 1\n"));
+    assert!(!report.contains("  2026-03-01  "));
+    assert!(report.contains("Rules:\n  rule[0]  3\n  rule[2]  1\n"));
+    assert!(report.contains("Classes:\n  kitty  2\n  firefox  1\n"));
+    assert!(report.ends_with("Never fired: rule[1], rule[3]\n"));
+    assert_eq!(Stats::default().report(7, None), "no rules have fired yet\n");
+}
+
+#[test]
+fn keeps_recent_days_across_save_and_load() {
+    let mut stats = Stats::default();
+    for day in 0..KEEP_DAYS + 5 {
+        stats.record(&format!("2026-{:03}", day), 0, "kitty");
+    }
+    assert_eq!(stats.days.len(), KEEP_DAYS);
+    assert_eq!(stats.days.keys().next().map(String::as_str), Some("2026-005"));
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state/cherrypie/stats.toml");
+    assert_eq!(Stats::load(&path).unwrap(), Stats::default());
+    stats.save(&path).unwrap();
+    assert_eq!(Stats::load(&path).unwrap(), stats);
+}